@@ -0,0 +1,71 @@
+//! The logs and artifacts of tests, written to `Config::logs_dir` to diagnose failures after
+//! the fact, see `Config::dump_artifacts`.
+
+use crate::{Config, DumpArtifacts};
+use std::path::{Component, Path, PathBuf};
+
+/// The files collected while running a revision of a test, named after what they contain.
+pub(crate) struct Artifacts {
+    /// `None` if the artifacts are never written, so there's no need to collect them.
+    files: Option<Vec<(String, Vec<u8>)>>,
+}
+
+impl Artifacts {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            files: (config.dump_artifacts != DumpArtifacts::Never).then(Vec::new),
+        }
+    }
+
+    /// Remembers `contents` as the file `name`, replacing an earlier file of the same name.
+    pub fn add(&mut self, name: impl Into<String>, contents: &[u8]) {
+        if let Some(files) = &mut self.files {
+            let name = name.into();
+            files.retain(|(file, _)| *file != name);
+            files.push((name, contents.to_vec()));
+        }
+    }
+
+    /// Writes the files to the directory of the test in `Config::logs_dir`, if the test gets
+    /// one, and returns that directory.
+    pub fn write(
+        self,
+        config: &Config,
+        path: &Path,
+        revision: &str,
+        failed: bool,
+    ) -> Option<PathBuf> {
+        let files = self.files?;
+        if !failed && config.dump_artifacts == DumpArtifacts::OnFailure {
+            return None;
+        }
+        let dir = dir(config, path, revision);
+        // The logs only help with diagnosing failures, failing to write them isn't one.
+        std::fs::create_dir_all(&dir).ok()?;
+        for (name, contents) in files {
+            let _ = std::fs::write(dir.join(name), contents);
+        }
+        Some(dir)
+    }
+}
+
+/// The directory of a revision of a test in `Config::logs_dir`, mirroring the test's path.
+fn dir(config: &Config, path: &Path, revision: &str) -> PathBuf {
+    let relative: PathBuf = path
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect();
+    let mut dir = config.logs_dir.join(relative).into_os_string();
+    if !revision.is_empty() {
+        dir.push(".");
+        dir.push(revision);
+    }
+    dir.into()
+}
+
+/// Removes the artifacts of earlier runs, so they can't be mistaken for those of this run.
+pub(crate) fn clean(config: &Config) {
+    if config.dump_artifacts != DumpArtifacts::Never {
+        let _ = std::fs::remove_dir_all(&config.logs_dir);
+    }
+}
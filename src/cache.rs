@@ -0,0 +1,166 @@
+//! Reusing the dependency and aux builds of earlier runs, see `Config::cache_dir`.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Mutex,
+    time::UNIX_EPOCH,
+};
+
+use lazy_static::lazy_static;
+
+#[cfg(test)]
+mod tests;
+
+/// A hash of everything that influences a build, used as the name of its cache entry.
+pub(crate) struct Key(u64);
+
+impl Default for Key {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Key {
+    /// Starts a key for a build with `program`, so updating the compiler invalidates the cache.
+    pub fn new(program: &Path) -> Self {
+        let mut key = Self::default();
+        key.add(program.to_string_lossy().as_bytes());
+        key.add(program_version(program));
+        key
+    }
+
+    /// Hashes `bytes`. Uses FNV-1a, as the hash must be the same in every run.
+    pub fn add(&mut self, bytes: impl AsRef<[u8]>) {
+        let bytes = bytes.as_ref();
+        // The length keeps `["ab", "c"]` and `["a", "bc"]` apart.
+        for byte in (bytes.len() as u64).to_le_bytes().iter().chain(bytes) {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    /// Hashes the program, arguments and environment of `cmd`.
+    pub fn add_command(&mut self, cmd: &Command) {
+        self.add(cmd.get_program().to_string_lossy().as_bytes());
+        for arg in cmd.get_args() {
+            self.add(arg.to_string_lossy().as_bytes());
+        }
+        for (var, val) in cmd.get_envs() {
+            self.add(var.to_string_lossy().as_bytes());
+            self.add(
+                val.map_or_else(|| "\0".into(), |val| val.to_string_lossy())
+                    .as_bytes(),
+            );
+        }
+    }
+
+    /// Hashes the metadata of the artifacts that `cmd` passes via `--extern` and of all files
+    /// in the directories it passes via `-L`, as rebuilt dependencies keep their paths.
+    pub fn add_artifacts(&mut self, cmd: &Command) {
+        let args: Vec<_> = cmd.get_args().map(|arg| arg.to_string_lossy()).collect();
+        for pair in args.windows(2) {
+            // `--extern name=path` and `-L kind=path`
+            let path = Path::new(pair[1].split_once('=').map_or(&*pair[1], |(_, path)| path));
+            if pair[0] == "--extern" {
+                self.add_file_metadata(path);
+            } else if pair[0] == "-L" {
+                self.add_dir(path, &[]);
+            }
+        }
+    }
+
+    /// Hashes the names, sizes and modification times of all files in `dir`, except for hidden
+    /// files and the files and directories in `dir` itself that are named in `skip`.
+    pub fn add_dir(&mut self, dir: &Path, skip: &[&str]) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        let mut entries: Vec<_> = entries.filter_map(|entry| entry.ok()).collect();
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let name = entry.file_name();
+            let hidden = name.to_string_lossy().starts_with('.');
+            if hidden || skip.iter().any(|skip| name == *skip) {
+                continue;
+            }
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            self.add(name.to_string_lossy().as_bytes());
+            if metadata.is_dir() {
+                self.add_dir(&entry.path(), &[]);
+            } else {
+                self.add_metadata(&metadata);
+            }
+        }
+    }
+
+    /// Hashes the size and modification time of the file at `path`.
+    pub fn add_file_metadata(&mut self, path: &Path) {
+        match std::fs::metadata(path) {
+            Ok(metadata) => self.add_metadata(&metadata),
+            Err(_) => self.add("\0missing"),
+        }
+    }
+
+    fn add_metadata(&mut self, metadata: &std::fs::Metadata) {
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        self.add(metadata.len().to_le_bytes());
+        self.add(modified.as_nanos().to_le_bytes());
+    }
+
+    /// The path of the cache entry in `cache_dir`.
+    pub fn entry(&self, cache_dir: &Path, kind: &str) -> PathBuf {
+        cache_dir.join(format!("{kind}-{}", self.hex()))
+    }
+
+    /// The hash as a fixed width hex string.
+    pub fn hex(&self) -> String {
+        format!("{:016x}", self.0)
+    }
+}
+
+lazy_static! {
+    static ref VERSIONS: Mutex<HashMap<PathBuf, Vec<u8>>> = Mutex::new(HashMap::new());
+}
+
+/// The output of `program -vV`, which contains the commit hash for `rustc` and `cargo`.
+fn program_version(program: &Path) -> Vec<u8> {
+    VERSIONS
+        .lock()
+        .unwrap()
+        .entry(program.to_path_buf())
+        .or_insert_with(|| {
+            Command::new(program)
+                .arg("-vV")
+                .output()
+                .map(|output| output.stdout)
+                .unwrap_or_default()
+        })
+        .clone()
+}
+
+/// Moves the finished build in `tmp` to the cache `entry`. Builds are created in a temporary
+/// directory in the cache directory and then renamed, so other processes never see unfinished
+/// entries. If another process or thread created the entry in the meantime, its entry is kept,
+/// as it was built from the same inputs.
+pub(crate) fn store(tmp: tempfile::TempDir, entry: &Path) -> std::io::Result<()> {
+    match std::fs::rename(tmp.path(), entry) {
+        Ok(()) => Ok(()),
+        Err(_) if entry.exists() => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Writes `contents` to the file `entry`, atomically like `store`.
+pub(crate) fn store_file(cache_dir: &Path, entry: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut tmp = tempfile::NamedTempFile::new_in(cache_dir)?;
+    std::io::Write::write_all(&mut tmp, contents)?;
+    tmp.persist(entry).map_err(|err| err.error)?;
+    Ok(())
+}
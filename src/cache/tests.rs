@@ -0,0 +1,52 @@
+use super::Key;
+use std::{path::Path, process::Command};
+
+#[test]
+fn cache_keys() {
+    let key = |args: &[&str], content: &str| {
+        let mut cmd = Command::new("rustc");
+        cmd.args(args);
+        let mut key = Key::new(Path::new("rustc"));
+        key.add(content);
+        key.add_command(&cmd);
+        key.entry(Path::new("cache"), "aux")
+    };
+    let entry = key(&["-O"], "fn main() {}");
+    assert_eq!(entry, key(&["-O"], "fn main() {}"));
+    assert!(entry.starts_with("cache"));
+    assert!(entry
+        .file_name()
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("aux-"));
+    assert_ne!(entry, key(&[], "fn main() {}"));
+    assert_ne!(entry, key(&["-O"], "fn main() { }"));
+    assert_ne!(key(&["-O", "x"], ""), key(&["-Ox"], ""));
+}
+
+#[test]
+fn cache_keys_of_rebuilt_artifacts() {
+    let dir = tempfile::tempdir().unwrap();
+    let rlib = dir.path().join("libdep.rlib");
+    std::fs::write(&rlib, "old").unwrap();
+    let mut cmd = Command::new("rustc");
+    cmd.arg("--extern")
+        .arg(format!("dep={}", rlib.display()))
+        .arg("-L")
+        .arg(dir.path());
+    let key = || {
+        let mut key = Key::new(Path::new("rustc"));
+        key.add_command(&cmd);
+        key.add_artifacts(&cmd);
+        key.hex()
+    };
+    let old = key();
+    // Rebuilding the dependency keeps its path, but not its contents.
+    std::fs::write(&rlib, "rebuilt").unwrap();
+    let rebuilt = key();
+    assert_ne!(old, rebuilt);
+    // So do the other crates in the `-L` directory, without changing the directory itself.
+    std::fs::write(dir.path().join("libother.rlib"), "new").unwrap();
+    assert_ne!(rebuilt, key());
+}
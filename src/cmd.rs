@@ -1,7 +1,9 @@
 use std::{
-    ffi::OsString,
+    ffi::{OsStr, OsString},
+    io::{Read, Write},
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, Output, Stdio},
+    time::{Duration, Instant},
 };
 
 #[derive(Debug, Clone)]
@@ -48,6 +50,15 @@ impl CommandBuilder {
         }
     }
 
+    /// Uses the `RUSTDOC` env var or just a program named `rustdoc` and the argument
+    /// `--error-format=json`. See [`Config::rustdoc`](crate::Config::rustdoc).
+    pub fn rustdoc() -> Self {
+        Self {
+            program: PathBuf::from(std::env::var_os("RUSTDOC").unwrap_or_else(|| "rustdoc".into())),
+            ..Self::rustc()
+        }
+    }
+
     /// Same as [`rustc`], but with arguments for obtaining the cfgs.
     pub fn cfgs() -> Self {
         Self {
@@ -118,3 +129,149 @@ impl CommandBuilder {
         }
     }
 }
+
+/// Render the command as a line that can be pasted into a POSIX shell to run it again,
+/// including the working directory and the environment variables it sets or removes.
+pub fn shell_command(cmd: &Command) -> String {
+    let mut line = String::new();
+    if let Some(dir) = cmd.get_current_dir() {
+        line.push_str("cd ");
+        line.push_str(&shell_quote(dir.as_os_str()));
+        line.push_str(" && ");
+    }
+    let (set, removed): (Vec<_>, Vec<_>) = cmd.get_envs().partition(|(_, val)| val.is_some());
+    if !removed.is_empty() {
+        line.push_str("env");
+        for (var, _) in removed {
+            line.push_str(" -u ");
+            line.push_str(&shell_quote(var));
+        }
+        line.push(' ');
+    }
+    for (var, val) in set {
+        line.push_str(&var.to_string_lossy());
+        line.push('=');
+        line.push_str(&shell_quote(val.unwrap()));
+        line.push(' ');
+    }
+    line.push_str(&shell_quote(cmd.get_program()));
+    for arg in cmd.get_args() {
+        line.push(' ');
+        line.push_str(&shell_quote(arg));
+    }
+    line
+}
+
+/// Quotes `s` for a POSIX shell, unless it only consists of characters the shell leaves alone.
+fn shell_quote(s: &OsStr) -> String {
+    let s = s.to_string_lossy();
+    if !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%^".contains(c))
+    {
+        s.into_owned()
+    } else {
+        format!("'{}'", s.replace('\'', r"'\''"))
+    }
+}
+
+/// Run the command to completion and collect its output, like [`Command::output`].
+/// If `stdin` is given, it is written to the command's stdin.
+/// If `timeout` is given and the command is still running after it elapsed,
+/// the command (and on unix, every process it spawned) is killed and `Ok(None)` is returned.
+pub(crate) fn output_with_timeout(
+    cmd: &mut Command,
+    stdin: Option<Vec<u8>>,
+    timeout: Option<Duration>,
+) -> std::io::Result<Option<Output>> {
+    if stdin.is_none() && timeout.is_none() {
+        return cmd.output().map(Some);
+    }
+    // Put the child into its own process group, so we can kill everything it spawned.
+    #[cfg(unix)]
+    if timeout.is_some() {
+        // SAFETY: `setpgid` is async-signal-safe.
+        unsafe {
+            std::os::unix::process::CommandExt::pre_exec(cmd, || {
+                if libc::setpgid(0, 0) == 0 {
+                    Ok(())
+                } else {
+                    Err(std::io::Error::last_os_error())
+                }
+            });
+        }
+    }
+    let mut child = cmd
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(input) = stdin {
+        let mut child_stdin = child.stdin.take().unwrap();
+        std::thread::spawn(move || {
+            // The program may exit without reading all of its input, that's fine.
+            let _ = child_stdin.write_all(&input);
+        });
+    }
+    // Read the output in separate threads, so the child doesn't block on full pipes.
+    let mut stdout = child.stdout.take().unwrap();
+    let mut stderr = child.stderr.take().unwrap();
+    let stdout = std::thread::spawn(move || {
+        let mut buf = vec![];
+        stdout.read_to_end(&mut buf).map(|_| buf)
+    });
+    let stderr = std::thread::spawn(move || {
+        let mut buf = vec![];
+        stderr.read_to_end(&mut buf).map(|_| buf)
+    });
+
+    let status = match timeout {
+        None => child.wait()?,
+        Some(timeout) => {
+            let deadline = Instant::now() + timeout;
+            loop {
+                if let Some(status) = child.try_wait()? {
+                    break status;
+                }
+                if Instant::now() >= deadline {
+                    #[cfg(unix)]
+                    // SAFETY: only sends a signal to the process group we created above.
+                    unsafe {
+                        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+                    }
+                    #[cfg(not(unix))]
+                    child.kill()?;
+                    child.wait()?;
+                    return Ok(None);
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+    };
+    Ok(Some(Output {
+        status,
+        stdout: stdout.join().unwrap()?,
+        stderr: stderr.join().unwrap()?,
+    }))
+}
+
+/// Like [`output_with_timeout`] without `stdin`, but reports the command timing out or
+/// failing to start as an [`Error`](crate::Error).
+pub(crate) fn output_or_error(
+    cmd: &mut Command,
+    timeout: Option<Duration>,
+) -> Result<Output, crate::Error> {
+    match output_with_timeout(cmd, None, timeout) {
+        Ok(Some(output)) => Ok(output),
+        Ok(None) => Err(crate::Error::TimedOut {
+            timeout: timeout.unwrap(),
+        }),
+        Err(err) => Err(crate::Error::Bug(format!(
+            "could not execute {cmd:?}: {err}"
+        ))),
+    }
+}
@@ -1,29 +1,43 @@
 use regex::bytes::Regex;
 
-use crate::{dependencies::build_dependencies, CommandBuilder, Filter, Match, Mode};
+use cargo_platform::Cfg;
+
+use crate::{
+    dependencies::{build_dependencies, cfgs},
+    parser::{CustomCommandFn, Endianness},
+    CommandBuilder, Filter, Level, Match, Mode,
+};
 pub use color_eyre;
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{bail, Result, WrapErr};
 use std::{
+    collections::HashMap,
     ffi::OsString,
     num::NonZeroUsize,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 mod args;
-pub use args::Args;
+mod file;
+pub use args::{Args, Format};
 
 #[derive(Debug, Clone)]
 /// Central datastructure containing all information to run the tests.
 pub struct Config {
     /// Host triple; usually will be auto-detected.
     pub host: Option<String>,
-    /// `None` to run on the host, otherwise a target triple
+    /// `None` to run on the host, otherwise a target triple. If it is not the host, it is
+    /// passed to the program via `--target`, except for proc-macro aux builds, which need to
+    /// be built for the host. Overridden by `Args::target`.
     pub target: Option<String>,
     /// Filters applied to stderr output before processing it.
     /// By default contains a filter for replacing backslashes in paths with
     /// regular slashes.
     /// On windows, contains a filter to remove `\r`.
     pub stderr_filters: Filter,
+    /// Filters applied to stderr output after `stderr_filters`, which individual
+    /// tests can opt out of by name via `//@disable-filter: name`.
+    pub named_stderr_filters: Vec<(String, Match, &'static [u8])>,
     /// Filters applied to stdout output before processing it.
     /// On windows, contains a filter to remove `\r`.
     pub stdout_filters: Filter,
@@ -31,8 +45,17 @@ pub struct Config {
     pub root_dir: PathBuf,
     /// The mode in which to run the tests.
     pub mode: Mode,
-    /// The binary to actually execute.
+    /// The binary to actually execute. It does not need to be `rustc`, e.g. `clippy-driver`
+    /// or a wrapper works, too, as long as it accepts rustc's arguments. It gets its `args`,
+    /// then the out dir (see `CommandBuilder::out_dir_flag`), the test file, `--target`
+    /// when cross compiling, `--cfg=REVISION`, the `//@compile-flags`, `--edition` and
+    /// finally the `--extern` and `-L` flags for aux builds, in that order.
     pub program: CommandBuilder,
+    /// Whether `program` is `rustdoc`, see `Config::rustdoc`. The documentation it writes is
+    /// put into a directory of its own per test in `out_dir`, flags that rustdoc doesn't
+    /// have (like `--emit`) are not passed to it, and annotations don't conflict with
+    /// `Mode::Pass`, as rustdoc's diagnostics are lints that don't make it fail.
+    pub rustdoc: bool,
     /// The command to run to obtain the cfgs that the output is supposed to
     pub cfgs: CommandBuilder,
     /// What to do in case the stdout/stderr output differs from the expected one.
@@ -40,41 +63,213 @@ pub struct Config {
     /// that running `cargo test -- -- --bless` will automatically overwrite the
     /// `.stdout` and `.stderr` files with the latest output.
     pub output_conflict_handling: OutputConflictHandling,
+    /// When blessing, write the output files into this directory (mirroring their path
+    /// relative to `root_dir`) instead of next to the test files.
+    /// Use this if the test files live in a read-only directory.
+    pub output_base: Option<PathBuf>,
+    /// Compare and bless the output with the line endings it has. By default, `\r\n` line
+    /// endings are turned into `\n` in both the output and the output files before comparing
+    /// them, a missing newline at the end of either doesn't count as a difference, and blessed
+    /// output files always use `\n`, so checkouts with `core.autocrlf` don't fail all tests.
+    pub preserve_line_endings: bool,
     /// Path to a `Cargo.toml` that describes which dependencies the tests can access.
     pub dependencies_crate_manifest_path: Option<PathBuf>,
     /// The command to run can be changed from `cargo` to any custom command to build the
     /// dependencies in `dependencies_crate_manifest_path`
     pub dependency_builder: CommandBuilder,
-    /// How many threads to use for running tests. Defaults to number of cores
+    /// Features of the `dependencies_crate_manifest_path` crate to enable via `--features`.
+    /// Extended by `Args::dependency_features`.
+    pub dependency_builder_features: Vec<String>,
+    /// Build the `dependencies_crate_manifest_path` crate with `--no-default-features`.
+    /// Also set by `Args::dependency_no_default_features`.
+    pub dependency_builder_no_default_features: bool,
+    /// How many threads to use for running tests. Defaults to number of cores.
+    /// Aux builds happen on the thread of the test that needs them, so they are bounded by this, too.
+    /// Overridden by `Args::threads`.
     pub num_test_threads: NonZeroUsize,
     /// Where to dump files like the binaries compiled from tests.
     /// Defaults to `target/ui` in the current directory.
     pub out_dir: PathBuf,
+    /// Where the builds of `dependencies_crate_manifest_path` and of aux files are cached, so
+    /// later runs can reuse them if their inputs, flags and the compiler didn't change.
+    /// Defaults to `target/ui_test/cache` in the current directory, `None` disables the cache.
+    pub cache_dir: Option<PathBuf>,
+    /// Where to remember which tests passed, so later runs can skip them as long as neither the
+    /// test, its aux files and output files, nor the compiler and the settings used to compile
+    /// and check it changed. Such tests are reported as `TestResult::Cached`. Blessing and
+    /// `--force-rerun` always run all tests. `None` (the default) runs all tests every time.
+    pub fingerprint_dir: Option<PathBuf>,
+    /// Where the logs and artifacts of tests are written, see `dump_artifacts`.
+    /// Defaults to `target/ui_test/logs` in the current directory.
+    pub logs_dir: PathBuf,
+    /// Which tests get a directory in `logs_dir` with the raw and the normalized output of the
+    /// compiler and of the test binary, the JSON diagnostics, the fixed code and the commands
+    /// that were run. `logs_dir` is emptied at the start of every run, unless this is `Never`.
+    pub dump_artifacts: DumpArtifacts,
+    /// The file that the tests that failed are written to after every run, for `--rerun-failed`.
+    /// Defaults to `target/ui_test/last-failures.json` in the current directory.
+    pub last_failures: PathBuf,
     /// The default edition to use on all tests
     pub edition: Option<String>,
+    /// Environment variables that must not be visible to any test's compiler or binary.
+    /// Tests can forbid more via `//@forbid-env`.
+    pub forbidden_env: Vec<String>,
+    /// How often `//@run-rustfix` tests may apply suggestions and recompile the result
+    /// before giving up on reaching a state without suggestions.
+    pub fix_iterations: NonZeroUsize,
+    /// Make `//@run-rustfix` tests apply all suggestions, not just the machine applicable ones.
+    /// Tests can opt out via `//@rustfix-only-machine-applicable`.
+    pub rustfix_all_suggestions: bool,
+    /// Fail `//@run-rustfix` tests if compiling the `.fixed` file emits diagnostics
+    /// of this level or higher. Tests can opt out via `//@no-verify-fixed`.
+    pub fixed_diagnostics_level: Level,
+    /// All diagnostics of this level or higher need an annotation, unless a test overrides
+    /// it with `//@require-annotations-for-level`. If `None`, it is the lowest level of
+    /// the test's annotations, or `Level::Error` if it has none.
+    pub require_annotations_for_level: Option<Level>,
+    /// Render diagnostics ordered by file, line, column and error code instead of
+    /// in the order the compiler emitted them. Use this if the compiler emits
+    /// independent diagnostics in a nondeterministic order.
+    pub sort_diagnostics: bool,
+    /// Which of the compiler's output streams its JSON diagnostics are read from.
+    pub diagnostics_stream: DiagnosticsStream,
+    /// How many unchanged lines are displayed around the changed lines when the actual output
+    /// differs from the expected output.
+    pub diff_context: usize,
+    /// Kill the compiler and test binaries running longer than this,
+    /// unless the test specifies its own `//@timeout`.
+    pub default_timeout: Option<Duration>,
+    /// Warn about tests that take longer than this, without failing them.
+    pub time_limit_warning: Option<Duration>,
+    /// Lines in the program's stderr that mark an internal compiler error (ICE), if the program
+    /// also exited with code 101 or was aborted by `SIGABRT`. A test that ICEs fails with
+    /// `Error::Ice` in every mode, unless it has a `//@should-ice` comment. By default, these
+    /// match rustc's `error: internal compiler error` banner and `thread 'rustc' panicked`;
+    /// add the patterns of custom drivers whose panic output differs.
+    pub ice_patterns: Vec<Match>,
+    /// Warn about `normalize-*-test` rules of passing tests that replaced nothing, and, if no
+    /// test failed, about `stderr_filters`, `named_stderr_filters` and `stdout_filters` that
+    /// replaced nothing in any of the tests that were run (so not in cached or filtered ones).
+    /// The filters that `Config::rustc` adds are only needed on some platforms and never reported.
+    pub report_unused_normalizations: bool,
+    /// Make tests using deprecated comments fail instead of just emitting a warning.
+    pub deny_deprecated: bool,
+    /// Accept compiletest's `//[rev]~` annotation order without a warning,
+    /// so suites can migrate to `//~[rev]` incrementally.
+    pub allow_legacy_directives: bool,
+    /// When blessing, rewrite the text of `//~` annotations that no longer match, if their
+    /// line has exactly one diagnostic of their level left. Regex patterns are never rewritten.
+    /// Also rewrites compiletest's `//[rev]~` annotations to `//~[rev]`.
+    pub bless_annotations: bool,
+    /// When blessing, insert `//~` annotations for diagnostics that need one but have none.
+    /// The annotations are added at the end of the diagnostic's line if they fit,
+    /// and in new lines below it otherwise. Tests with revisions are left alone.
+    pub insert_annotations: bool,
+    /// When blessing, remove `//~` annotations that match no diagnostic anymore, and fix up the
+    /// offsets of the annotations around them. Tests with revisions are never pruned, as an
+    /// annotation that matches nothing in one revision may still match in another.
+    pub prune_annotations: bool,
+    /// Conditions that `//@ignore-NAME` and `//@only-NAME` accept in addition to the built-in
+    /// ones, and whether they are met. Use this for capabilities of the program under test that
+    /// can't be derived from the target, e.g. `("backend-x".into(), true)`.
+    pub custom_conditions: HashMap<String, bool>,
+    /// Commands that `//@NAME: ARGS` comments accept in addition to the built-in ones, e.g. for
+    /// flags of a wrapper around the program under test. Names of built-in commands can't be
+    /// used, and running the tests fails if any of these names collide with them.
+    pub custom_comment_parsers: HashMap<String, CustomCommandFn>,
+    /// Ignore `Mode::Run` tests (including tests with a `//@run` comment) if the target is not
+    /// the host and there is no `runner`, instead of failing them, as their binaries can't be
+    /// executed on the host.
+    pub skip_run_tests_when_cross: bool,
+    /// The command (and its leading arguments) to execute the binaries of run tests with,
+    /// e.g. an emulator like `qemu-aarch64` when cross compiling. The path of the binary is
+    /// appended, unless an argument contains `{}`, which is replaced by the path instead.
+    /// Run tests fail when cross compiling without a runner, unless `skip_run_tests_when_cross`
+    /// is set.
+    pub runner: Option<Vec<String>>,
+    /// The release channel of the program, for `//@only-nightly` and similar conditions;
+    /// usually will be auto-detected. Stays `None` if the program does not support
+    /// `--version --verbose`, in which case tests using channel conditions fail.
+    pub channel: Option<Channel>,
+    /// The pointer width of the target in bits; usually will be auto-detected via
+    /// `--print cfg`. If `None`, it is guessed from the target triple.
+    pub target_pointer_width: Option<u8>,
+    /// The sysroot of the program, which replaces `{{sysroot}}` in `//@compile-flags` and
+    /// `//@rustc-env`; usually will be auto-detected via `--print sysroot`. Stays `None` if
+    /// the program does not support that, in which case tests using `{{sysroot}}` fail.
+    pub sysroot: Option<PathBuf>,
+    /// The target triples known to the program; usually will be auto-detected via
+    /// `--print target-list`. `target-` and `host-` conditions that are not part of any of
+    /// these triples (or the host and target) are reported, as they are probably misspelled.
+    /// `None` disables this check.
+    pub known_triples: Option<Vec<String>>,
+    /// Make tests with `target-` and `host-` conditions that are not part of any known target
+    /// triple fail instead of just emitting a warning.
+    pub check_conditions: bool,
+    /// The target directory of the cargo workspace the tests are run in.
+    /// Set by `with_cargo_metadata`.
+    pub target_dir: Option<PathBuf>,
+    /// The root directory of the cargo workspace the tests are run in.
+    /// Set by `with_cargo_metadata`.
+    pub workspace_root: Option<PathBuf>,
+}
+
+/// The release channel of a Rust toolchain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    /// A stable release.
+    Stable,
+    /// A beta release.
+    Beta,
+    /// A nightly release or a locally built compiler.
+    Nightly,
+}
+
+impl std::fmt::Display for Channel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Channel::Stable => write!(f, "stable"),
+            Channel::Beta => write!(f, "beta"),
+            Channel::Nightly => write!(f, "nightly"),
+        }
+    }
+}
+
+/// The replacements of the filters that `Config::rustc` adds. The filters are recognized by
+/// their replacement pointing into this static, so user filters with the same text are not.
+static PLATFORM_REPLACEMENTS: [u8; 1] = *b"/";
+
+/// Whether the filter with this `replacement` is one that `Config::rustc` added.
+pub(crate) fn is_platform_filter(replacement: &[u8]) -> bool {
+    std::ptr::eq(replacement.as_ptr(), PLATFORM_REPLACEMENTS.as_ptr())
 }
 
 impl Config {
     /// Create a configuration for testing the output of running
     /// `rustc` on the test files.
     pub fn rustc(root_dir: PathBuf) -> Self {
+        let target_dir = std::env::var_os("CARGO_TARGET_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| std::env::current_dir().unwrap().join("target"));
         Self {
             host: None,
             target: None,
             stderr_filters: vec![
-                (Match::PathBackslash, b"/"),
+                (Match::PathBackslash, &PLATFORM_REPLACEMENTS[..1]),
                 #[cfg(windows)]
-                (Match::Exact(vec![b'\r']), b""),
+                (Match::Exact(vec![b'\r']), &PLATFORM_REPLACEMENTS[..0]),
             ],
+            named_stderr_filters: vec![],
             stdout_filters: vec![
                 #[cfg(windows)]
-                (Match::Exact(vec![b'\r']), b""),
+                (Match::Exact(vec![b'\r']), &PLATFORM_REPLACEMENTS[..0]),
             ],
             root_dir,
             mode: Mode::Fail {
                 require_patterns: true,
             },
             program: CommandBuilder::rustc(),
+            rustdoc: false,
             cfgs: CommandBuilder::cfgs(),
             output_conflict_handling: OutputConflictHandling::Error(format!(
                 "{} --bless",
@@ -83,14 +278,51 @@ impl Config {
                     .collect::<Vec<_>>()
                     .join(" ")
             )),
+            output_base: None,
+            preserve_line_endings: false,
             dependencies_crate_manifest_path: None,
             dependency_builder: CommandBuilder::cargo(),
+            dependency_builder_features: vec![],
+            dependency_builder_no_default_features: false,
             num_test_threads: std::thread::available_parallelism().unwrap(),
-            out_dir: std::env::var_os("CARGO_TARGET_DIR")
-                .map(PathBuf::from)
-                .unwrap_or_else(|| std::env::current_dir().unwrap().join("target"))
-                .join("ui"),
+            out_dir: target_dir.join("ui"),
+            cache_dir: Some(target_dir.join("ui_test").join("cache")),
+            fingerprint_dir: None,
+            logs_dir: target_dir.join("ui_test").join("logs"),
+            dump_artifacts: DumpArtifacts::OnFailure,
+            last_failures: target_dir.join("ui_test").join("last-failures.json"),
             edition: Some("2021".into()),
+            forbidden_env: vec![],
+            fix_iterations: NonZeroUsize::new(4).unwrap(),
+            rustfix_all_suggestions: false,
+            fixed_diagnostics_level: Level::Warn,
+            require_annotations_for_level: None,
+            sort_diagnostics: false,
+            diagnostics_stream: DiagnosticsStream::Auto,
+            diff_context: 5,
+            default_timeout: None,
+            time_limit_warning: None,
+            ice_patterns: vec![
+                Match::Exact(b"error: internal compiler error".to_vec()),
+                Match::Exact(b"thread 'rustc' panicked".to_vec()),
+            ],
+            report_unused_normalizations: false,
+            deny_deprecated: false,
+            allow_legacy_directives: false,
+            bless_annotations: false,
+            insert_annotations: false,
+            prune_annotations: false,
+            custom_conditions: HashMap::new(),
+            custom_comment_parsers: HashMap::new(),
+            skip_run_tests_when_cross: false,
+            runner: None,
+            channel: None,
+            target_pointer_width: None,
+            sysroot: None,
+            known_triples: None,
+            check_conditions: false,
+            target_dir: None,
+            workspace_root: None,
         }
     }
 
@@ -104,35 +336,156 @@ impl Config {
         }
     }
 
+    /// Create a configuration for testing the diagnostics of running `rustdoc` on the test
+    /// files, e.g. broken intra-doc links. The tests are expected to pass, with their
+    /// warnings annotated like `//~ WARN unresolved link`.
+    pub fn rustdoc(root_dir: PathBuf) -> Self {
+        Self {
+            program: CommandBuilder::rustdoc(),
+            rustdoc: true,
+            mode: Mode::Pass,
+            ..Self::rustc(root_dir)
+        }
+    }
+
+    /// Ask `cargo metadata` for the target directory and workspace root of the
+    /// workspace containing the current directory. Build artifacts and dependencies
+    /// are placed in `ui` in that target directory (so `CARGO_TARGET_DIR` is respected),
+    /// the cache, the logs and the last failures in `ui_test` in it. The target directory
+    /// and the workspace root get replaced by `$DIR` and `$WORKSPACE` in stderr.
+    pub fn with_cargo_metadata(mut self) -> Result<Self> {
+        let metadata = cargo_metadata::MetadataCommand::new()
+            .no_deps()
+            .exec()
+            .wrap_err("failed to run `cargo metadata`")?;
+        let target_dir = metadata.target_directory.into_std_path_buf();
+        let workspace_root = metadata.workspace_root.into_std_path_buf();
+        self.out_dir = target_dir.join("ui");
+        self.cache_dir = Some(target_dir.join("ui_test").join("cache"));
+        self.logs_dir = target_dir.join("ui_test").join("logs");
+        self.last_failures = target_dir.join("ui_test").join("last-failures.json");
+        // The target directory usually is inside the workspace, so it must be replaced first.
+        self.stderr_filters
+            .push((target_dir.as_path().into(), b"$DIR"));
+        self.stderr_filters
+            .push((workspace_root.as_path().into(), b"$WORKSPACE"));
+        self.target_dir = Some(target_dir);
+        self.workspace_root = Some(workspace_root);
+        Ok(self)
+    }
+
+    /// Replace all occurrences of a path in stderr with a byte string, no matter if the
+    /// path is printed with slashes, backslashes or as a windows verbatim (`\\?\`) path.
+    /// Errors if the path does not exist.
+    pub fn try_path_stderr_filter(
+        &mut self,
+        path: &Path,
+        replacement: &'static (impl AsRef<[u8]> + ?Sized),
+    ) -> Result<()> {
+        let pattern = path
+            .canonicalize()
+            .wrap_err_with(|| format!("invalid path for stderr filter: {}", path.display()))?;
+        for variant in Match::path_variants(pattern.parent().unwrap()) {
+            self.stderr_filters.push((variant, replacement.as_ref()));
+        }
+        Ok(())
+    }
+
+    /// Replace all occurrences of a regex pattern in stderr with a byte string.
+    /// Errors if the pattern is not a valid regex.
+    pub fn try_stderr_filter(
+        &mut self,
+        pattern: &str,
+        replacement: &'static (impl AsRef<[u8]> + ?Sized),
+    ) -> Result<()> {
+        let regex = filter_regex("stderr", pattern)?;
+        self.stderr_filters
+            .push((regex.into(), replacement.as_ref()));
+        Ok(())
+    }
+
+    /// Replace all occurrences of a regex pattern in stderr with a byte string.
+    /// Unlike `stderr_filter`, tests can disable this filter via `//@disable-filter: name`.
+    /// Errors if the pattern is not a valid regex.
+    pub fn try_named_stderr_filter(
+        &mut self,
+        name: impl Into<String>,
+        pattern: &str,
+        replacement: &'static (impl AsRef<[u8]> + ?Sized),
+    ) -> Result<()> {
+        let regex = filter_regex("stderr", pattern)?;
+        self.named_stderr_filters
+            .push((name.into(), regex.into(), replacement.as_ref()));
+        Ok(())
+    }
+
+    /// Replace all occurrences of a regex pattern in stdout with a byte string.
+    /// Errors if the pattern is not a valid regex.
+    pub fn try_stdout_filter(
+        &mut self,
+        pattern: &str,
+        replacement: &'static (impl AsRef<[u8]> + ?Sized),
+    ) -> Result<()> {
+        let regex = filter_regex("stdout", pattern)?;
+        self.stdout_filters
+            .push((regex.into(), replacement.as_ref()));
+        Ok(())
+    }
+
     /// Replace all occurrences of a path in stderr with a byte string.
+    #[deprecated(since = "0.11.8", note = "use `try_path_stderr_filter` instead")]
     pub fn path_stderr_filter(
         &mut self,
         path: &Path,
         replacement: &'static (impl AsRef<[u8]> + ?Sized),
     ) {
-        let pattern = path.canonicalize().unwrap();
-        self.stderr_filters
-            .push((pattern.parent().unwrap().into(), replacement.as_ref()));
+        self.try_path_stderr_filter(path, replacement).unwrap()
     }
 
     /// Replace all occurrences of a regex pattern in stderr with a byte string.
+    #[deprecated(since = "0.11.8", note = "use `try_stderr_filter` instead")]
     pub fn stderr_filter(
         &mut self,
         pattern: &str,
         replacement: &'static (impl AsRef<[u8]> + ?Sized),
     ) {
-        self.stderr_filters
-            .push((Regex::new(pattern).unwrap().into(), replacement.as_ref()));
+        self.try_stderr_filter(pattern, replacement).unwrap()
     }
 
     /// Replace all occurrences of a regex pattern in stdout with a byte string.
+    #[deprecated(since = "0.11.8", note = "use `try_stdout_filter` instead")]
     pub fn stdout_filter(
         &mut self,
         pattern: &str,
         replacement: &'static (impl AsRef<[u8]> + ?Sized),
     ) {
-        self.stdout_filters
-            .push((Regex::new(pattern).unwrap().into(), replacement.as_ref()));
+        self.try_stdout_filter(pattern, replacement).unwrap()
+    }
+
+    /// Make the crate at `path` available to all tests under `name`, like `--extern name=path`.
+    /// Errors if `name` is not a valid crate name.
+    pub fn extern_crate(&mut self, name: &str, path: impl AsRef<Path>) -> Result<()> {
+        let valid = matches!(name.chars().next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            bail!("invalid crate name `{name}`: must be a non-empty identifier, and can't contain `-`");
+        }
+        let mut arg = OsString::from(name);
+        arg.push("=");
+        arg.push(path.as_ref());
+        self.program.args.push("--extern".into());
+        self.program.args.push(arg);
+        Ok(())
+    }
+
+    /// Write build artifacts into `path`. The directory is created immediately,
+    /// so unusable paths are reported before any test runs.
+    pub fn out_dir(&mut self, path: impl Into<PathBuf>) -> Result<()> {
+        let path = path.into();
+        std::fs::create_dir_all(&path)
+            .wrap_err_with(|| format!("could not create out dir {}", path.display()))?;
+        self.out_dir = path;
+        Ok(())
     }
 
     /// Compile dependencies and make sure `Config::program` contains the right flags
@@ -155,25 +508,82 @@ impl Config {
         Ok(())
     }
 
-    /// Make sure we have the host and target triples.
+    /// The target triple if it is not the host, i.e. if the tests are cross compiled.
+    /// It is passed to the program via `--target`.
+    pub(crate) fn cross_target(&self) -> Option<&str> {
+        self.target
+            .as_deref()
+            .filter(|target| self.host.as_deref() != Some(*target))
+    }
+
+    /// Make sure we have the host and target triples, and detect the release channel.
     pub fn fill_host_and_target(&mut self) -> Result<()> {
-        if self.host.is_none() {
-            self.host = Some(
-                rustc_version::VersionMeta::for_command(std::process::Command::new(
-                    &self.program.program,
-                ))
-                .map_err(|err| {
-                    color_eyre::eyre::Report::new(err).wrap_err(format!(
+        if self.host.is_none() || self.channel.is_none() {
+            match rustc_version::VersionMeta::for_command(std::process::Command::new(
+                &self.program.program,
+            )) {
+                Ok(version) => {
+                    self.host.get_or_insert(version.host);
+                    self.channel.get_or_insert(match version.channel {
+                        rustc_version::Channel::Stable => Channel::Stable,
+                        rustc_version::Channel::Beta => Channel::Beta,
+                        rustc_version::Channel::Nightly | rustc_version::Channel::Dev => {
+                            Channel::Nightly
+                        }
+                    });
+                }
+                Err(err) if self.host.is_none() => {
+                    return Err(color_eyre::eyre::Report::new(err).wrap_err(format!(
                         "failed to parse rustc version info: {}",
                         self.program.display()
-                    ))
-                })?
-                .host,
-            );
+                    )))
+                }
+                // Without a channel, only tests using channel conditions fail.
+                Err(_) => {}
+            }
         }
         if self.target.is_none() {
             self.target = Some(self.host.clone().unwrap());
         }
+        if let Some(target) = self.cross_target() {
+            if self
+                .program
+                .args
+                .iter()
+                .any(|arg| arg.to_str().map_or(false, is_target_flag))
+            {
+                bail!("the program's arguments contain `--target`, but it is already passed for `Config::target` ({target})");
+            }
+        }
+        if self.target_pointer_width.is_none() {
+            // Programs that don't support `--print cfg` get the pointer width guessed from the triple.
+            self.target_pointer_width = cfgs(self).ok().and_then(|cfgs| {
+                cfgs.iter().find_map(|cfg| match cfg {
+                    Cfg::KeyPair(key, value) if key == "target_pointer_width" => value.parse().ok(),
+                    _ => None,
+                })
+            });
+        }
+        if self.sysroot.is_none() {
+            self.sysroot = std::process::Command::new(&self.program.program)
+                .args(["--print", "sysroot"])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+                .map(|sysroot| sysroot.trim_end().into());
+        }
+        if self.known_triples.is_none() {
+            // Programs that don't support `--print target-list` just don't get their
+            // conditions checked.
+            self.known_triples = std::process::Command::new(&self.program.program)
+                .args(["--print", "target-list"])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+                .map(|list| list.lines().map(String::from).collect());
+        }
         Ok(())
     }
 
@@ -188,6 +598,31 @@ impl Config {
             .iter()
             .any(|arch| self.target.as_ref().unwrap().contains(arch))
     }
+
+    pub(crate) fn endianness(&self) -> Endianness {
+        static BIG_ENDIAN_ARCHS: &[&str] = &[
+            "aarch64_be",
+            "armeb",
+            "armebv7r",
+            "m68k",
+            "mips",
+            "mips64",
+            "mipsisa32r6",
+            "mipsisa64r6",
+            "powerpc",
+            "powerpc64",
+            "s390x",
+            "sparc",
+            "sparc64",
+            "sparcv9",
+        ];
+        let arch = self.target.as_ref().unwrap().split('-').next().unwrap();
+        if BIG_ENDIAN_ARCHS.contains(&arch) {
+            Endianness::Big
+        } else {
+            Endianness::Little
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -203,3 +638,37 @@ pub enum OutputConflictHandling {
     /// automatically replace it with the found output (after applying filters).
     Bless,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Where the compiler emits its JSON diagnostics, see `Config::diagnostics_stream`. Only
+/// the JSON lines of that stream are parsed, the rest of both streams is compared against
+/// the `.stderr` and `.stdout` files.
+pub enum DiagnosticsStream {
+    /// The default: whichever stream contains JSON diagnostics. If both do, they must
+    /// contain the same ones.
+    Auto,
+    /// Like rustc, on stderr.
+    Stderr,
+    /// On stdout, like some drivers do, which render the diagnostics on stderr instead.
+    Stdout,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Which tests get their logs and artifacts written to `Config::logs_dir`.
+pub enum DumpArtifacts {
+    /// Every test that was run.
+    Always,
+    /// The default: only the tests that failed.
+    OnFailure,
+    /// No test, and `Config::logs_dir` is left alone.
+    Never,
+}
+
+/// Whether `arg` is a `--target` flag, which conflicts with `Config::target`.
+pub(crate) fn is_target_flag(arg: &str) -> bool {
+    arg == "--target" || arg.starts_with("--target=")
+}
+
+fn filter_regex(kind: &str, pattern: &str) -> Result<Regex> {
+    Regex::new(pattern).wrap_err_with(|| format!("invalid regex for {kind} filter `{pattern}`"))
+}
@@ -1,7 +1,10 @@
 //! Default argument processing when `ui_test` is used
 //! as a test driver.
 
-use clap::Parser;
+use std::{ffi::OsString, num::NonZeroUsize};
+
+use clap::{error::ErrorKind, CommandFactory, Parser, ValueEnum};
+use color_eyre::eyre::{bail, Result};
 
 /// Plain arguments if `ui_test` is used as a binary.
 #[derive(Parser, Debug, Clone)]
@@ -18,19 +21,220 @@ pub struct Args {
     /// output. Will update the files otherwise.
     #[arg(long, default_value_t = false)]
     pub check: bool,
+
+    /// Bless the output files instead of erroring on mismatches.
+    #[arg(long, default_value_t = false)]
+    pub bless: bool,
+
+    /// Remove output files that no test produces anymore, e.g. after renaming or deleting
+    /// tests, without blessing anything else. Blessing removes them, too.
+    #[arg(long, default_value_t = false)]
+    pub prune: bool,
+
+    /// Skip tests whose path contains this string. Can be passed multiple times.
+    #[arg(long, value_name = "FILTER")]
+    pub skip: Vec<String>,
+
+    /// Only run tests whose path is exactly one of the filters,
+    /// instead of all tests whose path contains one of them.
+    #[arg(long, default_value_t = false)]
+    pub exact: bool,
+
+    /// Stop starting new tests after the first failure. Tests that are already running
+    /// still finish. Also enabled by setting the `UI_TEST_FAIL_FAST` env var.
+    #[arg(long, default_value_t = false)]
+    pub fail_fast: bool,
+
+    /// How many tests to run in parallel. Defaults to `Config::num_test_threads`, which is the
+    /// available parallelism. `--jobs 1` runs the tests one after the other, in a fixed order.
+    /// Also settable via the `RUST_TEST_THREADS` env var.
+    #[arg(
+        long = "jobs",
+        short = 'j',
+        visible_alias = "test-threads",
+        value_name = "N"
+    )]
+    pub threads: Option<NonZeroUsize>,
+
+    /// Only run the tests that failed in the last run, and the tests they depend on.
+    /// Runs all tests if no failures were recorded.
+    #[arg(long, default_value_t = false)]
+    pub rerun_failed: bool,
+
+    /// Run all tests, even those that passed in an earlier run and didn't change since,
+    /// which are skipped if `Config::fingerprint_dir` is set.
+    #[arg(long, default_value_t = false)]
+    pub force_rerun: bool,
+
+    /// Start the tests in a random order determined by this seed, instead of sorted by path.
+    /// Tests still start after the tests they depend on.
+    #[arg(long, value_name = "SEED")]
+    pub shuffle_seed: Option<u64>,
+
+    /// How many of the slowest tests to list after running all tests. `0` lists none.
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    pub report_slowest: usize,
+
+    /// Only run the tests of shard `I` of `N` (counting from 1), to split the tests across
+    /// multiple machines. Which shard a test belongs to depends only on its name.
+    #[arg(long, value_name = "I/N", value_parser = parse_shard)]
+    pub shard: Option<(usize, usize)>,
+
+    /// Compile the tests for this target triple instead of the host, overriding `Config::target`.
+    #[arg(long, value_name = "TRIPLE")]
+    pub target: Option<String>,
+
+    /// Enable these features when building the crate at `Config::dependencies_crate_manifest_path`,
+    /// in addition to `Config::dependency_builder_features`. Like cargo's `--features`, this takes
+    /// a comma separated list and can be passed multiple times.
+    #[arg(long, value_name = "FEATURES", value_delimiter = ',')]
+    pub dependency_features: Vec<String>,
+
+    /// Build the crate at `Config::dependencies_crate_manifest_path` without its default features.
+    #[arg(long, default_value_t = false)]
+    pub dependency_no_default_features: bool,
+
+    /// List the tests that would be run, one per line, instead of running them.
+    #[arg(long, default_value_t = false)]
+    pub list: bool,
+
+    /// The format of the `--list` output.
+    #[arg(long, value_enum, default_value_t)]
+    pub format: Format,
+
+    /// Also list ignored tests, with the reason they are ignored for.
+    #[arg(long, default_value_t = false)]
+    pub include_ignored: bool,
+
+    /// Bless the output files, and also insert `//~` annotations for unmatched
+    /// diagnostics into the test files and rewrite reworded ones.
+    /// Also enabled by setting the `BLESS` env var to `annotations`.
+    #[arg(long, default_value_t = false)]
+    pub bless_annotations: bool,
+}
+
+/// Parses `--shard I/N`.
+fn parse_shard(shard: &str) -> Result<(usize, usize), String> {
+    let (index, count) = shard
+        .split_once('/')
+        .ok_or_else(|| format!("expected `I/N`, got `{shard}`"))?;
+    let parse = |n: &str| {
+        n.trim()
+            .parse::<usize>()
+            .map_err(|err| format!("invalid number `{n}`: {err}"))
+    };
+    let (index, count) = (parse(index)?, parse(count)?);
+    if count == 0 {
+        return Err("the number of shards must be at least 1".into());
+    }
+    if index == 0 || index > count {
+        return Err(format!("shards are numbered from 1 to {count}"));
+    }
+    Ok((index, count))
 }
 
+/// The output formats of `--list`.
+#[derive(ValueEnum, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// The name of the test, i.e. its path relative to the root directory
+    /// and `#revision` for tests with revisions.
+    #[default]
+    Terse,
+    /// One JSON object per line, with the `name`, `path` and `revision` of the test,
+    /// why it's `ignored`, if it is, and the names of the named stderr `filters` that apply
+    /// to it.
+    Json,
+}
+
+/// Panics if the env vars are invalid, use `Args::from_args` to get an error instead.
 impl Default for Args {
     fn default() -> Self {
         Self::parse_from([std::env::current_exe().unwrap()])
+            .with_env()
+            .unwrap()
     }
 }
 
 impl Args {
     /// Arguments if `ui_test` is used as a `cargo test`, so we need
     /// to skip a level of `--` in order to not pick up `cargo`'s test
-    /// flags.
+    /// flags. Panics if the env vars are invalid.
     pub fn test() -> Self {
         Args::parse_from(std::env::args().skip_while(|arg| arg != "--"))
+            .with_env()
+            .unwrap()
+    }
+
+    /// Parse the arguments the test binary got, e.g. `std::env::args_os()`. The first
+    /// argument is the name of the binary. This follows the conventions of libtest, so
+    /// `cargo test -- FILTER` works. `--help` prints the usage and exits, while unknown
+    /// arguments are errors listing the accepted options.
+    pub fn from_args(args: impl IntoIterator<Item = impl Into<OsString> + Clone>) -> Result<Self> {
+        match Self::try_parse_from(args) {
+            Ok(args) => args.with_env(),
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    ErrorKind::DisplayHelp | ErrorKind::DisplayVersion
+                ) =>
+            {
+                err.exit()
+            }
+            Err(err) => {
+                let options = Self::command()
+                    .get_arguments()
+                    .filter_map(|arg| Some(format!("`--{}`", arg.get_long()?)))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let err = err.to_string();
+                let err = err.trim_end().trim_start_matches("error: ");
+                bail!("{err}\n\naccepted options: {options} and test name filters")
+            }
+        }
+    }
+
+    /// Whether the test named `path` is selected by the filters and not skipped. If the test
+    /// has revisions, it is run for every `revision` for which this returns `true`, and
+    /// filters can also match `path#revision`. With `--exact`, either name must be equal
+    /// to one of the filters, while skipping always matches substrings.
+    pub fn selects(&self, path: &str, revision: &str) -> bool {
+        let name = if revision.is_empty() {
+            path.to_owned()
+        } else {
+            format!("{path}#{revision}")
+        };
+        if self.skip.iter().any(|skip| name.contains(skip)) {
+            return false;
+        }
+        if self.filters.is_empty() {
+            return true;
+        }
+        self.filters.iter().any(|f| {
+            if self.exact {
+                path == f || name == *f
+            } else {
+                name.contains(f)
+            }
+        })
+    }
+
+    /// Apply the settings that can also be made via env vars. Fails if `RUST_TEST_THREADS`
+    /// is not a positive integer.
+    pub fn with_env(mut self) -> Result<Self> {
+        self.bless_annotations |=
+            std::env::var_os("BLESS").map_or(false, |bless| bless == "annotations");
+        self.fail_fast |= std::env::var_os("UI_TEST_FAIL_FAST").is_some();
+        if self.threads.is_none() {
+            if let Some(threads) = std::env::var_os("RUST_TEST_THREADS") {
+                let threads = threads.to_string_lossy();
+                match threads.parse() {
+                    Ok(threads) => self.threads = Some(threads),
+                    Err(_) => {
+                        bail!("RUST_TEST_THREADS is `{threads}`, should be a positive integer")
+                    }
+                }
+            }
+        }
+        Ok(self)
     }
 }
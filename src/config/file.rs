@@ -0,0 +1,166 @@
+//! Loading a `Config` from a `ui_test.toml` file.
+
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{bail, Result, WrapErr};
+use serde::Deserialize;
+use toml::Spanned;
+
+use super::{filter_regex, Config, OutputConflictHandling};
+use crate::{ExitExpectation, Mode};
+
+/// The contents of a `ui_test.toml` file. All keys are optional, except for `root_dir`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    root_dir: PathBuf,
+    mode: Option<Spanned<ModeName>>,
+    /// Only allowed for `mode = "fail"`.
+    require_patterns: Option<Spanned<bool>>,
+    /// Only allowed for `mode = "run"`.
+    exit_code: Option<Spanned<i32>>,
+    edition: Option<String>,
+    target: Option<String>,
+    output_conflict_handling: Option<OutputConflictHandlingName>,
+    dependencies_crate_manifest_path: Option<PathBuf>,
+    runner: Option<Vec<String>>,
+    #[serde(default)]
+    compile_flags: Vec<String>,
+    #[serde(default)]
+    filters: Vec<FilterEntry>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum ModeName {
+    Pass,
+    Check,
+    Panic,
+    Fail,
+    Fix,
+    Run,
+    Yolo,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum OutputConflictHandlingName {
+    Error,
+    Ignore,
+    Bless,
+}
+
+/// A `[[filters]]` entry.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FilterEntry {
+    pattern: Spanned<String>,
+    replacement: String,
+    #[serde(default)]
+    stream: Stream,
+}
+
+/// The output a filter applies to.
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+enum Stream {
+    #[default]
+    Stderr,
+    Stdout,
+    Both,
+}
+
+impl Config {
+    /// Load a configuration for testing the output of running `rustc` from a TOML file.
+    /// Unknown keys and invalid filter regexes are errors. Relative paths are relative to
+    /// the current directory, just like the paths set programmatically. Settings that
+    /// are not in the file keep the defaults of `Config::rustc`, and can be overridden
+    /// afterwards like with any other `Config`.
+    pub fn from_file(path: &Path) -> Result<Config> {
+        let content = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read {}", path.display()))?;
+        Self::from_toml(&content).wrap_err_with(|| format!("invalid config {}", path.display()))
+    }
+
+    /// Like `from_file`, but for the `content` of the file.
+    pub(crate) fn from_toml(content: &str) -> Result<Config> {
+        let file: ConfigFile = toml::from_str(content)?;
+        let line = |offset: usize| content[..offset].matches('\n').count() + 1;
+        let mut config = Config::rustc(file.root_dir);
+
+        let mode = file.mode.as_ref().map(|mode| *mode.get_ref());
+        if let Some(require_patterns) = &file.require_patterns {
+            if mode != Some(ModeName::Fail) {
+                bail!(
+                    "`require_patterns` at line {} is only allowed with `mode = \"fail\"`",
+                    line(require_patterns.start())
+                );
+            }
+        }
+        if let Some(exit_code) = &file.exit_code {
+            if mode != Some(ModeName::Run) {
+                bail!(
+                    "`exit_code` at line {} is only allowed with `mode = \"run\"`",
+                    line(exit_code.start())
+                );
+            }
+        }
+        if let Some(mode) = mode {
+            config.mode = match mode {
+                ModeName::Pass => Mode::Pass,
+                ModeName::Check => Mode::Check,
+                ModeName::Panic => Mode::Panic,
+                ModeName::Fail => Mode::Fail {
+                    require_patterns: file.require_patterns.map_or(true, Spanned::into_inner),
+                },
+                ModeName::Fix => Mode::Fix,
+                ModeName::Run => Mode::Run {
+                    exit: ExitExpectation::Code(file.exit_code.map_or(0, Spanned::into_inner)),
+                },
+                ModeName::Yolo => Mode::Yolo,
+            };
+        }
+        if let Some(edition) = file.edition {
+            config.edition = Some(edition);
+        }
+        config.target = file.target;
+        match file.output_conflict_handling {
+            // Keep the default bless command.
+            None | Some(OutputConflictHandlingName::Error) => {}
+            Some(OutputConflictHandlingName::Ignore) => {
+                config.output_conflict_handling = OutputConflictHandling::Ignore
+            }
+            Some(OutputConflictHandlingName::Bless) => {
+                config.output_conflict_handling = OutputConflictHandling::Bless
+            }
+        }
+        config.dependencies_crate_manifest_path = file.dependencies_crate_manifest_path;
+        config.runner = file.runner;
+        config
+            .program
+            .args
+            .extend(file.compile_flags.into_iter().map(Into::into));
+
+        for filter in file.filters {
+            let line = line(filter.pattern.start());
+            let kind = match filter.stream {
+                Stream::Stderr => "stderr",
+                Stream::Stdout => "stdout",
+                Stream::Both => "stderr and stdout",
+            };
+            let regex = filter_regex(kind, filter.pattern.get_ref())
+                .wrap_err_with(|| format!("in the filter at line {line}"))?;
+            // The filters live as long as the test suite.
+            let replacement: &'static [u8] = Box::leak(filter.replacement.into_bytes().into());
+            if let Stream::Stderr | Stream::Both = filter.stream {
+                config
+                    .stderr_filters
+                    .push((regex.clone().into(), replacement));
+            }
+            if let Stream::Stdout | Stream::Both = filter.stream {
+                config.stdout_filters.push((regex.into(), replacement));
+            }
+        }
+        Ok(config)
+    }
+}
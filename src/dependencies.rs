@@ -3,14 +3,14 @@ use cargo_platform::Cfg;
 use color_eyre::eyre::{bail, Result};
 use std::{
     collections::{HashMap, HashSet},
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::Command,
     str::FromStr,
 };
 
-use crate::{Config, Mode, OutputConflictHandling};
+use crate::{cache, Config, Mode, OutputConflictHandling};
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Dependencies {
     /// All paths that must be imported with `-L dependency=`. This is for
     /// finding proc macros run on the host and dependencies for the target.
@@ -19,7 +19,7 @@ pub struct Dependencies {
     pub dependencies: Vec<(String, Vec<Utf8PathBuf>)>,
 }
 
-fn cfgs(config: &Config) -> Result<Vec<Cfg>> {
+pub(crate) fn cfgs(config: &Config) -> Result<Vec<Cfg>> {
     let mut cmd = config.cfgs.build(&config.out_dir);
     cmd.arg("--target").arg(config.target.as_ref().unwrap());
     let output = cmd.output()?;
@@ -49,12 +49,22 @@ pub fn build_dependencies(config: &mut Config) -> Result<Dependencies> {
     let manifest_path = &manifest_path;
     config.fill_host_and_target()?;
     eprintln!("   Building test dependencies...");
-    let mut build = config.dependency_builder.build(&config.out_dir);
+    let mut build = config
+        .dependency_builder
+        .build(&dependency_target_dir(config));
     build.arg(manifest_path);
 
     if let Some(target) = &config.target {
         build.arg(format!("--target={target}"));
     }
+    if !config.dependency_builder_features.is_empty() {
+        build
+            .arg("--features")
+            .arg(config.dependency_builder_features.join(","));
+    }
+    if config.dependency_builder_no_default_features {
+        build.arg("--no-default-features");
+    }
 
     // Reusable closure for setting up the environment both for artifact generation and `cargo_metadata`
     let set_locking = |cmd: &mut Command| match (&config.output_conflict_handling, &config.mode) {
@@ -68,6 +78,59 @@ pub fn build_dependencies(config: &mut Config) -> Result<Dependencies> {
     set_locking(&mut build);
     build.arg("--message-format=json");
 
+    let cache_entry = config.cache_dir.as_ref().map(|cache_dir| {
+        let mut key = cache::Key::new(&config.program.program);
+        key.add_command(&build);
+        if let Some(dir) = manifest_path
+            .canonicalize()
+            .ok()
+            .as_deref()
+            .and_then(Path::parent)
+        {
+            // The tests themselves are not part of the build.
+            key.add_dir(dir, &["target", "tests", "benches", "examples"]);
+            if let Some(lock) = dir
+                .ancestors()
+                .map(|dir| dir.join("Cargo.lock"))
+                .find(|lock| lock.exists())
+            {
+                key.add(std::fs::read(lock).unwrap_or_default());
+            }
+        }
+        let entry = key.entry(cache_dir, "dependencies");
+        (cache_dir, entry.with_extension("json"))
+    });
+    if let Some((_, entry)) = &cache_entry {
+        if let Some(dependencies) = read_cached_dependencies(entry) {
+            return Ok(dependencies);
+        }
+    }
+    let dependencies = build_and_find_dependencies(config, manifest_path, build, set_locking)?;
+    if let Some((cache_dir, entry)) = &cache_entry {
+        std::fs::create_dir_all(cache_dir)?;
+        cache::store_file(cache_dir, entry, &serde_json::to_vec(&dependencies)?)?;
+    }
+    Ok(dependencies)
+}
+
+/// The dependencies recorded in the cache `entry`, if all of their artifacts still exist.
+fn read_cached_dependencies(entry: &Path) -> Option<Dependencies> {
+    let dependencies: Dependencies = serde_json::from_slice(&std::fs::read(entry).ok()?).ok()?;
+    let artifacts_exist = dependencies
+        .dependencies
+        .iter()
+        .flat_map(|(_, artifacts)| artifacts)
+        .all(|artifact| artifact.exists());
+    artifacts_exist.then_some(dependencies)
+}
+
+/// Runs the `build` command and finds the artifacts of the dependencies in its output.
+fn build_and_find_dependencies(
+    config: &Config,
+    manifest_path: &Path,
+    mut build: Command,
+    set_locking: impl Fn(&mut Command),
+) -> Result<Dependencies> {
     let output = build.output()?;
 
     if !output.status.success() {
@@ -83,7 +146,7 @@ pub fn build_dependencies(config: &mut Config) -> Result<Dependencies> {
     let mut artifacts = HashMap::new();
     for line in artifact_output.lines() {
         let Ok(message) = serde_json::from_str::<cargo_metadata::Message>(line) else {
-            continue
+            continue;
         };
         if let cargo_metadata::Message::CompilerArtifact(artifact) = message {
             for filename in &artifact.filenames {
@@ -169,6 +232,11 @@ pub fn build_dependencies(config: &mut Config) -> Result<Dependencies> {
                             // instead of a library. We simply add no artifacts, meaning you can't depend on functions
                             // and types declared in the root crate.
                             None
+                        } else if root.dependencies.iter().any(|dep| {
+                            dep.optional && dep.rename.as_ref().unwrap_or(&dep.name) == &name
+                        }) {
+                            // Optional dependencies are only built if an enabled feature needs them.
+                            None
                         } else {
                             panic!("no artifact found for `{name}`(`{id}`):`\n{artifact_output}")
                         }
@@ -185,3 +253,29 @@ pub fn build_dependencies(config: &mut Config) -> Result<Dependencies> {
 
     bail!("no json found in cargo-metadata output")
 }
+
+/// The directory to build the dependencies in. Each set of features gets its own directory,
+/// so switching between them doesn't mix up the artifacts built with different features.
+pub(crate) fn dependency_target_dir(config: &Config) -> PathBuf {
+    let mut features = config.dependency_builder_features.clone();
+    features.sort();
+    features.dedup();
+    if config.dependency_builder_no_default_features {
+        features.insert(0, "no-default-features".into());
+    }
+    if features.is_empty() {
+        return config.out_dir.clone();
+    }
+    let name: String = features
+        .join(",")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | ',') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    config.out_dir.join(format!("dependencies-{name}"))
+}
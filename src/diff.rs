@@ -1,175 +1,339 @@
+//! Diffs between the expected and the actual output of tests, for humans.
+
+use bstr::ByteSlice;
 use colored::*;
-use diff::{chars, lines, Result, Result::*};
-
-#[derive(Default)]
-struct DiffState<'a> {
-    /// Whether we've already printed something, so we should print starting context, too.
-    print_start_context: bool,
-    /// When we skip lines, remember the last `CONTEXT` ones to
-    /// display after the "skipped N lines" message
-    skipped_lines: Vec<&'a str>,
-    /// When we see a removed line, we don't print it, we
-    /// keep it around to compare it with the next added line.
-    prev_left: Option<&'a str>,
-}
-
-/// How many lines of context are displayed around the actual diffs
-const CONTEXT: usize = 2;
-
-impl<'a> DiffState<'a> {
-    /// Print `... n lines skipped ...` followed by the last `CONTEXT` lines.
-    fn print_end_skip(&self, skipped: usize) {
-        self.print_skipped_msg(skipped);
-        for line in self.skipped_lines.iter().rev().take(CONTEXT).rev() {
-            eprintln!(" {line}");
+use diff::{lines, slice, Result::*};
+use std::borrow::Cow;
+
+#[cfg(test)]
+mod tests;
+
+/// Lines of the diff are truncated after this many characters.
+const MAX_LINE_LEN: usize = 300;
+
+/// Changed lines that are longer than this many bytes together are not diffed word by word,
+/// as that takes quadratic time.
+const MAX_WORD_DIFF_LEN: usize = 4 * MAX_LINE_LEN;
+
+/// Converts output to text for displaying it, replacing every sequence of bytes that isn't
+/// valid UTF-8 with `<invalid utf8>`. Only the display is lossy, the output itself is compared
+/// and blessed as bytes.
+pub(crate) fn lossy(output: &[u8]) -> Cow<'_, str> {
+    if let Ok(text) = std::str::from_utf8(output) {
+        return Cow::Borrowed(text);
+    }
+    let mut text = String::new();
+    let mut invalid = false;
+    for chunk in ByteSlice::utf8_chunks(output) {
+        text.push_str(chunk.valid());
+        // Consecutive invalid bytes get a single marker.
+        let continued = invalid && chunk.valid().is_empty();
+        if !chunk.invalid().is_empty() && !continued {
+            text.push_str("<invalid utf8>");
         }
+        invalid = !chunk.invalid().is_empty();
     }
+    Cow::Owned(text)
+}
 
-    fn print_skipped_msg(&self, skipped: usize) {
-        match skipped {
-            // When the amount of skipped lines is exactly `CONTEXT * 2`, we already
-            // print all the context and don't actually skip anything.
-            0 => {}
-            // Instead of writing a line saying we skipped one line, print that one line
-            1 => eprintln!(" {}", self.skipped_lines[CONTEXT]),
-            _ => eprintln!("... {skipped} lines skipped ..."),
+/// A line of a diff.
+enum Row<'a> {
+    Unchanged(&'a str),
+    Changed(String),
+}
+
+/// Builds a line of the diff, truncating it after `MAX_LINE_LEN` characters.
+struct Line {
+    line: String,
+    len: usize,
+    truncated: usize,
+}
+
+impl Line {
+    fn new(prefix: ColoredString) -> Self {
+        Self {
+            line: prefix.to_string(),
+            len: 0,
+            truncated: 0,
         }
     }
 
-    /// Print an initial `CONTEXT` amount of lines.
-    fn print_start_skip(&self) {
-        for line in self.skipped_lines.iter().take(CONTEXT) {
-            eprintln!(" {line}");
+    fn push(&mut self, text: &str, color: Option<Color>) {
+        let keep = MAX_LINE_LEN.saturating_sub(self.len);
+        let mut chars = text.chars();
+        let kept: String = chars.by_ref().take(keep).collect();
+        self.truncated += chars.count();
+        self.len += kept.chars().count();
+        match color {
+            _ if kept.is_empty() => {}
+            Some(color) => self.line.push_str(&kept.color(color).to_string()),
+            None => self.line.push_str(&kept),
         }
     }
 
-    fn print_skip(&mut self) {
-        let half = self.skipped_lines.len() / 2;
-        if !self.print_start_context {
-            self.print_start_context = true;
-            self.print_end_skip(self.skipped_lines.len().saturating_sub(CONTEXT));
-        } else if half < CONTEXT {
-            // Print all the skipped lines if the amount of context desired is less than the amount of lines
-            for line in self.skipped_lines.drain(..) {
-                eprintln!(" {line}");
-            }
-        } else {
-            self.print_start_skip();
-            let skipped = self.skipped_lines.len() - CONTEXT * 2;
-            self.print_end_skip(skipped);
+    fn finish(mut self) -> String {
+        if self.truncated > 0 {
+            let marker = format!("... ({} more characters)", self.truncated);
+            self.line.push_str(&marker.dimmed().to_string());
         }
-        self.skipped_lines.clear();
+        self.line
     }
+}
+
+/// Splits a line into the line and its `\n` or `\r\n` terminator.
+fn split_terminator(line: &str) -> (&str, &str) {
+    let content = line.strip_suffix('\n').unwrap_or(line);
+    let content = content.strip_suffix('\r').unwrap_or(content);
+    line.split_at(content.len())
+}
 
-    fn skip(&mut self, line: &'a str) {
-        self.skipped_lines.push(line);
+/// The `\r` and `\n` of a line terminator, to diff them like words.
+fn words_of_terminator(end: &str) -> Vec<&str> {
+    (0..end.len()).map(|i| &end[i..=i]).collect()
+}
+
+/// Splits a line into words and the single characters between them.
+fn words(line: &str) -> Vec<&str> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let mut words = vec![];
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        let len = if is_word(c) {
+            rest.find(|c| !is_word(c)).unwrap_or(rest.len())
+        } else {
+            c.len_utf8()
+        };
+        let (word, tail) = rest.split_at(len);
+        words.push(word);
+        rest = tail;
     }
+    words
+}
+
+/// Makes line terminators visible in added or removed words, and spaces if there is nothing
+/// else, as they would be invisible otherwise.
+fn visible_whitespace(words: &str) -> String {
+    let spaces = words.trim().is_empty();
+    words
+        .chars()
+        .map(|c| match c {
+            ' ' if spaces => '·',
+            '\r' => '␍',
+            '\n' => '⏎',
+            c => c,
+        })
+        .collect()
+}
+
+/// A line that only exists in the expected output.
+fn removed(line: &str) -> String {
+    let mut out = Line::new("-".red());
+    out.push(split_terminator(line).0, Some(Color::Red));
+    out.finish()
+}
+
+/// A line that only exists in the actual output.
+fn added(line: &str) -> String {
+    let mut out = Line::new("+".green());
+    out.push(split_terminator(line).0, Some(Color::Green));
+    out.finish()
+}
 
-    fn print_prev(&mut self) {
-        if let Some(l) = self.prev_left.take() {
-            self.print_left(l);
+/// A removed line directly followed by an added line, with the changed words highlighted.
+fn changed(l: &str, r: &str) -> Vec<String> {
+    if l.len() + r.len() > MAX_WORD_DIFF_LEN {
+        return vec![removed(l), added(r)];
+    }
+    let (l, l_end) = split_terminator(l);
+    let (r, r_end) = split_terminator(r);
+    let (l_words, r_words) = (words(l), words(r));
+    let words = slice(&l_words, &r_words);
+    // Line terminators only show up if they differ, e.g. `\r\n` vs `\n` or a missing last `\n`.
+    let (l_end, r_end) = (words_of_terminator(l_end), words_of_terminator(r_end));
+    let ends = slice(&l_end, &r_end);
+    // Adjacent words of the same side are highlighted together, so spaces are only made
+    // visible if nothing else changed around them.
+    let mut runs: Vec<Run> = vec![];
+    for word in words.iter().chain(&ends) {
+        match (runs.last_mut(), word) {
+            (Some(Run::Removed(run)), Left(l)) => run.push_str(l),
+            (Some(Run::Added(run)), Right(r)) => run.push_str(r),
+            (Some(Run::Same(run)), Both(w, _)) => run.push_str(w),
+            (_, Left(l)) => runs.push(Run::Removed(l.to_string())),
+            (_, Right(r)) => runs.push(Run::Added(r.to_string())),
+            (_, Both(w, _)) => runs.push(Run::Same(w.to_string())),
         }
     }
 
-    fn print_left(&self, l: &str) {
-        eprintln!("{}{}", "-".red(), l.red());
+    let seen_l = runs
+        .iter()
+        .any(|run| matches!(run, Run::Removed(l) if !l.trim().is_empty()));
+    let seen_r = runs
+        .iter()
+        .any(|run| matches!(run, Run::Added(r) if !r.trim().is_empty()));
+    let push = |out: &mut Line, run: &Run, removed: bool, added: bool| match run {
+        Run::Removed(l) if removed => out.push(&visible_whitespace(l), Some(Color::Red)),
+        Run::Added(r) if added => out.push(&visible_whitespace(r), Some(Color::Green)),
+        Run::Same(w) => out.push(w.trim_end_matches(['\r', '\n']), None),
+        _ => {}
+    };
+    if seen_l && seen_r {
+        // The line both adds and removes words, print both lines, but highlight their
+        // differences instead of drawing the entire line in red/green.
+        let mut left = Line::new("-".red());
+        let mut right = Line::new("+".green());
+        for run in &runs {
+            push(&mut left, run, true, false);
+            push(&mut right, run, false, true);
+        }
+        vec![left.finish(), right.finish()]
+    } else {
+        // The line only adds or only removes words, print a single line highlighting them.
+        let mut out = Line::new("~".yellow());
+        for run in &runs {
+            push(&mut out, run, true, true);
+        }
+        vec![out.finish()]
     }
+}
 
-    fn print_right(&self, r: &str) {
-        eprintln!("{}{}", "+".green(), r.green());
-    }
+/// Consecutive words of a changed line that were removed, added or kept.
+enum Run {
+    Removed(String),
+    Added(String),
+    Same(String),
+}
 
-    fn row(&mut self, row: Result<&'a str>) {
+/// Renders the diff of the lines of `expected` and `actual`, with only `context` unchanged
+/// lines around every change.
+fn render(expected: &str, actual: &str, context: usize) -> String {
+    let expected: Vec<_> = expected.split_inclusive('\n').collect();
+    let actual: Vec<_> = actual.split_inclusive('\n').collect();
+    let mut rows = vec![];
+    // A removed line is kept around to compare it with the next added line.
+    let mut prev_left = None;
+    for row in slice(&expected, &actual) {
         match row {
             Left(l) => {
-                self.print_skip();
-                self.print_prev();
-                self.prev_left = Some(l);
+                if let Some(prev) = prev_left.replace(*l) {
+                    rows.push(Row::Changed(removed(prev)));
+                }
             }
             Both(l, _) => {
-                self.print_prev();
-                self.skip(l);
-            }
-            Right(r) => {
-                // When there's an added line after a removed line, we'll want to special case some print cases.
-                // FIXME(oli-obk): also do special printing modes when there are multiple lines that only have minor changes.
-                if let Some(l) = self.prev_left.take() {
-                    let diff = chars(l, r);
-                    let mut seen_l = false;
-                    let mut seen_r = false;
-                    for char in &diff {
-                        match char {
-                            Left(l) if !l.is_whitespace() => seen_l = true,
-                            Right(r) if !r.is_whitespace() => seen_r = true,
-                            _ => {}
-                        }
-                    }
-                    if seen_l && seen_r {
-                        // The line both adds and removes chars, print both lines, but highlight their differences instead of
-                        // drawing the entire line in red/green.
-                        eprint!("{}", "-".red());
-                        for char in &diff {
-                            match *char {
-                                Left(l) => eprint!("{}", l.to_string().red()),
-                                Right(_) => {}
-                                Both(l, _) => eprint!("{l}"),
-                            }
-                        }
-                        eprintln!();
-                        eprint!("{}", "+".green());
-                        for char in diff {
-                            match char {
-                                Left(_) => {}
-                                Right(r) => eprint!("{}", r.to_string().green()),
-                                Both(l, _) => eprint!("{l}"),
-                            }
-                        }
-                        eprintln!();
-                    } else {
-                        // The line only adds or only removes chars, print a single line highlighting their differences.
-                        eprint!("{}", "~".yellow());
-                        for char in diff {
-                            match char {
-                                Left(l) => eprint!("{}", l.to_string().red()),
-                                Both(l, _) => eprint!("{l}"),
-                                Right(r) => eprint!("{}", r.to_string().green()),
-                            }
-                        }
-                        eprintln!();
-                    }
-                } else {
-                    self.print_skip();
-                    self.print_right(r);
+                if let Some(prev) = prev_left.take() {
+                    rows.push(Row::Changed(removed(prev)));
                 }
+                rows.push(Row::Unchanged(l));
             }
+            Right(r) => match prev_left.take() {
+                Some(l) => rows.extend(changed(l, r).into_iter().map(Row::Changed)),
+                None => rows.push(Row::Changed(added(r))),
+            },
         }
     }
+    if let Some(prev) = prev_left {
+        rows.push(Row::Changed(removed(prev)));
+    }
 
-    fn finish(self) {
-        self.print_start_skip();
-        self.print_skipped_msg(self.skipped_lines.len().saturating_sub(CONTEXT));
-        eprintln!()
+    let mut visible = vec![false; rows.len()];
+    for (i, row) in rows.iter().enumerate() {
+        if let Row::Changed(_) = row {
+            let end = (i + context).min(rows.len() - 1);
+            visible[i.saturating_sub(context)..=end].fill(true);
+        }
+    }
+    let mut out = String::new();
+    let mut skipped = vec![];
+    let flush_skipped = |out: &mut String, skipped: &mut Vec<&str>| {
+        match skipped[..] {
+            [] => {}
+            // Instead of writing a line saying we skipped one line, print that one line
+            [line] => out.push_str(&unchanged(line)),
+            _ => out.push_str(&format!("... {} lines skipped ...\n", skipped.len())),
+        }
+        skipped.clear();
+    };
+    for (row, visible) in rows.iter().zip(visible) {
+        match row {
+            Row::Unchanged(line) if !visible => skipped.push(*line),
+            Row::Unchanged(line) => {
+                flush_skipped(&mut out, &mut skipped);
+                out.push_str(&unchanged(line));
+            }
+            Row::Changed(line) => {
+                flush_skipped(&mut out, &mut skipped);
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
     }
+    flush_skipped(&mut out, &mut skipped);
+    out
 }
 
-pub fn print_diff(expected: &[u8], actual: &[u8]) {
-    let expected_str = String::from_utf8_lossy(expected);
-    let actual_str = String::from_utf8_lossy(actual);
+/// A line that is the same in the expected and the actual output.
+fn unchanged(line: &str) -> String {
+    let mut out = Line::new(" ".normal());
+    out.push(split_terminator(line).0, None);
+    out.finish() + "\n"
+}
 
-    if expected_str.as_bytes() != expected || actual_str.as_bytes() != actual {
-        eprintln!(
-            "{}",
-            "Non-UTF8 characters in output, diff may be imprecise.".red()
-        );
-    }
+/// Prints a colored diff of `expected` and `actual` to stderr, keeping `context` unchanged
+/// lines around every change (see `Config::diff_context`).
+pub fn print_diff(expected: &[u8], actual: &[u8], context: usize) {
+    let expected_str = lossy(expected);
+    let actual_str = lossy(actual);
 
     let pat = |c: char| c.is_whitespace() && c != ' ' && c != '\n' && c != '\r';
     let expected_str = expected_str.replace(pat, "░");
     let actual_str = actual_str.replace(pat, "░");
 
-    let mut state = DiffState::default();
-    for row in lines(&expected_str, &actual_str) {
-        state.row(row);
+    eprintln!("{}", render(&expected_str, &actual_str, context));
+}
+
+/// A diff of the lines of `expected` and `actual` without colors, for reports that are not
+/// printed to a terminal. Like `print_diff`, only `context` unchanged lines are kept around
+/// every change.
+pub fn plain_diff(expected: &[u8], actual: &[u8], context: usize) -> String {
+    let expected = lossy(expected);
+    let actual = lossy(actual);
+    let mut rows = lines(&expected, &actual);
+    // Both ending in a newline shows up as an empty last line.
+    if let Some(Both("", "")) = rows.last() {
+        rows.pop();
+    }
+    let changed: Vec<usize> = rows
+        .iter()
+        .enumerate()
+        .filter(|(_, row)| !matches!(row, Both(..)))
+        .map(|(i, _)| i)
+        .collect();
+    let near_change = |i: usize| {
+        changed
+            .iter()
+            .any(|&change| i + context >= change && i <= change + context)
+    };
+    let mut diff = String::new();
+    let mut skipped = 0;
+    for (i, row) in rows.iter().enumerate() {
+        let line = match row {
+            Left(l) => format!("-{l}"),
+            Right(r) => format!("+{r}"),
+            Both(l, _) if near_change(i) => format!(" {l}"),
+            Both(..) => {
+                skipped += 1;
+                continue;
+            }
+        };
+        if skipped > 0 {
+            diff.push_str(&format!("... {skipped} lines skipped ...\n"));
+            skipped = 0;
+        }
+        diff.push_str(&line);
+        diff.push('\n');
+    }
+    if skipped > 0 {
+        diff.push_str(&format!("... {skipped} lines skipped ...\n"));
     }
-    state.finish();
+    diff
 }
@@ -0,0 +1,91 @@
+use super::*;
+
+/// The diff without colors, which depend on the terminal the tests run in.
+fn diff(expected: &str, actual: &str, context: usize) -> String {
+    let diff = render(expected, actual, context);
+    regex::Regex::new("\x1b\\[[0-9;]*m")
+        .unwrap()
+        .replace_all(&diff, "")
+        .into_owned()
+}
+
+#[test]
+fn changed_words() {
+    assert_eq!(
+        diff(
+            "error: expected `usize`, found `i32`\n",
+            "error: expected `u32`, found `i64`\n",
+            5
+        ),
+        "-error: expected `usize`, found `i32`\n+error: expected `u32`, found `i64`\n"
+    );
+    // Only added words are shown in a single line.
+    assert_eq!(
+        diff(
+            "error: aborting due to previous error\n",
+            "error: aborting due to 1 previous error\n",
+            5
+        ),
+        "~error: aborting due to 1 previous error\n"
+    );
+}
+
+#[test]
+fn context() {
+    let expected: String = (0..20).map(|i| format!("line {i}\n")).collect();
+    let actual = expected.replace("line 10\n", "line ten\n");
+    assert_eq!(
+        diff(&expected, &actual, 2),
+        "... 8 lines skipped ...\n line 8\n line 9\n-line 10\n+line ten\n line 11\n line 12\n... 7 lines skipped ...\n"
+    );
+    // A single skipped line is printed instead of saying that it was skipped.
+    assert_eq!(
+        diff(&expected, &actual, 9),
+        " line 0\n line 1\n line 2\n line 3\n line 4\n line 5\n line 6\n line 7\n line 8\n line 9\n-line 10\n+line ten\n line 11\n line 12\n line 13\n line 14\n line 15\n line 16\n line 17\n line 18\n line 19\n"
+    );
+    assert_eq!(diff("a\nb\nc\n", "a\nc\n", 0), " a\n-b\n c\n");
+    assert_eq!(
+        plain_diff(expected.as_bytes(), actual.as_bytes(), 1),
+        "... 9 lines skipped ...\n line 9\n-line 10\n+line ten\n line 11\n... 8 lines skipped ...\n"
+    );
+}
+
+#[test]
+fn trailing_whitespace() {
+    assert_eq!(diff("foo \nbar\n", "foo\nbar\n", 5), "~foo·\n bar\n");
+    assert_eq!(diff("foo\n", "foo\t\n", 5), "~foo\t\n");
+}
+
+#[test]
+fn crlf() {
+    assert_eq!(diff("foo\r\nbar\r\n", "foo\nbar\r\n", 5), "~foo␍\n bar\n");
+}
+
+#[test]
+fn missing_final_newline() {
+    assert_eq!(diff("foo\nbar\n", "foo\nbar", 5), " foo\n~bar⏎\n");
+    assert_eq!(diff("foo\nbar", "foo\nbaz\n", 5), " foo\n-bar\n+baz⏎\n");
+}
+
+#[test]
+fn long_lines() {
+    let expected = format!("{}\n", "a".repeat(400));
+    let actual = format!("{}\n", "b".repeat(400));
+    assert_eq!(
+        diff(&expected, &actual, 5),
+        format!(
+            "-{0}... (100 more characters)\n+{1}... (100 more characters)\n",
+            "a".repeat(300),
+            "b".repeat(300)
+        )
+    );
+}
+
+#[test]
+fn invalid_utf8() {
+    assert_eq!(lossy(b"foo"), "foo");
+    assert_eq!(
+        lossy(b"foo \xff\xfe bar \xc3"),
+        "foo <invalid utf8> bar <invalid utf8>"
+    );
+}
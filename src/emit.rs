@@ -0,0 +1,78 @@
+//! Tests comparing the assembly, LLVM IR or MIR the compiler emits against a snapshot,
+//! see the `//@emit` command.
+
+use crate::{Filter, Match};
+use regex::bytes::Regex;
+use std::fmt::Display;
+
+#[cfg(test)]
+mod tests;
+
+/// What an `//@emit` test compares against its snapshot.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Emit {
+    /// Assembly, compared against `.s` files.
+    Asm,
+    /// LLVM IR, compared against `.ll` files.
+    LlvmIr,
+    /// MIR, compared against `.mir` files.
+    Mir,
+}
+
+impl Emit {
+    const ALL: [(Emit, &'static str); 3] = [
+        (Emit::Asm, "asm"),
+        (Emit::LlvmIr, "llvm-ir"),
+        (Emit::Mir, "mir"),
+    ];
+
+    /// The extension of the snapshot files, which is also the extension rustc uses.
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            Emit::Asm => "s",
+            Emit::LlvmIr => "ll",
+            Emit::Mir => "mir",
+        }
+    }
+
+    /// The filters removing the parts of the output that depend on the machine or on the
+    /// compiler version. `strip_value_names` also replaces the names of local values and
+    /// labels in LLVM IR with `%_`, as they change with unrelated changes to the compiler.
+    pub(crate) fn filters(self, strip_value_names: bool) -> Filter {
+        let regex = |regex: &str| Match::Regex(Regex::new(regex).unwrap());
+        match self {
+            Emit::Asm => vec![(regex(r"(?m)^[ \t]*\.(file|ident)[ \t].*\n"), b"")],
+            Emit::LlvmIr => {
+                let mut filters = vec![
+                    (regex(r"(?m)^; ModuleID = .*\n"), b"" as &[u8]),
+                    (regex(r"(?m)^source_filename = .*\n"), b""),
+                    (regex(r"(?m)^!llvm\.ident = .*\n"), b""),
+                    (regex(r#"(?m)^![0-9]+ = !\{!"rustc version .*\n"#), b""),
+                ];
+                if strip_value_names {
+                    filters.push((regex(r"%[-a-zA-Z$._][-a-zA-Z$._0-9]*"), b"%_"));
+                }
+                filters
+            }
+            Emit::Mir => vec![],
+        }
+    }
+}
+
+impl std::str::FromStr for Emit {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Emit::ALL
+            .iter()
+            .find(|&&(_, name)| name == s)
+            .map(|&(emit, _)| emit)
+            .ok_or_else(|| format!("`emit` expects `asm`, `llvm-ir` or `mir`, but got `{s}`"))
+    }
+}
+
+impl Display for Emit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (_, name) = Emit::ALL.iter().find(|(emit, _)| emit == self).unwrap();
+        write!(f, "{name}")
+    }
+}
@@ -0,0 +1,74 @@
+use super::Emit;
+use crate::{
+    parse_comments_in_file, test_file, tests::config, CommandBuilder, Config, Error, Mode,
+    OutputConflictHandling, TestResult,
+};
+
+#[test]
+fn emit_filters() {
+    let apply = |emit: Emit, strip_value_names: bool, text: &str| {
+        let mut text = text.as_bytes().to_vec();
+        for (rule, replacement) in emit.filters(strip_value_names) {
+            text = rule.replace_all(&text, replacement).0.into_owned();
+        }
+        String::from_utf8(text).unwrap()
+    };
+    let asm = "\t.text\n\t.file\t\"foo.abc123-cgu.0\"\nadd_one:\n\tretq\n\t.ident\t\"rustc version 1.80.0\"\n";
+    assert_eq!(apply(Emit::Asm, false, asm), "\t.text\nadd_one:\n\tretq\n");
+
+    let ir = "; ModuleID = 'foo.abc123-cgu.0'\nsource_filename = \"foo.abc123-cgu.0\"\n\
+              define i32 @add_one(i32 %x) {\nstart:\n  %_0 = add i32 %x, 1\n  ret i32 %_0\n}\n\
+              !llvm.ident = !{!2}\n!2 = !{!\"rustc version 1.80.0\"}\n";
+    let body = "define i32 @add_one(i32 %x) {\nstart:\n  %_0 = add i32 %x, 1\n  ret i32 %_0\n}\n";
+    assert_eq!(apply(Emit::LlvmIr, false, ir), body);
+    assert_eq!(
+        apply(Emit::LlvmIr, true, ir),
+        body.replace("%x", "%_").replace("%_0", "%_")
+    );
+}
+
+/// `//@emit` tests compared against synthetic output, as the real output depends on the
+/// toolchain.
+#[cfg(unix)]
+#[test]
+fn emit_snapshot() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("foo.rs");
+    std::fs::write(&path, "//@emit: llvm-ir strip-value-names\n").unwrap();
+    let run = |emitted: &str| {
+        let mut config = Config {
+            out_dir: dir.path().join("out"),
+            mode: Mode::Pass,
+            output_conflict_handling: OutputConflictHandling::Error("bless".into()),
+            ..config()
+        };
+        // Writes its first argument to the file named by the `--emit` flag.
+        config.program = CommandBuilder::cmd("sh");
+        config.program.args = vec![
+            "-c".into(),
+            r#"for arg; do case "$arg" in --emit=llvm-ir=*) printf '%s' "$0" > "${arg#--emit=llvm-ir=}";; esac; done"#.into(),
+            emitted.into(),
+        ];
+        let comments = parse_comments_in_file(&path, &config).unwrap();
+        let runs = test_file(&path, &config, comments, |_| true, None, None);
+        match runs.into_iter().next().unwrap().result {
+            TestResult::Ok => vec![],
+            TestResult::Errored { errors, .. } => errors,
+            result => panic!("{result:#?}"),
+        }
+    };
+    std::fs::write(
+        path.with_extension("ll"),
+        "define i32 @add_one(i32 %_) {\nstart:\n  %_ = add i32 %_, 1\n  ret i32 %_\n}\n",
+    )
+    .unwrap();
+    let ir = "; ModuleID = 'foo.abc123-cgu.0'\nsource_filename = \"foo.abc123-cgu.0\"\n\
+              define i32 @add_one(i32 %x) {\nstart:\n  %_0 = add i32 %x, 1\n  ret i32 %_0\n}\n\
+              !llvm.ident = !{!2}\n!2 = !{!\"rustc version 1.80.0\"}\n";
+    let errors = run(ir);
+    assert!(errors.is_empty(), "{errors:#?}");
+    match &run(&ir.replace("add i32", "sub i32"))[..] {
+        [Error::OutputDiffers { path: p, .. }] => assert_eq!(p, &path.with_extension("ll")),
+        errors => panic!("{errors:#?}"),
+    }
+}
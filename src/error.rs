@@ -1,17 +1,54 @@
-use crate::{parser::Pattern, rustc_stderr::Message, Mode};
-use std::{path::PathBuf, process::ExitStatus};
+use crate::{parser::Pattern, rustc_stderr::Message, ExitExpectation, Level, Mode};
+use std::{path::PathBuf, process::ExitStatus, time::Duration};
 
 /// All the ways in which a test can fail.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Error {
     /// Got an invalid exit status for the given mode.
     ExitStatus {
         /// The expected mode.
         mode: Mode,
         /// The exit status of the command.
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serialize::display"))]
         status: ExitStatus,
         /// The expected exit status as set in the file or derived from the mode.
-        expected: i32,
+        expected: ExitExpectation,
+    },
+    /// The compiler crashed with an internal compiler error, see `Config::ice_patterns`.
+    Ice {
+        /// The ICE's diagnostic or panic message.
+        message: String,
+        /// The queries the compiler was executing when it crashed, if it printed them.
+        query_stack: Vec<String>,
+    },
+    /// The test has a `//@should-ice` comment, but the compiler did not crash with an
+    /// internal compiler error.
+    IceNotFound,
+    /// The binary of a `//@run-panic` test exited successfully.
+    DidNotPanic,
+    /// The binary of a `//@run-panic` test panicked, but its stderr does not contain the
+    /// expected panic message.
+    PanicMessageNotFound {
+        /// The expected panic message.
+        pattern: Pattern,
+        /// The line of the `run-panic` comment.
+        line: usize,
+    },
+    /// The compiler emitted JSON diagnostics on both stdout and stderr, and they differ,
+    /// see `Config::diagnostics_stream`.
+    ConflictingDiagnostics,
+    /// The compiler or the test binary was killed because it ran for too long.
+    TimedOut {
+        /// The time after which the command was killed.
+        timeout: Duration,
+    },
+    /// The test took longer than `Config::time_limit_warning`. Only used as a warning.
+    TimeLimitExceeded {
+        /// How long the test took.
+        duration: Duration,
+        /// The time limit.
+        limit: Duration,
     },
     /// A pattern was declared but had no matching error.
     PatternNotFound {
@@ -19,6 +56,89 @@ pub enum Error {
         pattern: Pattern,
         /// The line in which the pattern was defined.
         definition_line: usize,
+        /// Unmatched diagnostics near the annotated line that are similar to the pattern.
+        near_misses: Vec<NearMiss>,
+    },
+    /// A pattern's error code did not match the code of the diagnostic it matched.
+    CodeMismatch {
+        /// The error code the pattern expected.
+        expected: String,
+        /// The error code of the diagnostic, if it had one and there was a diagnostic to check.
+        actual: Option<String>,
+        /// The line in which the pattern was defined.
+        definition_line: usize,
+    },
+    /// A pattern with a count (`//~ ERROR*3: XXX`) matched a different number of diagnostics.
+    WrongMatchCount {
+        /// How many diagnostics the pattern expected.
+        expected: usize,
+        /// How many diagnostics it matched.
+        found: usize,
+        /// The line in which the pattern was defined.
+        definition_line: usize,
+    },
+    /// A negated pattern (`//~ !ERROR: XXX`) matched a diagnostic.
+    NegatedPatternFound {
+        /// The line in which the pattern was defined.
+        definition_line: usize,
+        /// The diagnostic that must not have been emitted.
+        msg: Message,
+    },
+    /// An annotation with a column (`//~ ERROR@17: XXX`) found no diagnostic at that column.
+    ColumnNotFound {
+        /// The column the annotation expected a diagnostic at.
+        column: usize,
+        /// The columns of the diagnostics on the annotated line.
+        columns: Vec<usize>,
+        /// The line in which the pattern was defined.
+        definition_line: usize,
+    },
+    /// A `//~ SUGGESTION: XXX` annotation matched none of the suggestions on its line.
+    SuggestionNotFound {
+        /// The pattern that was missing a suggestion.
+        pattern: Pattern,
+        /// The replacements suggested on the line.
+        suggestions: Vec<String>,
+        /// The line in which the pattern was defined.
+        definition_line: usize,
+    },
+    /// When blessing annotations, the text of an annotation was replaced with the
+    /// message of the diagnostic it now stands for. This does not fail the test.
+    AnnotationRewritten {
+        /// The line of the annotation.
+        definition_line: usize,
+        /// The text that was replaced.
+        old: String,
+        /// The new text.
+        new: String,
+    },
+    /// When blessing annotations, annotations were inserted for the diagnostics of a line
+    /// that had none. This does not fail the test.
+    AnnotationsInserted {
+        /// The line of the diagnostics.
+        line: usize,
+        /// The line as it was before, if the annotation was appended to it.
+        removed: Option<String>,
+        /// The lines that were added, or the line with the appended annotation.
+        added: Vec<String>,
+    },
+    /// When pruning annotations, an annotation that matched no diagnostic was removed.
+    /// This does not fail the test.
+    AnnotationRemoved {
+        /// The line of the annotation.
+        definition_line: usize,
+        /// The removed annotation.
+        annotation: String,
+    },
+    /// When blessing annotations, an annotation that did not match could not be rewritten,
+    /// because there are multiple diagnostics it could stand for.
+    AmbiguousAnnotation {
+        /// The line of the annotation.
+        definition_line: usize,
+        /// The line the annotation refers to.
+        line: usize,
+        /// The diagnostics the annotation could stand for.
+        msgs: Vec<Message>,
     },
     /// A ui test checking for failure does not have any failure patterns
     NoPatternsFound,
@@ -29,11 +149,28 @@ pub enum Error {
         /// The file containing the expected output that differs from the actual output.
         path: PathBuf,
         /// The output from the command.
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serialize::lossy"))]
         actual: Vec<u8>,
         /// The contents of the file.
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serialize::lossy"))]
         expected: Vec<u8>,
         /// A command, that when run, causes the output to get blessed instead of erroring.
         bless_command: String,
+        /// How many unchanged lines are displayed around the changed lines of the diff,
+        /// see `Config::diff_context`.
+        diff_context: usize,
+    },
+    /// An output file exists, but the test is not supposed to produce one.
+    StaleOutput {
+        /// The file that should not exist.
+        path: PathBuf,
+        /// A command, that when run, causes the file to get removed instead of erroring.
+        bless_command: String,
+    },
+    /// Blessing could not write an output file, because it is in a read-only location.
+    ReadOnlyOutput {
+        /// The file that could not be written.
+        path: PathBuf,
     },
     /// There were errors that don't have a pattern.
     ErrorsWithoutPattern {
@@ -41,6 +178,8 @@ pub enum Error {
         msgs: Vec<Message>,
         /// File and line information of the error.
         path: Option<(PathBuf, usize)>,
+        /// Patterns near the line that were not found, but are similar to one of the messages.
+        similar_patterns: Vec<SimilarPattern>,
     },
     /// A comment failed to parse.
     InvalidComment {
@@ -49,13 +188,96 @@ pub enum Error {
         /// THe line in which it was defined.
         line: usize,
     },
+    /// A comment uses a deprecated form. This is only a warning, unless
+    /// `Config::deny_deprecated` is set.
+    DeprecatedComment {
+        /// What is deprecated and what to use instead.
+        msg: String,
+        /// The line in which it was defined.
+        line: usize,
+        /// The `ui_test` version that deprecated it.
+        since: &'static str,
+    },
+    /// A `normalize-*-test` comment or a filter of the `Config` did not replace anything.
+    /// Only used as a warning, see `Config::report_unused_normalizations`.
+    UnusedNormalization {
+        /// The pattern, and for filters also the replacement.
+        rule: String,
+        /// The line of the `normalize-*-test` comment, `None` for filters.
+        line: Option<usize>,
+    },
+    /// A test named in a `//@depends-on` comment did not pass.
+    DependencyFailed {
+        /// The test that was depended on.
+        path: PathBuf,
+        /// Why the dependency can't be used.
+        reason: &'static str,
+        /// The line of the `depends-on` comment.
+        line: usize,
+    },
+    /// Applying rustfix suggestions kept producing new suggestions.
+    FixpointNotReached {
+        /// How often suggestions were applied.
+        iterations: usize,
+        /// The diagnostics that still had suggestions.
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serialize::lossy"))]
+        diagnostics: Vec<u8>,
+    },
+    /// Compiling the `.fixed` file of a `//@run-rustfix` test emitted diagnostics.
+    FixedHasDiagnostics {
+        /// The lowest level of diagnostics that isn't allowed.
+        level: Level,
+        /// The rendered diagnostics.
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serialize::lossy"))]
+        stderr: Vec<u8>,
+    },
+    /// A forbidden environment variable would have been visible to the test.
+    ForbiddenEnv {
+        /// The environment variable.
+        var: String,
+        /// What made the variable visible.
+        layer: &'static str,
+    },
+    /// The file named in a `//@stdin` comment could not be read.
+    StdinFile {
+        /// The file that was supposed to be passed to stdin.
+        path: PathBuf,
+        /// The line of the `stdin` comment.
+        line: usize,
+        /// Why the file could not be read.
+        error: String,
+    },
     /// A subcommand (e.g. rustfix) of a test failed.
     Command {
         /// The name of the subcommand (e.g. "rustfix").
         kind: String,
         /// The exit status of the command.
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serialize::display"))]
         status: ExitStatus,
     },
+    /// A `target-` or `host-` condition is not part of any known target triple,
+    /// so it is probably misspelled.
+    UnknownTriple {
+        /// The condition, e.g. `only-target-x86_46`.
+        condition: String,
+        /// The line of the condition.
+        line: usize,
+    },
+    /// A channel condition like `only-nightly` is used, but the release channel of the
+    /// program could not be detected.
+    UnknownChannel {
+        /// The condition, e.g. `only-nightly`.
+        condition: String,
+    },
+    /// `{{sysroot}}` is used in a flag or env var of the test, but the sysroot of the
+    /// program could not be detected.
+    UnknownSysroot,
+    /// A run test was cross compiled, but there is no `Config::runner` to execute
+    /// its binary with.
+    RunnerRequired {
+        /// The target the binary was compiled for.
+        target: String,
+    },
     /// This catches crashes of ui tests and reports them along the failed test.
     Bug(String),
     /// An auxiliary build failed with its own set of errors.
@@ -70,3 +292,27 @@ pub enum Error {
 }
 
 pub(crate) type Errors = Vec<Error>;
+
+/// A diagnostic that is similar to a pattern that was not found.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct NearMiss {
+    /// The diagnostic.
+    pub msg: Message,
+    /// The line the diagnostic was reported in, `None` if it is not in the test file.
+    pub line: Option<usize>,
+    /// How similar the diagnostic is to the pattern, in percent.
+    pub similarity: usize,
+}
+
+/// A pattern that was not found, but is similar to a diagnostic without a pattern.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SimilarPattern {
+    /// The pattern.
+    pub pattern: Pattern,
+    /// The line in which the pattern was defined.
+    pub definition_line: usize,
+    /// How similar the pattern is to the diagnostic, in percent.
+    pub similarity: usize,
+}
@@ -0,0 +1,124 @@
+//! Remembering which tests passed, to skip them in later runs if nothing they depend on
+//! changed, see `Config::fingerprint_dir`.
+
+use crate::{
+    aux_file_path, build_command, cache::Key, output_path, parser::Comments, revised, Config,
+};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+/// The output files a test can be compared against.
+const OUTPUT_KINDS: [&str; 5] = ["stderr", "stdout", "fixed", "run.stderr", "run.stdout"];
+
+/// The directory with one file per test and revision, containing the fingerprint
+/// the test last passed with.
+pub(crate) struct Fingerprints {
+    dir: PathBuf,
+    /// Whether passed tests may be skipped, or only have their fingerprints recorded.
+    reuse: bool,
+}
+
+impl Fingerprints {
+    /// `None` if fingerprints are disabled in the `config`. Blessing never skips tests,
+    /// as it must update all output files.
+    pub fn new(config: &Config, force_rerun: bool) -> Option<Self> {
+        let dir = config.fingerprint_dir.clone()?;
+        if let crate::OutputConflictHandling::Bless = config.output_conflict_handling {
+            return None;
+        }
+        // If this fails, so does writing the fingerprints, which only makes later runs slower.
+        let _ = std::fs::create_dir_all(&dir);
+        Some(Self {
+            dir,
+            reuse: !force_rerun,
+        })
+    }
+
+    /// Whether the test passed the last time it was run with this `fingerprint`.
+    pub fn unchanged(&self, path: &Path, revision: &str, fingerprint: &Key) -> bool {
+        self.reuse
+            && std::fs::read(self.file(path, revision)).ok() == Some(fingerprint.hex().into_bytes())
+    }
+
+    /// Remembers the `fingerprint` of a test that passed, or forgets the test if it failed.
+    pub fn record(&self, path: &Path, revision: &str, fingerprint: &Key, passed: bool) {
+        let file = self.file(path, revision);
+        // Failing to write a fingerprint just means that the test gets run again.
+        if passed {
+            let _ = crate::cache::store_file(&self.dir, &file, fingerprint.hex().as_bytes());
+        } else {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+
+    /// The file the fingerprint of the test is stored in.
+    fn file(&self, path: &Path, revision: &str) -> PathBuf {
+        let mut key = Key::default();
+        key.add(path.to_string_lossy().as_bytes());
+        key.add(revision);
+        key.entry(&self.dir, "test")
+    }
+}
+
+/// Hashes everything that can change the result of the test: the test file, its aux files,
+/// its output files, the compiler, the command the test is compiled with and the settings
+/// of the `config` that decide how the output is checked.
+pub(crate) fn fingerprint(
+    path: &Path,
+    config: &Config,
+    revision: &str,
+    comments: &Comments,
+) -> Key {
+    let mut key = Key::new(&config.program.program);
+    key.add(std::fs::read(path).unwrap_or_default());
+
+    let aux_dir = path.parent().unwrap().join("auxiliary");
+    let mut aux_dirs = BTreeSet::new();
+    for rev in comments.for_revision(revision) {
+        for (aux, kind, _) in &rev.aux_builds {
+            let aux_file = aux_file_path(&aux_dir, aux);
+            key.add(kind);
+            key.add(std::fs::read(&aux_file).unwrap_or_default());
+            // Aux files can have aux builds of their own.
+            aux_dirs.insert(aux_file.parent().unwrap().to_owned());
+        }
+    }
+    for dir in aux_dirs {
+        key.add_dir(&dir, &[]);
+    }
+
+    for kind in OUTPUT_KINDS {
+        let output = output_path(path, comments, revised(revision, kind), config, revision);
+        match std::fs::read(output) {
+            Ok(content) => key.add(content),
+            Err(_) => key.add("\0missing"),
+        }
+    }
+
+    let cmd = build_command(path, config, revision, comments, &mut vec![]);
+    key.add_command(&cmd);
+    key.add_artifacts(&cmd);
+    key.add(format!(
+        "{:?}",
+        (
+            &config.mode,
+            &config.stderr_filters,
+            &config.named_stderr_filters,
+            &config.stdout_filters,
+            &config.runner,
+            &config.forbidden_env,
+            config.fix_iterations,
+            config.rustfix_all_suggestions,
+            (
+                config.fixed_diagnostics_level,
+                config.require_annotations_for_level,
+            ),
+            (config.sort_diagnostics, config.diagnostics_stream),
+            config.preserve_line_endings,
+            &config.ice_patterns,
+        )
+    ));
+    key
+}
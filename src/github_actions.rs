@@ -4,6 +4,7 @@ use std::fmt::{Debug, Write};
 
 /// Shows an error message directly in a github diff view on drop.
 pub struct Error {
+    kind: &'static str,
     file: String,
     line: usize,
     title: String,
@@ -20,6 +21,18 @@ impl Error {
 /// Create an error to be shown for the given file and with the given title.
 pub fn error(file: impl std::fmt::Display, title: impl Into<String>) -> Error {
     Error {
+        kind: "error",
+        file: file.to_string(),
+        line: 0,
+        title: title.into(),
+        message: String::new(),
+    }
+}
+
+/// Create a warning to be shown for the given file and with the given title.
+pub fn warning(file: impl std::fmt::Display, title: impl Into<String>) -> Error {
+    Error {
+        kind: "warning",
         file: file.to_string(),
         line: 0,
         title: title.into(),
@@ -33,27 +46,43 @@ impl Write for Error {
     }
 }
 
+impl Error {
+    /// The workflow command, without the leading `::`.
+    pub(crate) fn command(&self) -> String {
+        let Error {
+            kind,
+            file,
+            line,
+            title,
+            message,
+        } = self;
+        let file = github_action_property_escape(file);
+        let title = github_action_property_escape(title);
+        let message = message.trim();
+        let message = if message.is_empty() {
+            "::no message".into()
+        } else {
+            format!("::{}", github_action_multiline_escape(message))
+        };
+        format!("{kind} file={file},line={line},title={title}{message}")
+    }
+}
+
 impl Drop for Error {
     fn drop(&mut self) {
-        if std::env::var_os("GITHUB_ACTION").is_some() {
-            let Error {
-                file,
-                line,
-                title,
-                message,
-            } = self;
-            let message = message.trim();
-            let message = if message.is_empty() {
-                "::no message".into()
-            } else {
-                format!("::{}", github_action_multiline_escape(message))
-            };
-            eprintln!("::error file={file},line={line},title={title}{message}");
-            eprintln!("error file={file},line={line},title={title}{message}");
+        if enabled() {
+            let command = self.command();
+            eprintln!("::{command}");
+            eprintln!("{command}");
         }
     }
 }
 
+/// Whether we are running in github actions, which sets `GITHUB_ACTIONS=true`.
+pub fn enabled() -> bool {
+    std::env::var_os("GITHUB_ACTIONS").map_or(false, |var| var == "true")
+}
+
 /// Append to the summary file that will be shown for the entire CI run.
 pub fn summary() -> Option<impl std::io::Write> {
     let path = std::env::var_os("GITHUB_STEP_SUMMARY")?;
@@ -66,11 +95,18 @@ fn github_action_multiline_escape(s: &str) -> String {
         .replace('\r', "%0D")
 }
 
+/// Properties like the file and title additionally end at `,` and `:`.
+fn github_action_property_escape(s: &str) -> String {
+    github_action_multiline_escape(s)
+        .replace(':', "%3A")
+        .replace(',', "%2C")
+}
+
 /// All github actions log messages from this call to the Drop of the return value
 /// will be grouped and hidden by default in logs. Note that nesting these does
 /// not really work.
 pub fn group(name: impl std::fmt::Display) -> Group {
-    if std::env::var_os("GITHUB_ACTION").is_some() {
+    if enabled() {
         eprintln!("::group::{name}");
     }
     Group(())
@@ -87,7 +123,7 @@ impl Debug for Group {
 
 impl Drop for Group {
     fn drop(&mut self) {
-        if std::env::var_os("GITHUB_ACTION").is_some() {
+        if enabled() {
             eprintln!("::endgroup::");
         }
     }
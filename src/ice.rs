@@ -0,0 +1,70 @@
+//! Internal compiler errors (ICEs), which fail tests no matter what they expect,
+//! see `Config::ice_patterns`.
+
+use crate::{diff::lossy, Error, Match};
+use bstr::ByteSlice;
+use std::process::{ExitStatus, Output};
+
+#[cfg(test)]
+mod tests;
+
+/// Whether the program crashed like a panicking compiler does: with exit code 101, or
+/// aborted by `SIGABRT`, e.g. when it panicked while panicking.
+fn crashed(status: ExitStatus) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if status.signal() == Some(6) {
+            return true;
+        }
+    }
+    status.code() == Some(101)
+}
+
+/// The `Error::Ice` for the `output` of a program that crashed and has a line in its
+/// stderr matching one of the `patterns`.
+pub(crate) fn detect(output: &Output, patterns: &[Match]) -> Option<Error> {
+    if !crashed(output.status) {
+        return None;
+    }
+    let lines: Vec<&[u8]> = output.stderr.lines().collect();
+    let start = lines
+        .iter()
+        .position(|line| patterns.iter().any(|pattern| pattern.is_match(line)))?;
+    Some(Error::Ice {
+        message: message(&lines[start..]),
+        query_stack: query_stack(&lines),
+    })
+}
+
+/// The message of the ICE starting at the first of the `lines`. JSON diagnostics are
+/// rendered, and panic messages in the line after `panicked at file:line:col:` are included.
+fn message(lines: &[&[u8]]) -> String {
+    let line = lines[0];
+    if line.starts_with(b"{") {
+        let rendered = serde_json::from_slice::<serde_json::Value>(line)
+            .ok()
+            .and_then(|diagnostic| Some(diagnostic["rendered"].as_str()?.trim_end().to_owned()));
+        if let Some(rendered) = rendered {
+            return rendered;
+        }
+    }
+    let mut message = lossy(line).into_owned();
+    if let (true, Some(next)) = (line.ends_with(b":"), lines.get(1)) {
+        message.push('\n');
+        message.push_str(&lossy(next));
+    }
+    message
+}
+
+/// The queries the compiler was executing, as printed between `query stack during panic:`
+/// and `end of query stack`.
+fn query_stack(lines: &[&[u8]]) -> Vec<String> {
+    lines
+        .iter()
+        .skip_while(|line| line.trim() != b"query stack during panic:")
+        .skip(1)
+        .take_while(|line| line.trim() != b"end of query stack")
+        .map(|line| lossy(line).into_owned())
+        .collect()
+}
@@ -0,0 +1,88 @@
+use crate::{
+    parse_comments_in_file, test_file, tests::config, CommandBuilder, Config, Error, Match, Mode,
+    OutputConflictHandling, TestResult,
+};
+use regex::bytes::Regex;
+
+#[cfg(unix)]
+#[test]
+fn ice() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("foo.rs");
+    let run = |config: &Config, source: &str, stderr: &str, status: i32| {
+        std::fs::write(&path, source).unwrap();
+        let mut config = config.clone();
+        config.program = CommandBuilder::cmd("sh");
+        config.program.args = vec![
+            "-c".into(),
+            format!("printf '%s' \"$0\" >&2; exit {status}").into(),
+            stderr.into(),
+        ];
+        let comments = parse_comments_in_file(&path, &config).unwrap();
+        let runs = test_file(&path, &config, comments, |_| true, None, None);
+        match runs.into_iter().next().unwrap().result {
+            TestResult::Ok => vec![],
+            TestResult::Errored { errors, .. } => errors,
+            result => panic!("{result:#?}"),
+        }
+    };
+    let config = Config {
+        mode: Mode::Fail {
+            require_patterns: false,
+        },
+        output_conflict_handling: OutputConflictHandling::Ignore,
+        ..config()
+    };
+    let ice = "error: internal compiler error: no type for node\n\n\
+        thread 'rustc' panicked at compiler/rustc_middle/src/ty/mod.rs:1:2:\n\
+        Box<dyn Any>\n\
+        query stack during panic:\n\
+        #0 [typeck] type-checking `main`\n\
+        end of query stack\n";
+
+    match &run(&config, "", ice, 101)[..] {
+        [Error::Ice {
+            message,
+            query_stack,
+        }] => {
+            assert_eq!(message, "error: internal compiler error: no type for node");
+            assert_eq!(query_stack, &["#0 [typeck] type-checking `main`"]);
+        }
+        errors => panic!("{errors:#?}"),
+    }
+    // Even if the mode expects a panic.
+    let panic = Config {
+        mode: Mode::Panic,
+        ..config.clone()
+    };
+    assert!(matches!(
+        &run(&panic, "", ice, 101)[..],
+        [Error::Ice { .. }]
+    ));
+    let errors = run(&config, "//@should-ice\n", ice, 101);
+    assert!(errors.is_empty(), "{errors:#?}");
+    assert!(matches!(
+        &run(&config, "//@should-ice\n", "error: mismatched types\n", 1)[..],
+        [Error::IceNotFound]
+    ));
+
+    // Panics of custom drivers need their own patterns.
+    let driver_panic = "thread 'main' panicked at src/driver.rs:3:4:\noops\n";
+    assert!(matches!(
+        &run(&config, "", driver_panic, 101)[..],
+        [Error::ExitStatus { .. }, Error::Bug(_)]
+    ));
+    let mut driver = config.clone();
+    driver.ice_patterns.push(Match::Regex(
+        Regex::new("^thread '[a-z]+' panicked").unwrap(),
+    ));
+    match &run(&driver, "", driver_panic, 101)[..] {
+        [Error::Ice { message, .. }] => {
+            assert_eq!(
+                message,
+                "thread 'main' panicked at src/driver.rs:3:4:\noops"
+            )
+        }
+        errors => panic!("{errors:#?}"),
+    }
+}
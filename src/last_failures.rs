@@ -0,0 +1,46 @@
+//! Remembering the tests that failed in the last run, for `--rerun-failed`.
+
+use crate::{TestResult, TestRun};
+use color_eyre::eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Serialize, Deserialize)]
+struct FailedTest {
+    path: PathBuf,
+    revision: String,
+}
+
+/// The tests and revisions that failed in the last run, as written by `write` to `path`
+/// (see `Config::last_failures`). `None` if there were none, or if the file can't be read.
+pub(crate) fn read(path: &Path) -> Option<HashSet<(PathBuf, String)>> {
+    let content = std::fs::read(path).ok()?;
+    let failures: Vec<FailedTest> = serde_json::from_slice(&content).ok()?;
+    let failures: HashSet<_> = failures
+        .into_iter()
+        .map(|test| (test.path, test.revision))
+        .collect();
+    (!failures.is_empty()).then_some(failures)
+}
+
+/// Replaces the recorded failures in `path` with the failed tests among the `runs`.
+pub(crate) fn write(path: &Path, runs: &[TestRun]) -> Result<()> {
+    let failures: Vec<_> = runs
+        .iter()
+        .filter(|run| matches!(run.result, TestResult::Errored { .. }))
+        .map(|run| FailedTest {
+            path: run.path.clone(),
+            revision: run.revision.clone(),
+        })
+        .collect();
+    path.parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|()| std::fs::write(path, serde_json::to_vec_pretty(&failures)?))
+        .wrap_err_with(|| format!("failed to write {}", path.display()))
+}
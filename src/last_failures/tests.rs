@@ -0,0 +1,80 @@
+use crate::{
+    default_file_filter, default_per_file_config, run_tests_generic,
+    tests::{noop_suite, recorded, Log, Recorder},
+    Args, Config, TestResult, TestRun, Timings,
+};
+use std::{process::Command, time::Duration};
+
+#[test]
+fn last_failures() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("ui_test").join("last-failures.json");
+    assert!(super::read(&path).is_none());
+    let run = |path: &str, revision: &str, result| TestRun {
+        result,
+        path: path.into(),
+        revision: revision.into(),
+        duration: Duration::ZERO,
+        timings: Timings::default(),
+        command: None,
+        warnings: vec![],
+    };
+    let failed = || TestResult::Errored {
+        command: Command::new("cake"),
+        steps: vec![],
+        errors: vec![],
+        stderr: vec![],
+        artifacts: None,
+    };
+    let runs = [
+        run("a.rs", "", TestResult::Ok),
+        run("b.rs", "x", failed()),
+        run("b.rs", "y", TestResult::Ok),
+        run("c.rs", "", failed()),
+    ];
+    super::write(&path, &runs).unwrap();
+    let failures = super::read(&path).unwrap();
+    let expected = [("b.rs".into(), "x".into()), ("c.rs".into(), String::new())];
+    assert_eq!(failures, expected.into_iter().collect());
+
+    // Passing runs clear the failures.
+    super::write(&path, &runs[..1]).unwrap();
+    assert!(super::read(&path).is_none());
+}
+
+#[cfg(unix)]
+#[test]
+fn rerun_failed() {
+    let dir = tempfile::tempdir().unwrap();
+    let tests = dir.path().join("tests");
+    std::fs::create_dir(&tests).unwrap();
+    std::fs::write(tests.join("a.rs"), "").unwrap();
+    std::fs::write(tests.join("b.rs"), "").unwrap();
+    std::fs::write(tests.join("b.stderr"), "error").unwrap();
+    let run = || {
+        let config = Config {
+            last_failures: dir.path().join("ui_test").join("last-failures.json"),
+            ..noop_suite(dir.path())
+        };
+        let args = Args {
+            rerun_failed: true,
+            ..Args::default()
+        };
+        let log = Log::default();
+        run_tests_generic(
+            config,
+            args,
+            default_file_filter,
+            default_per_file_config,
+            Recorder("", log.clone()),
+        )
+        .unwrap();
+        recorded(&log, &["no failures recorded", "started"])
+    };
+    // Without recorded failures, all tests are run, and the emitter is told why.
+    assert_eq!(
+        run(),
+        ["no failures recorded", "started a.rs", "started b.rs"]
+    );
+    assert_eq!(run(), ["started b.rs"]);
+}
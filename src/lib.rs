@@ -9,40 +9,66 @@
 //! A crate to run the Rust compiler (or other binaries) and test their command line output.
 
 use bstr::ByteSlice;
-use clap::Parser;
 pub use color_eyre;
-use color_eyre::eyre::{eyre, Result};
+use color_eyre::eyre::{bail, eyre, Result};
 use crossbeam_channel::{unbounded, Receiver, Sender};
 use lazy_static::lazy_static;
-use parser::{ErrorMatch, Revisioned};
+use parser::RevisionFilter;
 use regex::bytes::{Captures, Regex};
-use rustc_stderr::{Diagnostics, Level, Message};
+use rustc_stderr::Diagnostics;
 use status_emitter::StatusEmitter;
 use std::borrow::Cow;
-use std::collections::{HashSet, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::{OsStr, OsString};
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
 use std::thread;
-
-use crate::parser::{Comments, Condition};
-
+use std::time::{Duration, Instant};
+
+use crate::artifacts::Artifacts;
+use crate::emit::Emit;
+use crate::fingerprint::Fingerprints;
+use crate::parser::TARGET_OSES;
+use crate::placeholders::Placeholders;
+use crate::schedule::{Schedule, DEPENDENCY_ENV_PREFIX};
+use crate::usage::Usage;
+
+mod artifacts;
+mod cache;
 mod cmd;
 mod config;
 mod dependencies;
 mod diff;
+mod emit;
 mod error;
+mod fingerprint;
 pub mod github_actions;
+mod ice;
+mod last_failures;
 mod mode;
+mod orphans;
 mod parser;
+mod placeholders;
 mod rustc_stderr;
+mod schedule;
+#[cfg(feature = "serde")]
+mod serialize;
 pub mod status_emitter;
 #[cfg(test)]
 mod tests;
+mod usage;
 
 pub use cmd::*;
 pub use config::*;
 pub use error::*;
 pub use mode::*;
+pub use parser::{
+    Comments, Condition, CustomCommandContext, CustomCommandFn, Endianness, ErrorMatch, OptLevel,
+    Pattern, Revisioned,
+};
+pub use rustc_stderr::{Level, Message, UnknownLevel};
 
 /// A filter's match rule.
 #[derive(Clone, Debug)]
@@ -53,12 +79,44 @@ pub enum Match {
     Exact(Vec<u8>),
     /// Uses a heuristic to find backslashes in windows style paths
     PathBackslash,
+    /// Finds backslashes in paths that start with the given relative path (ignoring its root
+    /// and any windows prefix), e.g. `tests\ui\foo.rs` for `tests/ui`.
+    PathBackslashUnder(PathBuf),
 }
 impl Match {
-    fn replace_all<'a>(&self, text: &'a [u8], replacement: &[u8]) -> Cow<'a, [u8]> {
+    /// The ways `path` can show up in the output: with slashes or backslashes as separators,
+    /// and in the `\\?\` verbatim form windows uses for canonicalized paths. The verbatim
+    /// forms come first, so they are replaced as a whole.
+    pub(crate) fn path_variants(path: &Path) -> Vec<Self> {
+        let path = path.display().to_string();
+        let path = path.strip_prefix(r"\\?\").unwrap_or(&path);
+        let backslashes = path.replace('/', r"\");
+        let slashes = path.replace('\\', "/");
+        let mut variants = vec![
+            format!(r"\\?\{backslashes}"),
+            format!(r"\\?\{slashes}"),
+            backslashes,
+            slashes,
+        ];
+        variants.dedup();
+        variants
+            .into_iter()
+            .map(|variant| Self::Exact(variant.into_bytes()))
+            .collect()
+    }
+
+    /// Replaces all matches in `text`, and returns how many there were.
+    fn replace_all<'a>(&self, text: &'a [u8], replacement: &[u8]) -> (Cow<'a, [u8]>, usize) {
         match self {
-            Match::Regex(regex) => regex.replace_all(text, replacement),
-            Match::Exact(needle) => text.replace(needle, replacement).into(),
+            Match::Regex(regex) => replace_counted(regex, text, |caps| {
+                let mut replaced = vec![];
+                caps.expand(replacement, &mut replaced);
+                replaced
+            }),
+            Match::Exact(needle) => {
+                let count = text.find_iter(needle).count();
+                (text.replace(needle, replacement).into(), count)
+            }
             Match::PathBackslash => {
                 lazy_static! {
                     static ref PATH_RE: Regex = Regex::new(
@@ -74,9 +132,38 @@ impl Match {
                     .unwrap();
                 }
 
-                PATH_RE.replace_all(text, |caps: &Captures<'_>| {
-                    caps[0].replace(r"\", replacement)
-                })
+                replace_counted(&PATH_RE, text, |caps| caps[0].replace(r"\", replacement))
+            }
+            Match::PathBackslashUnder(root) => {
+                let components: Vec<_> = root
+                    .components()
+                    .filter_map(|c| match c {
+                        std::path::Component::Normal(c) => {
+                            Some(regex::escape(&c.to_string_lossy()))
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                if components.is_empty() {
+                    return (text.into(), 0);
+                }
+                let path_re = Regex::new(&format!(
+                    r"{}(?:[/\\][\pL\pN.\-_']+)*",
+                    components.join(r"[/\\]")
+                ))
+                .unwrap();
+                replace_counted(&path_re, text, |caps| caps[0].replace(r"\", replacement))
+            }
+        }
+    }
+
+    /// Whether there is a match in `text`.
+    fn is_match(&self, text: &[u8]) -> bool {
+        match self {
+            Match::Regex(regex) => regex.is_match(text),
+            Match::Exact(needle) => text.find(needle).is_some(),
+            Match::PathBackslash | Match::PathBackslashUnder(_) => {
+                self.replace_all(text, b"").1 > 0
             }
         }
     }
@@ -107,6 +194,20 @@ impl From<Regex> for Match {
     }
 }
 
+/// `Regex::replace_all`, but also returns how many matches were replaced.
+fn replace_counted<'a>(
+    regex: &Regex,
+    text: &'a [u8],
+    mut replace: impl FnMut(&Captures<'_>) -> Vec<u8>,
+) -> (Cow<'a, [u8]>, usize) {
+    let mut count = 0;
+    let replaced = regex.replace_all(text, |caps: &Captures<'_>| {
+        count += 1;
+        replace(caps)
+    });
+    (replaced, count)
+}
+
 /// Replacements to apply to output files.
 pub type Filter = Vec<(Match, &'static [u8])>;
 
@@ -117,7 +218,12 @@ pub fn run_tests(config: Config) -> Result<()> {
 
     let name = config.root_dir.display().to_string();
 
-    let args = Args::parse();
+    let args = Args::from_args(std::env::args_os())?;
+    let text = if args.quiet {
+        status_emitter::Text::quiet()
+    } else {
+        status_emitter::Text::verbose()
+    };
 
     run_tests_generic(
         config,
@@ -125,30 +231,63 @@ pub fn run_tests(config: Config) -> Result<()> {
         default_file_filter,
         default_per_file_config,
         (
-            status_emitter::Text::verbose(),
-            status_emitter::Gha::<true> { name },
+            text,
+            status_emitter::Gha {
+                name,
+                group_by_file: true,
+            },
         ),
-    )
+    )?
+    .into_result()
 }
 
-/// The filter used by `run_tests` to only run on `.rs` files
-/// and those specified in the command line args.
-pub fn default_file_filter(path: &Path, args: &Args) -> bool {
-    path.extension().is_some_and(|ext| ext == "rs") && default_filter_by_arg(path, args)
+/// The filter used by `run_tests` to only run on `.rs` files. The filters
+/// in the command line args are applied by `run_tests_generic` itself.
+pub fn default_file_filter(path: &Path, _args: &Args) -> bool {
+    path.extension().is_some_and(|ext| ext == "rs")
 }
 
-/// Run on all files that are matched by the filter in the argument list
+/// Run on all files that are matched by the filters in the argument list
+/// and not skipped via `--skip`. Unlike the filtering done by `run_tests_generic`,
+/// this matches against the full `path` and doesn't count the tests as filtered out.
 pub fn default_filter_by_arg(path: &Path, args: &Args) -> bool {
-    if args.filters.is_empty() {
-        return true;
-    }
-    let path = path.display().to_string();
-    args.filters.iter().any(|f| path.contains(f))
+    args.selects(&path.display().to_string(), "")
+}
+
+/// The name the command line filters match a test by:
+/// its path relative to the root directory, with `/` as separator.
+fn test_name(root_dir: &Path, path: &Path) -> String {
+    let relative = match path.strip_prefix(root_dir) {
+        Ok(relative) if !relative.as_os_str().is_empty() => relative,
+        // The root is the test file itself.
+        _ => path,
+    };
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
 }
 
 /// The default per-file config used by `run_tests`.
-pub fn default_per_file_config(config: &Config, path: &Path) -> Option<Config> {
+pub fn default_per_file_config(
+    config: &Config,
+    path: &Path,
+    comments: &Comments,
+) -> Option<Config> {
     let mut config = config.clone();
+    // Passing `--test` or `--crate-type` twice is an error, so tests whose `//@compile-flags`
+    // pick the crate type for all revisions don't get the heuristic below.
+    if comments
+        .revisioned
+        .get(&RevisionFilter::default())
+        .map_or(false, |r| {
+            r.compile_flags()
+                .any(|flag| flag == "--test" || flag.starts_with("--crate-type"))
+        })
+    {
+        return Some(config);
+    }
     // Heuristic:
     // * if the file contains `#[test]`, automatically pass `--cfg test`.
     // * if the file does not contain `fn main()` or `#[start]`, automatically pass `--crate-type=lib`.
@@ -169,13 +308,43 @@ pub fn default_per_file_config(config: &Config, path: &Path) -> Option<Config> {
     Some(config)
 }
 
+/// Run a single test file with all its revisions, without reporting anything or going through
+/// `Config::root_dir`. Failing tests are not an error, but returned in the `FileResult`.
+/// Can be called from multiple threads with the same `config`. Call `fill_host_and_target`
+/// and, if needed, `build_dependencies_and_link_them` on the `config` once beforehand,
+/// otherwise every call queries the compiler for the host and target.
+pub fn run_file(config: &Config, path: &Path) -> Result<FileResult> {
+    match Comments::parse_file(path, config)? {
+        Ok(comments) => run_file_with_comments(config, path, comments),
+        Err(errors) => Ok(FileResult {
+            revisions: vec![comment_parse_error(path, vec![], errors)],
+        }),
+    }
+}
+
+/// Like `run_file`, but with `comments` that were already parsed, e.g. via
+/// `Comments::parse_with_config` from contents kept in memory. The file still
+/// needs to exist at `path`, as that's where the compiler reads it from.
+pub fn run_file_with_comments(
+    config: &Config,
+    path: &Path,
+    comments: Comments,
+) -> Result<FileResult> {
+    parser::check_custom_commands(&config.custom_comment_parsers)?;
+    let mut config = config.clone();
+    config.fill_host_and_target()?;
+    Ok(FileResult {
+        revisions: test_file(path, &config, comments, |_| true, None, None),
+    })
+}
+
 /// Create a command for running a single file, with the settings from the `config` argument.
 /// Ignores various settings from `Config` that relate to finding test files.
 pub fn test_command(mut config: Config, path: &Path) -> Result<Command> {
     config.build_dependencies_and_link_them()?;
 
-    let comments =
-        Comments::parse_file(path)?.map_err(|errors| color_eyre::eyre::eyre!("{errors:#?}"))?;
+    let comments = Comments::parse_file(path, &config)?
+        .map_err(|errors| color_eyre::eyre::eyre!("{errors:#?}"))?;
     let mut errors = vec![];
     let result = build_command(path, &config, "", &comments, &mut errors);
     assert!(errors.is_empty(), "{errors:#?}");
@@ -184,86 +353,416 @@ pub fn test_command(mut config: Config, path: &Path) -> Result<Command> {
 
 #[allow(clippy::large_enum_variant)]
 /// The possible results a single test can have.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum TestResult {
     /// The test passed
     Ok,
-    /// The test was ignored due to a rule (`//@only-*` or `//@ignore-*`)
-    Ignored,
-    /// The test was filtered with the `file_filter` argument.
+    /// The test passed in an earlier run, and neither it nor anything it depends on changed
+    /// since, so it was not run again. See `Config::fingerprint_dir`.
+    Cached,
+    /// The test was ignored due to a rule (`//@only-*`, `//@ignore-*` or `//@ignore-test`)
+    Ignored {
+        /// Why the test was ignored.
+        reason: String,
+    },
+    /// The test was filtered out by the test name filters or `--skip` in the `Args`.
     Filtered,
     /// The test failed.
     Errored {
         /// Command that failed
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serialize::shell"))]
         command: Command,
+        /// The commands that ran successfully before `command`, e.g. the aux builds of the test
+        /// or the build of a test whose binary failed, labeled with what they did and rendered
+        /// by `shell_command`.
+        steps: Vec<(String, String)>,
         /// The errors that were encountered.
         errors: Vec<Error>,
         /// The full stderr of the test run.
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serialize::lossy"))]
         stderr: Vec<u8>,
+        /// The directory in `Config::logs_dir` the logs and artifacts of the test were
+        /// written to, see `Config::dump_artifacts`.
+        artifacts: Option<PathBuf>,
     },
 }
 
-struct TestRun {
-    result: TestResult,
-    path: PathBuf,
-    revision: String,
+/// The result of a single test, or of a single revision of a test with revisions.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TestRun {
+    /// Whether the test passed, and if not, why.
+    pub result: TestResult,
+    /// The test file.
+    pub path: PathBuf,
+    /// The revision of the test, empty if it has no revisions.
+    pub revision: String,
+    /// How long building and running the test took.
+    pub duration: Duration,
+    /// How `duration` splits into compiling, running and checking the output of the test.
+    pub timings: Timings,
+    /// The command that compiled the test, if it got that far.
+    pub command: Option<String>,
+    /// Problems that don't fail the test, but should be reported.
+    pub warnings: Errors,
+}
+
+/// Where the time of a test went, see `TestRun::timings`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Timings {
+    /// Compiling the test, its aux builds and, for `//@run-rustfix` tests, its `.fixed` file.
+    /// Also includes everything that is neither running nor checking, e.g. parsing comments.
+    pub compile: Duration,
+    /// Running the test binary of run tests.
+    pub run: Duration,
+    /// Comparing the output with the output files and the annotations.
+    pub check: Duration,
+}
+
+/// The results of all revisions of a test run via `run_file`.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FileResult {
+    /// One result per revision, or a single one if the test has no revisions.
+    pub revisions: Vec<TestRun>,
+}
+
+impl FileResult {
+    /// The revisions that failed.
+    pub fn failures(&self) -> impl Iterator<Item = &TestRun> {
+        self.revisions
+            .iter()
+            .filter(|run| matches!(run.result, TestResult::Errored { .. }))
+    }
+}
+
+/// The results of all tests run by `run_tests_generic`.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TestSuiteResult {
+    /// The results in the order the tests were scheduled in. Tests that were not run
+    /// due to `--fail-fast` are missing.
+    pub tests: Vec<TestRun>,
+    /// Output files next to the tests that none of them produce, e.g. because a test was
+    /// renamed, and that were not removed by blessing or `--prune`.
+    pub orphaned_outputs: Vec<PathBuf>,
+}
+
+impl TestSuiteResult {
+    /// The tests that failed.
+    pub fn failures(&self) -> impl Iterator<Item = &TestRun> {
+        self.tests
+            .iter()
+            .filter(|run| matches!(run.result, TestResult::Errored { .. }))
+    }
+
+    /// Turn failed tests into an error, like `run_tests` does.
+    pub fn into_result(self) -> Result<()> {
+        if self.failures().next().is_none() {
+            Ok(())
+        } else {
+            Err(eyre!("tests failed"))
+        }
+    }
 }
 
 /// A version of `run_tests` that allows more fine-grained control over running tests.
+/// The results are reported to the `status_emitter` and returned, failing tests are not
+/// an error. Use `TestSuiteResult::into_result` to get the behaviour of `run_tests`.
 pub fn run_tests_generic(
     mut config: Config,
     args: Args,
     file_filter: impl Fn(&Path, &Args) -> bool + Sync,
-    per_file_config: impl Fn(&Config, &Path) -> Option<Config> + Sync,
+    per_file_config: impl Fn(&Config, &Path, &Comments) -> Option<Config> + Sync,
     mut status_emitter: impl StatusEmitter + Send,
-) -> Result<()> {
-    config.fill_host_and_target()?;
+) -> Result<TestSuiteResult> {
+    if args.bless {
+        config.output_conflict_handling = OutputConflictHandling::Bless;
+    }
+    if args.bless_annotations {
+        config.output_conflict_handling = OutputConflictHandling::Bless;
+        config.bless_annotations = true;
+        config.insert_annotations = true;
+    }
+    if let Some(threads) = args.threads {
+        config.num_test_threads = threads;
+    }
+    if let Some(target) = &args.target {
+        config.target = Some(target.clone());
+    }
+    config
+        .dependency_builder_features
+        .extend(args.dependency_features.iter().cloned());
+    if args.dependency_no_default_features {
+        config.dependency_builder_no_default_features = true;
+    }
+    if args.fail_fast
+        && matches!(
+            config.output_conflict_handling,
+            OutputConflictHandling::Bless
+        )
+    {
+        bail!("`--fail-fast` can't be combined with blessing, as only some of the tests would get blessed");
+    }
 
-    config.build_dependencies_and_link_them()?;
+    config.fill_host_and_target()?;
+    parser::check_custom_commands(&config.custom_comment_parsers)?;
+    artifacts::clean(&config);
+    lazy_static::initialize(&CONDITION_ENV);
 
     let mut results = vec![];
 
+    let mut files = vec![];
+    let mut outputs = vec![];
+    let mut todo = VecDeque::new();
+    todo.push_back(config.root_dir.clone());
+    while let Some(path) = todo.pop_front() {
+        if path.is_dir() {
+            if path.file_name().unwrap() == "auxiliary" {
+                continue;
+            }
+            // Enqueue everything inside this directory.
+            // We want it sorted, to have some control over scheduling of slow tests.
+            let mut entries = std::fs::read_dir(path)
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            entries.sort_by_key(|e| e.file_name());
+            for entry in entries {
+                todo.push_back(entry.path());
+            }
+        } else if file_filter(&path, &args) {
+            files.push(path);
+        } else if orphans::is_output(&path) {
+            outputs.push(path);
+        }
+    }
+    // Start the tests in a stable order, unless asked to shuffle them.
+    // The revisions of a test are run in the order they are declared in.
+    files.sort();
+    if let Some(seed) = args.shuffle_seed {
+        shuffle(&mut files, seed);
+        status_emitter.shuffle_seed(seed);
+    }
+    status_emitter.report_slowest(args.report_slowest);
+    // Tests with `//@depends-on` must be submitted after their dependencies.
+    let schedule = Schedule::new(files, &config)?;
+    // With `--rerun-failed`, the last failures and everything they depend on.
+    let rerun = if args.rerun_failed {
+        match last_failures::read(&config.last_failures) {
+            Some(failures) => {
+                let dependencies =
+                    schedule.transitive_dependencies(failures.iter().map(|(path, _)| &**path));
+                Some((failures, dependencies))
+            }
+            None => {
+                status_emitter.no_failures_recorded();
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let root_dir = config.root_dir.clone();
+    let in_shard = |path: &Path, revision: &str| {
+        args.shard.map_or(true, |(index, count)| {
+            shard_of(&test_name(&root_dir, path), revision, count) == index
+        })
+    };
+    // Tests of other shards are still run if a test of this shard depends on them.
+    let shard_dependencies = if args.shard.is_some() {
+        schedule.transitive_dependencies(
+            schedule
+                .files
+                .iter()
+                .filter(|path| {
+                    schedule
+                        .revisions(path)
+                        .iter()
+                        .any(|revision| in_shard(path, revision))
+                })
+                .map(|path| &**path),
+        )
+    } else {
+        HashSet::new()
+    };
+    let select = |path: &Path, revision: &str| {
+        args.selects(&test_name(&root_dir, path), revision)
+            && rerun.as_ref().map_or(true, |(failures, dependencies)| {
+                failures.contains(&(path.to_owned(), revision.to_owned()))
+                    || dependencies.contains(&schedule::normalize(path))
+            })
+            && (in_shard(path, revision) || shard_dependencies.contains(&schedule::normalize(path)))
+    };
+    if args.list {
+        list_tests(
+            &config,
+            &args,
+            &schedule,
+            &per_file_config,
+            &select,
+            &mut std::io::stdout().lock(),
+        )?;
+        return Ok(TestSuiteResult {
+            tests: vec![],
+            orphaned_outputs: vec![],
+        });
+    }
+    // Found before running the tests, which consumes their comments.
+    let prune = args.prune
+        || matches!(
+            config.output_conflict_handling,
+            OutputConflictHandling::Bless
+        );
+    let orphans = if prune
+        || !matches!(
+            config.output_conflict_handling,
+            OutputConflictHandling::Ignore
+        ) {
+        orphans::find(&schedule, &outputs, select)
+    } else {
+        vec![]
+    };
+    // Only build the dependencies now, so listing the tests doesn't compile anything.
+    config.build_dependencies_and_link_them()?;
+    let fingerprints = Fingerprints::new(&config, args.force_rerun);
+    // With `--fail-fast`, set as soon as a failure gets collected, so no further tests get started.
+    let failed = AtomicBool::new(false);
+    let not_run = AtomicUsize::new(0);
+    // The filters that replaced anything in any test, see `Config::report_unused_normalizations`.
+    let used_filters = Mutex::new(HashSet::new());
+
     run_and_collect(
         config.num_test_threads.get(),
         |submit| {
-            let mut todo = VecDeque::new();
-            todo.push_back(config.root_dir.clone());
-            while let Some(path) = todo.pop_front() {
-                if path.is_dir() {
-                    if path.file_name().unwrap() == "auxiliary" {
-                        continue;
-                    }
-                    // Enqueue everything inside this directory.
-                    // We want it sorted, to have some control over scheduling of slow tests.
-                    let mut entries = std::fs::read_dir(path)
-                        .unwrap()
-                        .collect::<Result<Vec<_>, _>>()
-                        .unwrap();
-                    entries.sort_by_key(|e| e.file_name());
-                    for entry in entries {
-                        todo.push_back(entry.path());
-                    }
-                } else if file_filter(&path, &args) {
-                    // Forward .rs files to the test workers.
-                    submit.send(path).unwrap();
-                }
+            // Forward .rs files to the test workers.
+            for path in &schedule.files {
+                submit.send(path.clone()).unwrap();
             }
         },
         |receive, finished_files_sender| -> Result<()> {
             for path in receive {
+                let comments = schedule.take_comments(&path);
+                let selected = |revision: &str| select(&path, revision);
+                // Broken comments get reported unless the file itself is filtered out.
+                let revisions = match &comments {
+                    Ok(comments) => comments.revisions.clone(),
+                    Err(_) => None,
+                }
+                .unwrap_or_else(|| vec![String::new()]);
+                if !revisions.iter().any(|revision| selected(revision)) {
+                    let runs: Vec<_> = revisions
+                        .into_iter()
+                        .map(|revision| TestRun {
+                            result: TestResult::Filtered,
+                            path: path.clone(),
+                            revision,
+                            warnings: vec![],
+                            duration: Duration::ZERO,
+                            timings: Timings::default(),
+                            command: None,
+                        })
+                        .collect();
+                    schedule.finish(&path, &runs);
+                    for run in runs {
+                        finished_files_sender.send(TestEvent::Finished(run))?;
+                    }
+                    continue;
+                }
+                if args.fail_fast && failed.load(Ordering::Relaxed) {
+                    schedule.not_run(&path);
+                    not_run.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+                let errors = schedule.wait_for_dependencies(&path);
+                if !errors.is_empty() {
+                    let run = TestRun {
+                        result: TestResult::Errored {
+                            command: Command::new("depends-on"),
+                            steps: vec![],
+                            errors,
+                            stderr: vec![],
+                            artifacts: None,
+                        },
+                        path,
+                        revision: String::new(),
+                        warnings: vec![],
+                        duration: Duration::ZERO,
+                        timings: Timings::default(),
+                        command: None,
+                    };
+                    schedule.finish(&run.path, std::slice::from_ref(&run));
+                    finished_files_sender.send(TestEvent::Finished(run))?;
+                    continue;
+                }
+                let comments = match comments {
+                    Ok(comments) => comments,
+                    Err((stderr, errors)) => {
+                        let run = comment_parse_error(&path, stderr, errors);
+                        schedule.finish(&run.path, std::slice::from_ref(&run));
+                        finished_files_sender.send(TestEvent::Finished(run))?;
+                        continue;
+                    }
+                };
                 let maybe_config;
-                let config = match per_file_config(&config, &path) {
+                let config = match per_file_config(&config, &path, &comments) {
                     None => &config,
                     Some(config) => {
                         maybe_config = config;
                         &maybe_config
                     }
                 };
-                let result = match std::panic::catch_unwind(|| parse_and_test_file(&path, config)) {
+                let dependency_envs = schedule.dependency_envs(&path);
+                // The results of tests involved in `//@depends-on` also depend on the artifacts
+                // of other tests, which the fingerprints don't cover.
+                let fingerprints = fingerprints
+                    .as_ref()
+                    .filter(|_| dependency_envs.is_empty() && !schedule.is_dependency(&path));
+                let maybe_dependency_config;
+                let config = if dependency_envs.is_empty() && !schedule.is_dependency(&path) {
+                    config
+                } else {
+                    let mut config = config.clone();
+                    if schedule.is_dependency(&path) {
+                        // Give the artifacts their own directory, so dependents only see them.
+                        config.out_dir = schedule.artifact_dir(&path);
+                    }
+                    if !revisions.iter().any(|revision| in_shard(&path, revision)) {
+                        // Only run for a test of this shard, so leave its output files to its own shard.
+                        config.output_conflict_handling = OutputConflictHandling::Ignore;
+                    }
+                    for (var, dir) in dependency_envs {
+                        config.program.args.push("-L".into());
+                        config.program.args.push(dir.clone().into());
+                        config.program.envs.push((var, Some(dir.into())));
+                    }
+                    maybe_dependency_config = config;
+                    &maybe_dependency_config
+                };
+                for revision in revisions.iter().filter(|revision| selected(revision)) {
+                    finished_files_sender.send(TestEvent::Started {
+                        path: path.clone(),
+                        revision: revision.clone(),
+                    })?;
+                }
+                let result = match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                    test_file(
+                        &path,
+                        config,
+                        comments,
+                        selected,
+                        fingerprints,
+                        Some(&used_filters),
+                    )
+                })) {
                     Ok(res) => res,
                     Err(err) => {
-                        finished_files_sender.send(TestRun {
+                        let run = TestRun {
                             result: TestResult::Errored {
                                 command: Command::new("<unknown>"),
+                                steps: vec![],
                                 errors: vec![Error::Bug(
                                     *Box::<dyn std::any::Any + Send + 'static>::downcast::<String>(
                                         err,
@@ -271,59 +770,276 @@ pub fn run_tests_generic(
                                     .unwrap(),
                                 )],
                                 stderr: vec![],
+                                artifacts: None,
                             },
                             path,
                             revision: String::new(),
-                        })?;
+                            warnings: vec![],
+                            duration: Duration::ZERO,
+                            timings: Timings::default(),
+                            command: None,
+                        };
+                        schedule.finish(&run.path, std::slice::from_ref(&run));
+                        finished_files_sender.send(TestEvent::Finished(run))?;
                         continue;
                     }
                 };
+                schedule.finish(&path, &result);
                 for result in result {
-                    finished_files_sender.send(result)?;
+                    finished_files_sender.send(TestEvent::Finished(result))?;
                 }
             }
             Ok(())
         },
         |finished_files_recv| {
-            for run in finished_files_recv {
+            for event in finished_files_recv {
+                let run = match event {
+                    TestEvent::Started { path, revision } => {
+                        status_emitter.test_started(&path, &revision);
+                        continue;
+                    }
+                    TestEvent::Finished(run) => run,
+                };
+                if let TestResult::Errored { .. } = run.result {
+                    failed.store(true, Ordering::Relaxed);
+                }
                 status_emitter.test_result(&run.path, &run.revision, &run.result);
+                if !matches!(
+                    run.result,
+                    TestResult::Filtered | TestResult::Ignored { .. }
+                ) {
+                    status_emitter.test_duration(
+                        &run.path,
+                        &run.revision,
+                        run.duration,
+                        run.timings,
+                    );
+                }
+                if !run.warnings.is_empty() {
+                    status_emitter.test_warnings(&run.path, &run.revision, &run.warnings);
+                }
 
                 results.push(run);
             }
         },
     )?;
 
+    // Report the tests in the order they were scheduled in, no matter in which order they finished.
+    // The runs of a file come from the same worker, so the order of its revisions is kept.
+    let order: HashMap<&Path, usize> = schedule
+        .files
+        .iter()
+        .enumerate()
+        .map(|(i, path)| (path.as_path(), i))
+        .collect();
+    results.sort_by_key(|run| order.get(run.path.as_path()).copied());
+
     let mut failures = vec![];
     let mut succeeded = 0;
     let mut ignored = 0;
     let mut filtered = 0;
+    let mut other_shards = 0;
 
-    for run in results {
-        match run.result {
-            TestResult::Ok => succeeded += 1,
-            TestResult::Ignored => ignored += 1,
+    for run in &results {
+        match &run.result {
+            TestResult::Ok | TestResult::Cached => succeeded += 1,
+            TestResult::Ignored { .. } => ignored += 1,
+            TestResult::Filtered if !in_shard(&run.path, &run.revision) => other_shards += 1,
             TestResult::Filtered => filtered += 1,
             TestResult::Errored {
                 command,
+                steps,
                 errors,
                 stderr,
-            } => failures.push((run.path, command, run.revision, errors, stderr)),
+                artifacts,
+            } => failures.push((run, command, steps, artifacts, errors, stderr)),
         }
     }
 
+    if let Some(shard) = args.shard {
+        status_emitter.shard(shard, other_shards);
+    }
+    let not_run = not_run.into_inner();
+    if not_run > 0 {
+        status_emitter.tests_not_run(not_run);
+    }
+    // Failed tests might not have gotten to apply the filters. Tests that weren't run (like
+    // cached or filtered ones) don't count.
+    if config.report_unused_normalizations
+        && not_run == 0
+        && results
+            .iter()
+            .any(|run| matches!(run.result, TestResult::Ok))
+        && !results
+            .iter()
+            .any(|run| matches!(run.result, TestResult::Errored { .. }))
+    {
+        let used_filters = used_filters.into_inner().unwrap();
+        let mut unused: Vec<_> = config
+            .stderr_filters
+            .iter()
+            .chain(&config.stdout_filters)
+            .map(|(rule, replacement)| (rule, *replacement))
+            .chain(
+                config
+                    .named_stderr_filters
+                    .iter()
+                    .map(|(_, rule, replacement)| (rule, *replacement)),
+            )
+            .filter(|(_, replacement)| !config::is_platform_filter(replacement))
+            .map(|(rule, replacement)| usage::describe(rule, replacement))
+            .filter(|filter| !used_filters.contains(filter))
+            .collect();
+        unused.sort();
+        unused.dedup();
+        let warnings: Vec<_> = unused
+            .into_iter()
+            .map(|rule| Error::UnusedNormalization { rule, line: None })
+            .collect();
+        if !warnings.is_empty() {
+            status_emitter.test_warnings(&config.root_dir, "", &warnings);
+        }
+    }
+    let orphaned_outputs = if prune {
+        let (removed, kept): (Vec<_>, Vec<_>) = orphans
+            .into_iter()
+            .partition(|path| std::fs::remove_file(path).is_ok());
+        if !removed.is_empty() {
+            status_emitter.orphaned_outputs(&removed, true);
+        }
+        kept
+    } else {
+        orphans
+    };
+    if !orphaned_outputs.is_empty() {
+        status_emitter.orphaned_outputs(&orphaned_outputs, false);
+    }
     let mut failure_emitter = status_emitter.finalize(failures.len(), succeeded, ignored, filtered);
-    for (path, command, revision, errors, stderr) in &failures {
-        let _guard = status_emitter.failed_test(revision, path, command, stderr);
-        failure_emitter.test_failure(path, revision, errors);
+    for (run, command, steps, artifacts, errors, stderr) in failures {
+        let _guard = status_emitter.failed_test(
+            &run.revision,
+            &run.path,
+            command,
+            steps,
+            artifacts.as_deref(),
+            stderr,
+        );
+        failure_emitter.test_failure(&run.path, &run.revision, errors);
     }
+    drop(failure_emitter);
 
-    if failures.is_empty() {
-        Ok(())
-    } else {
-        Err(eyre!("tests failed"))
+    last_failures::write(&config.last_failures, &results)?;
+
+    Ok(TestSuiteResult {
+        tests: results,
+        orphaned_outputs,
+    })
+}
+
+/// What the test workers report to the thread collecting the results.
+#[allow(clippy::large_enum_variant)]
+enum TestEvent {
+    /// A revision of a test is about to be run.
+    Started { path: PathBuf, revision: String },
+    /// A revision of a test finished, or was not run at all.
+    Finished(TestRun),
+}
+
+/// Print the tests that would be run to `out` for `--list`, in the order they would be run in.
+fn list_tests(
+    config: &Config,
+    args: &Args,
+    schedule: &Schedule,
+    per_file_config: &impl Fn(&Config, &Path, &Comments) -> Option<Config>,
+    selected: &impl Fn(&Path, &str) -> bool,
+    out: &mut impl std::io::Write,
+) -> Result<()> {
+    for path in &schedule.files {
+        let name = test_name(&config.root_dir, path);
+        // Tests with broken comments are listed, as they would be run and fail.
+        let comments = schedule.take_comments(path).ok();
+        let file_config = comments
+            .as_ref()
+            .and_then(|comments| per_file_config(config, path, comments));
+        let file_config = file_config.as_ref().unwrap_or(config);
+        let revisions = comments
+            .as_ref()
+            .and_then(|comments| comments.revisions.clone())
+            .unwrap_or_else(|| vec![String::new()]);
+        for revision in revisions {
+            if !selected(path, &revision) {
+                continue;
+            }
+            let ignored = comments.as_ref().and_then(|comments| {
+                let ignore_test = comments
+                    .for_revision(&revision)
+                    .find_map(|r| r.ignore_test.clone());
+                ignore_test.map(|(reason, _)| reason).or_else(|| {
+                    test_file_conditions(comments, file_config, &revision)
+                        .map(|skip| skip.reason(file_config))
+                })
+            });
+            if ignored.is_some() && !args.include_ignored {
+                continue;
+            }
+            let full_name = if revision.is_empty() {
+                name.clone()
+            } else {
+                format!("{name}#{revision}")
+            };
+            match (args.format, ignored) {
+                (Format::Terse, None) => writeln!(out, "{full_name}")?,
+                (Format::Terse, Some(reason)) => writeln!(out, "{full_name} (ignored: {reason})")?,
+                (Format::Json, ignored) => writeln!(
+                    out,
+                    "{}",
+                    serde_json::json!({
+                        "name": full_name,
+                        "path": path,
+                        "revision": revision,
+                        "ignored": ignored,
+                        "filters": comments
+                            .as_ref()
+                            .map(|comments| active_filter_names(file_config, comments, &revision)),
+                    })
+                )?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Shuffle the `items` in an order that only depends on the `seed`, so the order can be reproduced.
+fn shuffle<T>(items: &mut [T], seed: u64) {
+    // SplitMix64, which is good enough for this and the same everywhere.
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let z = state;
+        let z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        let z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+    // Fisher-Yates
+    for i in (1..items.len()).rev() {
+        let j = (next() % (i as u64 + 1)) as usize;
+        items.swap(i, j);
     }
 }
 
+/// Which of the `count` shards (counting from 1) the `revision` of the test `name` belongs to.
+fn shard_of(name: &str, revision: &str, count: usize) -> usize {
+    // FNV-1a, as the partition must be the same on all machines and with all Rust versions.
+    let hash = name
+        .bytes()
+        .chain(Some(b'#').filter(|_| !revision.is_empty()))
+        .chain(revision.bytes())
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    (hash % count as u64) as usize + 1
+}
+
 /// A generic multithreaded runner that has a thread for producing work,
 /// a thread for collecting work, and `num_threads` threads for doing the work.
 pub fn run_and_collect<SUBMISSION: Send, RESULT: Send>(
@@ -361,57 +1077,300 @@ pub fn run_and_collect<SUBMISSION: Send, RESULT: Send>(
     })
 }
 
+#[cfg(test)]
 fn parse_and_test_file(path: &Path, config: &Config) -> Vec<TestRun> {
-    let comments = match parse_comments_in_file(path) {
-        Ok(comments) => comments,
-        Err((stderr, errors)) => {
-            return vec![TestRun {
-                result: TestResult::Errored {
-                    command: Command::new("parse comments"),
-                    errors,
-                    stderr,
+    match parse_comments_in_file(path, config) {
+        Ok(comments) => test_file(path, config, comments, |_| true, None, None),
+        Err((stderr, errors)) => vec![comment_parse_error(path, stderr, errors)],
+    }
+}
+
+/// The result of a test whose comments could not be parsed.
+fn comment_parse_error(path: &Path, stderr: Vec<u8>, errors: Vec<Error>) -> TestRun {
+    TestRun {
+        result: TestResult::Errored {
+            command: Command::new("parse comments"),
+            steps: vec![],
+            errors,
+            stderr,
+            artifacts: None,
+        },
+        path: path.into(),
+        revision: "".into(),
+        warnings: vec![],
+        duration: Duration::ZERO,
+        timings: Timings::default(),
+        command: None,
+    }
+}
+
+/// Runs all revisions of the test that are `selected`, the others are reported as filtered out.
+/// Revisions that passed with the same fingerprint before are reported as cached instead.
+fn test_file(
+    path: &Path,
+    config: &Config,
+    mut comments: Comments,
+    selected: impl Fn(&str) -> bool,
+    fingerprints: Option<&Fingerprints>,
+    used_filters: Option<&Mutex<HashSet<String>>>,
+) -> Vec<TestRun> {
+    let mut deprecations = std::mem::take(&mut comments.deprecations);
+    let mut migrated = vec![];
+    if !comments.legacy_annotations.is_empty()
+        && config.bless_annotations
+        && matches!(
+            config.output_conflict_handling,
+            OutputConflictHandling::Bless
+        )
+    {
+        match migrate_legacy_annotations(path) {
+            Ok(rewritten) => {
+                migrated = rewritten;
+                comments.legacy_annotations.clear();
+            }
+            Err(err) => migrated.push(err),
+        }
+    }
+    if !config.allow_legacy_directives {
+        deprecations.append(&mut comments.legacy_annotations);
+    }
+    if config.deny_deprecated && !deprecations.is_empty() {
+        return vec![TestRun {
+            result: TestResult::Errored {
+                command: Command::new("parse comments"),
+                steps: vec![],
+                errors: deprecations,
+                stderr: vec![],
+                artifacts: None,
+            },
+            path: path.into(),
+            revision: "".into(),
+            warnings: vec![],
+            duration: Duration::ZERO,
+            timings: Timings::default(),
+            command: None,
+        }];
+    }
+    let mut unknown_triples = check_triple_conditions(&comments, config);
+    if config.check_conditions && !unknown_triples.is_empty() {
+        return vec![TestRun {
+            result: TestResult::Errored {
+                command: Command::new("parse comments"),
+                steps: vec![],
+                errors: unknown_triples,
+                stderr: vec![],
+                artifacts: None,
+            },
+            path: path.into(),
+            revision: "".into(),
+            warnings: deprecations,
+            duration: Duration::ZERO,
+            timings: Timings::default(),
+            command: None,
+        }];
+    }
+    deprecations.append(&mut unknown_triples);
+    deprecations.append(&mut migrated);
+    // What replaced anything in the output of any revision.
+    let mut usage = Usage::default();
+    // Run the test for all revisions
+    let mut test_revision = |revision: String| {
+        if !selected(&revision) {
+            return TestRun {
+                result: TestResult::Filtered,
+                path: path.into(),
+                revision,
+                warnings: vec![],
+                duration: Duration::ZERO,
+                timings: Timings::default(),
+                command: None,
+            };
+        }
+        let ignore_test = comments
+            .for_revision(&revision)
+            .find_map(|r| r.ignore_test.clone());
+        if let Some((reason, _)) = ignore_test {
+            return TestRun {
+                result: TestResult::Ignored { reason },
+                path: path.into(),
+                revision,
+                warnings: vec![],
+                duration: Duration::ZERO,
+                timings: Timings::default(),
+                command: None,
+            };
+        }
+        if config.channel.is_none() {
+            let channel_condition = comments.for_revision(&revision).find_map(|r| {
+                let ignore = r.ignore.iter().map(|(c, _)| ("ignore", c));
+                let only = r.only.iter().map(|(c, _)| ("only", c));
+                ignore
+                    .chain(only)
+                    .find(|(_, c)| matches!(c, Condition::Channel(_)))
+                    .map(|(kind, c)| format!("{kind}-{c}"))
+            });
+            if let Some(condition) = channel_condition {
+                return TestRun {
+                    result: TestResult::Errored {
+                        command: Command::new("check conditions"),
+                        steps: vec![],
+                        errors: vec![Error::UnknownChannel { condition }],
+                        stderr: vec![],
+                        artifacts: None,
+                    },
+                    path: path.into(),
+                    revision,
+                    warnings: vec![],
+                    duration: Duration::ZERO,
+                    timings: Timings::default(),
+                    command: None,
+                };
+            }
+        }
+        // Ignore file if only/ignore rules do (not) apply
+        if let Some(skip) = test_file_conditions(&comments, config, &revision) {
+            return TestRun {
+                result: TestResult::Ignored {
+                    reason: skip.reason(config),
                 },
                 path: path.into(),
-                revision: "".into(),
-            }]
+                revision,
+                warnings: vec![],
+                duration: Duration::ZERO,
+                timings: Timings::default(),
+                command: None,
+            };
+        }
+        let fingerprint = fingerprints.map(|fingerprints| {
+            let fingerprint = fingerprint::fingerprint(path, config, &revision, &comments);
+            (fingerprints, fingerprint)
+        });
+        if let Some((fingerprints, fingerprint)) = &fingerprint {
+            if fingerprints.unchanged(path, &revision, fingerprint) {
+                return TestRun {
+                    result: TestResult::Cached,
+                    path: path.into(),
+                    revision,
+                    warnings: vec![],
+                    duration: Duration::ZERO,
+                    timings: Timings::default(),
+                    command: None,
+                };
+            }
+        }
+        let mut steps = vec![];
+        let mut timings = Timings::default();
+        let mut artifacts = Artifacts::new(config);
+        let (command, errors, stderr) = run_test(
+            path,
+            config,
+            &revision,
+            &comments,
+            &mut steps,
+            &mut timings,
+            &mut artifacts,
+            &mut usage,
+        );
+        let command_line = shell_command(&command);
+        let (warnings, errors): (Vec<_>, Vec<_>) = errors.into_iter().partition(|error| {
+            matches!(
+                error,
+                Error::AnnotationRewritten { .. }
+                    | Error::AnnotationsInserted { .. }
+                    | Error::AnnotationRemoved { .. }
+            )
+        });
+        if let Some((fingerprints, fingerprint)) = &fingerprint {
+            // Tests that rewrote their annotations changed since the fingerprint was taken.
+            let passed = errors.is_empty() && warnings.is_empty();
+            fingerprints.record(path, &revision, fingerprint, passed);
+        }
+        let mut commands = String::new();
+        for (label, step) in steps
+            .iter()
+            .chain([&("command".into(), command_line.clone())])
+        {
+            commands.push_str(&format!("# {label}\n{step}\n"));
+        }
+        artifacts.add("commands.sh", commands.as_bytes());
+        let artifacts = artifacts.write(config, path, &revision, !errors.is_empty());
+        let result = if errors.is_empty() {
+            TestResult::Ok
+        } else {
+            TestResult::Errored {
+                command,
+                steps,
+                errors,
+                stderr,
+                artifacts,
+            }
+        };
+        TestRun {
+            result,
+            revision,
+            path: path.into(),
+            warnings,
+            duration: Duration::ZERO,
+            timings,
+            command: Some(command_line),
         }
     };
-    // Run the test for all revisions
-    comments
+    let mut runs: Vec<_> = comments
         .revisions
         .clone()
         .unwrap_or_else(|| vec![String::new()])
         .into_iter()
         .map(|revision| {
-            // Ignore file if only/ignore rules do (not) apply
-            if !test_file_conditions(&comments, config, &revision) {
-                return TestRun {
-                    result: TestResult::Ignored,
-                    path: path.into(),
-                    revision,
-                };
-            }
-            let (command, errors, stderr) = run_test(path, config, &revision, &comments);
-            let result = if errors.is_empty() {
-                TestResult::Ok
-            } else {
-                TestResult::Errored {
-                    command,
-                    errors,
-                    stderr,
+            let start = Instant::now();
+            let mut run = test_revision(revision);
+            run.duration = start.elapsed();
+            let timings = &mut run.timings;
+            timings.compile = run.duration.saturating_sub(timings.run + timings.check);
+            if let Some(limit) = config.time_limit_warning {
+                if run.duration > limit {
+                    run.warnings.push(Error::TimeLimitExceeded {
+                        duration: run.duration,
+                        limit,
+                    });
                 }
-            };
-            TestRun {
-                result,
-                revision,
-                path: path.into(),
             }
+            run
         })
-        .collect()
+        .collect();
+    if config.report_unused_normalizations {
+        if let Some(used_filters) = used_filters {
+            used_filters.lock().unwrap().extend(usage.filters.drain());
+        }
+        // Failed revisions might not have gotten to apply all normalizations.
+        if !runs
+            .iter()
+            .any(|run| matches!(run.result, TestResult::Errored { .. }))
+        {
+            let passed = runs
+                .iter()
+                .filter(|run| matches!(run.result, TestResult::Ok))
+                .map(|run| run.revision.as_str());
+            deprecations.extend(usage::unused_normalizations(&comments, passed, &usage));
+        }
+    }
+    // The comments are shared by all revisions, so only report their warnings once,
+    // on a revision that wasn't filtered out.
+    let first = runs
+        .iter()
+        .position(|run| !matches!(run.result, TestResult::Filtered))
+        .unwrap_or(0);
+    if let Some(run) = runs.get_mut(first) {
+        deprecations.append(&mut run.warnings);
+        run.warnings = deprecations;
+    }
+    runs
 }
 
-fn parse_comments_in_file(path: &Path) -> Result<Comments, (Vec<u8>, Vec<Error>)> {
-    match Comments::parse_file(path) {
+pub(crate) fn parse_comments_in_file(
+    path: &Path,
+    config: &Config,
+) -> Result<Comments, (Vec<u8>, Vec<Error>)> {
+    match Comments::parse_file(path, config) {
         Ok(Ok(comments)) => Ok(comments),
         Ok(Err(errors)) => Err((vec![], errors)),
         Err(err) => Err((format!("{err:?}").into(), vec![])),
@@ -425,31 +1384,84 @@ fn build_command(
     comments: &Comments,
     errors: &mut Vec<Error>,
 ) -> Command {
-    let mut cmd = config.program.build(&config.out_dir);
-    cmd.arg(path);
-    if !revision.is_empty() {
-        cmd.arg(format!("--cfg={revision}"));
+    let crate_type = comments.crate_type(errors, revision);
+    // The crate type of the test replaces the one guessed by `default_per_file_config`.
+    let mut program = Cow::Borrowed(&config.program);
+    if crate_type.is_some() {
+        strip_crate_type_flags(&mut program.to_mut().args);
+    }
+    let out_dir = if config.rustdoc {
+        // The documentation is never looked at, but revisions and tests of the same name
+        // must not write it into the same directory in parallel.
+        let mut doc_dir = config.out_dir.join("rustdoc");
+        doc_dir.push(path.strip_prefix(&config.root_dir).unwrap_or(path));
+        if !revision.is_empty() {
+            doc_dir.push(revision);
+        }
+        Cow::Owned(doc_dir)
+    } else {
+        Cow::Borrowed(&config.out_dir)
+    };
+    let mut cmd = program.build(&out_dir);
+    let placeholders = Placeholders {
+        config,
+        out_dir: &out_dir,
+        file: path,
+    };
+    cmd.arg(path);
+    if let Some(target) = config.cross_target() {
+        cmd.arg("--target").arg(target);
+    }
+    if !revision.is_empty() {
+        cmd.arg(format!("--cfg={revision}"));
     }
     for arg in comments
         .for_revision(revision)
         .flat_map(|r| r.compile_flags.iter())
     {
-        cmd.arg(arg);
+        cmd.arg(&*placeholders.replace(arg, errors));
     }
     let edition = comments.edition(errors, revision, config);
     if let Some((edition, _)) = edition {
         cmd.arg("--edition").arg(edition);
     }
-    cmd.envs(
-        comments
-            .for_revision(revision)
-            .flat_map(|r| r.env_vars.iter())
-            .map(|(k, v)| (k, v)),
-    );
+    if let Some(crate_type) = crate_type {
+        cmd.arg("--crate-type").arg(crate_type);
+    }
+    if let Some(crate_name) = comments.crate_name(errors, revision) {
+        cmd.arg("--crate-name").arg(crate_name);
+    }
+    for (k, v) in comments
+        .for_revision(revision)
+        .flat_map(|r| r.env_vars.iter())
+    {
+        cmd.env(k, &*placeholders.replace(v, errors));
+    }
 
     cmd
 }
 
+/// Remove `--crate-type` and `--test` flags, e.g. the ones added by `default_per_file_config`.
+fn strip_crate_type_flags(args: &mut Vec<OsString>) {
+    let mut prev_was_crate_type = false;
+    args.retain(|arg| {
+        if prev_was_crate_type {
+            prev_was_crate_type = false;
+            return false;
+        }
+        if arg == "--test" {
+            false
+        } else if arg == "--crate-type" {
+            prev_was_crate_type = true;
+            false
+        } else if let Some(arg) = arg.to_str() {
+            !arg.starts_with("--crate-type=")
+        } else {
+            true
+        }
+    });
+}
+
 fn build_aux(
     aux_file: &Path,
     path: &Path,
@@ -459,8 +1471,9 @@ fn build_aux(
     kind: &str,
     aux: &Path,
     extra_args: &mut Vec<String>,
+    steps: &mut Vec<(String, String)>,
 ) -> std::result::Result<(), (Command, Vec<Error>, Vec<u8>)> {
-    let comments = match parse_comments_in_file(aux_file) {
+    let comments = match parse_comments_in_file(aux_file, config) {
         Ok(comments) => comments,
         Err((msg, mut errors)) => {
             return Err((
@@ -474,92 +1487,184 @@ fn build_aux(
 
     let mut config = config.clone();
 
+    // rustdoc can't build the crates that the tests need, so aux builds use rustc instead.
+    if config.rustdoc {
+        config.program.program = CommandBuilder::rustc().program;
+        config.rustdoc = false;
+    }
+
     // Strip any `crate-type` flags from the args, as we need to set our own,
     // and they may conflict (e.g. `lib` vs `proc-macro`);
-    let mut prev_was_crate_type = false;
-    config.program.args.retain(|arg| {
-        if prev_was_crate_type {
-            prev_was_crate_type = false;
-            return false;
-        }
-        if arg == "--test" {
-            false
-        } else if arg == "--crate-type" {
-            prev_was_crate_type = true;
-            false
-        } else if let Some(arg) = arg.to_str() {
-            !arg.starts_with("--crate-type=")
-        } else {
-            true
-        }
-    });
+    strip_crate_type_flags(&mut config.program.args);
 
-    // Put aux builds into a separate directory per test so that
+    // Without a cache, put aux builds into a separate directory per test so that
     // tests running in parallel but building the same aux build don't conflict.
     // FIXME: put aux builds into the regular build queue.
-    config.out_dir = config.out_dir.join(path.with_extension(""));
+    // Only use the relative parts of the path, so absolute paths don't end up
+    // writing the aux build next to the test file.
+    let relative: PathBuf = path
+        .with_extension("")
+        .components()
+        .filter(|c| matches!(c, std::path::Component::Normal(_)))
+        .collect();
+    config.out_dir = config.out_dir.join(relative);
+    // Proc macros run in the compiler, so they need to be built for the host.
+    if kind == "proc-macro" {
+        config.target = config.host.clone();
+    }
 
-    let mut errors = vec![];
+    let current_extra_args = build_aux_files(
+        aux_file,
+        aux_file.parent().unwrap(),
+        &comments,
+        "",
+        &config,
+        steps,
+    )?;
+    // Make sure our dependents also see our dependencies.
+    extra_args.extend(current_extra_args.iter().cloned());
 
-    let mut aux_cmd = build_command(aux_file, &config, revision, &comments, &mut errors);
+    let aux_command = |out_dir: PathBuf, errors: &mut Vec<Error>| {
+        let config = Config {
+            out_dir,
+            ..config.clone()
+        };
+        let mut aux_cmd = build_command(aux_file, &config, revision, &comments, errors);
+        // Make sure we see our dependencies
+        aux_cmd.args(current_extra_args.iter());
+        if comments.crate_type(errors, "").is_none() {
+            aux_cmd.arg("--crate-type").arg(kind);
+        }
+        aux_cmd.arg("--emit=link");
+        aux_cmd
+    };
 
-    if !errors.is_empty() {
-        return Err((aux_cmd, errors, vec![]));
-    }
+    // Aux builds are cached by their contents and the command building them, without the
+    // out dir, so they are shared between tests and runs. The aux file can load other files
+    // of its directory, e.g. with `mod foo;`, so all of them are part of the key.
+    let cache_entry = config.cache_dir.as_ref().map(|cache_dir| {
+        let mut key = cache::Key::new(&config.program.program);
+        key.add(std::fs::read(aux_file).unwrap_or_default());
+        key.add_dir(aux_file.parent().unwrap(), &[]);
+        let cmd = aux_command(PathBuf::new(), &mut vec![]);
+        key.add_command(&cmd);
+        key.add_artifacts(&cmd);
+        (cache_dir, key.entry(cache_dir, "aux"))
+    });
 
-    let current_extra_args =
-        build_aux_files(aux_file, aux_file.parent().unwrap(), &comments, "", &config)?;
-    // Make sure we see our dependencies
-    aux_cmd.args(current_extra_args.iter());
-    // Make sure our dependents also see our dependencies.
-    extra_args.extend(current_extra_args);
+    // Builds the aux file into `out_dir`, returning the flags for its dependents,
+    // which find it in `final_dir`.
+    let mut compile = |out_dir: PathBuf, final_dir: &Path| {
+        let mut errors = vec![];
 
-    aux_cmd.arg("--crate-type").arg(kind);
-    aux_cmd.arg("--emit=link");
-    let filename = aux.file_stem().unwrap().to_str().unwrap();
-    let output = aux_cmd.output().unwrap();
-    if !output.status.success() {
-        let error = Error::Command {
-            kind: "compilation of aux build failed".to_string(),
-            status: output.status,
+        let mut aux_cmd = aux_command(out_dir, &mut errors);
+
+        if !errors.is_empty() {
+            return Err((aux_cmd, errors, vec![]));
+        }
+
+        let filename = aux.file_stem().unwrap().to_str().unwrap();
+        let timeout = comments.timeout(&mut errors, "", &config);
+        let output = match output_or_error(&mut aux_cmd, timeout) {
+            Ok(output) => output,
+            Err(error) => return Err((aux_cmd, vec![error], vec![])),
         };
-        return Err((
-            aux_cmd,
-            vec![error],
-            rustc_stderr::process(path, &output.stderr).rendered,
+        if !output.status.success() {
+            let error = Error::Command {
+                kind: "compilation of aux build failed".to_string(),
+                status: output.status,
+            };
+            return Err((
+                aux_cmd,
+                vec![error],
+                rustc_stderr::process(path, &output.stderr, false, true).rendered,
+            ));
+        }
+
+        steps.push((
+            format!("aux-build: {}", aux_file.display()),
+            shell_command(&aux_cmd),
         ));
-    }
 
-    // Now run the command again to fetch the output filenames
-    aux_cmd.arg("--print").arg("file-names");
-    let output = aux_cmd.output().unwrap();
-    assert!(output.status.success());
+        // Now run the command again to fetch the output filenames
+        aux_cmd.arg("--print").arg("file-names");
+        let output = match output_or_error(&mut aux_cmd, timeout) {
+            Ok(output) => output,
+            Err(error) => return Err((aux_cmd, vec![error], vec![])),
+        };
+        assert!(output.status.success());
+
+        let mut externs = vec![];
+        for file in output.stdout.lines() {
+            let file = std::str::from_utf8(file).unwrap();
+            let crate_name = filename.replace('-', "_");
+            let path = final_dir.join(file);
+            externs.push("--extern".into());
+            externs.push(format!("{crate_name}={}", path.display()));
+            // Help cargo find the crates added with `--extern`.
+            externs.push("-L".into());
+            externs.push(final_dir.display().to_string());
+        }
+        Ok(externs)
+    };
 
-    for file in output.stdout.lines() {
-        let file = std::str::from_utf8(file).unwrap();
-        let crate_name = filename.replace('-', "_");
-        let path = config.out_dir.join(file);
-        extra_args.push("--extern".into());
-        extra_args.push(format!("{crate_name}={}", path.display()));
-        // Help cargo find the crates added with `--extern`.
-        extra_args.push("-L".into());
-        extra_args.push(config.out_dir.display().to_string());
+    if let Some((cache_dir, entry)) = &cache_entry {
+        // The file is only there if the build finished.
+        if let Ok(externs) = std::fs::read_to_string(entry.join(AUX_EXTERNS)) {
+            extra_args.extend(externs.lines().map(String::from));
+            return Ok(());
+        }
+        let tmp_dir =
+            std::fs::create_dir_all(cache_dir).and_then(|()| tempfile::tempdir_in(cache_dir));
+        if let Ok(tmp_dir) = tmp_dir {
+            let externs = compile(tmp_dir.path().to_path_buf(), entry)?;
+            let stored = std::fs::write(tmp_dir.path().join(AUX_EXTERNS), externs.join("\n"))
+                .and_then(|()| cache::store(tmp_dir, entry));
+            if stored.is_ok() {
+                extra_args.extend(externs);
+                return Ok(());
+            }
+        }
+        // The cache dir is read-only or full, so build without the cache.
     }
+    extra_args.extend(compile(config.out_dir.clone(), &config.out_dir)?);
     Ok(())
 }
 
+/// The file in the cache entry of an aux build with the flags to pass to its dependents.
+const AUX_EXTERNS: &str = "externs";
+
+/// Compiles the test and, depending on its mode, runs it or checks its fixed version.
+/// The commands that succeeded before the returned command are pushed to `steps`,
+/// and the time spent running and checking the test is added to `timings`. The outputs
+/// of the commands are collected in `artifacts`.
 fn run_test(
     path: &Path,
     config: &Config,
     revision: &str,
     comments: &Comments,
+    steps: &mut Vec<(String, String)>,
+    timings: &mut Timings,
+    artifacts: &mut Artifacts,
+    usage: &mut Usage,
 ) -> (Command, Errors, Vec<u8>) {
+    if let Some((flags, _)) = comments
+        .for_revision(revision)
+        .find_map(|r| r.probe.as_ref())
+    {
+        return run_probe(path, config, revision, comments, flags, artifacts, usage);
+    }
+    if comments.for_revision(revision).any(|r| r.emit.is_some()) {
+        return run_emit(path, config, revision, comments, steps, artifacts, usage);
+    }
+
     let extra_args = match build_aux_files(
         path,
         &path.parent().unwrap().join("auxiliary"),
         comments,
         revision,
         config,
+        steps,
     ) {
         Ok(value) => value,
         Err(value) => return value,
@@ -567,89 +1672,375 @@ fn run_test(
 
     let mut errors = vec![];
 
+    let mode = config.mode.maybe_override(comments, revision, &mut errors);
     let mut cmd = build_command(path, config, revision, comments, &mut errors);
     cmd.args(&extra_args);
+    // rustdoc never generates code anyway.
+    if let (Mode::Check, false) = (mode, config.rustdoc) {
+        cmd.arg("--emit=metadata");
+    }
+    if let (Mode::Run { .. }, true) = (mode, config.rustdoc) {
+        let line = comments
+            .for_revision(revision)
+            .find_map(|r| r.mode)
+            .map_or(0, |(_, line)| line);
+        errors.push(Error::InvalidComment {
+            msg: "rustdoc does not build binaries, so there is nothing to run".into(),
+            line,
+        });
+    }
 
-    let output = cmd
-        .output()
-        .unwrap_or_else(|err| panic!("could not execute {cmd:?}: {err}"));
-    let mode = config.mode.maybe_override(comments, revision, &mut errors);
-    let status_check = mode.ok(output.status);
-    if matches!(mode, Mode::Run { .. }) && Mode::Pass.ok(output.status).is_empty() {
-        let cmd = run_test_binary(mode, path, revision, comments, cmd, config, &mut errors);
+    check_forbidden_env(config, comments, revision, true, &mut errors);
+    if !errors.is_empty() {
         return (cmd, errors, vec![]);
     }
-    errors.extend(status_check);
-    if output.status.code() == Some(101) && !matches!(config.mode, Mode::Panic | Mode::Yolo) {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        errors.push(Error::Bug(format!(
-            "test panicked: stderr:\n{stderr}\nstdout:\n{stdout}",
-        )));
+
+    let timeout = comments.timeout(&mut errors, revision, config);
+    let Some(output) = output_with_timeout(&mut cmd, None, timeout)
+        .unwrap_or_else(|err| panic!("could not execute {cmd:?}: {err}"))
+    else {
+        errors.push(Error::TimedOut {
+            timeout: timeout.unwrap(),
+        });
+        return (cmd, errors, vec![]);
+    };
+    artifacts.add("raw.stdout", &output.stdout);
+    artifacts.add("raw.stderr", &output.stderr);
+    let yolo = comments.yolo(revision, config);
+    let status_check = if yolo { vec![] } else { mode.ok(output.status) };
+    if matches!(mode, Mode::Run { .. }) && Mode::Pass.ok(output.status).is_empty() {
+        steps.push(("build".into(), shell_command(&cmd)));
+        let cmd = run_test_binary(
+            mode,
+            path,
+            revision,
+            comments,
+            cmd,
+            config,
+            &mut errors,
+            timings,
+            artifacts,
+            usage,
+        );
         return (cmd, errors, vec![]);
     }
+    let should_ice = comments.for_revision(revision).any(|r| r.should_ice);
+    match ice::detect(&output, &config.ice_patterns) {
+        Some(ice) if !should_ice => {
+            errors.push(ice);
+            return (cmd, errors, vec![]);
+        }
+        // An expected ICE exits with whatever status the crash produced.
+        Some(_) => {}
+        None => {
+            if should_ice {
+                errors.push(Error::IceNotFound);
+            }
+            errors.extend(status_check);
+            if output.status.code() == Some(101) && !yolo && !matches!(config.mode, Mode::Panic) {
+                let stderr = diff::lossy(&output.stderr);
+                let stdout = diff::lossy(&output.stdout);
+                errors.push(Error::Bug(format!(
+                    "test panicked: stderr:\n{stderr}\nstdout:\n{stdout}",
+                )));
+                return (cmd, errors, vec![]);
+            }
+        }
+    }
     // Always remove annotation comments from stderr.
-    let diagnostics = rustc_stderr::process(path, &output.stderr);
-    let rustfixed = matches!(mode, Mode::Fix).then(|| {
+    let expansion_site = !comments
+        .for_revision(revision)
+        .any(|r| r.match_expansion_site == Some(false));
+    let streams = rustc_stderr::split_output(&output, config.diagnostics_stream, &mut errors);
+    let json: Vec<&[u8]> = streams
+        .json
+        .lines_with_terminator()
+        .filter(|line| line.starts_with(b"{"))
+        .collect();
+    artifacts.add("diagnostics.json", &json.concat());
+    let diagnostics = streams.process(path, config.sort_diagnostics, expansion_site);
+    let rustfixed = (matches!(mode, Mode::Fix) && !yolo).then(|| {
         run_rustfix(
-            &output.stderr,
+            &streams.json,
             path,
             comments,
             revision,
             config,
             extra_args,
+            timeout,
             &mut errors,
+            artifacts,
+            usage,
         )
     });
+    let check_start = Instant::now();
     let stderr = check_test_result(
         path,
         config,
         revision,
         comments,
         &mut errors,
-        &output.stdout,
+        &streams.stdout,
         diagnostics,
+        artifacts,
+        usage,
     );
+    timings.check += check_start.elapsed();
     if let Some((mut rustfix, rustfix_path)) = rustfixed {
-        // picking the crate name from the file name is problematic when `.revision_name` is inserted
-        rustfix.arg("--crate-name").arg(
-            path.file_stem()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .replace('-', "_"),
-        );
-        let output = rustfix.output().unwrap();
+        let output = match output_or_error(&mut rustfix, timeout) {
+            Ok(output) => output,
+            Err(error) => {
+                errors.push(error);
+                steps.push(("build".into(), shell_command(&cmd)));
+                return (rustfix, errors, vec![]);
+            }
+        };
+        artifacts.add("raw.fixed.stderr", &output.stderr);
         if !output.status.success() {
             errors.push(Error::Command {
                 kind: "rustfix".into(),
                 status: output.status,
             });
+            steps.push(("build".into(), shell_command(&cmd)));
             return (
                 rustfix,
                 errors,
-                rustc_stderr::process(&rustfix_path, &output.stderr).rendered,
+                rustc_stderr::process(&rustfix_path, &output.stderr, false, true).rendered,
             );
         }
+        if !comments.for_revision(revision).any(|r| r.no_verify_fixed) {
+            let streams =
+                rustc_stderr::split_output(&output, config.diagnostics_stream, &mut errors);
+            let diagnostics = streams.process(&rustfix_path, false, true);
+            if diagnostics
+                .messages
+                .iter()
+                .flatten()
+                .chain(&diagnostics.messages_from_unknown_file_or_line)
+                .any(|msg| msg.level >= config.fixed_diagnostics_level)
+            {
+                errors.push(Error::FixedHasDiagnostics {
+                    level: config.fixed_diagnostics_level,
+                    stderr: diagnostics.rendered,
+                });
+            }
+        }
     }
     (cmd, errors, stderr)
 }
 
+/// Run the program with the flags from a `//@probe` command instead of compiling the test file,
+/// and compare the program's stdout against the `.stdout` file.
+fn run_probe(
+    path: &Path,
+    config: &Config,
+    revision: &str,
+    comments: &Comments,
+    flags: &[String],
+    artifacts: &mut Artifacts,
+    usage: &mut Usage,
+) -> (Command, Errors, Vec<u8>) {
+    let mut errors = vec![];
+    for error_match in comments
+        .for_revision(revision)
+        .flat_map(|r| r.error_matches.iter())
+    {
+        errors.push(Error::InvalidComment {
+            msg: "`//~` annotations are not supported in `probe` tests".into(),
+            line: error_match.definition_line,
+        });
+    }
+
+    let mut cmd = config.program.build(&config.out_dir);
+    cmd.args(flags);
+    let placeholders = Placeholders {
+        config,
+        out_dir: &config.out_dir,
+        file: path,
+    };
+    for arg in comments
+        .for_revision(revision)
+        .flat_map(|r| r.compile_flags.iter())
+    {
+        cmd.arg(&*placeholders.replace(arg, &mut errors));
+    }
+    for (k, v) in comments
+        .for_revision(revision)
+        .flat_map(|r| r.env_vars.iter())
+    {
+        cmd.env(k, &*placeholders.replace(v, &mut errors));
+    }
+
+    let timeout = comments.timeout(&mut errors, revision, config);
+    let output = match output_or_error(&mut cmd, timeout) {
+        Ok(output) => output,
+        Err(error) => {
+            errors.push(error);
+            return (cmd, errors, vec![]);
+        }
+    };
+    artifacts.add("raw.stdout", &output.stdout);
+    artifacts.add("raw.stderr", &output.stderr);
+    if !output.status.success() {
+        errors.push(Error::Command {
+            kind: "probe".into(),
+            status: output.status,
+        });
+        return (cmd, errors, output.stderr);
+    }
+    check_output(
+        &output.stdout,
+        path,
+        &mut errors,
+        revised(revision, "stdout"),
+        &config.stdout_filters,
+        |r| &r.normalize_stdout,
+        config,
+        comments,
+        revision,
+        artifacts,
+        usage,
+    );
+    (cmd, errors, output.stderr)
+}
+
+/// Compile the file with `--emit` instead of checking its diagnostics, and compare the emitted
+/// assembly, LLVM IR or MIR against the `.s`, `.ll` or `.mir` file.
+fn run_emit(
+    path: &Path,
+    config: &Config,
+    revision: &str,
+    comments: &Comments,
+    steps: &mut Vec<(String, String)>,
+    artifacts: &mut Artifacts,
+    usage: &mut Usage,
+) -> (Command, Errors, Vec<u8>) {
+    let mut errors = vec![];
+    let (emit, line) = comments.emit(&mut errors, revision).unwrap();
+    if config.rustdoc {
+        errors.push(Error::InvalidComment {
+            msg: "`emit` is not supported by rustdoc".into(),
+            line,
+        });
+        return (config.program.build(&config.out_dir), errors, vec![]);
+    }
+    for error_match in comments
+        .for_revision(revision)
+        .flat_map(|r| r.error_matches.iter())
+    {
+        errors.push(Error::InvalidComment {
+            msg: "`//~` annotations are not supported in `emit` tests, they must compile cleanly"
+                .into(),
+            line: error_match.definition_line,
+        });
+    }
+
+    let extra_args = match build_aux_files(
+        path,
+        &path.parent().unwrap().join("auxiliary"),
+        comments,
+        revision,
+        config,
+        steps,
+    ) {
+        Ok(value) => value,
+        Err(value) => return value,
+    };
+    let mut cmd = build_command(path, config, revision, comments, &mut errors);
+    cmd.args(&extra_args);
+    // Written to a directory of its own, as revisions of the test may be compiled in parallel.
+    let dir = match std::fs::create_dir_all(&config.out_dir)
+        .and_then(|()| tempfile::tempdir_in(&config.out_dir))
+    {
+        Ok(dir) => dir,
+        Err(err) => {
+            errors.push(Error::Bug(format!(
+                "could not create a directory for the emitted `.{}` in `{}`: {err}",
+                emit.extension(),
+                config.out_dir.display()
+            )));
+            return (cmd, errors, vec![]);
+        }
+    };
+    let file = dir.path().join("emitted").with_extension(emit.extension());
+    let mut flag = OsString::from(format!("--emit={emit}="));
+    flag.push(&file);
+    cmd.arg(flag);
+    if emit != Emit::Mir {
+        // Otherwise the code is split up into several files.
+        cmd.arg("-Ccodegen-units=1");
+    }
+
+    check_forbidden_env(config, comments, revision, true, &mut errors);
+    if !errors.is_empty() {
+        return (cmd, errors, vec![]);
+    }
+
+    let timeout = comments.timeout(&mut errors, revision, config);
+    let Some(output) = output_with_timeout(&mut cmd, None, timeout)
+        .unwrap_or_else(|err| panic!("could not execute {cmd:?}: {err}"))
+    else {
+        errors.push(Error::TimedOut {
+            timeout: timeout.unwrap(),
+        });
+        return (cmd, errors, vec![]);
+    };
+    artifacts.add("raw.stdout", &output.stdout);
+    artifacts.add("raw.stderr", &output.stderr);
+    let stderr = rustc_stderr::process(path, &output.stderr, false, true).rendered;
+    if let Some(ice) = ice::detect(&output, &config.ice_patterns) {
+        errors.push(ice);
+        return (cmd, errors, stderr);
+    }
+    if !output.status.success() {
+        errors.push(Error::Command {
+            kind: format!("compiling with `--emit={emit}`"),
+            status: output.status,
+        });
+        return (cmd, errors, stderr);
+    }
+    let emitted = match std::fs::read(&file) {
+        Ok(emitted) => emitted,
+        Err(err) => {
+            errors.push(Error::Bug(format!(
+                "could not read the emitted `{}`: {err}",
+                file.display()
+            )));
+            return (cmd, errors, stderr);
+        }
+    };
+
+    let strip_value_names = comments.for_revision(revision).any(|r| r.strip_value_names);
+    let mut filters = stderr_filters(config, comments, revision, &mut errors);
+    filters.extend(emit.filters(strip_value_names));
+    check_output(
+        &emitted,
+        path,
+        &mut errors,
+        revised(revision, emit.extension()),
+        &filters,
+        |r| &r.normalize_stdout,
+        config,
+        comments,
+        revision,
+        artifacts,
+        usage,
+    );
+    (cmd, errors, stderr)
+}
+
 fn build_aux_files(
     path: &Path,
     aux_dir: &Path,
     comments: &Comments,
     revision: &str,
     config: &Config,
+    steps: &mut Vec<(String, String)>,
 ) -> Result<Vec<String>, (Command, Vec<Error>, Vec<u8>)> {
     let mut extra_args = vec![];
     for rev in comments.for_revision(revision) {
         for (aux, kind, line) in &rev.aux_builds {
-            let aux_file = if aux.starts_with("..") {
-                aux_dir.parent().unwrap().join(aux)
-            } else {
-                aux_dir.join(aux)
-            };
+            let aux_file = aux_file_path(aux_dir, aux);
             if let Err((command, errors, msg)) = build_aux(
                 &aux_file,
                 path,
@@ -659,6 +2050,7 @@ fn build_aux_files(
                 kind,
                 aux,
                 &mut extra_args,
+                steps,
             ) {
                 return Err((
                     command,
@@ -675,6 +2067,16 @@ fn build_aux_files(
     Ok(extra_args)
 }
 
+/// The path of the aux file named in an `//@aux-build`. Paths starting with `..` are
+/// relative to the directory of the test instead of its `auxiliary` directory.
+fn aux_file_path(aux_dir: &Path, aux: &Path) -> PathBuf {
+    if aux.starts_with("..") {
+        aux_dir.parent().unwrap().join(aux)
+    } else {
+        aux_dir.join(aux)
+    }
+}
+
 fn run_test_binary(
     mode: Mode,
     path: &Path,
@@ -683,6 +2085,9 @@ fn run_test_binary(
     mut cmd: Command,
     config: &Config,
     errors: &mut Vec<Error>,
+    timings: &mut Timings,
+    artifacts: &mut Artifacts,
+    usage: &mut Usage,
 ) -> Command {
     cmd.arg("--print").arg("file-names");
     let output = cmd.output().unwrap();
@@ -693,24 +2098,150 @@ fn run_test_binary(
     assert_eq!(files.next(), None);
     let file = std::str::from_utf8(file).unwrap();
     let exe = config.out_dir.join(file);
-    let mut exe = Command::new(exe);
-    let output = exe.output().unwrap();
-
-    check_test_output(
-        path,
-        errors,
-        revision,
-        config,
-        comments,
-        &output.stdout,
-        &output.stderr,
-    );
+    let mut exe = match (&config.runner, config.cross_target()) {
+        (Some(runner), _) => runner_command(runner, &exe),
+        (None, Some(target)) => {
+            errors.push(Error::RunnerRequired {
+                target: target.to_owned(),
+            });
+            return Command::new(exe);
+        }
+        (None, None) => Command::new(exe),
+    };
+    // Keep the panic output of `//@run-panic` tests free of backtraces, so it can be
+    // compared against `.run.stderr` files.
+    if let Mode::Run {
+        exit: ExitExpectation::Panic,
+    } = mode
+    {
+        exe.env("RUST_BACKTRACE", "0");
+    }
+    // Dependent tests can find the artifacts of their dependencies at runtime, too.
+    for (var, val) in &config.program.envs {
+        if let Some(val) = val {
+            if var.to_string_lossy().starts_with(DEPENDENCY_ENV_PREFIX) {
+                exe.env(var, val);
+            }
+        }
+    }
+    let len = errors.len();
+    check_forbidden_env(config, comments, revision, false, errors);
+    if errors.len() > len {
+        return exe;
+    }
+    let stdin = match comments.stdin(errors, revision) {
+        Some((stdin, line)) => {
+            let stdin = path.parent().unwrap().join(stdin);
+            match std::fs::read(&stdin) {
+                Ok(stdin) => Some(stdin),
+                Err(err) => {
+                    errors.push(Error::StdinFile {
+                        path: stdin,
+                        line,
+                        error: err.to_string(),
+                    });
+                    return exe;
+                }
+            }
+        }
+        None => None,
+    };
+    let timeout = comments.timeout(errors, revision, config);
+    let run_start = Instant::now();
+    let output = output_with_timeout(&mut exe, stdin, timeout).unwrap();
+    timings.run += run_start.elapsed();
+    let Some(output) = output else {
+        errors.push(Error::TimedOut {
+            timeout: timeout.unwrap(),
+        });
+        return exe;
+    };
+    artifacts.add("raw.run.stdout", &output.stdout);
+    artifacts.add("raw.run.stderr", &output.stderr);
 
-    errors.extend(mode.ok(output.status));
+    let check_start = Instant::now();
+    if comments.for_revision(revision).any(|r| r.check_run_results) {
+        check_run_results(
+            path,
+            errors,
+            revision,
+            config,
+            comments,
+            &output.stdout,
+            &output.stderr,
+            artifacts,
+            usage,
+        );
+    } else {
+        check_test_output(
+            path,
+            errors,
+            revision,
+            config,
+            comments,
+            &output.stdout,
+            Some(&output.stderr),
+            artifacts,
+            usage,
+        );
+    }
+    timings.check += check_start.elapsed();
+
+    let yolo = comments.yolo(revision, config);
+    let status_errors = if yolo {
+        let expected = ExitExpectation::AnyCode;
+        if expected.matches(output.status) {
+            vec![]
+        } else {
+            vec![Error::ExitStatus {
+                mode: Mode::Yolo,
+                status: output.status,
+                expected,
+            }]
+        }
+    } else {
+        mode.ok(output.status)
+    };
+    if let (
+        true,
+        Mode::Run {
+            exit: ExitExpectation::Panic,
+        },
+    ) = (status_errors.is_empty() && !yolo, mode)
+    {
+        if let Some((pattern, line)) = comments.run_panic(errors, revision) {
+            if !pattern.is_match_in(&output.stderr) {
+                errors.push(Error::PanicMessageNotFound { pattern, line });
+            }
+        }
+    }
+    errors.extend(status_errors);
 
     exe
 }
 
+/// The command executing `exe` via the `runner`, substituting `{}` in its arguments with
+/// the path of `exe` or appending the path if there is no `{}`.
+fn runner_command(runner: &[String], exe: &Path) -> Command {
+    let (program, args) = runner
+        .split_first()
+        .expect("`Config::runner` needs at least the command to run");
+    let mut cmd = Command::new(program);
+    let mut substituted = false;
+    for arg in args {
+        if arg.contains("{}") {
+            substituted = true;
+            cmd.arg(arg.replace("{}", &exe.display().to_string()));
+        } else {
+            cmd.arg(arg);
+        }
+    }
+    if !substituted {
+        cmd.arg(exe);
+    }
+    cmd
+}
+
 fn run_rustfix(
     stderr: &[u8],
     path: &Path,
@@ -718,39 +2249,37 @@ fn run_rustfix(
     revision: &str,
     config: &Config,
     extra_args: Vec<String>,
+    timeout: Option<Duration>,
     errors: &mut Vec<Error>,
+    artifacts: &mut Artifacts,
+    usage: &mut Usage,
 ) -> (Command, PathBuf) {
-    let input = std::str::from_utf8(stderr).unwrap();
-    let suggestions = rustfix::get_suggestions_from_json(
-        input,
-        &HashSet::new(),
-        if let Mode::Yolo = config.mode {
-            rustfix::Filter::Everything
-        } else {
-            rustfix::Filter::MachineApplicableOnly
-        },
-    )
-    .unwrap_or_else(|err| {
-        panic!("could not deserialize diagnostics json for rustfix {err}:{input}")
-    });
-    let fixed_code =
-        rustfix::apply_suggestions(&std::fs::read_to_string(path).unwrap(), &suggestions)
-            .unwrap_or_else(|e| {
-                panic!(
-                    "failed to apply suggestions for {:?} with rustfix: {e}",
-                    path.display()
-                )
-            });
     let edition = comments.edition(errors, revision, config);
+    let crate_type = comments
+        .crate_type(errors, revision)
+        .map(|crate_type| (crate_type.to_owned(), 0));
+    // Picking the crate name from the file name is problematic when `.revision_name` is inserted.
+    let crate_name = comments
+        .crate_name(errors, revision)
+        .map(str::to_owned)
+        .unwrap_or_else(|| {
+            path.file_stem()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .replace('-', "_")
+        });
     let rustfix_comments = Comments {
         revisions: None,
         revisioned: std::iter::once((
-            vec![],
+            RevisionFilter::default(),
             Revisioned {
                 line: 0,
                 ignore: vec![],
                 only: vec![],
+                ignore_test: None,
                 stderr_per_bitwidth: false,
+                dont_check_compiler_stderr: false,
                 compile_flags: comments
                     .for_revision(revision)
                     .flat_map(|r| r.compile_flags.iter().cloned())
@@ -760,6 +2289,9 @@ fn run_rustfix(
                     .flat_map(|r| r.env_vars.iter().cloned())
                     .collect(),
                 normalize_stderr: vec![],
+                normalize_stdout: vec![],
+                check_stdout: false,
+                disabled_filters: vec![],
                 error_in_other_files: vec![],
                 error_matches: vec![],
                 require_annotations_for_level: None,
@@ -768,21 +2300,119 @@ fn run_rustfix(
                     .flat_map(|r| r.aux_builds.iter().cloned())
                     .collect(),
                 edition,
+                crate_type,
+                crate_name: Some((crate_name, 0)),
                 mode: Some((Mode::Pass, 0)),
+                require_annotations: None,
+                probe: None,
+                emit: None,
+                strip_value_names: false,
+                timeout: None,
+                depends_on: vec![],
+                stdin: None,
+                run_panic: None,
+                yolo: false,
+                check_run_results: false,
+                rustfix_only_machine_applicable: false,
+                no_verify_fixed: false,
+                should_ice: false,
+                match_expansion_site: None,
+                forbid_env: vec![],
                 needs_asm_support: false,
+                custom: HashMap::new(),
             },
         ))
         .collect(),
+        deprecations: vec![],
+        legacy_annotations: vec![],
+    };
+
+    let filter = rustfix_filter(config, comments, revision);
+    let get_suggestions = |stderr: &[u8]| {
+        let input = diff::lossy(stderr);
+        rustfix::get_suggestions_from_json(&input, &HashSet::new(), filter).unwrap_or_else(|err| {
+            panic!("could not deserialize diagnostics json for rustfix {err}:{input}")
+        })
+    };
+    let apply_suggestions = |code: &str, suggestions: &[rustfix::Suggestion]| {
+        rustfix::apply_suggestions(code, suggestions).unwrap_or_else(|e| {
+            panic!(
+                "failed to apply suggestions for {:?} with rustfix: {e}",
+                path.display()
+            )
+        })
     };
+
+    let mut fixed_code = std::fs::read_to_string(path).unwrap();
+    let mut suggestions = get_suggestions(stderr);
+    // Applying suggestions can make new suggestions show up, so keep recompiling
+    // the fixed code until there's nothing left to apply.
+    let relative: PathBuf = path
+        .with_extension("")
+        .components()
+        .filter(|c| matches!(c, std::path::Component::Normal(_)))
+        .collect();
+    let intermediate_path = config
+        .out_dir
+        .join(relative)
+        .join(format!("{}.rs", revised(revision, "rustfix")));
+    for iteration in 1.. {
+        if suggestions.is_empty() {
+            break;
+        }
+        fixed_code = apply_suggestions(&fixed_code, &suggestions);
+
+        std::fs::create_dir_all(intermediate_path.parent().unwrap()).unwrap();
+        std::fs::write(&intermediate_path, &fixed_code).unwrap();
+        let mut cmd = build_command(
+            &intermediate_path,
+            config,
+            revision,
+            &rustfix_comments,
+            errors,
+        );
+        cmd.args(&extra_args);
+        cmd.arg("--emit=metadata");
+        let output = match output_or_error(&mut cmd, timeout) {
+            Ok(output) => output,
+            Err(error) => {
+                errors.push(error);
+                break;
+            }
+        };
+        // The diagnostics are about the intermediate file, so their spans match `fixed_code`.
+        // Suggestions for other files (e.g. aux builds) must not be applied to it.
+        let streams = rustc_stderr::split_output(&output, config.diagnostics_stream, errors);
+        suggestions = get_suggestions(&streams.json)
+            .into_iter()
+            .filter(|suggestion| {
+                suggestion
+                    .snippets
+                    .iter()
+                    .all(|snippet| Path::new(&snippet.file_name) == intermediate_path)
+            })
+            .collect();
+        if !suggestions.is_empty() && iteration == config.fix_iterations.get() {
+            errors.push(Error::FixpointNotReached {
+                iterations: iteration,
+                diagnostics: streams.process(&intermediate_path, false, true).rendered,
+            });
+            break;
+        }
+    }
+
     let path = check_output(
         fixed_code.as_bytes(),
         path,
         errors,
         revised(revision, "fixed"),
         &Filter::default(),
+        |r| &r.normalize_stderr,
         config,
         &rustfix_comments,
         revision,
+        artifacts,
+        usage,
     );
 
     let mut cmd = build_command(&path, config, revision, &rustfix_comments, errors);
@@ -790,6 +2420,73 @@ fn run_rustfix(
     (cmd, path)
 }
 
+/// Report variables from `Config::forbidden_env` and `//@forbid-env` that would be visible to
+/// the compiler (if `compile` is set) or to the test binary, naming what set them.
+fn check_forbidden_env(
+    config: &Config,
+    comments: &Comments,
+    revision: &str,
+    compile: bool,
+    errors: &mut Errors,
+) {
+    let forbidden = config.forbidden_env.iter().chain(
+        comments
+            .for_revision(revision)
+            .flat_map(|r| r.forbid_env.iter().map(|(var, _)| var)),
+    );
+    for var in forbidden {
+        let layer = if compile {
+            if comments
+                .for_revision(revision)
+                .flat_map(|r| r.env_vars.iter())
+                .any(|(k, _)| k == var)
+            {
+                Some("a `//@rustc-env` comment")
+            } else {
+                match config
+                    .program
+                    .envs
+                    .iter()
+                    .rev()
+                    .find(|(k, _)| k == var.as_str())
+                {
+                    Some((_, Some(_))) => Some("`Config::program.envs`"),
+                    // Explicitly removed from the compiler's environment.
+                    Some((_, None)) => None,
+                    None => std::env::var_os(var)
+                        .is_some()
+                        .then_some("the environment of the test runner"),
+                }
+            }
+        } else {
+            std::env::var_os(var)
+                .is_some()
+                .then_some("the environment of the test runner")
+        };
+        if let Some(layer) = layer {
+            errors.push(Error::ForbiddenEnv {
+                var: var.clone(),
+                layer,
+            });
+        }
+    }
+}
+
+/// Which suggestions rustfix should apply for this test.
+fn rustfix_filter(config: &Config, comments: &Comments, revision: &str) -> rustfix::Filter {
+    if comments
+        .for_revision(revision)
+        .any(|r| r.rustfix_only_machine_applicable)
+    {
+        return rustfix::Filter::MachineApplicableOnly;
+    }
+    if config.rustfix_all_suggestions {
+        rustfix::Filter::Everything
+    } else {
+        rustfix::Filter::MachineApplicableOnly
+    }
+}
+
 fn revised(revision: &str, extension: &str) -> String {
     if revision.is_empty() {
         extension.to_string()
@@ -806,7 +2503,27 @@ fn check_test_result(
     errors: &mut Errors,
     stdout: &[u8],
     diagnostics: Diagnostics,
+    artifacts: &mut Artifacts,
+    usage: &mut Usage,
 ) -> Vec<u8> {
+    // Without `check-stdout`, only the stdout of run tests is meant to be normalized.
+    let check_stdout = comments.for_revision(revision).any(|r| r.check_stdout);
+    if !check_stdout
+        && !matches!(
+            config.mode.maybe_override(comments, revision, &mut vec![]),
+            Mode::Run { .. }
+        )
+    {
+        for (_, _, line) in comments
+            .for_revision(revision)
+            .flat_map(|r| r.normalize_stdout.iter())
+        {
+            errors.push(Error::InvalidComment {
+                msg: "`normalize-stdout-test` needs `check-stdout`, unless the test is run".into(),
+                line: *line,
+            });
+        }
+    }
     check_test_output(
         path,
         errors,
@@ -814,7 +2531,12 @@ fn check_test_result(
         config,
         comments,
         stdout,
-        &diagnostics.rendered,
+        (!comments
+            .for_revision(revision)
+            .any(|r| r.dont_check_compiler_stderr))
+        .then_some(&diagnostics.rendered[..]),
+        artifacts,
+        usage,
     );
     // Check error annotations in the source against output
     check_annotations(
@@ -836,30 +2558,165 @@ fn check_test_output(
     config: &Config,
     comments: &Comments,
     stdout: &[u8],
-    stderr: &[u8],
+    stderr: Option<&[u8]>,
+    artifacts: &mut Artifacts,
+    usage: &mut Usage,
 ) {
     // Check output files (if any)
     // Check output files against actual output
+    match stderr {
+        Some(stderr) => {
+            let stderr_filters = stderr_filters(config, comments, revision, errors);
+            check_output(
+                stderr,
+                path,
+                errors,
+                revised(revision, "stderr"),
+                &stderr_filters,
+                |r| &r.normalize_stderr,
+                config,
+                comments,
+                revision,
+                artifacts,
+                usage,
+            );
+        }
+        // The stderr is not checked, so there should be no `.stderr` file.
+        None => check_stale_output(
+            path,
+            errors,
+            revised(revision, "stderr"),
+            config,
+            comments,
+            revision,
+        ),
+    }
+    check_output(
+        stdout,
+        path,
+        errors,
+        revised(revision, "stdout"),
+        &config.stdout_filters,
+        |r| &r.normalize_stdout,
+        config,
+        comments,
+        revision,
+        artifacts,
+        usage,
+    );
+}
+
+/// Compare the output of a test binary against the `.run.stdout` and `.run.stderr` files.
+fn check_run_results(
+    path: &Path,
+    errors: &mut Vec<Error>,
+    revision: &str,
+    config: &Config,
+    comments: &Comments,
+    stdout: &[u8],
+    stderr: &[u8],
+    artifacts: &mut Artifacts,
+    usage: &mut Usage,
+) {
+    let stderr_filters = stderr_filters(config, comments, revision, errors);
     check_output(
         stderr,
         path,
         errors,
-        revised(revision, "stderr"),
-        &config.stderr_filters,
+        revised(revision, "run.stderr"),
+        &stderr_filters,
+        |r| &r.normalize_stderr,
         config,
         comments,
         revision,
+        artifacts,
+        usage,
     );
     check_output(
         stdout,
         path,
         errors,
-        revised(revision, "stdout"),
+        revised(revision, "run.stdout"),
         &config.stdout_filters,
+        |r| &r.normalize_stdout,
         config,
         comments,
         revision,
+        artifacts,
+        usage,
+    );
+}
+
+/// The names of the `Config::named_stderr_filters` that the test did not disable, each once.
+fn active_filter_names<'a>(
+    config: &'a Config,
+    comments: &Comments,
+    revision: &str,
+) -> Vec<&'a str> {
+    let mut names = vec![];
+    for (name, ..) in &config.named_stderr_filters {
+        let disabled = comments
+            .for_revision(revision)
+            .flat_map(|r| r.disabled_filters.iter())
+            .any(|(disabled, _)| disabled == name);
+        if !disabled && !names.contains(&name.as_str()) {
+            names.push(name.as_str());
+        }
+    }
+    names
+}
+
+/// Combine the global stderr filters with the named ones that the test did not disable.
+fn stderr_filters(
+    config: &Config,
+    comments: &Comments,
+    revision: &str,
+    errors: &mut Errors,
+) -> Filter {
+    let disabled: Vec<_> = comments
+        .for_revision(revision)
+        .flat_map(|r| r.disabled_filters.iter())
+        .collect();
+    for (name, line) in &disabled {
+        if !config
+            .named_stderr_filters
+            .iter()
+            .any(|(registered, ..)| registered == name)
+        {
+            let mut known: Vec<_> = config
+                .named_stderr_filters
+                .iter()
+                .map(|(name, ..)| format!("`{name}`"))
+                .collect();
+            known.sort();
+            known.dedup();
+            errors.push(Error::InvalidComment {
+                msg: format!(
+                    "`{name}` is not a named filter, the registered filters are: {}",
+                    if known.is_empty() {
+                        "(none)".to_string()
+                    } else {
+                        known.join(", ")
+                    }
+                ),
+                line: *line,
+            });
+        }
+    }
+    // Paths to the tests are printed with slashes, no matter which system blessed the output.
+    let mut filters = vec![(
+        Match::PathBackslashUnder(config.root_dir.clone()),
+        b"/" as &[u8],
+    )];
+    filters.extend(config.stderr_filters.iter().cloned());
+    filters.extend(
+        config
+            .named_stderr_filters
+            .iter()
+            .filter(|(name, ..)| !disabled.iter().any(|(disabled, _)| disabled == name))
+            .map(|(_, rule, replacement)| (rule.clone(), *replacement)),
     );
+    filters
 }
 
 fn check_annotations(
@@ -871,26 +2728,81 @@ fn check_annotations(
     revision: &str,
     comments: &Comments,
 ) {
+    if comments.yolo(revision, config) {
+        return;
+    }
     let error_patterns = comments
         .for_revision(revision)
         .flat_map(|r| r.error_in_other_files.iter());
+    // Files outside the test are shown as they show up in the output, so they can be matched
+    // without knowing where e.g. the cargo registry is. Invalid filters were reported already.
+    let filters = stderr_filters(config, comments, revision, &mut vec![]);
+    for msg in &mut messages_from_unknown_file_or_line {
+        if let Some(file) = &mut msg.file {
+            let normalized = filters.iter().fold(
+                Cow::Borrowed(file.as_bytes()),
+                |file, (rule, replacement)| {
+                    Cow::Owned(rule.replace_all(&file, replacement).0.into_owned())
+                },
+            );
+            *file = diff::lossy(&normalized).into_owned();
+        }
+    }
 
+    // Patterns that were not found, with the line they were looking at.
+    let mut not_found = vec![];
+    // The lines of the test file, if annotations should be rewritten, inserted or removed.
+    // Removed lines are left empty and inserted lines are appended to the line above them,
+    // so the lines of the annotations keep their index until the file is written.
+    let mut source =
+        ((config.bless_annotations || config.insert_annotations || config.prune_annotations)
+            && matches!(
+                config.output_conflict_handling,
+                OutputConflictHandling::Bless
+            ))
+        .then(|| std::fs::read_to_string(path).ok())
+        .flatten()
+        .map(|source| {
+            source
+                .split_inclusive('\n')
+                .map(String::from)
+                .collect::<Vec<_>>()
+        });
+    let mut source_changed = false;
+    // The annotations that matched nothing and get removed.
+    let prune = config.prune_annotations && comments.revisions.is_none();
+    // Every revision would insert its own annotations into the same lines, so tests with
+    // revisions need their annotations to be written by hand.
+    let insert = config.insert_annotations && comments.revisions.is_none();
+    let mut pruned = vec![];
     let mut seen_error_match = false;
-    for (error_pattern, definition_line) in error_patterns {
+    for (error_pattern, definition_line, file) in error_patterns {
         seen_error_match = true;
         // first check the diagnostics messages outside of our file. We check this first, so that
         // you can mix in-file annotations with //@error-in-other-file annotations, even if there is overlap
         // in the messages.
+        let in_file = |msg: &Message| match (file, &msg.file) {
+            (None, _) => true,
+            (Some(file), Some(msg_file)) => Path::new(msg_file).ends_with(file),
+            (Some(_), None) => false,
+        };
         if let Some(i) = messages_from_unknown_file_or_line
             .iter()
-            .position(|msg| error_pattern.matches(&msg.message))
+            .position(|msg| in_file(msg) && error_pattern.matches(msg))
         {
             messages_from_unknown_file_or_line.remove(i);
         } else {
             errors.push(Error::PatternNotFound {
                 pattern: error_pattern.clone(),
                 definition_line: *definition_line,
+                near_misses: near_misses(
+                    error_pattern,
+                    None,
+                    &messages,
+                    &messages_from_unknown_file_or_line,
+                ),
             });
+            not_found.push((None, error_pattern, *definition_line));
         }
     }
 
@@ -898,48 +2810,254 @@ fn check_annotations(
     // We will ensure that *all* diagnostics of level at least `lowest_annotation_level`
     // are matched.
     let mut lowest_annotation_level = Level::Error;
-    for &ErrorMatch {
-        ref pattern,
-        definition_line,
-        line,
-        level,
-    } in comments
+    // Negated annotations are checked before any diagnostics are consumed by other annotations.
+    // They neither count as patterns of fail tests nor require other diagnostics to be annotated.
+    for error_match in comments
         .for_revision(revision)
         .flat_map(|r| r.error_matches.iter())
+        .filter(|error_match| error_match.negated)
     {
+        let msgs = match error_match.line {
+            Some(line) => messages.get(line),
+            None => Some(&messages_from_unknown_file_or_line),
+        };
+        let found = msgs
+            .into_iter()
+            .flatten()
+            .find(|msg| error_match.matches(msg))
+            .or_else(|| {
+                let line = error_match.line?;
+                let &(msg_line, i) =
+                    matches_on_other_lines(&messages, line, error_match).first()?;
+                Some(&messages[msg_line][i])
+            });
+        if let Some(msg) = found {
+            errors.push(Error::NegatedPatternFound {
+                definition_line: error_match.definition_line,
+                msg: msg.clone(),
+            });
+        }
+    }
+    let mut error_matches: Vec<_> = comments
+        .for_revision(revision)
+        .flat_map(|r| r.error_matches.iter())
+        .filter(|error_match| !error_match.negated)
+        .collect();
+    // Annotations with a column get the first pick, so the diagnostics they match
+    // aren't consumed by an annotation without a column on the same line.
+    error_matches.sort_by_key(|error_match| error_match.column.is_none());
+    for error_match in error_matches {
+        let &ErrorMatch {
+            ref pattern,
+            ref code,
+            count,
+            column,
+            definition_line,
+            line,
+            level,
+            ..
+        } = error_match;
         seen_error_match = true;
         // If we found a diagnostic with a level annotation, make sure that all
         // diagnostics of that level have annotations, even if we don't end up finding a matching diagnostic
-        // for this pattern.
-        lowest_annotation_level = std::cmp::min(lowest_annotation_level, level);
+        // for this pattern. Suggestions are not diagnostics, so they never need annotations.
+        if level != Level::Suggestion {
+            lowest_annotation_level = std::cmp::min(lowest_annotation_level, level);
+        }
 
-        if let Some(msgs) = messages.get_mut(line) {
-            let found = msgs
-                .iter()
-                .position(|msg| pattern.matches(&msg.message) && msg.level == level);
-            if let Some(found) = found {
-                msgs.remove(found);
+        // Only if nothing on its own line matches, the annotation consumes diagnostics that
+        // are reported on another line, but have another primary span on its line.
+        if let Some(line) = line {
+            let direct = messages
+                .get(line)
+                .is_some_and(|msgs| msgs.iter().any(|msg| error_match.matches(msg)));
+            let found = match direct {
+                true => vec![],
+                false => matches_on_other_lines(&messages, line, error_match),
+            };
+            if !found.is_empty() {
+                let consumed = match count {
+                    None => &found[..1],
+                    Some(_) => &found[..],
+                };
+                // Backwards, so removing a message doesn't move the ones still to be removed.
+                for &(msg_line, i) in consumed.iter().rev() {
+                    messages[msg_line].remove(i);
+                }
+                if let Some(expected) = count.filter(|&expected| expected != found.len()) {
+                    errors.push(Error::WrongMatchCount {
+                        expected,
+                        found: found.len(),
+                        definition_line,
+                    });
+                }
+                continue;
+            }
+        }
+        let msgs = match line {
+            Some(line) => messages.get_mut(line),
+            None => Some(&mut messages_from_unknown_file_or_line),
+        };
+        if let (Some(column), Some(msgs)) = (column, &msgs) {
+            if !msgs.iter().any(|msg| msg.column == Some(column)) {
+                let mut columns: Vec<_> = msgs.iter().filter_map(|msg| msg.column).collect();
+                columns.sort_unstable();
+                columns.dedup();
+                if prune && prunable(&source, definition_line) {
+                    pruned.push(definition_line);
+                    continue;
+                }
+                errors.push(Error::ColumnNotFound {
+                    column,
+                    columns,
+                    definition_line,
+                });
                 continue;
             }
         }
+        let candidate = |msg: &Message| {
+            msg.level == level
+                && column.map_or(true, |column| msg.column == Some(column))
+                && pattern
+                    .as_ref()
+                    .map_or(true, |pattern| pattern.matches(msg))
+        };
+        let matches = |msg: &Message| error_match.matches(msg);
+        let mut actual_code = None;
+        let mut suggestions = vec![];
+        if let Some(msgs) = msgs {
+            match count {
+                None => {
+                    if let Some(found) = msgs.iter().position(matches) {
+                        msgs.remove(found);
+                        continue;
+                    }
+                }
+                Some(expected) => {
+                    let found = msgs.iter().filter(|msg| matches(msg)).count();
+                    if found > 0 {
+                        msgs.retain(|msg| !matches(msg));
+                        if found != expected {
+                            errors.push(Error::WrongMatchCount {
+                                expected,
+                                found,
+                                definition_line,
+                            });
+                        }
+                        continue;
+                    }
+                }
+            }
+            // When blessing annotations, an annotation that only has a single diagnostic of its
+            // level (and code and column) left on its line is rewritten to match that diagnostic.
+            if let (Some(source), true, None, Some(Pattern::SubString(old)), Some(line)) =
+                (&mut source, config.bless_annotations, count, pattern, line)
+            {
+                let rewritable: Vec<_> = msgs
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, msg)| {
+                        msg.level == level
+                            && level != Level::Suggestion
+                            && column.map_or(true, |column| msg.column == Some(column))
+                            && code
+                                .as_ref()
+                                .map_or(true, |code| msg.code.as_ref() == Some(code))
+                    })
+                    .map(|(i, _)| i)
+                    .collect();
+                match rewritable[..] {
+                    [] => {}
+                    [i] => {
+                        let new = msgs[i].message.lines().next().unwrap_or_default();
+                        if rewrite_annotation(source, definition_line, old, new) {
+                            errors.push(Error::AnnotationRewritten {
+                                definition_line,
+                                old: old.clone(),
+                                new: new.to_owned(),
+                            });
+                            msgs.remove(i);
+                            source_changed = true;
+                            continue;
+                        }
+                    }
+                    _ => {
+                        errors.push(Error::AmbiguousAnnotation {
+                            definition_line,
+                            line,
+                            msgs: rewritable.iter().map(|&i| msgs[i].clone()).collect(),
+                        });
+                        continue;
+                    }
+                }
+            }
+            actual_code = msgs.iter().find(|msg| candidate(msg)).map(|msg| &msg.code);
+            suggestions = msgs
+                .iter()
+                .filter(|msg| msg.level == Level::Suggestion)
+                .map(|msg| msg.message.clone())
+                .collect();
+        }
 
-        errors.push(Error::PatternNotFound {
-            pattern: pattern.clone(),
-            definition_line,
-        });
+        if prune && prunable(&source, definition_line) {
+            pruned.push(definition_line);
+            continue;
+        }
+
+        if let (Level::Suggestion, Some(pattern)) = (level, pattern) {
+            errors.push(Error::SuggestionNotFound {
+                pattern: pattern.clone(),
+                suggestions,
+                definition_line,
+            });
+            continue;
+        }
+
+        match (pattern, code) {
+            (Some(pattern), Some(_)) if actual_code.is_none() => {
+                errors.push(Error::PatternNotFound {
+                    pattern: pattern.clone(),
+                    definition_line,
+                    near_misses: near_misses(
+                        pattern,
+                        line,
+                        &messages,
+                        &messages_from_unknown_file_or_line,
+                    ),
+                });
+                not_found.push((line, pattern, definition_line));
+            }
+            (Some(pattern), None) => {
+                errors.push(Error::PatternNotFound {
+                    pattern: pattern.clone(),
+                    definition_line,
+                    near_misses: near_misses(
+                        pattern,
+                        line,
+                        &messages,
+                        &messages_from_unknown_file_or_line,
+                    ),
+                });
+                not_found.push((line, pattern, definition_line));
+            }
+            (_, Some(code)) => errors.push(Error::CodeMismatch {
+                expected: code.clone(),
+                actual: actual_code.cloned().flatten(),
+                definition_line,
+            }),
+            (None, None) => unreachable!("annotations without pattern must have an error code"),
+        }
+    }
+
+    if let Some(source) = &mut source {
+        for &definition_line in &pruned {
+            errors.push(prune_annotation(source, definition_line));
+            source_changed = true;
+        }
     }
 
     let required_annotation_level = comments
-        .find_one_for_revision(
-            revision,
-            |r| r.require_annotations_for_level,
-            |_| {
-                errors.push(Error::InvalidComment {
-                    msg: "`require_annotations_for_level` specified twice for same revision".into(),
-                    line: 0,
-                })
-            },
-        )
+        .require_annotations_for_level(errors, revision, config)
         .unwrap_or(lowest_annotation_level);
     let filter = |mut msgs: Vec<Message>| -> Vec<_> {
         msgs.retain(|msg| msg.level >= required_annotation_level);
@@ -948,28 +3066,59 @@ fn check_annotations(
 
     let mode = config.mode.maybe_override(comments, revision, errors);
 
-    if !matches!(config.mode, Mode::Yolo) {
-        let messages_from_unknown_file_or_line = filter(messages_from_unknown_file_or_line);
-        if !messages_from_unknown_file_or_line.is_empty() {
+    let messages_from_unknown_file_or_line = filter(messages_from_unknown_file_or_line);
+    if !messages_from_unknown_file_or_line.is_empty() {
+        errors.push(Error::ErrorsWithoutPattern {
+            path: None,
+            similar_patterns: similar_patterns(
+                &not_found,
+                None,
+                &messages_from_unknown_file_or_line,
+            ),
+            msgs: messages_from_unknown_file_or_line,
+        });
+    }
+
+    // Lines whose diagnostics get annotations inserted. Going from the bottom of the file
+    // upwards, so the inserted lines don't move the lines that still need annotations.
+    let mut unannotated = vec![];
+    for (line, msgs) in messages.into_iter().enumerate() {
+        let msgs = filter(msgs);
+        if msgs.is_empty() {
+            continue;
+        }
+        if insert
+            && source.is_some()
+            && msgs.iter().all(|msg| msg.level.annotation_name().is_some())
+        {
+            unannotated.push((line, msgs));
+        } else {
             errors.push(Error::ErrorsWithoutPattern {
-                path: None,
-                msgs: messages_from_unknown_file_or_line,
+                path: Some((path.to_path_buf(), line)),
+                similar_patterns: similar_patterns(&not_found, Some(line), &msgs),
+                msgs,
             });
         }
+    }
+    if let Some(source) = &mut source {
+        for (line, msgs) in unannotated.into_iter().rev() {
+            errors.push(insert_annotations(source, line, &msgs));
+            source_changed = true;
+            seen_error_match = true;
+        }
+    }
 
-        for (line, msgs) in messages.into_iter().enumerate() {
-            let msgs = filter(msgs);
-            if !msgs.is_empty() {
-                errors.push(Error::ErrorsWithoutPattern {
-                    path: Some((path.to_path_buf(), line)),
-                    msgs,
-                });
-            }
+    if let (Some(mut source), true) = (source, source_changed) {
+        fix_annotation_offsets(&mut source, comments, &pruned);
+        if let Err(err) = write_atomically(path, source.concat().as_bytes()) {
+            errors.push(write_error(path, err));
         }
     }
 
     match (mode, seen_error_match) {
-        (Mode::Pass, true) | (Mode::Panic, true) => errors.push(Error::PatternFoundInPassTest),
+        (Mode::Pass | Mode::Check | Mode::Panic, true) if !config.rustdoc => {
+            errors.push(Error::PatternFoundInPassTest)
+        }
         (
             Mode::Fail {
                 require_patterns: true,
@@ -980,35 +3129,361 @@ fn check_annotations(
     }
 }
 
+/// When blessing annotations, rewrite compiletest's `//[rev]~` annotations in the test file
+/// to `//~[rev]`, once for all revisions. Returns the rewritten annotations.
+fn migrate_legacy_annotations(path: &Path) -> std::result::Result<Errors, Error> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|err| Error::Bug(format!("could not read `{}`: {err}", path.display())))?;
+    let mut source: Vec<_> = source.split_inclusive('\n').map(String::from).collect();
+    let migrated = parser::migrate_legacy_annotations(&mut source);
+    write_atomically(path, source.concat().as_bytes()).map_err(|err| write_error(path, err))?;
+    Ok(migrated
+        .into_iter()
+        .map(|(definition_line, old, new)| Error::AnnotationRewritten {
+            definition_line,
+            old,
+            new,
+        })
+        .collect())
+}
+
+/// Replace the last occurrence of `old` in the annotation on line `definition_line` with `new`.
+/// Returns whether the annotation was found.
+fn rewrite_annotation(source: &mut [String], definition_line: usize, old: &str, new: &str) -> bool {
+    let Some(line) = source.get_mut(definition_line - 1) else {
+        return false;
+    };
+    let Some(start) = line.find("//~").or_else(|| line.find("/*~")) else {
+        return false;
+    };
+    match line[start..].rfind(old) {
+        Some(pos) => {
+            line.replace_range(start + pos..start + pos + old.len(), new);
+            true
+        }
+        // Another revision already rewrote the annotation.
+        None => line[start..].contains(new),
+    }
+}
+
+/// The longest line that an annotation is appended to instead of being put in a new line.
+const MAX_ANNOTATED_LINE_LENGTH: usize = 100;
+
+/// Annotate the diagnostics reported in `line`, either at the end of the line, or in new lines
+/// below it (and below any annotations that already follow it).
+fn insert_annotations(source: &mut [String], line: usize, msgs: &[Message]) -> Error {
+    let annotation = |msg: &Message| {
+        format!(
+            "{}: {}",
+            msg.level.annotation_name().unwrap(),
+            msg.message.lines().next().unwrap_or_default()
+        )
+    };
+    let code = &source[line - 1];
+    let newline = if code.ends_with("\r\n") { "\r\n" } else { "\n" };
+    let trimmed = code.trim_end();
+    if let [msg] = msgs {
+        let annotated = format!("{trimmed} //~ {}", annotation(msg));
+        if !trimmed.contains("//") && annotated.len() <= MAX_ANNOTATED_LINE_LENGTH {
+            let removed = std::mem::replace(&mut source[line - 1], annotated.clone() + newline);
+            return Error::AnnotationsInserted {
+                line,
+                removed: Some(removed.trim_end().to_owned()),
+                added: vec![annotated],
+            };
+        }
+    }
+    let indent = &trimmed[..trimmed.len() - trimmed.trim_start().len()];
+    let mut insert_at = line;
+    while source
+        .get(insert_at)
+        .map_or(false, |next| next.trim_start().starts_with("//~"))
+    {
+        insert_at += 1;
+    }
+    let carets = "^".repeat(insert_at + 1 - line);
+    let added: Vec<_> = msgs
+        .iter()
+        .enumerate()
+        .map(|(i, msg)| {
+            let offset = if i == 0 { &carets } else { "|" };
+            format!("{indent}//~{offset} {}", annotation(msg))
+        })
+        .collect();
+    let above = &mut source[insert_at - 1];
+    if !above.ends_with('\n') {
+        above.push_str(newline);
+    }
+    for added in &added {
+        above.push_str(added);
+        above.push_str(newline);
+    }
+    Error::AnnotationsInserted {
+        line,
+        removed: None,
+        added,
+    }
+}
+
+/// Whether the annotation defined in `definition_line` can be removed from the source.
+/// Only `//~` annotations that end in their line are removed.
+fn prunable(source: &Option<Vec<String>>, definition_line: usize) -> bool {
+    source
+        .as_ref()
+        .and_then(|source| source.get(definition_line - 1))
+        .map_or(false, |line| {
+            line.contains("//~") && !line.contains("/*~") && !line.trim_end().ends_with('\\')
+        })
+}
+
+/// Remove the annotation defined in `definition_line`, or the entire line if it only
+/// contains the annotation.
+fn prune_annotation(source: &mut [String], definition_line: usize) -> Error {
+    let line = &mut source[definition_line - 1];
+    let start = line.find("//~").unwrap();
+    let code = line[..start].trim_end();
+    let annotation = line[start..].trim_end().to_owned();
+    *line = if code.is_empty() {
+        String::new()
+    } else {
+        let newline = if line.ends_with("\r\n") {
+            "\r\n"
+        } else if line.ends_with('\n') {
+            "\n"
+        } else {
+            ""
+        };
+        format!("{code}{newline}")
+    };
+    Error::AnnotationRemoved {
+        definition_line,
+        annotation,
+    }
+}
+
+/// After lines were removed or inserted, make the `^`, `v`, `|` and `LINE:` offsets of the
+/// remaining `//~` annotations point to the lines they pointed to before.
+fn fix_annotation_offsets(source: &mut [String], comments: &Comments, pruned: &[usize]) {
+    // The line number each line will have once the file is written.
+    let mut new_lines = Vec::with_capacity(source.len() + 1);
+    new_lines.push(0);
+    let mut next = 1;
+    for line in source.iter() {
+        new_lines.push(next);
+        next += line.matches('\n').count();
+    }
+    let annotations: Vec<_> = comments
+        .revisioned
+        .values()
+        .flat_map(|r| r.error_matches.iter())
+        .filter(|error_match| !pruned.contains(&error_match.definition_line))
+        .filter_map(|error_match| Some((error_match, error_match.line?)))
+        .collect();
+    let targets: HashMap<usize, usize> = annotations
+        .iter()
+        .map(|(error_match, line)| (new_lines[error_match.definition_line], new_lines[*line]))
+        .collect();
+    for (error_match, line) in annotations {
+        let definition_line = error_match.definition_line;
+        let new_definition_line = new_lines[definition_line];
+        let new_line = new_lines[line];
+        let text = &mut source[definition_line - 1];
+        if text.contains("/*~") {
+            continue;
+        }
+        let Some(mut start) = text.find("//~").map(|start| start + 3) else {
+            continue;
+        };
+        if text[start..].starts_with('[') {
+            let Some(end) = text[start..].find(']') else {
+                continue;
+            };
+            start += end + 1;
+        }
+        let rest = &text[start..];
+        let len = if let Some(digits) = rest.strip_prefix("LINE:") {
+            5 + digits.len()
+                - digits
+                    .trim_start_matches(|c: char| c.is_ascii_digit())
+                    .len()
+        } else if rest.starts_with('|') {
+            1
+        } else {
+            rest.len() - rest.trim_start_matches(['^', 'v']).len()
+        };
+        let offset = if error_match.absolute_line {
+            format!("LINE:{new_line}")
+        } else if rest.starts_with('|')
+            && targets.get(&(new_definition_line - 1)) == Some(&new_line)
+        {
+            "|".into()
+        } else if new_line <= new_definition_line {
+            "^".repeat(new_definition_line - new_line)
+        } else {
+            "v".repeat(new_line - new_definition_line)
+        };
+        if rest[..len] != offset {
+            text.replace_range(start..start + len, &offset);
+        }
+    }
+}
+
+/// Write `contents` to a temporary file next to `path`, and then move it to `path`,
+/// so that `path` is never left half written.
+fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, path)
+}
+
+/// The positions (line and index) in `messages` of the diagnostics that have another primary
+/// span on `line` than the one they are reported on, and that `error_match` matches there.
+fn matches_on_other_lines(
+    messages: &[Vec<Message>],
+    line: usize,
+    error_match: &ErrorMatch,
+) -> Vec<(usize, usize)> {
+    let mut found = vec![];
+    for (msg_line, msgs) in messages.iter().enumerate() {
+        for (i, msg) in msgs.iter().enumerate() {
+            let matches = msg.other_sites.iter().any(|&(other_line, column)| {
+                other_line == line
+                    && error_match.matches(&Message {
+                        column: Some(column),
+                        ..msg.clone()
+                    })
+            });
+            if matches {
+                found.push((msg_line, i));
+            }
+        }
+    }
+    found
+}
+
+/// How similar, in percent, a diagnostic and a pattern must be to be reported as a near miss.
+const NEAR_MISS_SIMILARITY: usize = 50;
+/// How many lines above and below a pattern's line are searched for near misses.
+const NEAR_MISS_LINES: usize = 2;
+/// How many near misses are reported at most.
+const NEAR_MISS_COUNT: usize = 3;
+
+/// The unmatched diagnostics near `line` that are the most similar to the pattern.
+fn near_misses(
+    pattern: &Pattern,
+    line: Option<usize>,
+    messages: &[Vec<Message>],
+    messages_from_unknown_file_or_line: &[Message],
+) -> Vec<NearMiss> {
+    let candidates: Vec<_> = match line {
+        Some(line) => (line.saturating_sub(NEAR_MISS_LINES)..=line + NEAR_MISS_LINES)
+            .flat_map(|line| {
+                messages
+                    .get(line)
+                    .into_iter()
+                    .flatten()
+                    .map(move |msg| (Some(line), msg))
+            })
+            .collect(),
+        None => messages_from_unknown_file_or_line
+            .iter()
+            .map(|msg| (None, msg))
+            .collect(),
+    };
+    let mut near_misses: Vec<_> = candidates
+        .into_iter()
+        .filter(|(_, msg)| msg.level != Level::Suggestion)
+        .map(|(line, msg)| NearMiss {
+            similarity: pattern.similarity(msg),
+            msg: msg.clone(),
+            line,
+        })
+        .filter(|near_miss| near_miss.similarity >= NEAR_MISS_SIMILARITY)
+        .collect();
+    near_misses.sort_by_key(|near_miss| std::cmp::Reverse(near_miss.similarity));
+    near_misses.truncate(NEAR_MISS_COUNT);
+    near_misses
+}
+
+/// The patterns that were not found near `line`, and are the most similar to one of the `msgs`.
+fn similar_patterns(
+    not_found: &[(Option<usize>, &Pattern, usize)],
+    line: Option<usize>,
+    msgs: &[Message],
+) -> Vec<SimilarPattern> {
+    let mut similar_patterns: Vec<_> = not_found
+        .iter()
+        .filter(|(pattern_line, ..)| match (line, pattern_line) {
+            (Some(line), Some(pattern_line)) => line.abs_diff(*pattern_line) <= NEAR_MISS_LINES,
+            (None, None) => true,
+            _ => false,
+        })
+        .filter_map(|&(_, pattern, definition_line)| {
+            let similarity = msgs.iter().map(|msg| pattern.similarity(msg)).max()?;
+            (similarity >= NEAR_MISS_SIMILARITY).then(|| SimilarPattern {
+                pattern: pattern.clone(),
+                definition_line,
+                similarity,
+            })
+        })
+        .collect();
+    similar_patterns.sort_by_key(|similar| std::cmp::Reverse(similar.similarity));
+    similar_patterns.truncate(NEAR_MISS_COUNT);
+    similar_patterns
+}
+
 fn check_output(
     output: &[u8],
     path: &Path,
     errors: &mut Errors,
     kind: String,
     filters: &Filter,
+    normalizations: Normalizations,
     config: &Config,
     comments: &Comments,
     revision: &str,
+    artifacts: &mut Artifacts,
+    usage: &mut Usage,
 ) -> PathBuf {
-    let target = config.target.as_ref().unwrap();
-    let output = normalize(path, output, filters, comments, revision);
-    let path = output_path(path, comments, kind, target, revision);
-    match &config.output_conflict_handling {
-        OutputConflictHandling::Bless => {
-            if output.is_empty() {
-                let _ = std::fs::remove_file(&path);
-            } else {
-                std::fs::write(&path, &output).unwrap();
+    if comments.yolo(revision, config) {
+        return output_path(path, comments, kind, config, revision);
+    }
+    let normalized = normalize(path, output, filters, normalizations, comments, revision);
+    if config.report_unused_normalizations {
+        for ((rule, replacement), count) in filters.iter().zip(&normalized.filter_matches) {
+            if *count > 0 && !config::is_platform_filter(replacement) {
+                usage.filters.insert(usage::describe(rule, replacement));
+            }
+        }
+        for (line, count) in &normalized.normalization_matches {
+            if *count > 0 {
+                usage.normalizations.insert(*line);
             }
         }
+    }
+    let mut output = normalized.text;
+    if !config.preserve_line_endings {
+        output = output.replace("\r\n", "\n");
+    }
+    artifacts.add(&*kind, &output);
+    let path = output_path(path, comments, kind, config, revision);
+    match &config.output_conflict_handling {
+        OutputConflictHandling::Bless => return bless_output(&path, &output, config, errors),
         OutputConflictHandling::Error(bless_command) => {
-            let expected_output = std::fs::read(&path).unwrap_or_default();
-            if output != expected_output {
+            // Empty output means there is no output file, so a missing file and an empty one
+            // are both expected to be empty output.
+            let mut expected_output = std::fs::read(&path).unwrap_or_default();
+            if !config.preserve_line_endings {
+                expected_output = expected_output.replace("\r\n", "\n");
+            }
+            if !same_output(&output, &expected_output, config) {
                 errors.push(Error::OutputDiffers {
                     path: path.clone(),
                     actual: output,
                     expected: expected_output,
                     bless_command: bless_command.clone(),
+                    diff_context: config.diff_context,
                 });
             }
         }
@@ -1017,51 +3492,307 @@ fn check_output(
     path
 }
 
+/// Whether the `actual` output matches the `expected` output. Unless
+/// `Config::preserve_line_endings` is set, a missing newline at the end of either of them
+/// doesn't count, as editors disagree about whether files should end with one.
+fn same_output(actual: &[u8], expected: &[u8], config: &Config) -> bool {
+    if config.preserve_line_endings {
+        return actual == expected;
+    }
+    actual.strip_suffix(b"\n").unwrap_or(actual) == expected.strip_suffix(b"\n").unwrap_or(expected)
+}
+
+/// Write `output` to the output file at `path`, or remove the file if `output` is empty.
+/// If `Config::output_base` is set, the file is written there instead, and the new path is returned.
+fn bless_output(path: &Path, output: &[u8], config: &Config, errors: &mut Errors) -> PathBuf {
+    let path = match &config.output_base {
+        Some(base) => {
+            let path = base.join(path.strip_prefix(&config.root_dir).unwrap_or(path));
+            if let Err(err) = std::fs::create_dir_all(path.parent().unwrap()) {
+                errors.push(write_error(&path, err));
+                return path;
+            }
+            path
+        }
+        None => path.to_owned(),
+    };
+    let result = if output.is_empty() {
+        match std::fs::remove_file(&path) {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    } else {
+        std::fs::write(&path, output)
+    };
+    if let Err(err) = result {
+        errors.push(write_error(&path, err));
+    }
+    path
+}
+
+fn write_error(path: &Path, err: std::io::Error) -> Error {
+    // `EROFS` has the same value on all unix platforms we support
+    let read_only = err.kind() == std::io::ErrorKind::PermissionDenied
+        || (cfg!(unix) && err.raw_os_error() == Some(30));
+    if read_only {
+        Error::ReadOnlyOutput {
+            path: path.to_owned(),
+        }
+    } else {
+        Error::Bug(format!("failed to write `{}`: {err}", path.display()))
+    }
+}
+
+/// Report (or remove, when blessing) an output file that the test is not supposed to have.
+/// An empty file is the same as a missing one, so it is only removed, never reported.
+fn check_stale_output(
+    path: &Path,
+    errors: &mut Errors,
+    kind: String,
+    config: &Config,
+    comments: &Comments,
+    revision: &str,
+) {
+    let path = output_path(path, comments, kind, config, revision);
+    if !path.exists() || comments.yolo(revision, config) {
+        return;
+    }
+    match &config.output_conflict_handling {
+        OutputConflictHandling::Bless => {
+            bless_output(&path, &[], config, errors);
+        }
+        OutputConflictHandling::Error(_) if is_empty_file(&path) => {}
+        OutputConflictHandling::Error(bless_command) => errors.push(Error::StaleOutput {
+            path,
+            bless_command: bless_command.clone(),
+        }),
+        OutputConflictHandling::Ignore => {}
+    }
+}
+
+fn is_empty_file(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|meta| meta.is_file() && meta.len() == 0)
+}
+
 fn output_path(
     path: &Path,
     comments: &Comments,
     kind: String,
-    target: &str,
+    config: &Config,
     revision: &str,
 ) -> PathBuf {
     if comments
         .for_revision(revision)
         .any(|r| r.stderr_per_bitwidth)
     {
-        return path.with_extension(format!("{}bit.{kind}", get_pointer_width(target)));
+        return path.with_extension(format!("{}bit.{kind}", pointer_width(config)));
     }
     path.with_extension(kind)
 }
 
-fn test_condition(condition: &Condition, config: &Config) -> bool {
+fn test_condition(
+    condition: &Condition,
+    config: &Config,
+    comments: &Comments,
+    revision: &str,
+) -> bool {
     let target = config.target.as_ref().unwrap();
     match condition {
-        Condition::Bitwidth(bits) => get_pointer_width(target) == *bits,
+        Condition::Bitwidth(bits) => pointer_width(config) == *bits,
         Condition::Target(t) => target.contains(t),
+        Condition::TargetRegex(regex) => regex.is_match(target.as_bytes()),
         Condition::Host(t) => config.host.as_ref().unwrap().contains(t),
         Condition::OnHost => target == config.host.as_ref().unwrap(),
+        Condition::CrossCompile => target != config.host.as_ref().unwrap(),
+        Condition::Channel(channel) => config.channel == Some(*channel),
+        Condition::OsFamily(name) => match name.as_str() {
+            "unix" => TARGET_OSES
+                .iter()
+                .any(|&(os, unix)| unix && get_target_os(target) == Some(os)),
+            "wasm" => target.starts_with("wasm"),
+            os => get_target_os(target) == Some(os),
+        },
+        Condition::Endian(endianness) => config.endianness() == *endianness,
+        Condition::OptLevel(level) => {
+            let flags = config.program.args.iter().map(|arg| arg.as_os_str()).chain(
+                comments
+                    .for_revision(revision)
+                    .flat_map(|r| r.compile_flags.iter())
+                    .map(OsStr::new),
+            );
+            debug_assertions(flags) == (*level == OptLevel::Debug)
+        }
+        Condition::Custom(name) => config.custom_conditions.get(name).copied().unwrap_or(false),
+        Condition::Env { name, value } => {
+            test_env_condition(name, value.as_deref(), &CONDITION_ENV)
+        }
     }
 }
 
-/// Returns whether according to the in-file conditions, this file should be run.
-fn test_file_conditions(comments: &Comments, config: &Config, revision: &str) -> bool {
-    if comments
+lazy_static! {
+    /// The environment as it was when the test suite started, so `ignore-env-*` and
+    /// `only-env-*` conditions see the same env vars in all tests.
+    static ref CONDITION_ENV: HashMap<OsString, OsString> = std::env::vars_os().collect();
+}
+
+/// Whether the env var `name` is set in `env`, and has the given value, if any.
+fn test_env_condition(name: &str, value: Option<&str>, env: &HashMap<OsString, OsString>) -> bool {
+    match (env.get(OsStr::new(name)), value) {
+        (None, _) => false,
+        (Some(_), None) => true,
+        (Some(actual), Some(value)) => *actual == *value,
+    }
+}
+
+/// Whether the compile flags enable debug assertions. Like with rustc, they are enabled
+/// unless optimizations are, and later flags override earlier ones.
+fn debug_assertions<'a>(flags: impl Iterator<Item = &'a OsStr>) -> bool {
+    let mut optimized = false;
+    let mut debug_assertions = None;
+    let mut flags = flags.map(|flag| flag.to_string_lossy());
+    while let Some(flag) = flags.next() {
+        let codegen = match flag.strip_prefix("-C") {
+            _ if flag == "-O" => {
+                optimized = true;
+                continue;
+            }
+            Some("") => match flags.next() {
+                Some(codegen) => codegen,
+                None => break,
+            },
+            Some(codegen) => codegen.to_owned().into(),
+            None => continue,
+        };
+        let (name, value) = codegen.split_once('=').unwrap_or((&codegen, "yes"));
+        match name {
+            "opt-level" => optimized = value != "0",
+            "debug-assertions" => {
+                debug_assertions = Some(matches!(value, "yes" | "y" | "on" | "true"));
+            }
+            _ => {}
+        }
+    }
+    debug_assertions.unwrap_or(!optimized)
+}
+
+/// The `target-` and `host-` conditions that are not part of any known target triple. As they
+/// never apply to a known target, they are probably misspelled.
+fn check_triple_conditions(comments: &Comments, config: &Config) -> Vec<Error> {
+    let Some(known_triples) = &config.known_triples else {
+        return vec![];
+    };
+    let known = known_triples
+        .iter()
+        .chain(&config.host)
+        .chain(&config.target);
+    comments
+        .revisioned
+        .values()
+        .flat_map(|r| {
+            let ignore = r.ignore.iter().map(|c| ("ignore", c));
+            let only = r.only.iter().map(|c| ("only", c));
+            ignore.chain(only)
+        })
+        .filter_map(|(kind, (c, line))| {
+            let (Condition::Target(substr) | Condition::Host(substr)) = c else {
+                return None;
+            };
+            (!known.clone().any(|triple| triple.contains(substr.as_str()))).then(|| {
+                Error::UnknownTriple {
+                    condition: format!("{kind}-{c}"),
+                    line: *line,
+                }
+            })
+        })
+        .collect()
+}
+
+/// Why the in-file conditions say that a test should not be run.
+enum Skip<'a> {
+    /// An `ignore-` condition that applies, and its line.
+    Ignore(&'a Condition, usize),
+    /// An `only-` condition that doesn't apply, and its line.
+    Only(&'a Condition, usize),
+    /// `needs-asm-support` on a target without `asm!`.
+    NeedsAsmSupport,
+    /// `run: signal=...` on a host that has no signals.
+    NeedsSignals,
+    /// A run test while cross compiling, whose binary can't be executed on the host.
+    RunWhenCross,
+}
+
+impl Skip<'_> {
+    /// Renders the reason, adding the release channel to channel conditions, as which channel
+    /// is used is usually not obvious.
+    fn reason(&self, config: &Config) -> String {
+        let condition = |kind, c: &Condition, line| match (c, config.channel) {
+            (Condition::Channel(_), Some(channel)) => {
+                format!("{kind}-{c} at line {line}: the program is {channel}")
+            }
+            _ => format!("{kind}-{c} at line {line}"),
+        };
+        match *self {
+            Skip::Ignore(c, line) => condition("ignore", c, line),
+            Skip::Only(c, line) => condition("only", c, line),
+            Skip::NeedsAsmSupport => "needs-asm-support".into(),
+            Skip::NeedsSignals => {
+                "the test expects to be killed by a signal, which needs a unix host".into()
+            }
+            Skip::RunWhenCross => "run test while cross compiling".into(),
+        }
+    }
+}
+
+/// Returns why according to the in-file conditions this file should not be run, if it shouldn't.
+fn test_file_conditions<'a>(
+    comments: &'a Comments,
+    config: &Config,
+    revision: &'a str,
+) -> Option<Skip<'a>> {
+    if let Some((c, line)) = comments
         .for_revision(revision)
         .flat_map(|r| r.ignore.iter())
-        .any(|c| test_condition(c, config))
+        .find(|(c, _)| test_condition(c, config, comments, revision))
     {
-        return false;
+        return Some(Skip::Ignore(c, *line));
     }
     if comments
         .for_revision(revision)
         .any(|r| r.needs_asm_support && !config.has_asm_support())
     {
-        return false;
+        return Some(Skip::NeedsAsmSupport);
+    }
+    // Multiple mode changes are reported when the test runs.
+    let mode = config.mode.maybe_override(comments, revision, &mut vec![]);
+    if cfg!(not(unix))
+        && matches!(
+            mode,
+            Mode::Run {
+                exit: ExitExpectation::Signal(_)
+            }
+        )
+    {
+        return Some(Skip::NeedsSignals);
+    }
+    if config.skip_run_tests_when_cross
+        && config.runner.is_none()
+        && config.cross_target().is_some()
+        && matches!(mode, Mode::Run { .. })
+    {
+        return Some(Skip::RunWhenCross);
     }
     comments
         .for_revision(revision)
         .flat_map(|r| r.only.iter())
-        .all(|c| test_condition(c, config))
+        .find(|(c, _)| !test_condition(c, config, comments, revision))
+        .map(|(c, line)| Skip::Only(c, *line))
+}
+
+/// The pointer width of the target, guessed from the triple if it couldn't be detected.
+fn pointer_width(config: &Config) -> u8 {
+    config
+        .target_pointer_width
+        .unwrap_or_else(|| get_pointer_width(config.target.as_ref().unwrap()))
 }
 
 // Taken 1:1 from compiletest-rs
@@ -1070,20 +3801,54 @@ fn get_pointer_width(triple: &str) -> u8 {
         || triple.starts_with("s390x")
     {
         64
-    } else if triple.starts_with("avr") {
+    } else if triple.starts_with("avr") || triple.starts_with("msp430") {
         16
     } else {
         32
     }
 }
 
+/// The operating system of the target, as in `cfg(target_os = "...")`, if it is one of
+/// the [`TARGET_OSES`]. Triples are `arch-vendor-os-env`, where the vendor and env are optional.
+fn get_target_os(triple: &str) -> Option<&'static str> {
+    // `aarch64-linux-android` has `target_os = "android"`.
+    if triple.contains("-android") {
+        return Some("android");
+    }
+    triple
+        .split('-')
+        .skip(1)
+        .find_map(|component| match component {
+            "darwin" => Some("macos"),
+            _ => TARGET_OSES
+                .iter()
+                .map(|&(os, _)| os)
+                // `wasm32-wasip1` or `x86_64-unknown-freebsd`
+                .find(|os| component.starts_with(os)),
+        })
+}
+
+/// Selects which of the per-test normalizations to apply to an output.
+type Normalizations = fn(&Revisioned) -> &Vec<(Regex, Vec<u8>, usize)>;
+
+/// The output of a test after applying the filters and the `normalize-*-test` comments.
+struct Normalized {
+    text: Vec<u8>,
+    /// How many matches each of the filters replaced, in order.
+    filter_matches: Vec<usize>,
+    /// How many matches each of the applied `normalize-*-test` comments replaced,
+    /// with the line of the comment.
+    normalization_matches: Vec<(usize, usize)>,
+}
+
 fn normalize(
     path: &Path,
     text: &[u8],
     filters: &Filter,
+    normalizations: Normalizations,
     comments: &Comments,
     revision: &str,
-) -> Vec<u8> {
+) -> Normalized {
     // Useless paths
     let path_filter = (Match::from(path.parent().unwrap()), b"$DIR" as &[u8]);
     let filters = filters.iter().chain(std::iter::once(&path_filter));
@@ -1092,15 +3857,31 @@ fn normalize(
         text = text.replace(lib_path, "RUSTLIB");
     }
 
+    let mut filter_matches = vec![];
     for (rule, replacement) in filters {
-        text = rule.replace_all(&text, replacement).into_owned();
+        let (replaced, count) = rule.replace_all(&text, replacement);
+        text = replaced.into_owned();
+        filter_matches.push(count);
     }
+    // The `$DIR` filter is not one of the `filters`.
+    filter_matches.pop();
 
-    for (from, to) in comments
+    let mut normalization_matches = vec![];
+    for (from, to, line) in comments
         .for_revision(revision)
-        .flat_map(|r| r.normalize_stderr.iter())
+        .flat_map(|r| normalizations(r).iter())
     {
-        text = from.replace_all(&text, to).into_owned();
+        let (replaced, count) = replace_counted(from, &text, |caps| {
+            let mut replaced = vec![];
+            caps.expand(to, &mut replaced);
+            replaced
+        });
+        text = replaced.into_owned();
+        normalization_matches.push((*line, count));
+    }
+    Normalized {
+        text,
+        filter_matches,
+        normalization_matches,
     }
-    text
 }
@@ -5,16 +5,20 @@ use std::fmt::Display;
 use std::process::ExitStatus;
 
 #[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 /// Decides what is expected of each test's exit status.
 pub enum Mode {
     /// The test fails with an error, but passes after running rustfix
     Fix,
     /// The test passes a full execution of the rustc driver
     Pass,
+    /// The test passes type checking. It is compiled with `--emit=metadata`, so code
+    /// generation and linking are skipped
+    Check,
     /// The test produces an executable binary that can get executed on the host
     Run {
-        /// The expected exit code
-        exit_code: i32,
+        /// How the binary is expected to exit
+        exit: ExitExpectation,
     },
     /// The rustc driver panicked
     Panic,
@@ -23,21 +27,147 @@ pub enum Mode {
         /// Whether failing tests must have error patterns. Set to false if you just care about .stderr output.
         require_patterns: bool,
     },
-    /// Run the tests, but always pass them as long as all annotations are satisfied and stderr files match.
+    /// Only check that the compiler doesn't crash with an internal compiler error, and that
+    /// the binary of `//@run` tests is not killed by a signal. The exit status, annotations and
+    /// output files are not checked (nor blessed). Tests can opt into this via `//@yolo`.
     Yolo,
 }
 
+/// How the binary of a `Mode::Run` test is expected to exit.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum ExitExpectation {
+    /// Exiting with this exit code, e.g. `//@run: 101`.
+    Code(i32),
+    /// Being killed by this signal, e.g. `//@run: signal=abrt`. Only supported on unix,
+    /// tests expecting a signal are ignored elsewhere.
+    Signal(Signal),
+    /// Exiting with any exit code, i.e. not being killed by a signal. Used for the binaries
+    /// of `Mode::Yolo` tests.
+    AnyCode,
+    /// Panicking, e.g. `//@run-panic: message`: exiting with code 101, or being killed by
+    /// `SIGABRT` if the binary is compiled with `-Cpanic=abort`.
+    Panic,
+}
+
+/// The signals that `//@run: signal=NAME` can expect, named like their `SIG*` constant
+/// in lowercase and without the prefix.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Signal {
+    /// `SIGABRT`, e.g. by `std::process::abort` or a stack overflow.
+    Abrt,
+    /// `SIGBUS`
+    Bus,
+    /// `SIGFPE`
+    Fpe,
+    /// `SIGILL`, e.g. by `std::intrinsics::abort`.
+    Ill,
+    /// `SIGKILL`
+    Kill,
+    /// `SIGSEGV`
+    Segv,
+    /// `SIGTERM`
+    Term,
+    /// `SIGTRAP`
+    Trap,
+}
+
+impl Signal {
+    const ALL: [(Signal, &'static str); 8] = [
+        (Signal::Abrt, "abrt"),
+        (Signal::Bus, "bus"),
+        (Signal::Fpe, "fpe"),
+        (Signal::Ill, "ill"),
+        (Signal::Kill, "kill"),
+        (Signal::Segv, "segv"),
+        (Signal::Term, "term"),
+        (Signal::Trap, "trap"),
+    ];
+
+    /// The number of the signal on this platform.
+    #[cfg(unix)]
+    fn number(self) -> i32 {
+        match self {
+            Signal::Abrt => libc::SIGABRT,
+            Signal::Bus => libc::SIGBUS,
+            Signal::Fpe => libc::SIGFPE,
+            Signal::Ill => libc::SIGILL,
+            Signal::Kill => libc::SIGKILL,
+            Signal::Segv => libc::SIGSEGV,
+            Signal::Term => libc::SIGTERM,
+            Signal::Trap => libc::SIGTRAP,
+        }
+    }
+}
+
+impl std::str::FromStr for Signal {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.strip_prefix("sig").unwrap_or(s);
+        Signal::ALL
+            .iter()
+            .find(|&&(_, n)| n == name)
+            .map(|&(signal, _)| signal)
+            .ok_or_else(|| {
+                let names: Vec<_> = Signal::ALL.iter().map(|(_, n)| format!("`{n}`")).collect();
+                format!("unknown signal `{s}`, expected one of {}", names.join(", "))
+            })
+    }
+}
+
+impl Display for Signal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (_, name) = Signal::ALL.iter().find(|(s, _)| s == self).unwrap();
+        write!(f, "SIG{}", name.to_uppercase())
+    }
+}
+
+impl ExitExpectation {
+    /// Whether the binary exited as expected.
+    pub(crate) fn matches(self, status: ExitStatus) -> bool {
+        match self {
+            ExitExpectation::Code(code) => status.code() == Some(code),
+            #[cfg(unix)]
+            ExitExpectation::Signal(signal) => {
+                use std::os::unix::process::ExitStatusExt;
+                status.signal() == Some(signal.number())
+            }
+            #[cfg(not(unix))]
+            ExitExpectation::Signal(_) => false,
+            ExitExpectation::AnyCode => status.code().is_some(),
+            ExitExpectation::Panic => {
+                ExitExpectation::Code(101).matches(status)
+                    || ExitExpectation::Signal(Signal::Abrt).matches(status)
+            }
+        }
+    }
+}
+
+impl Display for ExitExpectation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExitExpectation::Code(code) => write!(f, "{code}"),
+            ExitExpectation::Signal(signal) => write!(f, "signal {signal}"),
+            ExitExpectation::AnyCode => write!(f, "any exit code"),
+            ExitExpectation::Panic => write!(f, "a panic"),
+        }
+    }
+}
+
 impl Mode {
     pub(crate) fn ok(self, status: ExitStatus) -> Errors {
         let expected = match self {
-            Mode::Run { exit_code } => exit_code,
-            Mode::Pass => 0,
-            Mode::Panic => 101,
-            Mode::Fail { .. } => 1,
+            Mode::Run { exit } => exit,
+            Mode::Pass | Mode::Check => ExitExpectation::Code(0),
+            Mode::Panic => ExitExpectation::Code(101),
+            Mode::Fail { .. } => ExitExpectation::Code(1),
             Mode::Fix | Mode::Yolo => return vec![],
         };
-        if status.code() == Some(expected) {
+        if expected.matches(status) {
             vec![]
+        } else if expected == ExitExpectation::Panic && status.success() {
+            vec![Error::DidNotPanic]
         } else {
             vec![Error::ExitStatus {
                 mode: self,
@@ -52,7 +182,7 @@ impl Mode {
         revision: &str,
         errors: &mut Vec<Error>,
     ) -> Self {
-        comments
+        let mode = comments
             .find_one_for_revision(
                 revision,
                 |r| r.mode.as_ref(),
@@ -63,16 +193,48 @@ impl Mode {
                     })
                 },
             )
-            .map(|&(mode, _)| mode)
-            .unwrap_or(self)
+            .map(|&(mode, line)| match (mode, self) {
+                // `//@check-fail` keeps whether the suite requires patterns.
+                (Mode::Fail { .. }, Mode::Fail { require_patterns }) => {
+                    (Mode::Fail { require_patterns }, None)
+                }
+                (mode, _) => (mode, Some(line)),
+            });
+        let (mode, mode_line) = mode.unwrap_or((self, None));
+        match (comments.require_annotations(errors, revision), mode) {
+            (Some((require_patterns, _)), Mode::Fail { .. }) => Mode::Fail { require_patterns },
+            // Other suites may run the test in a failing mode, but its own mode never will.
+            (Some((require_patterns, line)), _) => {
+                if let Some(mode_line) = mode_line {
+                    let command = if require_patterns {
+                        "require-annotations"
+                    } else {
+                        "dont-require-annotations"
+                    };
+                    errors.push(Error::InvalidComment {
+                        msg: format!(
+                            "`{command}` only applies to tests that fail to compile, \
+                             but line {mode_line} changes the mode to `{mode}`"
+                        ),
+                        line,
+                    });
+                }
+                mode
+            }
+            (None, _) => mode,
+        }
     }
 }
 
 impl Display for Mode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Mode::Run { exit_code } => write!(f, "run({exit_code})"),
+            Mode::Run {
+                exit: ExitExpectation::Panic,
+            } => write!(f, "run-panic"),
+            Mode::Run { exit } => write!(f, "run({exit})"),
             Mode::Pass => write!(f, "pass"),
+            Mode::Check => write!(f, "check"),
             Mode::Panic => write!(f, "panic"),
             Mode::Fail {
                 require_patterns: _,
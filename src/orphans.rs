@@ -0,0 +1,107 @@
+//! Output files that no test produces, e.g. because the test was renamed or deleted,
+//! see `TestSuiteResult::orphaned_outputs`.
+
+use crate::{parser::Comments, schedule::Schedule};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+#[cfg(test)]
+mod tests;
+
+/// What output files end with, after the test's name, its bitwidth and its revision,
+/// e.g. `foo.64bit.rev.run.stderr`.
+const KINDS: &[&str] = &["stderr", "stdout", "fixed", "run.stderr", "run.stdout"];
+
+/// Whether `path` looks like an output file of some test.
+pub(crate) fn is_output(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == "stderr" || ext == "stdout" || ext == "fixed")
+}
+
+/// The `outputs` that none of the scheduled tests produce. Only files next to tests are
+/// considered, so the outputs of other tools (e.g. of nested test suites) are left alone.
+/// The outputs of tests that are not `selected` for any revision are never orphaned, while
+/// outputs without a test are only orphaned if their own path is `selected`.
+pub(crate) fn find(
+    schedule: &Schedule,
+    outputs: &[PathBuf],
+    selected: impl Fn(&Path, &str) -> bool,
+) -> Vec<PathBuf> {
+    let mut tests_by_dir: HashMap<&Path, Vec<&Path>> = HashMap::new();
+    for test in &schedule.files {
+        tests_by_dir.entry(dir(test)).or_default().push(test);
+    }
+    let mut orphans: Vec<_> = outputs
+        .iter()
+        .filter(|output| {
+            let Some(tests) = tests_by_dir.get(dir(output)) else {
+                return false;
+            };
+            let name = output.file_name().unwrap().to_string_lossy();
+            // The tests whose name the output starts with, and the rest of the output's name.
+            let owners: Vec<_> = tests
+                .iter()
+                .filter_map(|test| {
+                    let stem = test.file_stem()?.to_string_lossy();
+                    let rest = name.strip_prefix(&*stem)?.strip_prefix('.')?;
+                    Some((test, rest.to_owned()))
+                })
+                .collect();
+            if owners.is_empty() {
+                return selected(output, "");
+            }
+            owners.iter().all(|(test, rest)| {
+                schedule
+                    .revisions(test)
+                    .iter()
+                    .any(|revision| selected(test, revision))
+                    && !schedule.with_comments(test, |comments| produces(comments, rest))
+            })
+        })
+        .cloned()
+        .collect();
+    orphans.sort();
+    orphans
+}
+
+fn dir(path: &Path) -> &Path {
+    path.parent().unwrap_or(Path::new(""))
+}
+
+/// Whether a test with `comments` produces the output file named `rest` after the test's name,
+/// e.g. `64bit.rev.stderr`. Tests with broken comments might produce anything. The mode is not
+/// taken into account, as the per-file config can change it, so e.g. `.fixed` files are
+/// produced by any test.
+pub(crate) fn produces(comments: Option<&Comments>, rest: &str) -> bool {
+    let Some(comments) = comments else {
+        return true;
+    };
+    let revisions = comments
+        .revisions
+        .clone()
+        .unwrap_or_else(|| vec![String::new()]);
+    revisions.iter().any(|revision| {
+        let mut rest = rest;
+        if comments
+            .for_revision(revision)
+            .any(|r| r.stderr_per_bitwidth)
+        {
+            match rest.split_once("bit.") {
+                Some((bits, tail)) if bits.parse::<u8>().is_ok() => rest = tail,
+                _ => return false,
+            }
+        }
+        if !revision.is_empty() {
+            match rest
+                .strip_prefix(revision.as_str())
+                .and_then(|tail| tail.strip_prefix('.'))
+            {
+                Some(tail) => rest = tail,
+                None => return false,
+            }
+        }
+        KINDS.contains(&rest)
+    })
+}
@@ -0,0 +1,61 @@
+use super::produces;
+use crate::{
+    default_file_filter, default_per_file_config, parser::Comments, run_tests_generic,
+    status_emitter, tests::noop_suite, Args,
+};
+
+#[cfg(unix)]
+#[test]
+fn orphaned_outputs() {
+    let dir = tempfile::tempdir().unwrap();
+    let tests = dir.path().join("tests");
+    std::fs::create_dir_all(tests.join("nested")).unwrap();
+    std::fs::write(tests.join("kept.rs"), "//@revisions: x y\n").unwrap();
+    std::fs::write(tests.join("kept.z.stderr"), "error").unwrap();
+    std::fs::write(tests.join("renamed.stderr"), "error").unwrap();
+    std::fs::write(tests.join("filtered.rs"), "").unwrap();
+    std::fs::write(tests.join("filtered.old.stderr"), "error").unwrap();
+    // Not next to any test, so not an output of these tests.
+    std::fs::write(tests.join("nested/other.stderr"), "error").unwrap();
+    let run = |filters: &[&str], prune| {
+        let config = noop_suite(dir.path());
+        let args = Args {
+            filters: filters.iter().map(|f| f.to_string()).collect(),
+            prune,
+            ..Args::default()
+        };
+        run_tests_generic(
+            config,
+            args,
+            default_file_filter,
+            default_per_file_config,
+            status_emitter::Text::quiet(),
+        )
+        .unwrap()
+        .orphaned_outputs
+    };
+
+    // The outputs of filtered out tests are not orphaned.
+    assert_eq!(
+        run(&["kept", "renamed"], false),
+        [tests.join("kept.z.stderr"), tests.join("renamed.stderr")]
+    );
+    assert!(run(&["kept", "renamed"], true).is_empty());
+    assert!(!tests.join("kept.z.stderr").exists());
+    assert!(!tests.join("renamed.stderr").exists());
+    assert!(tests.join("nested/other.stderr").exists());
+    assert_eq!(run(&[], false), [tests.join("filtered.old.stderr")]);
+}
+
+#[test]
+fn orphaned_output_names() {
+    let comments = Comments::parse("//@revisions: a b\n//@stderr-per-bitwidth\n").unwrap();
+    assert!(produces(Some(&comments), "64bit.a.stderr"));
+    assert!(produces(Some(&comments), "32bit.b.run.stdout"));
+    assert!(!produces(Some(&comments), "a.stderr"));
+    assert!(!produces(Some(&comments), "64bit.c.stderr"));
+    let comments = Comments::parse("").unwrap();
+    assert!(produces(Some(&comments), "fixed"));
+    assert!(!produces(Some(&comments), "a.stderr"));
+    assert!(produces(None, "a.stderr"));
+}
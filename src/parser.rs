@@ -1,14 +1,23 @@
 use std::{
+    any::Any,
+    borrow::Cow,
     collections::HashMap,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 use bstr::{ByteSlice, Utf8Error};
 use regex::bytes::Regex;
 
-use crate::{rustc_stderr::Level, Error, Mode};
+use crate::{
+    config::is_target_flag,
+    emit::Emit,
+    placeholders,
+    rustc_stderr::{Level, Message},
+    Channel, Config, Error, ExitExpectation, Mode,
+};
 
-use color_eyre::eyre::{Context, Result};
+use color_eyre::eyre::{bail, Context, Result};
 
 #[cfg(test)]
 mod tests;
@@ -17,12 +26,58 @@ mod tests;
 /// configuration values. This struct parses them all in one go and then they
 /// get processed by their respective use sites.
 #[derive(Default, Debug)]
-pub(crate) struct Comments {
+pub struct Comments {
     /// List of revision names to execute. Can only be specified once
-    pub revisions: Option<Vec<String>>,
+    pub(crate) revisions: Option<Vec<String>>,
     /// Comments that are only available under specific revisions.
-    /// The defaults are in key `vec![]`
-    pub revisioned: HashMap<Vec<String>, Revisioned>,
+    /// The defaults are in key `RevisionFilter::default()`
+    pub(crate) revisioned: HashMap<RevisionFilter, Revisioned>,
+    /// Uses of deprecated comments. These do not cause parsing to fail.
+    pub(crate) deprecations: Vec<Error>,
+    /// Annotations written in compiletest's `//[rev]~` order. They are parsed just like
+    /// `//~[rev]`, but are reported like deprecations unless `Config::allow_legacy_directives` is set.
+    pub(crate) legacy_annotations: Vec<Error>,
+}
+
+/// The revisions a comment applies to, as written in the `[...]` after `//@` or `//~`.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct RevisionFilter {
+    /// The revision names. Empty if the comment applies to all revisions.
+    pub names: Vec<String>,
+    /// Whether the names were negated with `!`, so the comment applies to all other revisions.
+    pub negated: bool,
+}
+
+impl RevisionFilter {
+    /// Whether comments with this filter apply to the given revision.
+    pub fn matches(&self, revision: &str) -> bool {
+        self.names.is_empty() || self.names.iter().any(|rev| rev == revision) != self.negated
+    }
+}
+
+/// Deprecated commands, the command that replaces them, and the version that deprecated them.
+/// Deprecated commands keep working, but emit a warning mentioning their replacement.
+const DEPRECATED_COMMANDS: &[(&str, &str, &str)] =
+    &[("error-pattern", "error-in-other-file", "0.11.0")];
+
+/// Errors if a command of `Config::custom_comment_parsers` can't be used, because its name
+/// is not a valid command name or is already taken by a built-in command.
+pub(crate) fn check_custom_commands(custom: &HashMap<String, CustomCommandFn>) -> Result<()> {
+    let built_in = CommentParser::<&mut Revisioned>::commands(&HashMap::new());
+    for name in custom.keys() {
+        if name.is_empty() || name.contains(|c: char| !c.is_alphanumeric() && c != '-' && c != '_')
+        {
+            bail!("custom command `{name}` is not a valid command name");
+        }
+        if built_in.contains_key(name)
+            || name == "revisions"
+            || name.starts_with("ignore-")
+            || name.starts_with("only-")
+        {
+            bail!("custom command `{name}` collides with a built-in command");
+        }
+    }
+    Ok(())
 }
 
 impl Comments {
@@ -43,15 +98,23 @@ impl Comments {
         result
     }
 
+    /// The revisions declared via `//@revisions`, or `None` if the test has no revisions.
+    pub fn revisions(&self) -> Option<impl Iterator<Item = &str>> {
+        self.revisions
+            .as_ref()
+            .map(|revisions| revisions.iter().map(String::as_str))
+    }
+
     /// Returns an iterator over all revisioned comments that match the revision.
+    /// They are ordered by the line they start at, so duplicates are reported deterministically.
     pub fn for_revision<'a>(&'a self, revision: &'a str) -> impl Iterator<Item = &'a Revisioned> {
-        self.revisioned.iter().filter_map(move |(k, v)| {
-            if k.is_empty() || k.iter().any(|rev| rev == revision) {
-                Some(v)
-            } else {
-                None
-            }
-        })
+        let mut revisioned: Vec<_> = self
+            .revisioned
+            .iter()
+            .filter_map(move |(k, v)| k.matches(revision).then_some(v))
+            .collect();
+        revisioned.sort_by_key(|r| r.line);
+        revisioned.into_iter()
     }
 
     pub(crate) fn edition(
@@ -73,39 +136,317 @@ impl Comments {
         .cloned()
         .or(config.edition.clone().map(|e| (e, 0)))
     }
+
+    pub(crate) fn stdin(
+        &self,
+        errors: &mut Vec<Error>,
+        revision: &str,
+    ) -> Option<(PathBuf, usize)> {
+        self.find_one_for_revision(
+            revision,
+            |r| r.stdin.clone(),
+            |(_, line)| {
+                errors.push(Error::InvalidComment {
+                    msg: "`stdin` specified twice".into(),
+                    line,
+                })
+            },
+        )
+    }
+
+    pub(crate) fn emit(&self, errors: &mut Vec<Error>, revision: &str) -> Option<(Emit, usize)> {
+        self.find_one_for_revision(
+            revision,
+            |r| r.emit,
+            |(_, line)| {
+                errors.push(Error::InvalidComment {
+                    msg: "`emit` specified twice".into(),
+                    line,
+                })
+            },
+        )
+    }
+
+    /// Whether only internal compiler errors are checked, because the suite runs in
+    /// `Mode::Yolo` or the test has a `//@yolo` comment.
+    pub(crate) fn yolo(&self, revision: &str, config: &Config) -> bool {
+        matches!(config.mode, Mode::Yolo) || self.for_revision(revision).any(|r| r.yolo)
+    }
+
+    pub(crate) fn require_annotations(
+        &self,
+        errors: &mut Vec<Error>,
+        revision: &str,
+    ) -> Option<(bool, usize)> {
+        self.find_one_for_revision(
+            revision,
+            |r| r.require_annotations,
+            |(_, line)| {
+                errors.push(Error::InvalidComment {
+                    msg: "`require-annotations` or `dont-require-annotations` specified twice"
+                        .into(),
+                    line,
+                })
+            },
+        )
+    }
+
+    pub(crate) fn crate_type<'a>(
+        &'a self,
+        errors: &mut Vec<Error>,
+        revision: &'a str,
+    ) -> Option<&'a str> {
+        self.find_one_for_revision(
+            revision,
+            |r| r.crate_type.as_ref(),
+            |(_, line)| {
+                errors.push(Error::InvalidComment {
+                    msg: "`crate-type` specified twice".into(),
+                    line: *line,
+                })
+            },
+        )
+        .map(|(crate_type, _)| crate_type.as_str())
+    }
+
+    pub(crate) fn crate_name<'a>(
+        &'a self,
+        errors: &mut Vec<Error>,
+        revision: &'a str,
+    ) -> Option<&'a str> {
+        self.find_one_for_revision(
+            revision,
+            |r| r.crate_name.as_ref(),
+            |(_, line)| {
+                errors.push(Error::InvalidComment {
+                    msg: "`crate-name` specified twice".into(),
+                    line: *line,
+                })
+            },
+        )
+        .map(|(crate_name, _)| crate_name.as_str())
+    }
+
+    pub(crate) fn run_panic(
+        &self,
+        errors: &mut Vec<Error>,
+        revision: &str,
+    ) -> Option<(Pattern, usize)> {
+        self.find_one_for_revision(
+            revision,
+            |r| r.run_panic.clone(),
+            |(_, line)| {
+                errors.push(Error::InvalidComment {
+                    msg: "`run-panic` specified twice".into(),
+                    line,
+                })
+            },
+        )
+    }
+
+    /// The level from which on all diagnostics need an annotation, unless it is to be picked
+    /// from the annotations, see `Revisioned::require_annotations_for_level`.
+    pub(crate) fn require_annotations_for_level(
+        &self,
+        errors: &mut Vec<Error>,
+        revision: &str,
+        config: &crate::Config,
+    ) -> Option<Level> {
+        self.find_one_for_revision(
+            revision,
+            |r| r.require_annotations_for_level,
+            |(_, line)| {
+                errors.push(Error::InvalidComment {
+                    msg: "`require-annotations-for-level` specified twice".into(),
+                    line,
+                })
+            },
+        )
+        .map(|(level, _)| level)
+        .or(config.require_annotations_for_level)
+    }
+
+    pub(crate) fn timeout(
+        &self,
+        errors: &mut Vec<Error>,
+        revision: &str,
+        config: &crate::Config,
+    ) -> Option<Duration> {
+        self.find_one_for_revision(
+            revision,
+            |r| r.timeout,
+            |(_, line)| {
+                errors.push(Error::InvalidComment {
+                    msg: "`timeout` specified twice".into(),
+                    line,
+                })
+            },
+        )
+        .map(|(timeout, _)| timeout)
+        .or(config.default_timeout)
+    }
 }
 
 #[derive(Default, Debug)]
 /// Comments that can be filtered for specific revisions.
-pub(crate) struct Revisioned {
+pub struct Revisioned {
     /// The line in which this revisioned item was first added.
     /// Used for reporting errors on unknown revisions.
-    pub line: usize,
-    /// Don't run this test if any of these filters apply
-    pub ignore: Vec<Condition>,
-    /// Only run this test if all of these filters apply
-    pub only: Vec<Condition>,
+    pub(crate) line: usize,
+    /// Don't run this test if any of these filters apply, and the lines they were defined in
+    pub(crate) ignore: Vec<(Condition, usize)>,
+    /// Only run this test if all of these filters apply, and the lines they were defined in
+    pub(crate) only: Vec<(Condition, usize)>,
+    /// Never run this test, for the given reason.
+    pub(crate) ignore_test: Option<(String, usize)>,
     /// Generate one .stderr file per bit width, by prepending with `.64bit` and similar
-    pub stderr_per_bitwidth: bool,
+    pub(crate) stderr_per_bitwidth: bool,
+    /// Don't compare the compiler's stderr against a `.stderr` file.
+    pub(crate) dont_check_compiler_stderr: bool,
     /// Additional flags to pass to the executable
-    pub compile_flags: Vec<String>,
+    pub(crate) compile_flags: Vec<String>,
     /// Additional env vars to set for the executable
-    pub env_vars: Vec<(String, String)>,
-    /// Normalizations to apply to the stderr output before emitting it to disk
-    pub normalize_stderr: Vec<(Regex, Vec<u8>)>,
-    /// Arbitrary patterns to look for in the stderr.
+    pub(crate) env_vars: Vec<(String, String)>,
+    /// Env vars that must not be visible to the compiler or the test binary.
+    pub(crate) forbid_env: Vec<(String, usize)>,
+    /// Normalizations to apply to the stderr output before emitting it to disk,
+    /// and the lines they were defined in
+    pub(crate) normalize_stderr: Vec<(Regex, Vec<u8>, usize)>,
+    /// Normalizations to apply to the stdout output before emitting it to disk,
+    /// and the lines they were defined in. Only allowed if `check_stdout` is set or the
+    /// test is run. The `normalize_stderr` ones are never applied to stdout.
+    pub(crate) normalize_stdout: Vec<(Regex, Vec<u8>, usize)>,
+    /// Compare the compiler's stdout against the `.stdout` file, normalized with the `stdout`
+    /// normalizations.
+    pub(crate) check_stdout: bool,
+    /// Names of `Config::named_stderr_filters` that should not be applied to this test.
+    pub(crate) disabled_filters: Vec<(String, usize)>,
+    /// Arbitrary patterns to look for in the stderr, the lines they were defined in, and the
+    /// path the file of the diagnostic must end with, if given.
     /// The error must be from another file, as errors from the current file must be
     /// checked via `error_matches`.
-    pub error_in_other_files: Vec<(Pattern, usize)>,
-    pub error_matches: Vec<ErrorMatch>,
-    /// Ignore diagnostics below this level.
-    /// `None` means pick the lowest level from the `error_pattern`s.
-    pub require_annotations_for_level: Option<Level>,
-    pub aux_builds: Vec<(PathBuf, String, usize)>,
-    pub edition: Option<(String, usize)>,
+    pub(crate) error_in_other_files: Vec<(Pattern, usize, Option<String>)>,
+    pub(crate) error_matches: Vec<ErrorMatch>,
+    /// Ignore diagnostics below this level, and the line of the command.
+    /// `None` means use `Config::require_annotations_for_level`, or if that is not set either,
+    /// pick the lowest level from the `error_pattern`s.
+    pub(crate) require_annotations_for_level: Option<(Level, usize)>,
+    pub(crate) aux_builds: Vec<(PathBuf, String, usize)>,
+    /// Tests that must have passed before this test runs, relative to this test file.
+    /// They apply to all revisions.
+    pub(crate) depends_on: Vec<(PathBuf, usize)>,
+    pub(crate) edition: Option<(String, usize)>,
+    /// Passed to the compiler as `--crate-type`, replacing the crate type that
+    /// `default_per_file_config` guesses from the file's contents.
+    pub(crate) crate_type: Option<(String, usize)>,
+    /// Passed to the compiler as `--crate-name`.
+    pub(crate) crate_name: Option<(String, usize)>,
     /// Overwrites the mode from `Config`.
-    pub mode: Option<(Mode, usize)>,
-    pub needs_asm_support: bool,
+    pub(crate) mode: Option<(Mode, usize)>,
+    /// Overwrites whether `Mode::Fail` tests need at least one `//~` annotation, set via
+    /// `//@require-annotations` and `//@dont-require-annotations`.
+    pub(crate) require_annotations: Option<(bool, usize)>,
+    /// Only apply machine applicable suggestions in `//@run-rustfix` tests,
+    /// even if `Config::rustfix_all_suggestions` is set.
+    pub(crate) rustfix_only_machine_applicable: bool,
+    /// Don't fail `//@run-rustfix` tests if the `.fixed` file compiles with
+    /// diagnostics at or above `Config::fixed_diagnostics_level`.
+    pub(crate) no_verify_fixed: bool,
+    /// The compiler is expected to crash with an internal compiler error, see
+    /// `Config::ice_patterns`.
+    pub(crate) should_ice: bool,
+    /// Whether diagnostics inside macros are matched at the line of the macro invocation
+    /// (the default) instead of the line they point to in the macro definition.
+    pub(crate) match_expansion_site: Option<bool>,
+    /// In run mode, compare the test binary's output against the `.run.stdout` and
+    /// `.run.stderr` files instead of the `.stdout` and `.stderr` files.
+    pub(crate) check_run_results: bool,
+    /// A file, relative to the test file, whose contents are passed to the test binary's stdin.
+    pub(crate) stdin: Option<(PathBuf, usize)>,
+    /// The panic message that `//@run-panic` tests must print to stderr.
+    pub(crate) run_panic: Option<(Pattern, usize)>,
+    /// Kill the compiler and the test binary if they run for longer than this.
+    pub(crate) timeout: Option<(Duration, usize)>,
+    /// Instead of compiling the file, run the program with just these flags
+    /// and compare its stdout against the `.stdout` file.
+    pub(crate) probe: Option<(Vec<String>, usize)>,
+    /// Instead of checking the diagnostics, compare the assembly, LLVM IR or MIR emitted by
+    /// the compiler against the `.s`, `.ll` or `.mir` file.
+    pub(crate) emit: Option<(Emit, usize)>,
+    /// Replace the names of local values in emitted LLVM IR, see `Emit::filters`.
+    pub(crate) strip_value_names: bool,
+    pub(crate) needs_asm_support: bool,
+    /// Only check for internal compiler errors, like in `Mode::Yolo`.
+    pub(crate) yolo: bool,
+    /// Data stored by the commands of `Config::custom_comment_parsers`.
+    pub(crate) custom: HashMap<String, Box<dyn Any + Send + Sync>>,
+}
+
+impl Revisioned {
+    /// The line in which comments for these revisions were first given.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// The `//@ignore-` conditions, and the lines they are in.
+    pub fn ignore(&self) -> impl Iterator<Item = (&Condition, usize)> {
+        self.ignore.iter().map(|(c, line)| (c, *line))
+    }
+
+    /// The `//@only-` conditions, and the lines they are in.
+    pub fn only(&self) -> impl Iterator<Item = (&Condition, usize)> {
+        self.only.iter().map(|(c, line)| (c, *line))
+    }
+
+    /// The flags of `//@compile-flags` comments.
+    pub fn compile_flags(&self) -> impl Iterator<Item = &str> {
+        self.compile_flags.iter().map(String::as_str)
+    }
+
+    /// The env vars of `//@rustc-env` comments.
+    pub fn env_vars(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.env_vars.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// The `//~` annotations.
+    pub fn error_matches(&self) -> impl Iterator<Item = &ErrorMatch> {
+        self.error_matches.iter()
+    }
+
+    /// The mode set via `//@check-pass`, `//@run` or `//@run-rustfix`, and its line.
+    pub fn mode(&self) -> Option<(Mode, usize)> {
+        self.mode
+    }
+
+    /// The edition set via `//@edition`, and its line.
+    pub fn edition(&self) -> Option<(&str, usize)> {
+        self.edition
+            .as_ref()
+            .map(|(edition, line)| (edition.as_str(), *line))
+    }
+
+    /// The data stored under `name` by a command of `Config::custom_comment_parsers`,
+    /// if it has the type `T`.
+    pub fn custom<T: Any>(&self, name: &str) -> Option<&T> {
+        self.custom.get(name)?.downcast_ref()
+    }
+}
+
+/// Parses a custom `//@` command, see `Config::custom_comment_parsers`. Receives the
+/// arguments of the command, the line it is in, and a handle to modify the comments of
+/// the revisions it applies to. Errors are reported as invalid comments at that line.
+pub type CustomCommandFn =
+    fn(args: &str, line: usize, comments: &mut CustomCommandContext<'_>) -> Result<(), String>;
+
+/// The parts of a test's comments that custom `//@` commands can modify.
+#[derive(Debug)]
+pub struct CustomCommandContext<'a> {
+    /// Additional flags to pass to the program, just like `//@compile-flags`.
+    pub compile_flags: &'a mut Vec<String>,
+    /// Additional env vars to set for the program, just like `//@rustc-env`.
+    pub env_vars: &'a mut Vec<(String, String)>,
+    /// Arbitrary data, usually keyed by the name of the command that stores it.
+    pub custom: &'a mut HashMap<String, Box<dyn Any + Send + Sync>>,
 }
 
 #[derive(Debug)]
@@ -117,11 +458,23 @@ struct CommentParser<T> {
     /// The line currently being parsed.
     line: usize,
     /// The available commands and their parsing logic
-    commands: HashMap<&'static str, CommandParserFunc>,
+    commands: HashMap<String, CommandParser>,
+    /// The names of the conditions registered via `Config::custom_conditions`.
+    custom_conditions: Vec<String>,
+    /// The target passed to the program via `--target`, if cross compiling.
+    cross_target: Option<String>,
 }
 
 type CommandParserFunc = fn(&mut CommentParser<&mut Revisioned>, args: &str);
 
+/// The parsing logic of a command.
+#[derive(Debug, Clone, Copy)]
+enum CommandParser {
+    BuiltIn(CommandParserFunc),
+    /// Registered via `Config::custom_comment_parsers`.
+    Custom(CustomCommandFn),
+}
+
 impl<T> std::ops::Deref for CommentParser<T> {
     type Target = T;
 
@@ -138,89 +491,357 @@ impl<T> std::ops::DerefMut for CommentParser<T> {
 
 /// The conditions used for "ignore" and "only" filters.
 #[derive(Debug)]
-pub(crate) enum Condition {
+pub enum Condition {
     /// The given string must appear in the host triple.
     Host(String),
     /// The given string must appear in the target triple.
     Target(String),
+    /// The regex must match the target triple.
+    TargetRegex(Regex),
     /// Tests that the bitwidth is the given one.
     Bitwidth(u8),
     /// Tests that the target is the host.
     OnHost,
+    /// Tests that the target is not the host.
+    CrossCompile,
+    /// Tests that the program is from the given release channel.
+    Channel(Channel),
+    /// Tests that the target has the given operating system (e.g. `linux` or `macos`)
+    /// or belongs to the given family (`unix`, `windows` or `wasm`).
+    OsFamily(String),
+    /// Tests that the target has the given byte order.
+    Endian(Endianness),
+    /// Tests whether the test is built with debug assertions, according to the
+    /// compile flags of the `Config` and the test's revision.
+    OptLevel(OptLevel),
+    /// A condition registered via `Config::custom_conditions`.
+    Custom(String),
+    /// Tests that the env var is set when the test suite starts, and has the given value, if any.
+    Env {
+        /// The name of the env var.
+        name: String,
+        /// The value the env var must have, if any.
+        value: Option<String>,
+    },
+}
+
+/// The byte order of a target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    /// The most significant byte comes first.
+    Big,
+    /// The least significant byte comes first.
+    Little,
+}
+
+/// Whether a test is built like a debug or a release build.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptLevel {
+    /// Debug assertions are enabled.
+    Debug,
+    /// Debug assertions are disabled.
+    Release,
 }
 
+impl std::fmt::Display for Condition {
+    /// Renders the condition as it is written after `ignore-` or `only-`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Condition::Host(t) => write!(f, "host-{t}"),
+            Condition::Target(t) => write!(f, "target-{t}"),
+            Condition::TargetRegex(regex) => write!(f, "target: /{regex}/"),
+            Condition::Bitwidth(bits) => write!(f, "{bits}bit"),
+            Condition::OnHost => write!(f, "on-host"),
+            Condition::CrossCompile => write!(f, "cross-compile"),
+            Condition::Channel(channel) => write!(f, "{channel}"),
+            Condition::OsFamily(name) | Condition::Custom(name) => write!(f, "{name}"),
+            Condition::Endian(Endianness::Big) => write!(f, "endian-big"),
+            Condition::Endian(Endianness::Little) => write!(f, "endian-little"),
+            Condition::OptLevel(OptLevel::Debug) => write!(f, "debug"),
+            Condition::OptLevel(OptLevel::Release) => write!(f, "release"),
+            Condition::Env { name, value: None } => write!(f, "env-{name}"),
+            Condition::Env {
+                name,
+                value: Some(value),
+            } => write!(f, "env-{name}={value}"),
+        }
+    }
+}
+
+/// The crate types `//@crate-type` accepts, as listed by `rustc --help`.
+const CRATE_TYPES: &[&str] = &[
+    "bin",
+    "lib",
+    "rlib",
+    "dylib",
+    "cdylib",
+    "staticlib",
+    "proc-macro",
+];
+
+/// The target families that can be used in `ignore-FAMILY` and `only-FAMILY` conditions.
+const TARGET_FAMILIES: &[&str] = &["unix", "windows", "wasm"];
+
+/// The operating systems that can be used in `ignore-OS` and `only-OS` conditions,
+/// and whether they belong to the `unix` family.
+pub(crate) const TARGET_OSES: &[(&str, bool)] = &[
+    ("linux", true),
+    ("android", true),
+    ("macos", true),
+    ("ios", true),
+    ("freebsd", true),
+    ("netbsd", true),
+    ("openbsd", true),
+    ("dragonfly", true),
+    ("solaris", true),
+    ("illumos", true),
+    ("fuchsia", true),
+    ("redox", true),
+    ("haiku", true),
+    ("emscripten", true),
+    ("windows", false),
+    ("wasi", false),
+    ("uefi", false),
+];
+
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 /// An error pattern parsed from a `//~` comment.
 pub enum Pattern {
+    /// Matches diagnostics whose message contains this string.
     SubString(String),
-    Regex(Regex),
+    /// Matches diagnostics whose message matches this regex.
+    Regex(
+        #[cfg_attr(feature = "serde", serde(serialize_with = "crate::serialize::display"))] Regex,
+    ),
+    /// Matches diagnostics whose error code or lint name is exactly this string.
+    Code(String),
 }
 
 #[derive(Debug)]
-pub(crate) struct ErrorMatch {
-    pub pattern: Pattern,
-    pub level: Level,
+/// A `//~` annotation.
+pub struct ErrorMatch {
+    /// `None` if only an error code was specified, e.g. `//~ ERROR[E0308]`.
+    pub(crate) pattern: Option<Pattern>,
+    pub(crate) level: Level,
+    /// The error code the message must have, e.g. `E0308`.
+    pub(crate) code: Option<String>,
+    /// How often the message must occur, e.g. `3` for `//~ ERROR*3: XXX`.
+    /// `None` means at least once.
+    pub(crate) count: Option<usize>,
+    /// Whether this is a `//~ !ERROR: XXX` annotation, which fails if the message is found.
+    pub(crate) negated: bool,
+    /// The column (starting at 1) the message's primary span must start at, e.g. `17` for
+    /// `//~ ERROR@17: XXX`.
+    pub(crate) column: Option<usize>,
     /// The line where the message was defined, for reporting issues with it (e.g. in case it wasn't found).
-    pub definition_line: usize,
+    pub(crate) definition_line: usize,
     /// The line this pattern is expecting to find a message in.
-    pub line: usize,
+    /// `None` for `//~?` patterns, which match messages that aren't reported on any line.
+    pub(crate) line: Option<usize>,
+    /// Whether the line was given explicitly, e.g. `//~LINE:42 ERROR: XXX`.
+    pub(crate) absolute_line: bool,
 }
 
 impl Condition {
     fn parse(c: &str) -> std::result::Result<Self, String> {
         if c == "on-host" {
             Ok(Condition::OnHost)
-        } else if let Some(bits) = c.strip_suffix("bit") {
+        } else if c == "cross-compile" {
+            Ok(Condition::CrossCompile)
+        } else if c == "stable" {
+            Ok(Condition::Channel(Channel::Stable))
+        } else if c == "beta" {
+            Ok(Condition::Channel(Channel::Beta))
+        } else if c == "nightly" {
+            Ok(Condition::Channel(Channel::Nightly))
+        } else if c == "debug" {
+            Ok(Condition::OptLevel(OptLevel::Debug))
+        } else if c == "release" {
+            Ok(Condition::OptLevel(OptLevel::Release))
+        } else if let Some(bits) = c
+            .strip_suffix("bit")
+            .or_else(|| c.strip_prefix("ptr-width-"))
+        {
             let bits: u8 = bits.parse().map_err(|_err| {
-                format!("invalid ignore/only filter ending in 'bit': {c:?} is not a valid bitwdith")
+                format!("invalid ignore/only filter {c:?}: {bits:?} is not a valid bitwidth")
             })?;
+            if ![16, 32, 64, 128].contains(&bits) {
+                return Err(format!(
+                    "invalid ignore/only filter {c:?}: pointers are 16, 32, 64 or 128 bits wide"
+                ));
+            }
             Ok(Condition::Bitwidth(bits))
         } else if let Some(triple_substr) = c.strip_prefix("target-") {
             Ok(Condition::Target(triple_substr.to_owned()))
         } else if let Some(triple_substr) = c.strip_prefix("host-") {
             Ok(Condition::Host(triple_substr.to_owned()))
+        } else if let Some(env) = c.strip_prefix("env-") {
+            let (name, value) = match env.split_once('=') {
+                Some((_, "")) => {
+                    return Err(format!(
+                        "`{c}` is not a valid condition, expected a value after the `=`"
+                    ))
+                }
+                Some((name, value)) => (name, Some(value.to_owned())),
+                None => (env, None),
+            };
+            if name.is_empty() {
+                return Err(format!(
+                    "`{c}` is not a valid condition, expected the name of an env var after `env-`"
+                ));
+            }
+            Ok(Condition::Env {
+                name: name.to_owned(),
+                value,
+            })
+        } else if let Some(endianness) = c.strip_prefix("endian-") {
+            match endianness {
+                "big" => Ok(Condition::Endian(Endianness::Big)),
+                "little" => Ok(Condition::Endian(Endianness::Little)),
+                _ => Err(format!(
+                    "`{c}` is not a valid condition, expected `endian-big` or `endian-little`"
+                )),
+            }
+        } else if TARGET_FAMILIES.contains(&c) || TARGET_OSES.iter().any(|&(os, _)| os == c) {
+            Ok(Condition::OsFamily(c.to_owned()))
         } else {
+            let oses = TARGET_OSES
+                .iter()
+                .map(|&(os, _)| os)
+                .filter(|os| !TARGET_FAMILIES.contains(os));
+            let names: Vec<_> = TARGET_FAMILIES.iter().copied().chain(oses).collect();
             Err(format!(
-                "`{c}` is not a valid condition, expected `on-host`, /[0-9]+bit/, /host-.*/, or /target-.*/"
+                "`{c}` is not a valid condition, expected `on-host`, `cross-compile`, `stable`, `beta`, `nightly`, /[0-9]+bit/, /host-.*/, /target-.*/, `endian-big`, `endian-little`, or one of the target families and operating systems `{}`",
+                names.join("`, `")
             ))
         }
     }
 }
 
 impl Comments {
-    pub(crate) fn parse_file(path: &Path) -> Result<std::result::Result<Self, Vec<Error>>> {
+    /// Parse the comments of the file at `path`, including the custom conditions and
+    /// commands of the `config`.
+    pub(crate) fn parse_file(
+        path: &Path,
+        config: &Config,
+    ) -> Result<std::result::Result<Self, Vec<Error>>> {
         let content =
             std::fs::read(path).wrap_err_with(|| format!("failed to read {}", path.display()))?;
-        Ok(Self::parse(&content))
+        Ok(Self::parse_with_config(&content, config))
     }
 
-    /// Parse comments in `content`.
-    /// `path` is only used to emit diagnostics if parsing fails.
+    /// Parse comments in `content`, without any custom conditions or commands.
+    #[cfg(test)]
     pub(crate) fn parse(
         content: &(impl AsRef<[u8]> + ?Sized),
+    ) -> std::result::Result<Self, Vec<Error>> {
+        Self::parse_with_config(content, &Config::rustc(PathBuf::new()))
+    }
+
+    /// Parse comments in `content`. `ignore-` and `only-` additionally accept the
+    /// `Config::custom_conditions`, and the `Config::custom_comment_parsers` are available
+    /// as commands.
+    pub fn parse_with_config(
+        content: &(impl AsRef<[u8]> + ?Sized),
+        config: &Config,
     ) -> std::result::Result<Self, Vec<Error>> {
         let mut parser = CommentParser {
             comments: Comments::default(),
             errors: vec![],
             line: 0,
-            commands: CommentParser::<_>::commands(),
+            commands: CommentParser::<_>::commands(&config.custom_comment_parsers),
+            custom_conditions: config.custom_conditions.keys().cloned().collect(),
+            cross_target: config.cross_target().map(String::from),
         };
 
         let mut fallthrough_to = None; // The line that a `|` will refer to.
+
+        // Tracks string literals and block comments, so `//` within them is not mistaken for a comment.
+        let mut lexer = Lexer::Code;
+        // A command or annotation ending in `\`, the line it started in and the comments of that line.
+        let mut continued: Option<(usize, LineComments, Vec<u8>)> = None;
+        let mut last_line = 0;
         for (l, line) in content.as_ref().lines().enumerate() {
             let l = l + 1; // enumerate starts at 0, but line numbers start at 1
-            parser.line = l;
-            match parser.parse_checked_line(&mut fallthrough_to, line) {
+            last_line = l;
+            let lexed = lexer.lex_line(line);
+            let (start, lexed, line) = match continued.take() {
+                Some((start, first, mut joined)) => {
+                    match lexed
+                        .line_comment
+                        .filter(|&pos| line[..pos].trim().is_empty())
+                    {
+                        Some(next) => {
+                            joined.push(b' ');
+                            joined.extend_from_slice(line[next + 2..].trim());
+                            (start, first, Cow::Owned(joined))
+                        }
+                        None => {
+                            parser.errors.push(Error::InvalidComment {
+                                msg: "a comment ending in `\\` must be followed by a `//` comment"
+                                    .into(),
+                                line: start,
+                            });
+                            (l, lexed, Cow::Borrowed(line))
+                        }
+                    }
+                }
+                None => (l, lexed, Cow::Borrowed(line)),
+            };
+            if let (Some(rest), Some(pos)) =
+                (line.trim_end().strip_suffix(b"\\"), lexed.line_comment)
+            {
+                if start != l || is_command_or_annotation(&line[pos..], pos == 0) {
+                    continued = Some((start, lexed, rest.trim_end().to_vec()));
+                    continue;
+                }
+            }
+            // Errors in continued comments are reported at their first line.
+            parser.line = start;
+            match parser.parse_checked_line(&mut fallthrough_to, &line, &lexed) {
                 Ok(()) => {}
                 Err(e) => parser.errors.push(Error::InvalidComment {
                     msg: format!("Comment is not utf8: {e:?}"),
-                    line: l,
+                    line: start,
                 }),
             }
         }
+        if let Some((start, _, _)) = continued {
+            parser.errors.push(Error::InvalidComment {
+                msg: "the comment ends in `\\`, but there is no line to continue it with".into(),
+                line: start,
+            });
+        }
+        for revisioned in parser.comments.revisioned.values() {
+            for error_match in &revisioned.error_matches {
+                if let Some(line) = error_match.line.filter(|&line| line > last_line) {
+                    let msg = if error_match.absolute_line {
+                        format!(
+                            "//~LINE:{line} pattern is trying to refer to line {line}, but there are only {last_line} lines"
+                        )
+                    } else {
+                        format!(
+                            "//~v pattern is trying to refer to {} lines below, but there are only {} lines below",
+                            line - error_match.definition_line,
+                            last_line - error_match.definition_line,
+                        )
+                    };
+                    parser.errors.push(Error::InvalidComment {
+                        msg,
+                        line: error_match.definition_line,
+                    });
+                }
+            }
+        }
+        let (deprecations, errors) = std::mem::take(&mut parser.errors)
+            .into_iter()
+            .partition(|error| matches!(error, Error::DeprecatedComment { .. }));
+        parser.errors = errors;
+        parser.comments.deprecations = deprecations;
         if let Some(revisions) = &parser.comments.revisions {
             for (key, revisioned) in &parser.comments.revisioned {
-                for rev in key {
+                for rev in &key.names {
                     if !revisions.contains(rev) {
                         parser.errors.push(Error::InvalidComment {
                             msg: format!("the revision `{rev}` is not known"),
@@ -231,7 +852,7 @@ impl Comments {
             }
         } else {
             for (key, revisioned) in &parser.comments.revisioned {
-                if !key.is_empty() {
+                if !key.names.is_empty() {
                     parser.errors.push(Error::InvalidComment {
                         msg: "there are no revisions in this test".into(),
                         line: revisioned.line,
@@ -242,6 +863,10 @@ impl Comments {
         if parser.errors.is_empty() {
             Ok(parser.comments)
         } else {
+            parser.errors.append(&mut parser.comments.deprecations);
+            parser
+                .errors
+                .append(&mut parser.comments.legacy_annotations);
             Err(parser.errors)
         }
     }
@@ -252,16 +877,70 @@ impl CommentParser<Comments> {
         &mut self,
         fallthrough_to: &mut Option<usize>,
         line: &[u8],
+        lexed: &LineComments,
     ) -> std::result::Result<(), Utf8Error> {
-        if let Some(command) = line.strip_prefix(b"//@") {
+        // Whether a block comment in this line contained an annotation.
+        let mut annotated = false;
+        for &(start, end) in &lexed.block_comments {
+            let comment = &line[start..end.unwrap_or(line.len())];
+            let command = comment.starts_with(b"/*@");
+            if !command && !comment.starts_with(b"/*~") {
+                continue;
+            }
+            let Some(end) = end else {
+                self.error(
+                    "`/*~` annotations and `/*@` commands must end in the line they start in",
+                );
+                continue;
+            };
+            let text = line[start + 3..end - 2].to_str()?;
+            if !command {
+                let (revisions, pattern) = self.parse_revisions(text);
+                let pattern = pattern.trim_end();
+                self.revisioned(revisions, |this| {
+                    this.parse_pattern(pattern, fallthrough_to)
+                });
+                annotated = true;
+            } else if start == 0 {
+                self.parse_command(text.trim())
+            } else {
+                self.error("`/*@` test suite commands must be at the start of the line")
+            }
+        }
+        let Some(pos) = lexed.line_comment else {
+            if !annotated {
+                *fallthrough_to = None;
+            }
+            return Ok(());
+        };
+        let line = &line[pos..];
+        if let Some(command) = line.strip_prefix(b"//@").filter(|_| pos == 0) {
             self.parse_command(command.trim().to_str()?)
         } else if let Some((_, pattern)) = line.split_once_str("//~") {
             let (revisions, pattern) = self.parse_revisions(pattern.to_str()?);
             self.revisioned(revisions, |this| {
                 this.parse_pattern(pattern, fallthrough_to)
             })
+        } else if let Some((revisions, pattern)) = legacy_annotation(line) {
+            let revisions = revisions.to_str()?;
+            self.comments
+                .legacy_annotations
+                .push(Error::DeprecatedComment {
+                    msg: format!(
+                        "`//[{revisions}]~` is compiletest's order, use `//~[{revisions}]` instead"
+                    ),
+                    line: self.line,
+                    since: "0.11.8",
+                });
+            let revisions = self.parse_revision_filter(revisions);
+            let pattern = pattern.to_str()?;
+            self.revisioned(revisions, |this| {
+                this.parse_pattern(pattern, fallthrough_to)
+            })
         } else {
-            *fallthrough_to = None;
+            if !annotated {
+                *fallthrough_to = None;
+            }
             for pos in line.find_iter("//") {
                 let rest = &line[pos + 2..];
                 for rest in std::iter::once(rest).chain(rest.strip_prefix(b" ")) {
@@ -281,6 +960,8 @@ impl CommentParser<Comments> {
                             errors: vec![],
                             comments: Comments::default(),
                             commands: std::mem::take(&mut self.commands),
+                            custom_conditions: std::mem::take(&mut self.custom_conditions),
+                            cross_target: None,
                         };
                         parser.parse_command(rest.to_str()?);
                         if parser.errors.is_empty() {
@@ -291,6 +972,7 @@ impl CommentParser<Comments> {
                             );
                         }
                         self.commands = parser.commands;
+                        self.custom_conditions = parser.custom_conditions;
                     }
                 }
             }
@@ -299,6 +981,160 @@ impl CommentParser<Comments> {
     }
 }
 
+/// Whether the comment contains something that `parse_checked_line` parses,
+/// and may thus be continued on the next line by ending it in `\`.
+fn is_command_or_annotation(comment: &[u8], at_line_start: bool) -> bool {
+    (at_line_start && comment.starts_with(b"//@"))
+        || comment.contains_str("//~")
+        || legacy_annotation(comment).is_some()
+}
+
+/// Splits a compiletest style `//[rev]~ PATTERN` annotation into the revisions and the pattern.
+/// Only comments starting with it are annotations, not e.g. URLs in the middle of comments.
+fn legacy_annotation(comment: &[u8]) -> Option<(&[u8], &[u8])> {
+    let rest = comment.strip_prefix(b"//[")?;
+    let (revisions, rest) = rest.split_once_str("]")?;
+    Some((revisions, rest.strip_prefix(b"~")?))
+}
+
+/// Rewrites compiletest's `//[rev]~` annotations in `source` to `//~[rev]`. Returns the lines
+/// of the rewritten annotations with their old and new start. Lines are never added or
+/// removed, so the comments parsed from the old source still apply to the new one.
+pub(crate) fn migrate_legacy_annotations(source: &mut [String]) -> Vec<(usize, String, String)> {
+    let mut lexer = Lexer::Code;
+    let mut migrated = vec![];
+    for (l, line) in source.iter_mut().enumerate() {
+        let lexed = lexer.lex_line(line.trim_end_matches(&['\r', '\n'][..]).as_bytes());
+        let Some(pos) = lexed.line_comment else {
+            continue;
+        };
+        let Some((revisions, _)) = legacy_annotation(&line.as_bytes()[pos..]) else {
+            continue;
+        };
+        let revisions = String::from_utf8_lossy(revisions).into_owned();
+        let old = format!("//[{revisions}]~");
+        let new = format!("//~[{revisions}]");
+        line.replace_range(pos..pos + old.len(), &new);
+        migrated.push((l + 1, old, new));
+    }
+    migrated
+}
+
+/// What the start of a line is in the middle of. Only tracks as much of Rust's
+/// syntax as is needed to find out whether a `//` starts a line comment.
+#[derive(Copy, Clone, Debug)]
+enum Lexer {
+    Code,
+    Str,
+    /// A raw string with the given number of `#`s.
+    RawStr(usize),
+    /// A (possibly nested) block comment with the given depth.
+    BlockComment(usize),
+}
+
+/// The comments found in a single line by the [`Lexer`].
+#[derive(Debug)]
+struct LineComments {
+    /// The position of the `//` that starts a line comment.
+    line_comment: Option<usize>,
+    /// The start and end (after the `*/`) of all block comments that start in this line.
+    /// The end is `None` if the block comment does not end in this line.
+    block_comments: Vec<(usize, Option<usize>)>,
+}
+
+impl Lexer {
+    /// Finds the comments in `line` and advances the lexer to the start of the next line.
+    fn lex_line(&mut self, line: &[u8]) -> LineComments {
+        let is_ident = |c: u8| c.is_ascii_alphanumeric() || c == b'_';
+        let mut block_comments = vec![];
+        // The start of the outermost block comment, if it started in this line.
+        let mut block_start = None;
+        let mut i = 0;
+        while i < line.len() {
+            let rest = &line[i..];
+            match *self {
+                Lexer::Code if rest.starts_with(b"//") => {
+                    return LineComments {
+                        line_comment: Some(i),
+                        block_comments,
+                    }
+                }
+                Lexer::Code if rest.starts_with(b"/*") => {
+                    *self = Lexer::BlockComment(1);
+                    block_start = Some(i);
+                    i += 2;
+                }
+                Lexer::BlockComment(depth) if rest.starts_with(b"/*") => {
+                    *self = Lexer::BlockComment(depth + 1);
+                    i += 2;
+                }
+                Lexer::BlockComment(depth) if rest.starts_with(b"*/") => {
+                    i += 2;
+                    *self = match depth {
+                        1 => {
+                            if let Some(start) = block_start.take() {
+                                block_comments.push((start, Some(i)));
+                            }
+                            Lexer::Code
+                        }
+                        _ => Lexer::BlockComment(depth - 1),
+                    };
+                }
+                Lexer::Code if rest[0] == b'"' => {
+                    *self = Lexer::Str;
+                    i += 1;
+                }
+                Lexer::Code if rest[0] == b'\'' => {
+                    // Char literals, everything else (e.g. lifetimes) is skipped.
+                    i += match rest.get(1) {
+                        Some(b'\\') => rest
+                            .get(3..)
+                            .and_then(|rest| rest.find_byte(b'\''))
+                            .map_or(1, |end| end + 4),
+                        _ => match rest[1..].chars().next() {
+                            Some(c) if rest.get(1 + c.len_utf8()) == Some(&b'\'') => {
+                                2 + c.len_utf8()
+                            }
+                            _ => 1,
+                        },
+                    };
+                }
+                Lexer::Str if rest[0] == b'\\' => i += 2,
+                Lexer::Str if rest[0] == b'"' => {
+                    *self = Lexer::Code;
+                    i += 1;
+                }
+                Lexer::RawStr(hashes)
+                    if rest[0] == b'"'
+                        && rest[1..].iter().take_while(|&&c| c == b'#').count() >= hashes =>
+                {
+                    *self = Lexer::Code;
+                    i += 1 + hashes;
+                }
+                Lexer::Code if is_ident(rest[0]) => {
+                    // Whole identifiers are skipped, so only `r"`, `br"` and `cr"` start raw strings.
+                    let len = rest.iter().take_while(|&&c| is_ident(c)).count();
+                    let hashes = rest[len..].iter().take_while(|&&c| c == b'#').count();
+                    if matches!(&rest[..len], b"r" | b"br" | b"cr")
+                        && rest.get(len + hashes) == Some(&b'"')
+                    {
+                        *self = Lexer::RawStr(hashes);
+                        i += len + hashes + 1;
+                    } else {
+                        i += len;
+                    }
+                }
+                _ => i += 1,
+            }
+        }
+        block_comments.extend(block_start.map(|start| (start, None)));
+        LineComments {
+            line_comment: None,
+            block_comments,
+        }
+    }
+}
+
 impl<CommentsType> CommentParser<CommentsType> {
     fn error(&mut self, s: impl Into<String>) {
         self.errors.push(Error::InvalidComment {
@@ -317,6 +1153,22 @@ impl<CommentsType> CommentParser<CommentsType> {
         self.check(opt.is_some(), s);
         opt
     }
+
+    /// Report the `{{placeholders}}` in a flag or env var value that can't be replaced.
+    fn check_placeholders(&mut self, value: &str) {
+        for name in placeholders::find(value) {
+            if !placeholders::NAMES.contains(&name) {
+                self.error(format!(
+                    "unknown placeholder `{{{{{name}}}}}`, expected one of {}",
+                    placeholders::NAMES
+                        .iter()
+                        .map(|name| format!("`{{{{{name}}}}}`"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            }
+        }
+    }
 }
 
 impl CommentParser<Comments> {
@@ -329,6 +1181,14 @@ impl CommentParser<Comments> {
             .find_map(|(i, c)| (!c.is_alphanumeric() && c != '-' && c != '_').then_some(i))
         {
             None => (command, ""),
+            // `ignore-env-NAME=VALUE` conditions contain the value in the command itself.
+            Some(i)
+                if command[i..].starts_with('=')
+                    && (command.starts_with("ignore-env-") || command.starts_with("only-env-")) =>
+            {
+                let end = command.find(char::is_whitespace).unwrap_or(command.len());
+                (&command[..end], command[end..].trim())
+            }
             Some(i) => {
                 let (command, args) = command.split_at(i);
                 let mut args = args.chars();
@@ -346,11 +1206,16 @@ impl CommentParser<Comments> {
 
         if command == "revisions" {
             self.check(
-                revisions.is_empty(),
+                revisions.names.is_empty(),
                 "revisions cannot be declared under a revision",
             );
             self.check(self.revisions.is_none(), "cannot specify `revisions` twice");
-            self.revisions = Some(args.split_whitespace().map(|s| s.to_string()).collect());
+            let names: Vec<String> = args.split_whitespace().map(|s| s.to_string()).collect();
+            if names.is_empty() {
+                self.error("`revisions` needs at least one revision name");
+            } else {
+                self.revisions = Some(names);
+            }
             return;
         }
         self.revisioned(revisions, |this| this.parse_command(command, args));
@@ -358,13 +1223,15 @@ impl CommentParser<Comments> {
 
     fn revisioned(
         &mut self,
-        revisions: Vec<String>,
+        revisions: RevisionFilter,
         f: impl FnOnce(&mut CommentParser<&mut Revisioned>),
     ) {
         let line = self.line;
         let mut this = CommentParser {
             errors: std::mem::take(&mut self.errors),
             commands: std::mem::take(&mut self.commands),
+            custom_conditions: std::mem::take(&mut self.custom_conditions),
+            cross_target: self.cross_target.take(),
             line,
             comments: self
                 .revisioned
@@ -376,70 +1243,175 @@ impl CommentParser<Comments> {
         };
         f(&mut this);
         let CommentParser {
-            errors, commands, ..
+            errors,
+            commands,
+            custom_conditions,
+            cross_target,
+            ..
         } = this;
         self.commands = commands;
+        self.custom_conditions = custom_conditions;
+        self.cross_target = cross_target;
         self.errors = errors;
     }
 }
 
 impl CommentParser<&mut Revisioned> {
-    fn commands() -> HashMap<&'static str, CommandParserFunc> {
-        let mut commands = HashMap::<_, CommandParserFunc>::new();
+    /// The built-in commands, and the `custom` ones that don't collide with them.
+    fn commands(custom: &HashMap<String, CustomCommandFn>) -> HashMap<String, CommandParser> {
+        let mut commands = HashMap::new();
         macro_rules! commands {
             ($($name:expr => ($this:ident, $args:ident)$block:block)*) => {
-                $(commands.insert($name, |$this, $args| {
+                $(commands.insert($name.to_owned(), CommandParser::BuiltIn(|$this, $args| {
                     $block
-                });)*
+                }));)*
             };
         }
         commands! {
             "compile-flags" => (this, args){
                 if let Some(parsed) = comma::parse_command(args) {
+                    if let Some(target) = this.cross_target.clone() {
+                        if parsed.iter().any(|flag| is_target_flag(flag)) {
+                            this.error(format!("`--target` is already passed for `Config::target` ({target})"));
+                        }
+                    }
+                    for flag in &parsed {
+                        this.check_placeholders(flag);
+                    }
                     this.compile_flags.extend(parsed);
                 } else {
                     this.error(format!("`{args}` contains an unclosed quotation mark"));
                 }
             }
+            "probe" => (this, args){
+                this.check(this.probe.is_none(), "cannot specify `probe` twice");
+                match comma::parse_command(args) {
+                    Some(flags) if !flags.is_empty() => this.probe = Some((flags, this.line)),
+                    Some(_) => this.error("`probe` needs the flags to pass to the program"),
+                    None => this.error(format!("`{args}` contains an unclosed quotation mark")),
+                }
+            }
+            "rustfix-only-machine-applicable" => (this, _args){
+                // args are ignored (can be used as comment)
+                this.check(
+                    !this.rustfix_only_machine_applicable,
+                    "cannot specify `rustfix-only-machine-applicable` twice",
+                );
+                this.rustfix_only_machine_applicable = true;
+            }
+            "no-verify-fixed" => (this, _args){
+                // args are ignored (can be used as comment)
+                this.check(!this.no_verify_fixed, "cannot specify `no-verify-fixed` twice");
+                this.no_verify_fixed = true;
+            }
+            "match-expansion-site" => (this, args){
+                this.check(
+                    this.match_expansion_site.is_none(),
+                    "cannot specify `match-expansion-site` twice",
+                );
+                match args.trim() {
+                    "yes" => this.match_expansion_site = Some(true),
+                    "no" => this.match_expansion_site = Some(false),
+                    _ => this.error("`match-expansion-site` must be `yes` or `no`"),
+                }
+            }
+            "should-ice" => (this, _args){
+                // args are ignored (can be used as comment)
+                this.check(!this.should_ice, "cannot specify `should-ice` twice");
+                this.should_ice = true;
+            }
+            "check-run-results" => (this, _args){
+                // args are ignored (can be used as comment)
+                this.check(
+                    !this.check_run_results,
+                    "cannot specify `check-run-results` twice",
+                );
+                this.check_run_results = true;
+            }
+            "stdin" => (this, args){
+                this.check(this.stdin.is_none(), "cannot specify `stdin` twice");
+                let args = args.trim();
+                if args.is_empty() {
+                    this.error("`stdin` needs the path of the file to read stdin from");
+                } else {
+                    this.stdin = Some((args.into(), this.line));
+                }
+            }
+            "timeout" => (this, args){
+                this.check(this.timeout.is_none(), "cannot specify `timeout` twice");
+                match args.parse::<u64>() {
+                    Ok(0) => this.error("`timeout` must be at least one second"),
+                    Ok(secs) => this.timeout = Some((Duration::from_secs(secs), this.line)),
+                    Err(err) => this.error(format!("`timeout` expects a number of seconds: {err}")),
+                }
+            }
             "rustc-env" => (this, args){
                 for env in args.split_whitespace() {
                     if let Some((k, v)) = this.check_some(
                         env.split_once('='),
                         "environment variables must be key/value pairs separated by a `=`",
                     ) {
+                        this.check_placeholders(v);
                         this.env_vars.push((k.to_string(), v.to_string()));
                     }
                 }
             }
+            "forbid-env" => (this, args){
+                let var = args.trim();
+                if var.is_empty() || var.contains(|c: char| c == '=' || c.is_whitespace()) {
+                    this.error("`forbid-env` expects the name of a single environment variable");
+                } else {
+                    let line = this.line;
+                    this.forbid_env.push((var.into(), line));
+                }
+            }
             "normalize-stderr-test" => (this, args){
-                let (from, rest) = this.parse_str(args);
-
-                let to = match rest.strip_prefix("->") {
-                    Some(v) => v,
-                    None => {
-                        this.error("normalize-stderr-test needs a pattern and replacement separated by `->`");
-                        return;
-                    },
-                }.trim_start();
-                let (to, rest) = this.parse_str(to);
-
-                this.check(
-                    rest.is_empty(),
-                    format!("trailing text after pattern replacement: {rest}"),
-                );
-
-                if let Some(regex) = this.parse_regex(from) {
-                    this.normalize_stderr
-                        .push((regex, to.as_bytes().to_owned()))
+                if let Some(normalization) = this.parse_normalize_test(args, "stderr") {
+                    this.normalize_stderr.push(normalization)
+                }
+            }
+            "normalize-stdout-test" => (this, args){
+                if let Some(normalization) = this.parse_normalize_test(args, "stdout") {
+                    this.normalize_stdout.push(normalization)
+                }
+            }
+            "check-stdout" => (this, _args){
+                // args are ignored (can be used as comment)
+                this.check(!this.check_stdout, "cannot specify `check-stdout` twice");
+                this.check_stdout = true;
+            }
+            "disable-filter" => (this, args){
+                this.check(!args.is_empty(), "`disable-filter` needs the name of a filter");
+                for name in args.split_whitespace() {
+                    let line = this.line;
+                    this.disabled_filters.push((name.to_string(), line));
                 }
             }
-            "error-pattern" => (this, _args){
-                this.error("`error-pattern` has been renamed to `error-in-other-file`");
+            "error-pattern" => (this, args){
+                this.deprecated("error-pattern", args);
             }
             "error-in-other-file" => (this, args){
-                let pat = this.parse_error_pattern(args.trim());
+                let args = args.trim();
+                // A leading `path/to/file.rs:` restricts the pattern to diagnostics in that file.
+                let (file, pattern) = match args.split_once(": ") {
+                    Some((file, pattern))
+                        if file.ends_with(".rs") && !file.contains(char::is_whitespace) =>
+                    {
+                        (Some(file.to_owned()), pattern.trim_start())
+                    }
+                    _ => (None, args),
+                };
+                let pat = this.parse_error_pattern(pattern);
                 let line = this.line;
-                this.error_in_other_files.push((pat, line));
+                this.error_in_other_files.push((pat, line, file));
+            }
+            "ignore-test" => (this, args){
+                this.check(this.ignore_test.is_none(), "cannot specify `ignore-test` twice");
+                this.check(
+                    !args.is_empty(),
+                    "`ignore-test` needs a reason why the test is ignored",
+                );
+                this.ignore_test = Some((args.into(), this.line));
             }
             "stderr-per-bitwidth" => (this, _args){
                 // args are ignored (can be used as comment)
@@ -449,6 +1421,14 @@ impl CommentParser<&mut Revisioned> {
                 );
                 this.stderr_per_bitwidth = true;
             }
+            "dont-check-compiler-stderr" => (this, _args){
+                // args are ignored (can be used as comment)
+                this.check(
+                    !this.dont_check_compiler_stderr,
+                    "cannot specify `dont-check-compiler-stderr` twice",
+                );
+                this.dont_check_compiler_stderr = true;
+            }
             "run-rustfix" => (this, _args){
                 // args are ignored (can be used as comment)
                 this.check(
@@ -465,16 +1445,63 @@ impl CommentParser<&mut Revisioned> {
                 );
                 this.needs_asm_support = true;
             }
+            "yolo" => (this, _args){
+                // args are ignored (can be used as comment)
+                this.check(!this.yolo, "cannot specify `yolo` twice");
+                this.yolo = true;
+            }
             "aux-build" => (this, args){
                 let (name, kind) = args.split_once(':').unwrap_or((args, "lib"));
                 let line = this.line;
                 this.aux_builds.push((name.into(), kind.into(), line));
             }
+            "depends-on" => (this, args){
+                let args = args.trim();
+                if args.is_empty() {
+                    this.error("`depends-on` needs the path of the test it depends on");
+                } else {
+                    let line = this.line;
+                    this.depends_on.push((args.into(), line));
+                }
+            }
             "edition" => (this, args){
                 this.check(this.edition.is_none(), "cannot specify `edition` twice");
                 this.edition = Some((args.into(), this.line))
             }
+            "crate-type" => (this, args){
+                this.check(this.crate_type.is_none(), "cannot specify `crate-type` twice");
+                let args = args.trim();
+                if CRATE_TYPES.contains(&args) {
+                    this.crate_type = Some((args.into(), this.line));
+                } else {
+                    this.error(format!(
+                        "unknown crate type `{args}`, expected one of {}",
+                        CRATE_TYPES
+                            .iter()
+                            .map(|crate_type| format!("`{crate_type}`"))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ));
+                }
+            }
+            "crate-name" => (this, args){
+                this.check(this.crate_name.is_none(), "cannot specify `crate-name` twice");
+                let args = args.trim();
+                if args.is_empty() {
+                    this.error("`crate-name` needs the name of the crate");
+                } else {
+                    this.crate_name = Some((args.into(), this.line));
+                }
+            }
             "check-pass" => (this, _args){
+                // args are ignored (can be used as comment)
+                this.check(
+                    this.mode.is_none(),
+                    "cannot specify test mode changes twice",
+                );
+                this.mode = Some((Mode::Check, this.line))
+            }
+            "build-pass" => (this, _args){
                 // args are ignored (can be used as comment)
                 this.check(
                     this.mode.is_none(),
@@ -482,49 +1509,183 @@ impl CommentParser<&mut Revisioned> {
                 );
                 this.mode = Some((Mode::Pass, this.line))
             }
+            "check-fail" => (this, _args){
+                // args are ignored (can be used as comment)
+                this.check(
+                    this.mode.is_none(),
+                    "cannot specify test mode changes twice",
+                );
+                this.mode = Some((Mode::Fail { require_patterns: true }, this.line))
+            }
+            "require-annotations" => (this, _args){
+                // args are ignored (can be used as comment)
+                this.check(
+                    this.require_annotations.is_none(),
+                    "cannot specify `require-annotations` or `dont-require-annotations` twice",
+                );
+                this.require_annotations = Some((true, this.line));
+            }
+            "dont-require-annotations" => (this, _args){
+                // args are ignored (can be used as comment)
+                this.check(
+                    this.require_annotations.is_none(),
+                    "cannot specify `require-annotations` or `dont-require-annotations` twice",
+                );
+                this.require_annotations = Some((false, this.line));
+            }
             "run" => (this, args){
                 this.check(
                     this.mode.is_none(),
                     "cannot specify test mode changes twice",
                 );
-                let mut set = |exit_code| this.mode = Some((Mode::Run { exit_code }, this.line));
+                let mut set = |exit| this.mode = Some((Mode::Run { exit }, this.line));
                 if args.is_empty() {
-                    set(0);
+                    set(ExitExpectation::Code(0));
+                } else if let Some(signal) = args.trim().strip_prefix("signal=") {
+                    match signal.trim().parse() {
+                        Ok(signal) => set(ExitExpectation::Signal(signal)),
+                        Err(err) => this.error(err),
+                    }
                 } else {
                     match args.parse() {
-                        Ok(exit_code) => set(exit_code),
+                        Ok(exit_code) => set(ExitExpectation::Code(exit_code)),
                         Err(err) => this.error(err.to_string()),
                     }
                 }
             }
+            "run-panic" => (this, args){
+                this.check(
+                    this.mode.is_none(),
+                    "cannot specify test mode changes twice",
+                );
+                let args = args.trim();
+                if args.is_empty() {
+                    this.error("`run-panic` needs (part of) the expected panic message");
+                } else {
+                    let pattern = this.parse_error_pattern(args);
+                    this.run_panic = Some((pattern, this.line));
+                    this.mode = Some((Mode::Run { exit: ExitExpectation::Panic }, this.line));
+                }
+            }
+            "emit" => (this, args){
+                this.check(this.emit.is_none(), "cannot specify `emit` twice");
+                let mut args = args.split_whitespace();
+                match args.next().map(str::parse::<Emit>) {
+                    None => this.error("`emit` needs one of `asm`, `llvm-ir` or `mir`"),
+                    Some(Err(err)) => this.error(err),
+                    Some(Ok(emit)) => {
+                        this.emit = Some((emit, this.line));
+                        for arg in args {
+                            match arg {
+                                "strip-value-names" if emit == Emit::LlvmIr => {
+                                    this.strip_value_names = true
+                                }
+                                "strip-value-names" => this.error(
+                                    "`strip-value-names` is only supported for `emit: llvm-ir`",
+                                ),
+                                _ => this.error(format!("unknown `emit` option `{arg}`")),
+                            }
+                        }
+                    }
+                }
+            }
             "require-annotations-for-level" => (this, args){
                 this.check(
                     this.require_annotations_for_level.is_none(),
                     "cannot specify `require-annotations-for-level` twice",
                 );
                 match args.trim().parse() {
-                    Ok(it) => this.require_annotations_for_level = Some(it),
-                    Err(msg) => this.error(msg),
+                    Ok(it) => this.require_annotations_for_level = Some((it, this.line)),
+                    Err(err) => this.error(err.to_string()),
                 }
             }
         }
+        for (name, command) in custom {
+            commands
+                .entry(name.clone())
+                .or_insert(CommandParser::Custom(*command));
+        }
         commands
     }
 
+    /// Emit a deprecation warning for `command` and parse it as its replacement instead.
+    fn deprecated(&mut self, command: &str, args: &str) {
+        let &(_, replacement, since) = DEPRECATED_COMMANDS
+            .iter()
+            .find(|(deprecated, ..)| *deprecated == command)
+            .expect("deprecated command has no entry in `DEPRECATED_COMMANDS`");
+        self.errors.push(Error::DeprecatedComment {
+            msg: format!("`{command}` has been renamed to `{replacement}`"),
+            line: self.line,
+            since,
+        });
+        self.parse_command(replacement, args);
+    }
+
+    fn parse_normalize_test(&mut self, args: &str, mode: &str) -> Option<(Regex, Vec<u8>, usize)> {
+        let (from, rest) = self.parse_str(args);
+
+        let to = match rest.strip_prefix("->") {
+            Some(v) => v,
+            None => {
+                self.error(format!(
+                    "normalize-{mode}-test needs a pattern and replacement separated by `->`"
+                ));
+                return None;
+            }
+        }
+        .trim_start();
+        let (to, rest) = self.parse_str(to);
+
+        self.check(
+            rest.is_empty(),
+            format!("trailing text after pattern replacement: {rest}"),
+        );
+
+        let regex = self.parse_regex(from)?;
+        Some((regex, to.as_bytes().to_owned(), self.line))
+    }
+
     fn parse_command(&mut self, command: &str, args: &str) {
-        if let Some(command) = self.commands.get(command) {
-            command(self, args);
+        if let Some(&command) = self.commands.get(command) {
+            match command {
+                CommandParser::BuiltIn(command) => command(self, args),
+                CommandParser::Custom(command) => {
+                    let line = self.line;
+                    let (flags, env_vars) = (
+                        self.comments.compile_flags.len(),
+                        self.comments.env_vars.len(),
+                    );
+                    let mut context = CustomCommandContext {
+                        compile_flags: &mut self.comments.compile_flags,
+                        env_vars: &mut self.comments.env_vars,
+                        custom: &mut self.comments.custom,
+                    };
+                    if let Err(msg) = command(args, line, &mut context) {
+                        self.error(msg);
+                    }
+                    let added: Vec<String> = self
+                        .comments
+                        .compile_flags
+                        .iter()
+                        .skip(flags)
+                        .chain(self.comments.env_vars.iter().skip(env_vars).map(|(_, v)| v))
+                        .cloned()
+                        .collect();
+                    for value in added {
+                        self.check_placeholders(&value);
+                    }
+                }
+            }
         } else if let Some(s) = command.strip_prefix("ignore-") {
-            // args are ignored (can be used as comment)
-            match Condition::parse(s) {
-                Ok(cond) => self.ignore.push(cond),
-                Err(msg) => self.error(msg),
+            if let Some(cond) = self.parse_condition(s, args) {
+                let line = self.line;
+                self.ignore.push((cond, line));
             }
         } else if let Some(s) = command.strip_prefix("only-") {
-            // args are ignored (can be used as comment)
-            match Condition::parse(s) {
-                Ok(cond) => self.only.push(cond),
-                Err(msg) => self.error(msg),
+            if let Some(cond) = self.parse_condition(s, args) {
+                let line = self.line;
+                self.only.push((cond, line));
             }
         } else {
             let best_match = self
@@ -540,6 +1701,31 @@ impl CommentParser<&mut Revisioned> {
 }
 
 impl<CommentsType> CommentParser<CommentsType> {
+    /// Parses the condition of an `ignore-` or `only-` command.
+    fn parse_condition(&mut self, c: &str, args: &str) -> Option<Condition> {
+        if c == "target" {
+            let Some(regex) = args
+                .strip_prefix('/')
+                .and_then(|args| args.strip_suffix('/'))
+            else {
+                self.error("`target` conditions must be followed by a `/regex/` matching the target triple, e.g. `ignore-target: /^arm-/`");
+                return None;
+            };
+            return Some(Condition::TargetRegex(self.parse_regex(regex)?));
+        }
+        // args are ignored (can be used as comment)
+        match Condition::parse(c) {
+            Ok(cond) => Some(cond),
+            Err(_) if self.custom_conditions.iter().any(|name| name == c) => {
+                Some(Condition::Custom(c.to_owned()))
+            }
+            Err(msg) => {
+                self.error(msg);
+                None
+            }
+        }
+    }
+
     fn parse_regex(&mut self, regex: &str) -> Option<Regex> {
         match Regex::new(regex) {
             Ok(regex) => Some(regex),
@@ -583,8 +1769,8 @@ impl<CommentsType> CommentParser<CommentsType> {
         }
     }
 
-    // parse something like \[[a-z]+(,[a-z]+)*\]
-    fn parse_revisions<'a>(&mut self, pattern: &'a str) -> (Vec<String>, &'a str) {
+    // parse something like \[!?[a-z]+(,!?[a-z]+)*\]
+    fn parse_revisions<'a>(&mut self, pattern: &'a str) -> (RevisionFilter, &'a str) {
         match pattern.chars().next() {
             Some('[') => {
                 // revisions
@@ -595,27 +1781,64 @@ impl<CommentsType> CommentParser<CommentsType> {
                 });
                 let Some(end) = end else {
                     self.error("`[` without corresponding `]`");
-                    return (vec![], pattern);
+                    return (RevisionFilter::default(), pattern);
                 };
                 let (revision, pattern) = s.split_at(end);
                 (
-                    revision.split(',').map(|s| s.trim().to_string()).collect(),
+                    self.parse_revision_filter(revision),
                     // 1.. because `split_at` includes the separator
                     pattern[1..].trim_start(),
                 )
             }
-            _ => (vec![], pattern),
+            _ => (RevisionFilter::default(), pattern),
+        }
+    }
+
+    /// Parse the comma separated revision names between the `[` and `]`.
+    fn parse_revision_filter(&mut self, revisions: &str) -> RevisionFilter {
+        let revisions: Vec<_> = revisions.split(',').map(|s| s.trim()).collect();
+        let negated = revisions.iter().any(|rev| rev.starts_with('!'));
+        self.check(
+            !negated || revisions.iter().all(|rev| rev.starts_with('!')),
+            "cannot mix negated and non-negated revisions in one `[...]`",
+        );
+        RevisionFilter {
+            names: revisions
+                .iter()
+                .map(|rev| rev.trim_start_matches('!').trim().to_string())
+                .collect(),
+            negated,
         }
     }
 }
 
 impl CommentParser<&mut Revisioned> {
-    // parse something like (\[[a-z]+(,[a-z]+)*\])?(?P<offset>\||[\^]+)? *(?P<level>ERROR|HELP|WARN|NOTE): (?P<text>.*)
+    // parse something like (\[[a-z]+(,[a-z]+)*\])?(?P<offset>\||[\^]+|v+|\?|LINE:[0-9]+)? *(?P<negated>!)?(?P<level>ERROR|HELP|WARN|NOTE|SUGGESTION)(@(?P<column>[0-9]+))?(\*(?P<count>[0-9]+))?(\[(?P<code>[^\]]*)\])?(: (?P<text>.*)| (?P<lint>[\w:]+))?
     fn parse_pattern(&mut self, pattern: &str, fallthrough_to: &mut Option<usize>) {
+        let absolute_line = pattern.starts_with("LINE:");
         let (match_line, pattern) = match pattern.chars().next() {
+            _ if absolute_line => {
+                let rest = &pattern["LINE:".len()..];
+                let end = rest
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len());
+                match rest[..end].parse() {
+                    // lines are one-indexed, so a target line of 0 is invalid
+                    Ok(0) => {
+                        self.error("//~LINE:0 pattern is invalid, line numbers start at 1");
+                        return;
+                    }
+                    // Whether the target line exists is checked once the whole file has been parsed.
+                    Ok(line) => (Some(line), &rest[end..]),
+                    Err(_) => {
+                        self.error("expected a line number after `//~LINE:`");
+                        return;
+                    }
+                }
+            }
             Some('|') => (
                 match fallthrough_to {
-                    Some(fallthrough) => *fallthrough,
+                    Some(fallthrough) => Some(*fallthrough),
                     None => {
                         self.error("`//~|` pattern without preceding line");
                         return;
@@ -627,7 +1850,7 @@ impl CommentParser<&mut Revisioned> {
                 let offset = pattern.chars().take_while(|&c| c == '^').count();
                 match self.line.checked_sub(offset) {
                     // lines are one-indexed, so a target line of 0 is invalid
-                    Some(match_line) if match_line > 0 => (match_line, &pattern[offset..]),
+                    Some(match_line) if match_line > 0 => (Some(match_line), &pattern[offset..]),
                     _ => {
                         self.error(format!(
                             "//~^ pattern is trying to refer to {} lines above, but there are only {} lines above",
@@ -638,7 +1861,13 @@ impl CommentParser<&mut Revisioned> {
                     }
                 }
             }
-            Some(_) => (self.line, pattern),
+            Some('v') => {
+                let offset = pattern.chars().take_while(|&c| c == 'v').count();
+                // Whether the target line exists is checked once the whole file has been parsed.
+                (Some(self.line + offset), &pattern[offset..])
+            }
+            Some('?') => (None, &pattern[1..]),
+            Some(_) => (Some(self.line), pattern),
             None => {
                 self.error("no pattern specified");
                 return;
@@ -646,7 +1875,15 @@ impl CommentParser<&mut Revisioned> {
         };
 
         let pattern = pattern.trim_start();
-        let offset = match pattern.chars().position(|c| !c.is_ascii_alphabetic()) {
+        let (negated, pattern) = match pattern.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern),
+        };
+        // Levels are letters, and `-` for `FAILURE-NOTE`.
+        let offset = match pattern
+            .chars()
+            .position(|c| !c.is_ascii_alphabetic() && c != '-')
+        {
             Some(offset) => offset,
             None => {
                 self.error("pattern without level");
@@ -654,45 +1891,222 @@ impl CommentParser<&mut Revisioned> {
             }
         };
 
-        let level = match pattern[..offset].parse() {
+        let level = match pattern[..offset].parse::<Level>() {
             Ok(level) => level,
-            Err(msg) => {
-                self.error(msg);
+            Err(err) => {
+                self.error(err.to_string());
                 return;
             }
         };
         let pattern = &pattern[offset..];
+        let Some((column, pattern)) = self.parse_number_suffix(pattern, '@') else {
+            return;
+        };
+        let Some((count, pattern)) = self.parse_number_suffix(pattern, '*') else {
+            return;
+        };
+        self.check(
+            !(negated && count.is_some()),
+            "negated annotations can't have a count",
+        );
+        let (code, pattern) = match pattern.strip_prefix('[') {
+            Some(rest) => match rest.split_once(']') {
+                Some((code, rest)) => {
+                    let code = code.trim();
+                    self.check(
+                        !code.is_empty(),
+                        "no error code specified between `[` and `]`",
+                    );
+                    (Some(code.to_string()), rest)
+                }
+                None => {
+                    self.error("`[` without corresponding `]`");
+                    return;
+                }
+            },
+            None => (None, pattern),
+        };
+        let is_code = |s: &str| {
+            !s.is_empty()
+                && s.chars()
+                    .all(|c| c.is_alphanumeric() || c == '_' || c == ':')
+        };
         let pattern = match pattern.strip_prefix(':') {
-            Some(offset) => offset,
+            Some(pattern) => {
+                let pattern = pattern.trim();
+                self.check(
+                    !pattern.is_empty() || code.is_some(),
+                    "no pattern specified",
+                );
+                (!pattern.is_empty()).then(|| self.parse_error_pattern(pattern))
+            }
+            // The message is optional if an error code was specified.
+            None if code.is_some() && pattern.trim().is_empty() => None,
+            // `//~ WARN clippy::needless_return` matches the lint name instead of the message.
+            None if level != Level::Suggestion
+                && pattern.starts_with(char::is_whitespace)
+                && is_code(pattern.trim()) =>
+            {
+                Some(Pattern::Code(pattern.trim().to_string()))
+            }
+            // `//~ ERROR*3 mismatched types` doesn't need the `:` either.
+            None if count.is_some()
+                && pattern.starts_with(char::is_whitespace)
+                && !pattern.trim().is_empty() =>
+            {
+                Some(self.parse_error_pattern(pattern.trim()))
+            }
             None => {
                 self.error("no `:` after level found");
                 return;
             }
         };
+        if level == Level::Suggestion && pattern.is_none() {
+            self.error("`SUGGESTION` annotations need the expected replacement after a `:`");
+            return;
+        }
 
-        let pattern = pattern.trim();
-
-        self.check(!pattern.is_empty(), "no pattern specified");
-
-        let pattern = self.parse_error_pattern(pattern);
-
-        *fallthrough_to = Some(match_line);
+        // A `//~|` after a `//~?` has no line to refer to.
+        *fallthrough_to = match_line;
 
         let definition_line = self.line;
         self.error_matches.push(ErrorMatch {
             pattern,
             level,
+            code,
+            count,
+            negated,
+            column,
             definition_line,
             line: match_line,
+            absolute_line,
         });
     }
+
+    /// Parses an optional `@17` or `*3` after the level of an annotation.
+    /// Returns `None` after emitting an error if the number is missing or zero.
+    fn parse_number_suffix<'a>(
+        &mut self,
+        pattern: &'a str,
+        prefix: char,
+    ) -> Option<(Option<usize>, &'a str)> {
+        let Some(rest) = pattern.strip_prefix(prefix) else {
+            return Some((None, pattern));
+        };
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        match rest[..digits].parse() {
+            Ok(n) if n > 0 => Some((Some(n), &rest[digits..])),
+            _ => {
+                self.error(format!(
+                    "`{prefix}` after the level must be followed by a positive number"
+                ));
+                None
+            }
+        }
+    }
+}
+
+impl ErrorMatch {
+    /// The pattern the message must match, or `None` if only an error code was specified.
+    pub fn pattern(&self) -> Option<&Pattern> {
+        self.pattern.as_ref()
+    }
+
+    /// The level the message must have.
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    /// The error code the message must have, if any.
+    pub fn code(&self) -> Option<&str> {
+        self.code.as_deref()
+    }
+
+    /// The line the message is expected in, or `None` for `//~?` annotations.
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+
+    /// The line the annotation is written in.
+    pub fn definition_line(&self) -> usize {
+        self.definition_line
+    }
+
+    /// Whether the message has the level, column, code and text this annotation asks for.
+    pub(crate) fn matches(&self, msg: &Message) -> bool {
+        msg.level == self.level
+            && self
+                .column
+                .map_or(true, |column| msg.column == Some(column))
+            && self
+                .code
+                .as_ref()
+                .map_or(true, |code| msg.code.as_ref() == Some(code))
+            && self
+                .pattern
+                .as_ref()
+                .map_or(true, |pattern| pattern.matches(msg))
+    }
 }
 
 impl Pattern {
-    pub(crate) fn matches(&self, message: &str) -> bool {
+    pub(crate) fn matches(&self, msg: &Message) -> bool {
+        match self {
+            Pattern::SubString(s) => msg.message.contains(s),
+            Pattern::Regex(r) => r.is_match(msg.message.as_bytes()),
+            Pattern::Code(code) => msg.code.as_ref() == Some(code),
+        }
+    }
+
+    /// Whether the pattern matches somewhere in `text`, e.g. the stderr of a test binary.
+    pub(crate) fn is_match_in(&self, text: &[u8]) -> bool {
+        match self {
+            Pattern::SubString(s) => text.contains_str(s),
+            Pattern::Regex(r) => r.is_match(text),
+            Pattern::Code(code) => text.contains_str(code),
+        }
+    }
+
+    /// How similar the message is to the pattern, in percent. Substrings are compared to the most
+    /// similar part of the message, regexes are compared to the whole message by their source.
+    pub(crate) fn similarity(&self, msg: &Message) -> usize {
+        let similarity = |pattern: &str, text: &str| {
+            let len = pattern.chars().count().max(text.chars().count());
+            if len == 0 {
+                return 100;
+            }
+            100 - 100 * distance::damerau_levenshtein(pattern, text) / len
+        };
+        match self {
+            Pattern::SubString(s) => {
+                let chars: Vec<_> = msg.message.char_indices().map(|(i, _)| i).collect();
+                let width = s.chars().count();
+                if chars.len() <= width {
+                    return similarity(s, &msg.message);
+                }
+                (0..=chars.len() - width)
+                    .map(|start| {
+                        let end = chars.get(start + width).copied();
+                        let end = end.unwrap_or(msg.message.len());
+                        similarity(s, &msg.message[chars[start]..end])
+                    })
+                    .max()
+                    .unwrap_or(0)
+            }
+            Pattern::Regex(r) => similarity(r.as_str(), &msg.message),
+            Pattern::Code(code) => similarity(code, msg.code.as_deref().unwrap_or_default()),
+        }
+    }
+}
+
+impl std::fmt::Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Pattern::SubString(s) => message.contains(s),
-            Pattern::Regex(r) => r.is_match(message.as_bytes()),
+            Pattern::SubString(s) => write!(f, "`{s}`"),
+            Pattern::Regex(r) => write!(f, "`/{r}/`"),
+            Pattern::Code(code) => write!(f, "`{code}`"),
         }
     }
 }
@@ -1,9 +1,18 @@
 use crate::{
-    parser::{Condition, Pattern},
-    Error,
+    emit::Emit,
+    parser::{Condition, Endianness, OptLevel, Pattern, RevisionFilter},
+    Channel, Config, CustomCommandContext, Error, ExitExpectation, Level, Mode, Signal,
 };
 
-use super::Comments;
+use super::{check_custom_commands, Comments};
+use std::{path::PathBuf, time::Duration};
+
+fn revisions(names: &[&str]) -> RevisionFilter {
+    RevisionFilter {
+        names: names.iter().map(|name| name.to_string()).collect(),
+        negated: false,
+    }
+}
 
 #[test]
 fn parse_simple_comment() {
@@ -17,10 +26,10 @@ fn main() {
     let comments = Comments::parse(s).unwrap();
     println!("parsed comments: {:#?}", comments);
     assert_eq!(comments.revisioned.len(), 1);
-    let revisioned = &comments.revisioned[&vec![]];
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
     assert_eq!(revisioned.error_matches[0].definition_line, 5);
     match &revisioned.error_matches[0].pattern {
-        Pattern::SubString(s) => {
+        Some(Pattern::SubString(s)) => {
             assert_eq!(
                 s,
                 "encountered a dangling reference (address $HEX is unallocated)"
@@ -43,11 +52,80 @@ fn main() {
     println!("parsed comments: {:#?}", errors);
     assert_eq!(errors.len(), 1);
     match &errors[0] {
-        Error::InvalidComment { msg, line: 5 } => assert_eq!(msg, "unknown level `encountered`"),
+        Error::InvalidComment { msg, line: 5 } => assert_eq!(
+            msg,
+            "unknown level `encountered`, expected one of `ERROR`, `WARN` (or `WARNING`), `HELP`, `NOTE`, `SUGGESTION`, `FAILURE-NOTE` or `ICE`"
+        ),
         _ => unreachable!(),
     }
 }
 
+#[test]
+fn parse_require_annotations_for_level_twice() {
+    let s = r"
+//@revisions: a b
+//@require-annotations-for-level: ERROR
+//@[a] require-annotations-for-level: WARN
+    ";
+    let comments = Comments::parse(s).unwrap();
+    let config = Config::rustc(PathBuf::new());
+    let mut errors = vec![];
+    let level = comments.require_annotations_for_level(&mut errors, "b", &config);
+    assert_eq!(level, Some(Level::Error));
+    assert!(errors.is_empty());
+    let level = comments.require_annotations_for_level(&mut errors, "a", &config);
+    // The first one wins, the later one is reported.
+    assert_eq!(level, Some(Level::Error));
+    match &errors[..] {
+        [Error::InvalidComment { msg, line: 4 }] => {
+            assert_eq!(msg, "`require-annotations-for-level` specified twice")
+        }
+        _ => panic!("{errors:#?}"),
+    }
+}
+
+#[test]
+fn parse_level_aliases() {
+    let s = r"
+fn main() {
+    let x = 42; //~ WARNING: unused variable
+    let y = 42; //~ WARN: unused variable
+    std::process::abort(); //~ ICE: unexpected panic
+}
+    ";
+    let comments = Comments::parse(s).unwrap();
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    let levels: Vec<_> = revisioned
+        .error_matches
+        .iter()
+        .map(|error_match| error_match.level)
+        .collect();
+    assert_eq!(levels, [Level::Warn, Level::Warn, Level::Ice]);
+
+    for (arg, level) in [
+        ("WARNING", Level::Warn),
+        ("FAILURE-NOTE", Level::FailureNote),
+        ("ICE", Level::Ice),
+    ] {
+        let s = format!("//@require-annotations-for-level: {arg}");
+        let comments = Comments::parse(&s).unwrap();
+        let revisioned = &comments.revisioned[&RevisionFilter::default()];
+        assert_eq!(revisioned.require_annotations_for_level, Some((level, 1)));
+        assert_eq!(level.to_string().parse::<Level>(), Ok(level));
+    }
+    // `Ice` is above and `FailureNote` below all levels that can be annotated.
+    assert!(Level::Ice > Level::Error);
+    assert!(Level::FailureNote < Level::Note);
+
+    let errors = Comments::parse("//@require-annotations-for-level: WARNINGS").unwrap_err();
+    match &errors[..] {
+        [Error::InvalidComment { msg, line: 1 }] => {
+            assert!(msg.starts_with("unknown level `WARNINGS`, expected one of"))
+        }
+        _ => panic!("{errors:#?}"),
+    }
+}
+
 #[test]
 fn parse_slash_slash_at() {
     let s = r"
@@ -58,7 +136,7 @@ use std::mem;
     let comments = Comments::parse(s).unwrap();
     println!("parsed comments: {:#?}", comments);
     assert_eq!(comments.revisioned.len(), 1);
-    let revisioned = &comments.revisioned[&vec![]];
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
     let pat = &revisioned.error_in_other_files[0];
     assert_eq!(format!("{:?}", pat.0), r#"SubString("foomp")"#);
     assert_eq!(pat.1, 2);
@@ -74,12 +152,42 @@ use std::mem;
     let comments = Comments::parse(s).unwrap();
     println!("parsed comments: {:#?}", comments);
     assert_eq!(comments.revisioned.len(), 1);
-    let revisioned = &comments.revisioned[&vec![]];
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
     let pat = &revisioned.error_in_other_files[0];
     assert_eq!(format!("{:?}", pat.0), r#"Regex(Regex("foomp"))"#);
     assert_eq!(pat.1, 2);
 }
 
+#[test]
+fn parse_error_in_other_file_with_path() {
+    let s = r"
+//@error-in-other-file: auxiliary/helper.rs: cannot find value
+//@error-in-other-file: expected type: foo
+//@error-in-other-file: src/lib.rs: /unused (variable|import)/
+    ";
+    let comments = Comments::parse(s).unwrap();
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    let patterns: Vec<_> = revisioned
+        .error_in_other_files
+        .iter()
+        .map(|(pattern, _, file)| (format!("{pattern:?}"), file.as_deref()))
+        .collect();
+    assert_eq!(
+        patterns,
+        [
+            (
+                r#"SubString("cannot find value")"#.to_string(),
+                Some("auxiliary/helper.rs")
+            ),
+            (r#"SubString("expected type: foo")"#.to_string(), None),
+            (
+                r#"Regex(Regex("unused (variable|import)"))"#.to_string(),
+                Some("src/lib.rs")
+            ),
+        ]
+    );
+}
+
 #[test]
 fn parse_slash_slash_at_fail() {
     let s = r"
@@ -128,10 +236,1450 @@ fn parse_x86_64() {
     let comments = Comments::parse(s).unwrap();
     println!("parsed comments: {:#?}", comments);
     assert_eq!(comments.revisioned.len(), 1);
-    let revisioned = &comments.revisioned[&vec![]];
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
     assert_eq!(revisioned.only.len(), 1);
     match &revisioned.only[0] {
-        Condition::Target(t) => assert_eq!(t, "x86_64-unknown-linux"),
+        (Condition::Target(t), _) => assert_eq!(t, "x86_64-unknown-linux"),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn parse_disable_filter() {
+    let s = r"
+//@disable-filter: timings paths
+//@[foo]disable-filter: hashes
+//@revisions: foo
+";
+    let comments = Comments::parse(s).unwrap();
+    println!("parsed comments: {:#?}", comments);
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    assert_eq!(
+        revisioned.disabled_filters,
+        vec![("timings".to_string(), 2), ("paths".to_string(), 2)]
+    );
+    let revisioned = &comments.revisioned[&revisions(&["foo"])];
+    assert_eq!(revisioned.disabled_filters, vec![("hashes".to_string(), 3)]);
+}
+
+#[test]
+fn parse_disable_filter_without_name() {
+    let s = r"//@disable-filter:";
+    let errors = Comments::parse(s).unwrap_err();
+    println!("parsed comments: {:#?}", errors);
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        Error::InvalidComment { msg, line: 1 } => {
+            assert_eq!(msg, "`disable-filter` needs the name of a filter")
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn parse_ignore_test() {
+    let s = r"
+//@revisions: foo bar
+//@[foo]ignore-test: rustc hangs on this, see #1234
+";
+    let comments = Comments::parse(s).unwrap();
+    println!("parsed comments: {:#?}", comments);
+    let revisioned = &comments.revisioned[&revisions(&["foo"])];
+    assert_eq!(
+        revisioned.ignore_test,
+        Some(("rustc hangs on this, see #1234".to_string(), 3))
+    );
+}
+
+#[test]
+fn parse_ignore_test_without_reason() {
+    let s = r"//@ignore-test";
+    let errors = Comments::parse(s).unwrap_err();
+    println!("parsed comments: {:#?}", errors);
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        Error::InvalidComment { msg, line: 1 } => {
+            assert_eq!(msg, "`ignore-test` needs a reason why the test is ignored")
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn parse_check_stdout() {
+    let s = r#"
+//@check-stdout
+//@normalize-stdout-test: "target_os=.*" -> "target_os=$$OS"
+"#;
+    let comments = Comments::parse(s).unwrap();
+    println!("parsed comments: {:#?}", comments);
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    assert!(revisioned.check_stdout);
+    assert!(revisioned.normalize_stderr.is_empty());
+    assert_eq!(revisioned.normalize_stdout.len(), 1);
+    assert_eq!(revisioned.normalize_stdout[0].0.as_str(), "target_os=.*");
+    assert_eq!(revisioned.normalize_stdout[0].2, 3);
+}
+
+#[test]
+fn parse_probe() {
+    let s = r#"
+//@probe: --print cfg
+//@compile-flags: --target x86_64-unknown-linux-gnu
+"#;
+    let comments = Comments::parse(s).unwrap();
+    println!("parsed comments: {:#?}", comments);
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    assert_eq!(
+        revisioned.probe,
+        Some((vec!["--print".to_string(), "cfg".to_string()], 2))
+    );
+}
+
+#[test]
+fn parse_probe_without_flags() {
+    let s = r"//@probe:";
+    let errors = Comments::parse(s).unwrap_err();
+    println!("parsed comments: {:#?}", errors);
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        Error::InvalidComment { msg, line: 1 } => {
+            assert_eq!(msg, "`probe` needs the flags to pass to the program")
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn parse_deprecated_error_pattern() {
+    let s = r"
+//@error-pattern: foomp
+";
+    let comments = Comments::parse(s).unwrap();
+    println!("parsed comments: {:#?}", comments);
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    assert_eq!(
+        format!("{:?}", revisioned.error_in_other_files[0].0),
+        r#"SubString("foomp")"#
+    );
+    match &comments.deprecations[..] {
+        [Error::DeprecatedComment { msg, line: 2, .. }] => assert_eq!(
+            msg,
+            "`error-pattern` has been renamed to `error-in-other-file`"
+        ),
+        _ => panic!("{:#?}", comments.deprecations),
+    }
+}
+
+#[test]
+fn parse_dont_check_compiler_stderr_twice() {
+    let s = r"
+//@dont-check-compiler-stderr
+//@dont-check-compiler-stderr
+";
+    let errors = Comments::parse(s).unwrap_err();
+    println!("parsed comments: {:#?}", errors);
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        Error::InvalidComment { msg, line: 3 } => {
+            assert_eq!(msg, "cannot specify `dont-check-compiler-stderr` twice")
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn parse_timeout() {
+    let s = r"
+//@timeout: 30
+";
+    let comments = Comments::parse(s).unwrap();
+    println!("parsed comments: {:#?}", comments);
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    assert_eq!(revisioned.timeout, Some((Duration::from_secs(30), 2)));
+}
+
+#[test]
+fn parse_invalid_timeout() {
+    let s = r"
+//@timeout: 0
+//@timeout: soon
+";
+    let errors = Comments::parse(s).unwrap_err();
+    println!("parsed comments: {:#?}", errors);
+    assert_eq!(errors.len(), 2);
+    match &errors[0] {
+        Error::InvalidComment { msg, line: 2 } => {
+            assert_eq!(msg, "`timeout` must be at least one second")
+        }
+        _ => unreachable!(),
+    }
+    match &errors[1] {
+        Error::InvalidComment { msg, line: 3 } => {
+            assert!(
+                msg.starts_with("`timeout` expects a number of seconds"),
+                "{msg}"
+            )
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn parse_depends_on() {
+    let s = r"
+//@depends-on: producer.rs
+//@depends-on:
+";
+    let errors = Comments::parse(s).unwrap_err();
+    println!("parsed comments: {:#?}", errors);
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        Error::InvalidComment { msg, line: 3 } => {
+            assert_eq!(msg, "`depends-on` needs the path of the test it depends on")
+        }
+        _ => unreachable!(),
+    }
+
+    let comments = Comments::parse("//@depends-on: producer.rs").unwrap();
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    assert_eq!(
+        revisioned.depends_on,
+        [(std::path::PathBuf::from("producer.rs"), 1)]
+    );
+}
+
+#[test]
+fn parse_stdin_twice() {
+    let s = r"
+//@stdin: input.txt
+//@stdin: other.txt
+";
+    let errors = Comments::parse(s).unwrap_err();
+    println!("parsed comments: {:#?}", errors);
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        Error::InvalidComment { msg, line: 3 } => {
+            assert_eq!(msg, "cannot specify `stdin` twice")
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn parse_check_run_results() {
+    let comments = Comments::parse("//@check-run-results").unwrap();
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    assert!(revisioned.check_run_results);
+}
+
+#[test]
+fn parse_forbid_env() {
+    let s = r"
+//@forbid-env: RUSTC_BOOTSTRAP
+//@forbid-env: CI
+//@forbid-env: CI=1
+";
+    let errors = Comments::parse(s).unwrap_err();
+    println!("parsed comments: {:#?}", errors);
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        Error::InvalidComment { msg, line: 4 } => {
+            assert_eq!(
+                msg,
+                "`forbid-env` expects the name of a single environment variable"
+            )
+        }
+        _ => unreachable!(),
+    }
+
+    let comments = Comments::parse("//@forbid-env: RUSTC_BOOTSTRAP\n//@forbid-env: CI").unwrap();
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    assert_eq!(
+        revisioned.forbid_env,
+        [("RUSTC_BOOTSTRAP".to_string(), 1), ("CI".to_string(), 2)]
+    );
+}
+
+#[test]
+fn parse_revisioned_annotations_in_both_orders() {
+    let canonical = r"
+//@revisions: foo bar
+fn main() {
+    let _x = 1; //~[foo] ERROR: first
+    //~[foo,bar]^ ERROR: second
+    //~[bar]| WARN: third
+}
+";
+    let legacy = r"
+//@revisions: foo bar
+fn main() {
+    let _x = 1; //[foo]~ ERROR: first
+    //[foo,bar]~^ ERROR: second
+    //[bar]~| WARN: third
+}
+";
+    let canonical = Comments::parse(canonical).unwrap();
+    assert!(canonical.legacy_annotations.is_empty());
+    let legacy = Comments::parse(legacy).unwrap();
+    println!("parsed comments: {:#?}", legacy);
+    assert_eq!(legacy.legacy_annotations.len(), 3);
+    match &legacy.legacy_annotations[0] {
+        Error::DeprecatedComment { msg, line: 4, .. } => assert_eq!(
+            msg,
+            "`//[foo]~` is compiletest's order, use `//~[foo]` instead"
+        ),
+        _ => unreachable!(),
+    }
+
+    for comments in [&canonical, &legacy] {
+        let lines = |names: &[&str]| {
+            comments.revisioned[&revisions(names)]
+                .error_matches
+                .iter()
+                .map(|m| (m.definition_line, m.line.unwrap()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(lines(&["foo"]), [(4, 4)]);
+        assert_eq!(lines(&["foo", "bar"]), [(5, 4)]);
+        assert_eq!(lines(&["bar"]), [(6, 4)]);
+    }
+}
+
+#[test]
+fn legacy_annotation_in_comment_text() {
+    // Not an annotation, but a command-like comment that is reported as such.
+    let errors = Comments::parse("fn main() {} // see https://example.com//[a]~b\n").unwrap_err();
+    match &errors[..] {
+        [Error::InvalidComment { msg, line: 1 }] => {
+            assert!(msg.starts_with("comment looks suspiciously like"), "{msg}")
+        }
+        _ => panic!("{errors:#?}"),
+    }
+}
+
+#[test]
+fn parse_should_ice() {
+    let comments = Comments::parse("//@should-ice: #12345").unwrap();
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    assert!(revisioned.should_ice);
+
+    let errors = Comments::parse("//@should-ice\n//@should-ice").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        Error::InvalidComment { msg, line: 2 } => {
+            assert_eq!(msg, "cannot specify `should-ice` twice")
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn parse_match_expansion_site() {
+    let comments = Comments::parse("//@match-expansion-site: no").unwrap();
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    assert_eq!(revisioned.match_expansion_site, Some(false));
+
+    let errors = Comments::parse("//@match-expansion-site: maybe").unwrap_err();
+    match &errors[..] {
+        [Error::InvalidComment { msg, line: 1 }] => {
+            assert_eq!(msg, "`match-expansion-site` must be `yes` or `no`")
+        }
+        _ => panic!("{errors:#?}"),
+    }
+}
+
+#[test]
+fn parse_no_verify_fixed() {
+    let comments =
+        Comments::parse("//@run-rustfix\n//@no-verify-fixed: suggestion leaves a warning").unwrap();
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    assert!(revisioned.no_verify_fixed);
+
+    let errors = Comments::parse("//@no-verify-fixed\n//@no-verify-fixed").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        Error::InvalidComment { msg, line: 2 } => {
+            assert_eq!(msg, "cannot specify `no-verify-fixed` twice")
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn parse_annotations_below() {
+    let s = r"
+//~v ERROR: first
+fn main() {
+    //~vv ERROR: second
+    //~| WARN: third
+    let _x = 1;
+    //~^ ERROR: fourth
+}
+";
+    let comments = Comments::parse(s).unwrap();
+    println!("parsed comments: {:#?}", comments);
+    let lines: Vec<_> = comments.revisioned[&RevisionFilter::default()]
+        .error_matches
+        .iter()
+        .map(|m| (m.definition_line, m.line.unwrap()))
+        .collect();
+    assert_eq!(lines, [(2, 3), (4, 6), (5, 6), (7, 6)]);
+}
+
+#[test]
+fn parse_annotation_below_last_line() {
+    let s = r"
+fn main() {}
+//~vv ERROR: nothing down there";
+    let errors = Comments::parse(s).unwrap_err();
+    println!("parsed comments: {:#?}", errors);
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        Error::InvalidComment { msg, line: 3 } => assert_eq!(
+            msg,
+            "//~v pattern is trying to refer to 2 lines below, but there are only 0 lines below"
+        ),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn parse_annotation_without_line() {
+    let s = r"
+//~? ERROR: no span
+//~[foo]? WARN: no span either
+//@revisions: foo
+fn main() {}
+";
+    let comments = Comments::parse(s).unwrap();
+    println!("parsed comments: {:#?}", comments);
+    let error_matches = &comments.revisioned[&RevisionFilter::default()].error_matches;
+    assert_eq!(error_matches[0].definition_line, 2);
+    assert_eq!(error_matches[0].line, None);
+    let error_matches = &comments.revisioned[&revisions(&["foo"])].error_matches;
+    assert_eq!(error_matches[0].definition_line, 3);
+    assert_eq!(error_matches[0].line, None);
+
+    let errors =
+        Comments::parse("//~? ERROR: no span\n//~| ERROR: no line to refer to").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        Error::InvalidComment { msg, line: 2 } => {
+            assert_eq!(msg, "`//~|` pattern without preceding line")
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn parse_error_code() {
+    let s = r"
+fn main() {
+    let _x: String = 42; //~ ERROR[E0308]
+    let _y: String = 42; //~ ERROR[E0308]: mismatched types
+}
+";
+    let comments = Comments::parse(s).unwrap();
+    println!("parsed comments: {:#?}", comments);
+    let error_matches = &comments.revisioned[&RevisionFilter::default()].error_matches;
+    assert_eq!(error_matches[0].code.as_deref(), Some("E0308"));
+    assert!(error_matches[0].pattern.is_none());
+    assert_eq!(error_matches[1].code.as_deref(), Some("E0308"));
+    match &error_matches[1].pattern {
+        Some(Pattern::SubString(s)) => assert_eq!(s, "mismatched types"),
+        other => panic!("expected substring, got {other:?}"),
+    }
+
+    let errors = Comments::parse("//~ ERROR[E0308 mismatched types").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        Error::InvalidComment { msg, line: 1 } => {
+            assert_eq!(msg, "`[` without corresponding `]`")
+        }
         _ => unreachable!(),
     }
 }
+
+#[test]
+fn parse_lint_name_pattern() {
+    let s = r"
+fn main() {
+    return; //~ WARN clippy::needless_return
+    return; //~ WARN: clippy::needless_return
+    return; //~ WARN[E0308] unused_mut
+}
+";
+    let comments = Comments::parse(s).unwrap();
+    println!("parsed comments: {:#?}", comments);
+    let error_matches = &comments.revisioned[&RevisionFilter::default()].error_matches;
+    match &error_matches[0].pattern {
+        Some(Pattern::Code(code)) => assert_eq!(code, "clippy::needless_return"),
+        other => panic!("expected code, got {other:?}"),
+    }
+    // With a `:`, the text is a substring of the message as before.
+    match &error_matches[1].pattern {
+        Some(Pattern::SubString(s)) => assert_eq!(s, "clippy::needless_return"),
+        other => panic!("expected substring, got {other:?}"),
+    }
+    assert_eq!(error_matches[2].code.as_deref(), Some("E0308"));
+    match &error_matches[2].pattern {
+        Some(Pattern::Code(code)) => assert_eq!(code, "unused_mut"),
+        other => panic!("expected code, got {other:?}"),
+    }
+
+    // Anything that isn't a single lint name still needs a `:`.
+    let errors = Comments::parse("//~ ERROR mismatched types").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        Error::InvalidComment { msg, line: 1 } => assert_eq!(msg, "no `:` after level found"),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn parse_count() {
+    let s = r"
+fn main() {
+    foo!(); //~ ERROR*3: mismatched types
+    foo!(); //~ ERROR*2[E0308]
+    foo!(); //~ ERROR: mismatched types
+    foo!(); //~ ERROR*3 mismatched types
+    foo!(); //~ WARN*2 unused_variables
+}
+";
+    let comments = Comments::parse(s).unwrap();
+    println!("parsed comments: {:#?}", comments);
+    let error_matches = &comments.revisioned[&RevisionFilter::default()].error_matches;
+    assert_eq!(error_matches[0].count, Some(3));
+    assert_eq!(error_matches[1].count, Some(2));
+    assert_eq!(error_matches[1].code.as_deref(), Some("E0308"));
+    assert_eq!(error_matches[2].count, None);
+    // The `:` is optional after a count.
+    assert_eq!(error_matches[3].count, Some(3));
+    assert!(
+        matches!(&error_matches[3].pattern, Some(Pattern::SubString(s)) if s == "mismatched types")
+    );
+    // A single word is still a lint name.
+    assert_eq!(error_matches[4].count, Some(2));
+    assert!(
+        matches!(&error_matches[4].pattern, Some(Pattern::Code(code)) if code == "unused_variables")
+    );
+
+    let errors = Comments::parse("//~ ERROR*0: mismatched types").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        Error::InvalidComment { msg, line: 1 } => {
+            assert_eq!(
+                msg,
+                "`*` after the level must be followed by a positive number"
+            )
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn parse_continued_pattern() {
+    let s = r"
+fn main() {
+    let _x: &i32 = unsafe { mem::transmute(16usize) }; //~ ERROR: encountered a dangling reference \
+    // (address $HEX \
+    // is unallocated)
+    //~^^^ WARN: on the first line
+}
+";
+    let comments = Comments::parse(s).unwrap();
+    println!("parsed comments: {:#?}", comments);
+    let error_matches = &comments.revisioned[&RevisionFilter::default()].error_matches;
+    assert_eq!(error_matches.len(), 2);
+    assert_eq!(error_matches[0].definition_line, 3);
+    assert_eq!(error_matches[0].line, Some(3));
+    match &error_matches[0].pattern {
+        Some(Pattern::SubString(s)) => assert_eq!(
+            s,
+            "encountered a dangling reference (address $HEX is unallocated)"
+        ),
+        other => panic!("expected substring, got {other:?}"),
+    }
+    assert_eq!(error_matches[1].line, Some(3));
+}
+
+#[test]
+fn parse_continued_command() {
+    let comments = Comments::parse("//@compile-flags: --edition \\\n// 2018").unwrap();
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    assert_eq!(revisioned.compile_flags, ["--edition", "2018"]);
+}
+
+#[test]
+fn parse_dangling_continuation() {
+    let s = r"
+fn main() {}
+//~ ERROR: never finished \";
+    let errors = Comments::parse(s).unwrap_err();
+    println!("parsed comments: {:#?}", errors);
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        Error::InvalidComment { msg, line: 3 } => assert_eq!(
+            msg,
+            "the comment ends in `\\`, but there is no line to continue it with"
+        ),
+        _ => unreachable!(),
+    }
+
+    let errors = Comments::parse("//~ ERROR: continued \\\nfn main() {}").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        Error::InvalidComment { msg, line: 1 } => assert_eq!(
+            msg,
+            "a comment ending in `\\` must be followed by a `//` comment"
+        ),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn parse_negated() {
+    let s = r"
+fn main() {
+    let x = 1;
+    //~^ !WARN: unused variable
+    //~| ERROR: mismatched types
+    //~| !WARN unused_mut
+}
+";
+    let comments = Comments::parse(s).unwrap();
+    println!("parsed comments: {:#?}", comments);
+    let error_matches = &comments.revisioned[&RevisionFilter::default()].error_matches;
+    let lines: Vec<_> = error_matches
+        .iter()
+        .map(|m| (m.negated, m.line.unwrap()))
+        .collect();
+    assert_eq!(lines, [(true, 3), (false, 3), (true, 3)]);
+    match &error_matches[2].pattern {
+        Some(Pattern::Code(code)) => assert_eq!(code, "unused_mut"),
+        other => panic!("expected code, got {other:?}"),
+    }
+}
+
+#[test]
+fn parse_column() {
+    let s = r"
+fn main() {
+    let _x: String = 1.foo(); //~ ERROR@22*2[E0599]: no method named `foo`
+    //~^ ERROR@5: mismatched types
+}
+";
+    let comments = Comments::parse(s).unwrap();
+    println!("parsed comments: {:#?}", comments);
+    let error_matches = &comments.revisioned[&RevisionFilter::default()].error_matches;
+    assert_eq!(error_matches[0].column, Some(22));
+    assert_eq!(error_matches[0].count, Some(2));
+    assert_eq!(error_matches[0].code.as_deref(), Some("E0599"));
+    assert_eq!(error_matches[1].column, Some(5));
+
+    let errors = Comments::parse("//~ ERROR@: mismatched types").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        Error::InvalidComment { msg, line: 1 } => {
+            assert_eq!(
+                msg,
+                "`@` after the level must be followed by a positive number"
+            )
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn parse_suggestion() {
+    let s = r"
+fn main() {
+    let _x = (1); //~ SUGGESTION: 1
+    let _y = (1); //~ SUGGESTION: /^1$/
+}
+";
+    let comments = Comments::parse(s).unwrap();
+    println!("parsed comments: {:#?}", comments);
+    let error_matches = &comments.revisioned[&RevisionFilter::default()].error_matches;
+    assert_eq!(error_matches[0].level, crate::Level::Suggestion);
+    assert!(matches!(error_matches[1].pattern, Some(Pattern::Regex(_))));
+
+    let errors = Comments::parse("//~ SUGGESTION unused_parens").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        Error::InvalidComment { msg, line: 1 } => assert_eq!(msg, "no `:` after level found"),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn parse_annotations_in_string_literals() {
+    let s = r##"
+fn main() {
+    let s = "//~ ERROR fake";
+    let s = "// @compile-flags: fake"; //~ ERROR: real
+    let c = '"'; //~ ERROR: after char
+    let s = r#"
+//~ ERROR fake
+//@compile-flags: fake
+"#;
+    let s = "\" //~ ERROR fake";
+    let s: &'static str = br"\"; //~ ERROR: after raw string
+}
+"##;
+    let comments = Comments::parse(s).unwrap();
+    println!("parsed comments: {:#?}", comments);
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    assert!(revisioned.compile_flags.is_empty());
+    let lines: Vec<_> = revisioned
+        .error_matches
+        .iter()
+        .map(|m| m.definition_line)
+        .collect();
+    assert_eq!(lines, [4, 5, 11]);
+}
+
+#[test]
+fn parse_block_comment_annotations() {
+    let s = r"
+/*@compile-flags: --foo */
+/*@ revisions: a */
+fn main() {
+    let _x = 1; /*~ ERROR: one */ /* // @ not a command */
+    /*~| WARN: two */
+    let _y = /*~[a] ERROR: three */ 2; //~ ERROR: four
+}
+";
+    let comments = Comments::parse(s).unwrap();
+    println!("parsed comments: {:#?}", comments);
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    assert_eq!(revisioned.compile_flags, ["--foo"]);
+    let lines: Vec<_> = revisioned
+        .error_matches
+        .iter()
+        .map(|m| (m.definition_line, m.line))
+        .collect();
+    assert_eq!(lines, [(5, Some(5)), (6, Some(5)), (7, Some(7))]);
+    match &comments.revisioned[&revisions(&["a"])].error_matches[0].pattern {
+        Some(Pattern::SubString(s)) => assert_eq!(s, "three"),
+        other => panic!("expected substring, got {other:?}"),
+    }
+}
+
+#[test]
+fn parse_multi_line_block_comment_annotation() {
+    let s = r"
+fn main() {
+    let _x = 1; /*~ ERROR: one
+    */
+}
+";
+    let errors = Comments::parse(s).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        Error::InvalidComment { msg, line: 3 } => assert_eq!(
+            msg,
+            "`/*~` annotations and `/*@` commands must end in the line they start in"
+        ),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn parse_negated_revisions() {
+    let s = r"
+//@revisions: a b nightly
+//@[!nightly] compile-flags: --stable
+fn main() {
+    let _x = 1; //~[!a, !b] ERROR: only nightly
+}
+";
+    let comments = Comments::parse(s).unwrap();
+    println!("parsed comments: {:#?}", comments);
+    let flags = |revision| {
+        comments
+            .for_revision(revision)
+            .flat_map(|r| r.compile_flags.iter())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(flags("a"), ["--stable"]);
+    assert_eq!(flags("b"), ["--stable"]);
+    assert!(flags("nightly").is_empty());
+    let matches = |revision| {
+        comments
+            .for_revision(revision)
+            .map(|r| r.error_matches.len())
+            .sum::<usize>()
+    };
+    assert_eq!((matches("a"), matches("b"), matches("nightly")), (0, 0, 1));
+
+    let s = r"
+//@revisions: a b
+//@[a, !b] compile-flags: --foo
+//@[!c] compile-flags: --bar
+";
+    let errors = Comments::parse(s).unwrap_err();
+    let msgs: Vec<_> = errors
+        .iter()
+        .map(|error| match error {
+            Error::InvalidComment { msg, line } => (msg.as_str(), *line),
+            _ => unreachable!(),
+        })
+        .collect();
+    assert_eq!(
+        msgs,
+        [
+            (
+                "cannot mix negated and non-negated revisions in one `[...]`",
+                3
+            ),
+            ("the revision `c` is not known", 4)
+        ]
+    );
+}
+
+#[test]
+fn parse_empty_revisions() {
+    for s in ["//@revisions:", "//@revisions"] {
+        let errors = Comments::parse(s).unwrap_err();
+        match &errors[..] {
+            [Error::InvalidComment { msg, line: 1 }] => {
+                assert_eq!(msg, "`revisions` needs at least one revision name")
+            }
+            _ => panic!("{s}: {errors:#?}"),
+        }
+    }
+}
+
+#[test]
+fn parse_absolute_line() {
+    let s = r"
+fn main() {
+    let _x = 1;
+}
+//~LINE:3 ERROR: first
+//~| WARN: second
+//~[foo]LINE:2 ERROR: third
+//@revisions: foo
+";
+    let comments = Comments::parse(s).unwrap();
+    println!("parsed comments: {:#?}", comments);
+    let lines: Vec<_> = comments.revisioned[&RevisionFilter::default()]
+        .error_matches
+        .iter()
+        .map(|m| (m.definition_line, m.line.unwrap()))
+        .collect();
+    assert_eq!(lines, [(5, 3), (6, 3)]);
+    assert_eq!(
+        comments.revisioned[&revisions(&["foo"])].error_matches[0].line,
+        Some(2)
+    );
+
+    let s = r"
+fn main() {} //~LINE:0 ERROR: zero
+//~LINE:4 ERROR: too far";
+    let errors = Comments::parse(s).unwrap_err();
+    let msgs: Vec<_> = errors
+        .iter()
+        .map(|error| match error {
+            Error::InvalidComment { msg, line } => (msg.as_str(), *line),
+            _ => unreachable!(),
+        })
+        .collect();
+    assert_eq!(
+        msgs,
+        [
+            ("//~LINE:0 pattern is invalid, line numbers start at 1", 2),
+            (
+                "//~LINE:4 pattern is trying to refer to line 4, but there are only 3 lines",
+                3
+            )
+        ]
+    );
+}
+
+#[test]
+fn parse_os_family_conditions() {
+    let s = "//@ignore-windows\n//@only-unix\n//@ignore-macos\n";
+    let comments = Comments::parse(s).unwrap();
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    assert!(matches!(
+        &revisioned.ignore[..],
+        [(Condition::OsFamily(windows), 1), (Condition::OsFamily(macos), 3)]
+            if windows == "windows" && macos == "macos"
+    ));
+    assert!(matches!(
+        &revisioned.only[..],
+        [(Condition::OsFamily(unix), 2)] if unix == "unix"
+    ));
+
+    let errors = Comments::parse("//@only-posix").unwrap_err();
+    match &errors[..] {
+        [Error::InvalidComment { msg, line: 1 }] => {
+            assert!(msg.starts_with("`posix` is not a valid condition"), "{msg}");
+            assert!(msg.contains("`unix`, `windows`, `wasm`, `linux`"), "{msg}");
+        }
+        _ => panic!("{errors:#?}"),
+    }
+}
+
+#[test]
+fn parse_endian_conditions() {
+    let comments = Comments::parse("//@ignore-endian-big\n//@only-endian-little\n").unwrap();
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    assert!(matches!(
+        &revisioned.ignore[..],
+        [(Condition::Endian(Endianness::Big), 1)]
+    ));
+    assert!(matches!(
+        &revisioned.only[..],
+        [(Condition::Endian(Endianness::Little), 2)]
+    ));
+
+    let errors = Comments::parse("//@ignore-endian-middle").unwrap_err();
+    match &errors[..] {
+        [Error::InvalidComment { msg, line: 1 }] => assert_eq!(
+            msg,
+            "`endian-middle` is not a valid condition, expected `endian-big` or `endian-little`"
+        ),
+        _ => panic!("{errors:#?}"),
+    }
+}
+
+#[test]
+fn parse_bitwidth_conditions() {
+    let comments = Comments::parse("//@ignore-16bit\n//@only-ptr-width-64\n").unwrap();
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    assert!(matches!(
+        &revisioned.ignore[..],
+        [(Condition::Bitwidth(16), 1)]
+    ));
+    assert!(matches!(
+        &revisioned.only[..],
+        [(Condition::Bitwidth(64), 2)]
+    ));
+
+    let errors = Comments::parse("//@ignore-24bit").unwrap_err();
+    match &errors[..] {
+        [Error::InvalidComment { msg, line: 1 }] => assert_eq!(
+            msg,
+            "invalid ignore/only filter \"24bit\": pointers are 16, 32, 64 or 128 bits wide"
+        ),
+        _ => panic!("{errors:#?}"),
+    }
+}
+
+#[test]
+fn parse_opt_level_conditions() {
+    let comments = Comments::parse("//@ignore-debug\n//@only-release\n").unwrap();
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    assert!(matches!(
+        &revisioned.ignore[..],
+        [(Condition::OptLevel(OptLevel::Debug), 1)]
+    ));
+    assert!(matches!(
+        &revisioned.only[..],
+        [(Condition::OptLevel(OptLevel::Release), 2)]
+    ));
+}
+
+#[test]
+fn parse_env_conditions() {
+    let s = "//@ignore-env-CI\n//@only-env-UI_TEST_SLOW=1 slow network tests\n";
+    let comments = Comments::parse(s).unwrap();
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    match (&revisioned.ignore[..], &revisioned.only[..]) {
+        (
+            [(
+                Condition::Env {
+                    name: ci,
+                    value: None,
+                },
+                1,
+            )],
+            [(
+                Condition::Env {
+                    name: slow,
+                    value: Some(one),
+                },
+                2,
+            )],
+        ) => {
+            assert_eq!(ci, "CI");
+            assert_eq!(slow, "UI_TEST_SLOW");
+            assert_eq!(one, "1");
+        }
+        _ => panic!("{revisioned:#?}"),
+    }
+
+    let errors = Comments::parse("//@only-env-UI_TEST_SLOW=").unwrap_err();
+    match &errors[..] {
+        [Error::InvalidComment { msg, line: 1 }] => assert_eq!(
+            msg,
+            "`env-UI_TEST_SLOW=` is not a valid condition, expected a value after the `=`"
+        ),
+        _ => panic!("{errors:#?}"),
+    }
+}
+
+#[test]
+fn parse_target_regex_conditions() {
+    let comments = Comments::parse("//@ignore-target: /^arm-/\n").unwrap();
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    match &revisioned.ignore[..] {
+        [(Condition::TargetRegex(regex), 1)] => assert_eq!(regex.as_str(), "^arm-"),
+        _ => panic!("{revisioned:#?}"),
+    }
+
+    let errors = Comments::parse("//@only-target: /(arm/").unwrap_err();
+    match &errors[..] {
+        [Error::InvalidComment { msg, line: 1 }] => {
+            assert!(msg.starts_with("invalid regex"), "{msg}")
+        }
+        _ => panic!("{errors:#?}"),
+    }
+
+    let errors = Comments::parse("//@only-target: arm").unwrap_err();
+    match &errors[..] {
+        [Error::InvalidComment { msg, line: 1 }] => assert!(msg.contains("`/regex/`"), "{msg}"),
+        _ => panic!("{errors:#?}"),
+    }
+}
+
+#[test]
+fn parse_channel_conditions() {
+    let comments = Comments::parse("//@only-nightly\n//@ignore-beta\n//@ignore-stable\n").unwrap();
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    assert!(matches!(
+        &revisioned.only[..],
+        [(Condition::Channel(Channel::Nightly), 1)]
+    ));
+    assert!(matches!(
+        &revisioned.ignore[..],
+        [
+            (Condition::Channel(Channel::Beta), 2),
+            (Condition::Channel(Channel::Stable), 3)
+        ]
+    ));
+}
+
+#[test]
+fn custom_commands() {
+    fn my_tool_flags(
+        args: &str,
+        line: usize,
+        comments: &mut CustomCommandContext<'_>,
+    ) -> Result<(), String> {
+        if args.is_empty() {
+            return Err("`my-tool-flags` needs at least one flag".into());
+        }
+        comments.compile_flags.push("--my-tool".into());
+        comments
+            .env_vars
+            .push(("MY_TOOL_FLAGS".into(), args.into()));
+        comments
+            .custom
+            .insert("my-tool-flags".into(), Box::new((args.to_owned(), line)));
+        Ok(())
+    }
+    let mut config = Config::rustc(PathBuf::new());
+    config
+        .custom_comment_parsers
+        .insert("my-tool-flags".into(), my_tool_flags);
+    check_custom_commands(&config.custom_comment_parsers).unwrap();
+
+    let s = "//@revisions: a b\n//@[a] my-tool-flags: -x -y\n";
+    let comments = Comments::parse_with_config(s, &config).unwrap();
+    let revisioned = &comments.revisioned[&revisions(&["a"])];
+    assert_eq!(revisioned.compile_flags, ["--my-tool"]);
+    assert_eq!(
+        revisioned.env_vars,
+        [("MY_TOOL_FLAGS".to_owned(), "-x -y".to_owned())]
+    );
+    let data = revisioned.custom["my-tool-flags"]
+        .downcast_ref::<(String, usize)>()
+        .unwrap();
+    assert_eq!(data, &("-x -y".to_owned(), 2));
+
+    let errors = Comments::parse_with_config("//@my-tool-flags", &config).unwrap_err();
+    match &errors[..] {
+        [Error::InvalidComment { msg, line: 1 }] => {
+            assert_eq!(msg, "`my-tool-flags` needs at least one flag")
+        }
+        _ => panic!("{errors:#?}"),
+    }
+
+    let errors = Comments::parse_with_config("//@my-tool-flag: -x", &config).unwrap_err();
+    match &errors[..] {
+        [Error::InvalidComment { msg, line: 1 }] => assert_eq!(
+            msg,
+            "`my-tool-flag` is not a command known to `ui_test`, did you mean `my-tool-flags`?"
+        ),
+        _ => panic!("{errors:#?}"),
+    }
+
+    for name in ["compile-flags", "revisions", "only-my-tool", "my tool"] {
+        config.custom_comment_parsers.clear();
+        config
+            .custom_comment_parsers
+            .insert(name.into(), my_tool_flags);
+        let err = check_custom_commands(&config.custom_comment_parsers).unwrap_err();
+        assert!(err.to_string().contains(&format!("`{name}`")), "{err}");
+    }
+}
+
+#[test]
+fn accessors() {
+    let s = r"//@revisions: a b
+//@compile-flags: --test
+//@[a] rustc-env: A=1
+//@[b] edition: 2018
+//@ignore-on-host
+fn main() {
+    let x: u32 = 0u8; //~[a] ERROR: mismatched types
+}
+";
+    let comments = Comments::parse(s).unwrap();
+    assert_eq!(
+        comments.revisions().unwrap().collect::<Vec<_>>(),
+        ["a", "b"]
+    );
+    let flags: Vec<_> = comments
+        .for_revision("a")
+        .flat_map(|r| r.compile_flags())
+        .collect();
+    assert_eq!(flags, ["--test"]);
+    let envs: Vec<_> = comments
+        .for_revision("a")
+        .flat_map(|r| r.env_vars())
+        .collect();
+    assert_eq!(envs, [("A", "1")]);
+    assert!(comments.for_revision("a").all(|r| r.edition().is_none()));
+    assert_eq!(
+        comments.for_revision("b").find_map(|r| r.edition()),
+        Some(("2018", 4))
+    );
+    let ignore: Vec<_> = comments
+        .for_revision("b")
+        .flat_map(|r| r.ignore())
+        .map(|(c, line)| (c.to_string(), line))
+        .collect();
+    assert_eq!(ignore, [("on-host".to_owned(), 5)]);
+    let annotation = comments
+        .for_revision("a")
+        .flat_map(|r| r.error_matches())
+        .next()
+        .unwrap();
+    assert_eq!(annotation.level(), crate::Level::Error);
+    assert_eq!(annotation.line(), Some(7));
+    assert!(comments
+        .for_revision("b")
+        .all(|r| r.error_matches().next().is_none()));
+
+    assert!(Comments::parse("").unwrap().revisions().is_none());
+}
+
+#[test]
+fn target_in_compile_flags_when_cross() {
+    let s = "//@compile-flags: --target=aarch64-unknown-linux-gnu";
+    let native = Config {
+        host: Some("x86_64-unknown-linux-gnu".into()),
+        target: Some("x86_64-unknown-linux-gnu".into()),
+        ..Config::rustc(PathBuf::new())
+    };
+    Comments::parse_with_config(s, &native).unwrap();
+    let cross = Config {
+        target: Some("aarch64-unknown-linux-gnu".into()),
+        ..native
+    };
+    let errors = Comments::parse_with_config(s, &cross).unwrap_err();
+    match &errors[..] {
+        [Error::InvalidComment { msg, line: 1 }] => assert_eq!(
+            msg,
+            "`--target` is already passed for `Config::target` (aarch64-unknown-linux-gnu)"
+        ),
+        _ => panic!("{errors:#?}"),
+    }
+}
+
+#[test]
+fn parse_test_modes() {
+    let mode = |s: &str| {
+        let comments = Comments::parse(s).unwrap();
+        comments.revisioned[&RevisionFilter::default()]
+            .mode
+            .map(|(mode, _)| mode)
+    };
+    assert!(matches!(mode("//@check-pass"), Some(Mode::Check)));
+    assert!(matches!(mode("//@build-pass"), Some(Mode::Pass)));
+    assert!(matches!(
+        mode("//@check-fail"),
+        Some(Mode::Fail {
+            require_patterns: true
+        })
+    ));
+    for s in [
+        "//@check-pass\n//@build-pass",
+        "//@check-fail\n//@run",
+        "//@build-pass\n//@check-fail",
+    ] {
+        let errors = Comments::parse(s).unwrap_err();
+        match &errors[..] {
+            [Error::InvalidComment { msg, line: 2 }] => {
+                assert_eq!(msg, "cannot specify test mode changes twice")
+            }
+            _ => panic!("{errors:#?}"),
+        }
+    }
+
+    // `//@check-fail` keeps whether the suite requires patterns.
+    let comments = Comments::parse("//@check-fail").unwrap();
+    let suite = Mode::Fail {
+        require_patterns: false,
+    };
+    let mut errors = vec![];
+    assert!(matches!(
+        suite.maybe_override(&comments, "", &mut errors),
+        Mode::Fail {
+            require_patterns: false
+        }
+    ));
+    assert!(matches!(
+        Mode::Pass.maybe_override(&comments, "", &mut errors),
+        Mode::Fail {
+            require_patterns: true
+        }
+    ));
+    assert!(errors.is_empty());
+}
+
+#[test]
+fn parse_require_annotations() {
+    // Whether the resulting mode requires patterns, or `None` if it doesn't fail.
+    let requires =
+        |suite: Mode, comments: &Comments, revision: &str, errors: &mut Vec<Error>| match suite
+            .maybe_override(comments, revision, errors)
+        {
+            Mode::Fail { require_patterns } => Some(require_patterns),
+            _ => None,
+        };
+    let strict = Mode::Fail {
+        require_patterns: true,
+    };
+    let lenient = Mode::Fail {
+        require_patterns: false,
+    };
+    let mut errors = vec![];
+    let comments = Comments::parse("//@revisions: a b\n//@[a] dont-require-annotations").unwrap();
+    assert_eq!(requires(strict, &comments, "a", &mut errors), Some(false));
+    assert_eq!(requires(strict, &comments, "b", &mut errors), Some(true));
+    // Also when `//@check-fail` turns a passing suite into a failing one.
+    let comments = Comments::parse("//@check-fail\n//@dont-require-annotations").unwrap();
+    assert_eq!(
+        requires(Mode::Pass, &comments, "", &mut errors),
+        Some(false)
+    );
+    let comments = Comments::parse("//@require-annotations").unwrap();
+    assert_eq!(requires(lenient, &comments, "", &mut errors), Some(true));
+    // Suites that don't fail to compile just ignore it.
+    assert_eq!(requires(Mode::Pass, &comments, "", &mut errors), None);
+    assert!(errors.is_empty());
+
+    let comments = Comments::parse("//@check-pass\n//@dont-require-annotations").unwrap();
+    assert_eq!(requires(strict, &comments, "", &mut errors), None);
+    match &errors[..] {
+        [Error::InvalidComment { msg, line: 2 }] => assert_eq!(
+            msg,
+            "`dont-require-annotations` only applies to tests that fail to compile, \
+             but line 1 changes the mode to `check`"
+        ),
+        _ => panic!("{errors:#?}"),
+    }
+
+    for s in [
+        "//@require-annotations\n//@dont-require-annotations",
+        "//@dont-require-annotations\n//@dont-require-annotations",
+    ] {
+        let errors = Comments::parse(s).unwrap_err();
+        match &errors[..] {
+            [Error::InvalidComment { msg, line: 2 }] => assert_eq!(
+                msg,
+                "cannot specify `require-annotations` or `dont-require-annotations` twice"
+            ),
+            _ => panic!("{errors:#?}"),
+        }
+    }
+}
+
+#[test]
+fn parse_run_exit() {
+    let exit =
+        |s: &str| match Comments::parse(s).unwrap().revisioned[&RevisionFilter::default()].mode {
+            Some((Mode::Run { exit }, _)) => exit,
+            mode => panic!("{mode:?}"),
+        };
+    assert_eq!(exit("//@run"), ExitExpectation::Code(0));
+    assert_eq!(exit("//@run: 101"), ExitExpectation::Code(101));
+    assert_eq!(
+        exit("//@run: signal=abrt"),
+        ExitExpectation::Signal(Signal::Abrt)
+    );
+    assert_eq!(
+        exit("//@run: signal=sigsegv"),
+        ExitExpectation::Signal(Signal::Segv)
+    );
+    assert_eq!(Signal::Abrt.to_string(), "SIGABRT");
+    assert_eq!(exit("//@run-panic: oops"), ExitExpectation::Panic);
+
+    let errors = Comments::parse("//@run: signal=hup").unwrap_err();
+    match &errors[..] {
+        [Error::InvalidComment { msg, line: 1 }] => assert!(
+            msg.starts_with("unknown signal `hup`, expected one of `abrt`"),
+            "{msg}"
+        ),
+        _ => panic!("{errors:#?}"),
+    }
+}
+
+#[test]
+fn parse_run_panic() {
+    let comments = Comments::parse("//@run-panic: /answer is \\d+/").unwrap();
+    let (pattern, line) = comments.run_panic(&mut vec![], "").unwrap();
+    assert_eq!(line, 1);
+    assert!(pattern.is_match_in(b"thread 'main' panicked at src/main.rs:2:5:\nanswer is 42\n"));
+    assert!(!pattern.is_match_in(b"answer is unknown"));
+
+    for (s, expected) in [
+        (
+            "//@run-panic",
+            "`run-panic` needs (part of) the expected panic message",
+        ),
+        (
+            "//@run\n//@run-panic: oops",
+            "cannot specify test mode changes twice",
+        ),
+    ] {
+        let errors = Comments::parse(s).unwrap_err();
+        match &errors[..] {
+            [Error::InvalidComment { msg, .. }] => assert_eq!(msg, expected),
+            _ => panic!("{errors:#?}"),
+        }
+    }
+}
+
+#[test]
+fn parse_yolo() {
+    let config = Config::rustc(PathBuf::new());
+    let comments = Comments::parse("//@revisions: a b\n//@[a] yolo").unwrap();
+    assert!(comments.yolo("a", &config));
+    assert!(!comments.yolo("b", &config));
+    let yolo_suite = Config {
+        mode: Mode::Yolo,
+        ..config
+    };
+    assert!(comments.yolo("b", &yolo_suite));
+
+    let errors = Comments::parse("//@yolo\n//@yolo").unwrap_err();
+    match &errors[..] {
+        [Error::InvalidComment { msg, line: 2 }] => {
+            assert_eq!(msg, "cannot specify `yolo` twice")
+        }
+        _ => panic!("{errors:#?}"),
+    }
+}
+
+#[test]
+fn parse_crate_type_and_name() {
+    let comments = Comments::parse("//@crate-type: proc-macro\n//@crate-name: mycrate").unwrap();
+    assert_eq!(comments.crate_type(&mut vec![], ""), Some("proc-macro"));
+    assert_eq!(comments.crate_name(&mut vec![], ""), Some("mycrate"));
+
+    for (s, expected) in [
+        (
+            "//@crate-type: library",
+            "unknown crate type `library`, expected one of `bin`, `lib`, `rlib`, `dylib`, \
+             `cdylib`, `staticlib`, `proc-macro`",
+        ),
+        ("//@crate-name", "`crate-name` needs the name of the crate"),
+        (
+            "//@crate-type: lib\n//@crate-type: bin",
+            "cannot specify `crate-type` twice",
+        ),
+        (
+            "//@crate-name: a\n//@crate-name: b",
+            "cannot specify `crate-name` twice",
+        ),
+    ] {
+        let errors = Comments::parse(s).unwrap_err();
+        match &errors[..] {
+            [Error::InvalidComment { msg, .. }] => assert_eq!(msg, expected),
+            _ => panic!("{s}: {errors:#?}"),
+        }
+    }
+
+    let comments =
+        Comments::parse("//@revisions: a b\n//@crate-type: lib\n//@[a] crate-type: bin").unwrap();
+    let mut errors = vec![];
+    comments.crate_type(&mut errors, "a");
+    match &errors[..] {
+        [Error::InvalidComment { msg, .. }] => assert_eq!(msg, "`crate-type` specified twice"),
+        _ => panic!("{errors:#?}"),
+    }
+}
+
+#[test]
+fn parse_placeholders() {
+    let comments =
+        Comments::parse("//@compile-flags: -L {{build-base}}\n//@rustc-env: A={{file}}").unwrap();
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    assert_eq!(revisioned.compile_flags, ["-L", "{{build-base}}"]);
+
+    for (s, line) in [
+        ("//@compile-flags: -L {{build-dir}}", 1),
+        ("//@compile-flags: -O\n//@rustc-env: A={{build-dir}}", 2),
+    ] {
+        let errors = Comments::parse(s).unwrap_err();
+        match &errors[..] {
+            [Error::InvalidComment { msg, line: l }] => {
+                assert_eq!(
+                    msg,
+                    "unknown placeholder `{{build-dir}}`, expected one of `{{src-base}}`, \
+                     `{{build-base}}`, `{{target}}`, `{{sysroot}}`, `{{file}}`"
+                );
+                assert_eq!(*l, line);
+            }
+            _ => panic!("{s}: {errors:#?}"),
+        }
+    }
+}
+
+#[test]
+fn parse_emit() {
+    let comments = Comments::parse("//@emit: llvm-ir strip-value-names").unwrap();
+    let revisioned = &comments.revisioned[&RevisionFilter::default()];
+    assert_eq!(revisioned.emit, Some((Emit::LlvmIr, 1)));
+    assert!(revisioned.strip_value_names);
+    assert_eq!(Emit::LlvmIr.extension(), "ll");
+
+    for (s, expected) in [
+        ("//@emit", "`emit` needs one of `asm`, `llvm-ir` or `mir`"),
+        (
+            "//@emit: wasm",
+            "`emit` expects `asm`, `llvm-ir` or `mir`, but got `wasm`",
+        ),
+        (
+            "//@emit: asm strip-value-names",
+            "`strip-value-names` is only supported for `emit: llvm-ir`",
+        ),
+        ("//@emit: mir quickly", "unknown `emit` option `quickly`"),
+        ("//@emit: asm\n//@emit: mir", "cannot specify `emit` twice"),
+    ] {
+        let errors = Comments::parse(s).unwrap_err();
+        match &errors[..] {
+            [Error::InvalidComment { msg, .. }] => assert_eq!(msg, expected),
+            _ => panic!("{s}: {errors:#?}"),
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! `{{name}}` placeholders in the values of `//@compile-flags` and `//@rustc-env`, which are
+//! replaced by paths and names that only the harness knows, e.g. `-L {{build-base}}`.
+
+use crate::{Config, Error};
+use std::{borrow::Cow, path::Path};
+
+#[cfg(test)]
+mod tests;
+
+/// The names of the known placeholders, without the braces.
+pub(crate) const NAMES: &[&str] = &["src-base", "build-base", "target", "sysroot", "file"];
+
+/// The names of all placeholders in `s`, e.g. `build-base` for `-L{{build-base}}`.
+pub(crate) fn find(s: &str) -> impl Iterator<Item = &str> {
+    s.split("{{")
+        .skip(1)
+        .filter_map(|rest| Some(rest.split_once("}}")?.0))
+}
+
+/// The values of the placeholders for compiling `file` into `out_dir`.
+pub(crate) struct Placeholders<'a> {
+    pub(crate) config: &'a Config,
+    pub(crate) out_dir: &'a Path,
+    pub(crate) file: &'a Path,
+}
+
+impl Placeholders<'_> {
+    /// Replace the known placeholders in `s`. Unknown placeholders were already reported
+    /// while parsing the comments, so they are left alone.
+    pub(crate) fn replace<'s>(&self, s: &'s str, errors: &mut Vec<Error>) -> Cow<'s, str> {
+        let mut s = Cow::Borrowed(s);
+        for name in NAMES {
+            let placeholder = format!("{{{{{name}}}}}");
+            if s.contains(&placeholder) {
+                let value = self.value(name, errors);
+                s = Cow::Owned(s.replace(&placeholder, &value));
+            }
+        }
+        s
+    }
+
+    fn value(&self, name: &str, errors: &mut Vec<Error>) -> String {
+        let path = match name {
+            "src-base" => &self.config.root_dir,
+            "build-base" => self.out_dir,
+            "target" => return self.config.target.clone().unwrap(),
+            "sysroot" => match &self.config.sysroot {
+                Some(sysroot) => sysroot,
+                None => {
+                    errors.push(Error::UnknownSysroot);
+                    return String::new();
+                }
+            },
+            "file" => self.file,
+            _ => unreachable!("unknown placeholder `{name}`"),
+        };
+        path.display().to_string()
+    }
+}
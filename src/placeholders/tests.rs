@@ -0,0 +1,40 @@
+use crate::{build_command, parser::Comments, Config, Error};
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+#[test]
+fn placeholders_in_flags() {
+    let config = Config {
+        out_dir: PathBuf::from("out"),
+        target: Some("x86_64-unknown-linux-gnu".into()),
+        host: Some("x86_64-unknown-linux-gnu".into()),
+        sysroot: Some(PathBuf::from("/sysroot")),
+        ..Config::rustc(PathBuf::from("tests/ui"))
+    };
+    let path = Path::new("tests/ui/remap.rs");
+    let comments = Comments::parse(
+        "//@compile-flags: -L {{build-base}} --remap-path-prefix={{src-base}}=SRC\n\
+         //@compile-flags: --cfg=x{{target}}{{target}}\n\
+         //@rustc-env: SYSROOT={{sysroot}} FILE={{file}}",
+    )
+    .unwrap();
+    let mut errors = vec![];
+    let cmd = build_command(path, &config, "", &comments, &mut errors);
+    assert!(errors.is_empty(), "{errors:#?}");
+    let args: Vec<_> = cmd.get_args().map(|arg| arg.to_str().unwrap()).collect();
+    assert!(args.windows(2).any(|w| w == ["-L", "out"]));
+    assert!(args.contains(&"--remap-path-prefix=tests/ui=SRC"));
+    assert!(args.contains(&"--cfg=xx86_64-unknown-linux-gnux86_64-unknown-linux-gnu"));
+    let envs: Vec<_> = cmd.get_envs().collect();
+    assert!(envs.contains(&(OsStr::new("SYSROOT"), Some(OsStr::new("/sysroot")))));
+    assert!(envs.contains(&(OsStr::new("FILE"), Some(OsStr::new("tests/ui/remap.rs")))));
+
+    let config = Config {
+        sysroot: None,
+        ..config
+    };
+    build_command(path, &config, "", &comments, &mut errors);
+    assert!(matches!(&errors[..], [Error::UnknownSysroot]));
+}
@@ -1,8 +1,11 @@
 use std::path::{Path, PathBuf};
+use std::process::Output;
 
 use bstr::ByteSlice;
 use regex::Regex;
 
+use crate::{DiagnosticsStream, Error, Errors};
+
 #[derive(serde::Deserialize, Debug)]
 struct RustcMessage {
     rendered: Option<String>,
@@ -10,24 +13,54 @@ struct RustcMessage {
     level: String,
     message: String,
     children: Vec<RustcMessage>,
+    code: Option<RustcCode>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct RustcCode {
+    code: String,
 }
 
 #[derive(Copy, Clone, Debug, PartialOrd, Ord, PartialEq, Eq)]
-pub(crate) enum Level {
-    Ice = 5,
-    Error = 4,
-    Warn = 3,
-    Help = 2,
-    Note = 1,
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+/// The level of a diagnostic. Levels are ordered by severity.
+pub enum Level {
+    /// An internal compiler error.
+    Ice = 6,
+    /// An error.
+    Error = 5,
+    /// A warning.
+    Warn = 4,
+    /// A help message.
+    Help = 3,
+    /// A note.
+    Note = 2,
     /// Only used for "For more information about this error, try `rustc --explain EXXXX`".
-    FailureNote = 0,
+    FailureNote = 1,
+    /// Not a diagnostic, but the replacement text of a suggestion, matched by `//~ SUGGESTION`.
+    Suggestion = 0,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 /// A diagnostic message.
 pub struct Message {
     pub(crate) level: Level,
     pub(crate) message: String,
+    /// The error code or lint name, e.g. `E0308`.
+    pub(crate) code: Option<String>,
+    /// The column (starting at 1) of the primary span, if it is in the test file.
+    pub(crate) column: Option<usize>,
+    /// The file of the primary span (or that of the parent diagnostic, if there is none).
+    /// For diagnostics outside the test file, `Config::stderr_filters` are applied to it,
+    /// e.g. `$CARGO_REGISTRY/foo/src/lib.rs`.
+    pub(crate) file: Option<String>,
+    /// The lines and columns of the other primary spans in the test file, if the diagnostic
+    /// has several, e.g. on the arms of a `match`. The message can be matched on any of them.
+    pub(crate) other_sites: Vec<(usize, usize)>,
+    /// For notes and helps attached to another diagnostic, e.g. "required by a bound in ...",
+    /// the message of that diagnostic. They are matched independently of it.
+    pub(crate) parent: Option<String>,
 }
 
 /// Information about macro expansion.
@@ -39,22 +72,123 @@ struct Expansion {
 #[derive(serde::Deserialize, Debug)]
 struct Span {
     line_start: usize,
+    column_start: usize,
     file_name: PathBuf,
     is_primary: bool,
     expansion: Option<Box<Expansion>>,
+    suggested_replacement: Option<String>,
 }
 
+/// A level that is neither the name of a `Level` in annotations nor one the compiler emits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownLevel(pub String);
+
+impl std::fmt::Display for UnknownLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "unknown level `{}`, expected one of `ERROR`, `WARN` (or `WARNING`), `HELP`, `NOTE`, `SUGGESTION`, `FAILURE-NOTE` or `ICE`",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for UnknownLevel {}
+
 impl std::str::FromStr for Level {
-    type Err = String;
+    type Err = UnknownLevel;
+    /// Parses both the names of levels in annotations (e.g. `WARN` or `WARNING`) and the
+    /// levels of the compiler's JSON diagnostics (e.g. `warning`).
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "ERROR" | "error" => Ok(Self::Error),
-            "WARN" | "warning" => Ok(Self::Warn),
+            "WARN" | "WARNING" | "warning" => Ok(Self::Warn),
             "HELP" | "help" => Ok(Self::Help),
             "NOTE" | "note" => Ok(Self::Note),
-            "failure-note" => Ok(Self::FailureNote),
-            "error: internal compiler error" => Ok(Self::Ice),
-            _ => Err(format!("unknown level `{s}`")),
+            "FAILURE-NOTE" | "failure-note" => Ok(Self::FailureNote),
+            "SUGGESTION" => Ok(Self::Suggestion),
+            "ICE" | "error: internal compiler error" => Ok(Self::Ice),
+            _ => Err(UnknownLevel(s.to_owned())),
+        }
+    }
+}
+
+impl std::fmt::Display for Level {
+    /// The name of the level in annotations and `//@require-annotations-for-level`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Ice => "ICE",
+            Self::Error => "ERROR",
+            Self::Warn => "WARN",
+            Self::Help => "HELP",
+            Self::Note => "NOTE",
+            Self::FailureNote => "FAILURE-NOTE",
+            Self::Suggestion => "SUGGESTION",
+        };
+        f.write_str(name)
+    }
+}
+
+impl Message {
+    /// A diagnostic with the given level and message, but without a code or location.
+    pub(crate) fn new(level: Level, message: impl Into<String>) -> Self {
+        Message {
+            level,
+            message: message.into(),
+            code: None,
+            column: None,
+            file: None,
+            other_sites: vec![],
+            parent: None,
+        }
+    }
+
+    /// The level of the diagnostic.
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    /// The message of the diagnostic.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The error code or lint name, e.g. `E0308`.
+    pub fn code(&self) -> Option<&str> {
+        self.code.as_deref()
+    }
+
+    /// The column (starting at 1) of the primary span, if it is in the test file.
+    pub fn column(&self) -> Option<usize> {
+        self.column
+    }
+
+    /// The file of the primary span, see `Message::file`.
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+
+    /// The message of the diagnostic this one is attached to, see `Message::parent`.
+    pub fn parent(&self) -> Option<&str> {
+        self.parent.as_deref()
+    }
+
+    /// The lines of the other primary spans in the test file, see `Message::other_sites`.
+    pub fn other_lines(&self) -> impl Iterator<Item = usize> + '_ {
+        self.other_sites.iter().map(|&(line, _)| line)
+    }
+}
+
+impl Level {
+    /// The name of the level in `//~` annotations, if diagnostics of this level can be annotated.
+    pub(crate) fn annotation_name(self) -> Option<&'static str> {
+        match self {
+            Self::Error => Some("ERROR"),
+            Self::Warn => Some("WARN"),
+            Self::Help => Some("HELP"),
+            Self::Note => Some("NOTE"),
+            Self::Suggestion => Some("SUGGESTION"),
+            Self::Ice | Self::FailureNote => None,
         }
     }
 }
@@ -70,53 +204,226 @@ pub(crate) struct Diagnostics {
     pub messages_from_unknown_file_or_line: Vec<Message>,
 }
 
+/// The file, line, column and error code of a diagnostic's primary span.
+type SortKey = (PathBuf, usize, usize, String);
+
 impl RustcMessage {
-    fn line(&self, file: &Path) -> Option<usize> {
-        let span = |primary| self.spans.iter().find_map(|span| span.line(file, primary));
-        span(true).or_else(|| span(false))
+    fn sort_key(&self) -> Option<SortKey> {
+        let span = self.spans.iter().find(|span| span.is_primary)?;
+        let code = self
+            .code
+            .as_ref()
+            .map(|c| c.code.clone())
+            .unwrap_or_default();
+        Some((
+            span.file_name.clone(),
+            span.line_start,
+            span.column_start,
+            code,
+        ))
     }
 
-    /// Put the message and its children into the line-indexed list.
+    /// The lines and columns in `file` of the primary spans, one per line. If no primary span
+    /// is in `file`, the first of the other spans in `file` is returned instead.
+    fn sites(&self, file: &Path, expansion_site: bool) -> Vec<(usize, usize)> {
+        let mut sites: Vec<(usize, usize)> = vec![];
+        for site in self
+            .spans
+            .iter()
+            .filter_map(|span| span.site(file, true, expansion_site))
+        {
+            if sites.iter().all(|&(line, _)| line != site.0) {
+                sites.push(site);
+            }
+        }
+        if sites.is_empty() {
+            sites.extend(
+                self.spans
+                    .iter()
+                    .find_map(|span| span.site(file, false, expansion_site)),
+            );
+        }
+        sites
+    }
+
+    /// Put the message and its children into the line-indexed list. Children are put on the
+    /// line of their own span, and only on the `line` of their `parent` if they have none in
+    /// the test file.
     fn insert_recursive(
         self,
         file: &Path,
         messages: &mut Vec<Vec<Message>>,
         messages_from_unknown_file_or_line: &mut Vec<Message>,
         line: Option<usize>,
+        msg_file: Option<String>,
+        parent: Option<String>,
+        expansion_site: bool,
     ) {
-        let line = self.line(file).or(line);
+        let mut sites = self.sites(file, expansion_site);
+        let line = sites.first().map(|&(line, _)| line).or(line);
+        let msg_file = self
+            .spans
+            .iter()
+            .find(|span| span.is_primary)
+            .map(|span| span.file_name.display().to_string())
+            .or(msg_file);
+        // Suggestions are matched on the line they replace code in.
+        for span in &self.spans {
+            if let (Some(replacement), true) = (&span.suggested_replacement, span.file_name == file)
+            {
+                if messages.len() <= span.line_start {
+                    messages.resize_with(span.line_start + 1, Vec::new);
+                }
+                messages[span.line_start].push(Message {
+                    column: Some(span.column_start),
+                    file: Some(span.file_name.display().to_string()),
+                    ..Message::new(Level::Suggestion, replacement.clone())
+                });
+            }
+        }
+        let column = self
+            .spans
+            .iter()
+            .find_map(|span| span.site(file, true, expansion_site))
+            .map(|(_, column)| column);
+        // Only diagnostics with several primary spans can be matched elsewhere.
+        let other_sites = if column.is_some() {
+            sites.split_off(1)
+        } else {
+            vec![]
+        };
         let msg = Message {
             level: self.level.parse().unwrap(),
             message: self.message,
+            code: self.code.map(|code| code.code),
+            column,
+            file: msg_file.clone(),
+            other_sites,
+            parent,
         };
+        let message = msg.message.clone();
         if let Some(line) = line {
             if messages.len() <= line {
                 messages.resize_with(line + 1, Vec::new);
             }
             messages[line].push(msg);
         // All other messages go into the general bin, unless they are specifically of the
-        // "aborting due to X previous errors" variety, as we never want to match those. They
-        // only count the number of errors and provide no useful information about the tests.
-        } else if !(msg.message.starts_with("aborting due to")
-            && msg.message.contains("previous error"))
-        {
+        // "aborting due to X previous errors" or "X warnings emitted" variety, as we never want
+        // to match those. They only count the number of errors and warnings and provide no
+        // useful information about the tests.
+        } else if !is_count(&msg.message) {
             messages_from_unknown_file_or_line.push(msg);
         }
         for child in self.children {
-            child.insert_recursive(file, messages, messages_from_unknown_file_or_line, line)
+            child.insert_recursive(
+                file,
+                messages,
+                messages_from_unknown_file_or_line,
+                line,
+                msg_file.clone(),
+                Some(message.clone()),
+                expansion_site,
+            )
         }
     }
 }
 
 impl Span {
-    /// Returns the most expanded line number *in the given file*, if possible.
-    fn line(&self, file: &Path, primary: bool) -> Option<usize> {
-        if let Some(exp) = &self.expansion {
-            if let Some(line) = exp.span.line(file, primary && !self.is_primary) {
-                return Some(line);
+    /// Returns the line and column *in the given file*, if possible. Spans inside macros are
+    /// attributed to the outermost macro invocation in the file, unless `expansion_site` is
+    /// false, see `//@match-expansion-site`.
+    fn site(&self, file: &Path, primary: bool, expansion_site: bool) -> Option<(usize, usize)> {
+        if let (Some(exp), true) = (&self.expansion, expansion_site) {
+            if let Some(site) = exp.span.site(file, primary && !self.is_primary, true) {
+                return Some(site);
+            }
+        }
+        ((!primary || self.is_primary) && self.file_name == file)
+            .then_some((self.line_start, self.column_start))
+    }
+}
+
+/// The compiler's output, with its JSON diagnostics taken from the stream
+/// `Config::diagnostics_stream` says.
+pub(crate) struct Streams {
+    /// The JSON diagnostics, interleaved with the rest of stderr if they are on stderr.
+    pub json: Vec<u8>,
+    /// The compiler's stdout without the JSON diagnostics.
+    pub stdout: Vec<u8>,
+    /// If the JSON diagnostics are on stdout, the compiler's stderr, where it already
+    /// rendered them.
+    pub rendered: Option<Vec<u8>>,
+}
+
+impl Streams {
+    /// Parses the JSON diagnostics, see `process`.
+    pub fn process(&self, file: &Path, sort: bool, expansion_site: bool) -> Diagnostics {
+        let mut diagnostics = process(file, &self.json, sort, expansion_site);
+        if let Some(rendered) = &self.rendered {
+            diagnostics.rendered = rendered.clone();
+        }
+        diagnostics
+    }
+}
+
+/// Whether the line looks like one of the compiler's JSON diagnostics.
+/// JSON is always valid UTF-8, other lines are kept as they are, even if they aren't.
+fn is_json(line: &[u8]) -> bool {
+    line.starts_with_str(b"{") && line.to_str().is_ok()
+}
+
+/// The lines that are JSON diagnostics. Other JSON, like the notifications of
+/// `--json=artifacts`, and anything else programs print doesn't count.
+fn is_json_diagnostic(line: &[u8]) -> bool {
+    is_json(line) && serde_json::from_slice::<RustcMessage>(line).is_ok()
+}
+
+/// Finds the compiler's JSON diagnostics in its `output`. If `stream` is
+/// `DiagnosticsStream::Auto` and both streams contain different ones, the ones on stderr
+/// are used and `Error::ConflictingDiagnostics` is reported.
+pub(crate) fn split_output(
+    output: &Output,
+    stream: DiagnosticsStream,
+    errors: &mut Errors,
+) -> Streams {
+    let (json, stdout): (Vec<&[u8]>, Vec<&[u8]>) = output
+        .stdout
+        .lines_with_terminator()
+        .partition(|line| is_json_diagnostic(line));
+    let on_stdout = match stream {
+        DiagnosticsStream::Stderr => false,
+        DiagnosticsStream::Stdout => true,
+        DiagnosticsStream::Auto if json.is_empty() => false,
+        DiagnosticsStream::Auto => {
+            let on_stderr: Vec<&[u8]> = output
+                .stderr
+                .lines_with_terminator()
+                .filter(|line| is_json_diagnostic(line))
+                .collect();
+            if !on_stderr.is_empty() && on_stderr != json {
+                errors.push(Error::ConflictingDiagnostics);
             }
+            on_stderr.is_empty()
+        }
+    };
+    // Unless they are pinned to stderr, JSON diagnostics on stdout are never compared against
+    // the `.stdout` file, even if the ones on stderr are used.
+    let stdout = match stream {
+        DiagnosticsStream::Stderr => output.stdout.clone(),
+        _ => stdout.concat(),
+    };
+    if on_stdout {
+        Streams {
+            json: json.concat(),
+            stdout,
+            rendered: Some(output.stderr.clone()),
+        }
+    } else {
+        Streams {
+            json: output.stderr.clone(),
+            stdout,
+            rendered: None,
         }
-        ((!primary || self.is_primary) && self.file_name == file).then_some(self.line_start)
     }
 }
 
@@ -125,22 +432,44 @@ pub(crate) fn filter_annotations_from_rendered(rendered: &str) -> std::borrow::C
     annotations.replace_all(rendered, "")
 }
 
-pub(crate) fn process(file: &Path, stderr: &[u8]) -> Diagnostics {
+/// Parse the compiler's json output. If `sort` is set, consecutive diagnostics are rendered
+/// ordered by their primary span, so nondeterministically ordered diagnostics render the same.
+/// Diagnostics inside macros are attributed to the line of the macro invocation, unless
+/// `expansion_site` is false. Either way, they are rendered with their original spans.
+pub(crate) fn process(file: &Path, stderr: &[u8], sort: bool, expansion_site: bool) -> Diagnostics {
     let mut rendered = Vec::new();
     let mut messages = vec![];
     let mut messages_from_unknown_file_or_line = vec![];
+    // Diagnostics that are waiting to be sorted and rendered. Anything without a
+    // primary span (e.g. "aborting due to ..." or non-json output) stays where it is.
+    let mut unsorted: Vec<(SortKey, String)> = vec![];
+    let flush = |unsorted: &mut Vec<(SortKey, String)>, rendered: &mut Vec<u8>| {
+        unsorted.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (_, msg) in unsorted.drain(..) {
+            rendered.extend(msg.as_bytes());
+        }
+    };
     for (line_number, line) in stderr.lines_with_terminator().enumerate() {
-        if line.starts_with_str(b"{") {
+        if is_json(line) {
             match serde_json::from_slice::<RustcMessage>(line) {
                 Ok(msg) => {
-                    rendered.extend(
-                        filter_annotations_from_rendered(msg.rendered.as_ref().unwrap()).as_bytes(),
-                    );
+                    let msg_rendered =
+                        filter_annotations_from_rendered(msg.rendered.as_ref().unwrap());
+                    match msg.sort_key().filter(|_| sort) {
+                        Some(key) => unsorted.push((key, msg_rendered.into_owned())),
+                        None => {
+                            flush(&mut unsorted, &mut rendered);
+                            rendered.extend(msg_rendered.as_bytes());
+                        }
+                    }
                     msg.insert_recursive(
                         file,
                         &mut messages,
                         &mut messages_from_unknown_file_or_line,
                         None,
+                        None,
+                        None,
+                        expansion_site,
                     );
                 }
                 Err(err) => {
@@ -149,12 +478,26 @@ pub(crate) fn process(file: &Path, stderr: &[u8]) -> Diagnostics {
             }
         } else {
             // FIXME: do we want to throw interpreter stderr into a separate file?
+            flush(&mut unsorted, &mut rendered);
             rendered.extend(line);
         }
     }
+    flush(&mut unsorted, &mut rendered);
     Diagnostics {
         rendered,
         messages,
         messages_from_unknown_file_or_line,
     }
 }
+
+/// Whether the message only counts the errors or warnings, like "aborting due to 2 previous
+/// errors" or "1 warning emitted".
+fn is_count(message: &str) -> bool {
+    if message.starts_with("aborting due to") && message.contains("previous error") {
+        return true;
+    }
+    message
+        .strip_suffix(" warning emitted")
+        .or_else(|| message.strip_suffix(" warnings emitted"))
+        .is_some_and(|count| count.parse::<usize>().is_ok())
+}
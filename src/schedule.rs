@@ -0,0 +1,323 @@
+//! Ordering of tests that declare `//@depends-on` other tests.
+
+use crate::{parse_comments_in_file, parser::Comments, Config, Error, TestResult, TestRun};
+use color_eyre::eyre::{bail, Result};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    path::{Component, Path, PathBuf},
+    sync::{Condvar, Mutex},
+};
+
+#[cfg(test)]
+mod tests;
+
+/// The prefix of the environment variables that point dependent tests to
+/// the artifact directories of their dependencies.
+pub(crate) const DEPENDENCY_ENV_PREFIX: &str = "UI_TEST_DEPENDENCY_DIR_";
+
+/// The order in which test files get submitted to the workers, and the
+/// bookkeeping needed to let dependent tests wait for their dependencies.
+pub(crate) struct Schedule {
+    /// The test files, with every test coming after all of its dependencies.
+    pub files: Vec<PathBuf>,
+    /// The directory in which the artifact directories of depended-on tests are created.
+    out_dir: PathBuf,
+    /// The resolved dependencies of each test and the line of the `depends-on` comment.
+    dependencies: HashMap<PathBuf, Vec<(PathBuf, usize)>>,
+    /// All tests that other tests depend on.
+    depended_on: HashSet<PathBuf>,
+    /// For finished tests that others depend on, why they can't be used, if they can't.
+    finished: Mutex<HashMap<PathBuf, Option<&'static str>>>,
+    finished_changed: Condvar,
+    /// The parsed comments of the tests that haven't been submitted to a worker yet,
+    /// or why they couldn't be parsed.
+    comments: Mutex<HashMap<PathBuf, ParsedComments>>,
+}
+
+/// The comments of a test, or the output and errors of parsing them.
+type ParsedComments = std::result::Result<Comments, (Vec<u8>, Vec<Error>)>;
+
+impl Schedule {
+    /// Parses the comments of all `files` and orders the tests according to their
+    /// `depends-on` comments. Errors if the dependencies form a cycle.
+    pub fn new(files: Vec<PathBuf>, config: &Config) -> Result<Self> {
+        let mut dependencies = HashMap::new();
+        let mut comments = HashMap::new();
+        for file in &files {
+            let parsed = parse_comments_in_file(file, config);
+            // Broken comments get reported when the test is run.
+            if let Ok(parsed) = &parsed {
+                let deps = resolve_dependencies(file, parsed);
+                if !deps.is_empty() {
+                    dependencies.insert(normalize(file), deps);
+                }
+            }
+            comments.insert(file.clone(), parsed);
+        }
+
+        let planned: HashMap<_, _> = files.iter().map(|file| (normalize(file), file)).collect();
+        let depended_on = dependencies
+            .values()
+            .flatten()
+            .map(|(dep, _)| dep.clone())
+            .filter(|dep| planned.contains_key(dep))
+            .collect();
+
+        // Depth first search, emitting each test after its dependencies.
+        enum State {
+            Visiting,
+            Done,
+        }
+        fn visit(
+            file: &Path,
+            dependencies: &HashMap<PathBuf, Vec<(PathBuf, usize)>>,
+            planned: &HashMap<PathBuf, &PathBuf>,
+            states: &mut HashMap<PathBuf, State>,
+            stack: &mut Vec<PathBuf>,
+            order: &mut Vec<PathBuf>,
+        ) -> Result<()> {
+            match states.get(file) {
+                Some(State::Done) => return Ok(()),
+                Some(State::Visiting) => {
+                    let start = stack.iter().position(|f| f == file).unwrap();
+                    let cycle = stack[start..]
+                        .iter()
+                        .chain(Some(&file.to_path_buf()))
+                        .map(|f| f.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(" -> ");
+                    bail!("tests have cyclic `depends-on` comments: {cycle}");
+                }
+                None => {}
+            }
+            states.insert(file.to_path_buf(), State::Visiting);
+            stack.push(file.to_path_buf());
+            for (dep, _) in dependencies.get(file).into_iter().flatten() {
+                if planned.contains_key(dep) {
+                    visit(dep, dependencies, planned, states, stack, order)?;
+                }
+            }
+            stack.pop();
+            states.insert(file.to_path_buf(), State::Done);
+            order.push(planned[file].clone());
+            Ok(())
+        }
+
+        let mut states = HashMap::new();
+        let mut order = Vec::with_capacity(files.len());
+        for file in &files {
+            visit(
+                &normalize(file),
+                &dependencies,
+                &planned,
+                &mut states,
+                &mut vec![],
+                &mut order,
+            )?;
+        }
+
+        Ok(Self {
+            files: order,
+            out_dir: config.out_dir.clone(),
+            dependencies,
+            depended_on,
+            finished: Default::default(),
+            finished_changed: Condvar::new(),
+            comments: Mutex::new(comments),
+        })
+    }
+
+    /// Hands out the comments parsed for the test, so they are only parsed once.
+    pub fn take_comments(&self, path: &Path) -> ParsedComments {
+        self.comments
+            .lock()
+            .unwrap()
+            .remove(path)
+            .expect("only scheduled tests are run, and each of them only once")
+    }
+
+    /// All tests that the given tests depend on, directly or indirectly, as normalized paths.
+    pub fn transitive_dependencies<'a>(
+        &self,
+        paths: impl IntoIterator<Item = &'a Path>,
+    ) -> HashSet<PathBuf> {
+        let mut todo: Vec<_> = paths.into_iter().map(normalize).collect();
+        let mut found = HashSet::new();
+        while let Some(path) = todo.pop() {
+            for (dep, _) in self.dependencies.get(&path).into_iter().flatten() {
+                if found.insert(dep.clone()) {
+                    todo.push(dep.clone());
+                }
+            }
+        }
+        found
+    }
+
+    /// The revisions of a test that hasn't been run yet, a single empty one if it has no
+    /// revisions or its comments couldn't be parsed.
+    pub fn revisions(&self, path: &Path) -> Vec<String> {
+        self.comments
+            .lock()
+            .unwrap()
+            .get(path)
+            .and_then(|comments| comments.as_ref().ok()?.revisions.clone())
+            .unwrap_or_else(|| vec![String::new()])
+    }
+
+    /// Passes the comments of a test that hasn't been run yet to `f`, `None` if they couldn't
+    /// be parsed.
+    pub fn with_comments<T>(&self, path: &Path, f: impl FnOnce(Option<&Comments>) -> T) -> T {
+        f(self
+            .comments
+            .lock()
+            .unwrap()
+            .get(path)
+            .and_then(|comments| comments.as_ref().ok()))
+    }
+
+    /// Whether any other test depends on this one.
+    pub fn is_dependency(&self, path: &Path) -> bool {
+        self.depended_on.contains(&normalize(path))
+    }
+
+    /// The directory into which a test that others depend on writes its artifacts.
+    pub fn artifact_dir(&self, path: &Path) -> PathBuf {
+        let relative: PathBuf = normalize(&path.with_extension(""))
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect();
+        self.out_dir.join(relative)
+    }
+
+    /// The environment variables pointing to the artifact directories of the test's dependencies.
+    pub fn dependency_envs(&self, path: &Path) -> Vec<(OsString, PathBuf)> {
+        self.dependencies
+            .get(&normalize(path))
+            .into_iter()
+            .flatten()
+            .map(|(dep, _)| (dependency_env_var(dep), self.artifact_dir(dep)))
+            .collect()
+    }
+
+    /// Blocks until all dependencies of the test have finished.
+    /// Returns errors for all dependencies that did not succeed.
+    pub fn wait_for_dependencies(&self, path: &Path) -> Vec<Error> {
+        let mut errors = vec![];
+        for (dep, line) in self
+            .dependencies
+            .get(&normalize(path))
+            .into_iter()
+            .flatten()
+        {
+            let reason = if !dep.exists() {
+                Some("does not exist")
+            } else if !self.depended_on.contains(dep) {
+                Some("was filtered out")
+            } else {
+                let mut finished = self.finished.lock().unwrap();
+                loop {
+                    match finished.get(dep) {
+                        Some(reason) => break *reason,
+                        None => finished = self.finished_changed.wait(finished).unwrap(),
+                    }
+                }
+            };
+            if let Some(reason) = reason {
+                errors.push(Error::DependencyFailed {
+                    path: dep.clone(),
+                    reason,
+                    line: *line,
+                });
+            }
+        }
+        errors
+    }
+
+    /// Records the results of a test, unblocking the tests that depend on it.
+    pub fn finish(&self, path: &Path, runs: &[TestRun]) {
+        let path = normalize(path);
+        if !self.depended_on.contains(&path) {
+            return;
+        }
+        let reason = if runs
+            .iter()
+            .any(|run| matches!(run.result, TestResult::Errored { .. }))
+        {
+            Some("failed")
+        } else if runs
+            .iter()
+            .all(|run| matches!(run.result, TestResult::Filtered))
+        {
+            Some("was filtered out")
+        } else if !runs
+            .iter()
+            .any(|run| matches!(run.result, TestResult::Ok | TestResult::Cached))
+        {
+            Some("was ignored")
+        } else {
+            None
+        };
+        self.finished.lock().unwrap().insert(path, reason);
+        self.finished_changed.notify_all();
+    }
+
+    /// Records that a test will not be run, unblocking the tests that depend on it.
+    pub fn not_run(&self, path: &Path) {
+        let path = normalize(path);
+        if self.depended_on.contains(&path) {
+            self.finished
+                .lock()
+                .unwrap()
+                .insert(path, Some("was not run"));
+            self.finished_changed.notify_all();
+        }
+    }
+}
+
+/// The dependencies of a test, relative to the current directory instead of the test file.
+fn resolve_dependencies(path: &Path, comments: &Comments) -> Vec<(PathBuf, usize)> {
+    comments
+        .revisioned
+        .values()
+        .flat_map(|r| r.depends_on.iter())
+        .map(|(dep, line)| (normalize(&path.parent().unwrap().join(dep)), *line))
+        .collect()
+}
+
+/// The name of the environment variable pointing to the artifact directory of `dep`.
+fn dependency_env_var(dep: &Path) -> OsString {
+    let stem = dep.file_stem().unwrap().to_string_lossy();
+    let stem: String = stem
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{DEPENDENCY_ENV_PREFIX}{stem}").into()
+}
+
+/// Remove `.` and `..` components without touching the file system,
+/// so paths written relative to different test files can be compared.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
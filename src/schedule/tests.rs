@@ -0,0 +1,107 @@
+use super::Schedule;
+use crate::{tests::config, Config, Error, TestResult, TestRun, Timings};
+use std::{process::Command, time::Duration};
+
+#[test]
+fn depends_on_ordering() {
+    let dir = tempfile::tempdir().unwrap();
+    let consumer = dir.path().join("a_consumer.rs");
+    let producer = dir.path().join("b_producer.rs");
+    let other = dir.path().join("c_other.rs");
+    std::fs::write(&consumer, "//@depends-on: ./b_producer.rs\n").unwrap();
+    std::fs::write(&producer, "").unwrap();
+    std::fs::write(&other, "").unwrap();
+
+    let schedule = Schedule::new(
+        vec![consumer.clone(), producer.clone(), other.clone()],
+        &Config {
+            out_dir: dir.path().join("out"),
+            ..config()
+        },
+    )
+    .unwrap();
+    assert_eq!(schedule.files, [producer.clone(), consumer.clone(), other]);
+    assert!(schedule.is_dependency(&producer));
+    assert!(!schedule.is_dependency(&consumer));
+    let envs = schedule.dependency_envs(&consumer);
+    assert_eq!(envs.len(), 1);
+    assert_eq!(envs[0].0, "UI_TEST_DEPENDENCY_DIR_B_PRODUCER");
+    assert_eq!(envs[0].1, schedule.artifact_dir(&producer));
+}
+
+#[test]
+fn depends_on_cycle() {
+    let dir = tempfile::tempdir().unwrap();
+    let a = dir.path().join("a.rs");
+    let b = dir.path().join("b.rs");
+    std::fs::write(&a, "//@depends-on: b.rs\n").unwrap();
+    std::fs::write(&b, "//@depends-on: a.rs\n").unwrap();
+
+    let err = Schedule::new(
+        vec![a, b],
+        &Config {
+            out_dir: dir.path().join("out"),
+            ..config()
+        },
+    )
+    .err()
+    .unwrap()
+    .to_string();
+    assert!(err.contains("cyclic `depends-on`"), "{err}");
+    assert!(err.contains("a.rs -> "), "{err}");
+}
+
+#[test]
+fn depends_on_failed_or_filtered() {
+    let dir = tempfile::tempdir().unwrap();
+    let consumer = dir.path().join("consumer.rs");
+    let producer = dir.path().join("producer.rs");
+    let filtered = dir.path().join("filtered.rs");
+    std::fs::write(
+        &consumer,
+        "//@depends-on: producer.rs\n//@depends-on: filtered.rs\n",
+    )
+    .unwrap();
+    std::fs::write(&producer, "").unwrap();
+    std::fs::write(&filtered, "").unwrap();
+
+    let schedule = Schedule::new(
+        vec![consumer.clone(), producer.clone()],
+        &Config {
+            out_dir: dir.path().join("out"),
+            ..config()
+        },
+    )
+    .unwrap();
+    schedule.finish(
+        &producer,
+        &[TestRun {
+            result: TestResult::Errored {
+                command: Command::new("cake"),
+                steps: vec![],
+                errors: vec![],
+                stderr: vec![],
+                artifacts: None,
+            },
+            path: producer.clone(),
+            revision: String::new(),
+            duration: Duration::ZERO,
+            timings: Timings::default(),
+            command: None,
+            warnings: vec![],
+        }],
+    );
+    let errors = schedule.wait_for_dependencies(&consumer);
+    match &errors[..] {
+        [Error::DependencyFailed {
+            reason: "failed",
+            line: 1,
+            ..
+        }, Error::DependencyFailed {
+            reason: "was filtered out",
+            line: 2,
+            ..
+        }] => {}
+        _ => panic!("{errors:#?}"),
+    }
+}
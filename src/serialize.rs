@@ -0,0 +1,26 @@
+//! `serialize_with` helpers for the fields of `TestSuiteResult` that don't implement `Serialize`.
+
+use std::{fmt::Display, process::Command};
+
+use serde::Serializer;
+
+/// Serializes a `Command` as a line that can be pasted into a shell, see `shell_command`.
+pub(crate) fn shell<S: Serializer>(cmd: &Command, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&crate::shell_command(cmd))
+}
+
+/// Serializes the `Display` output, e.g. of an `ExitStatus` or a `Regex`.
+pub(crate) fn display<T: Display, S: Serializer>(
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+/// Serializes the output of a command as a string, marking invalid UTF-8 as `<invalid utf8>`.
+pub(crate) fn lossy<T: AsRef<[u8]>, S: Serializer>(
+    bytes: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&crate::diff::lossy(bytes.as_ref()))
+}
@@ -1,31 +1,95 @@
 //! Variaous schemes for reporting messages during testing or after testing is done.
 
-use bstr::ByteSlice;
 use colored::Colorize;
 
-use crate::{github_actions, parser::Pattern, rustc_stderr::Message, Error, Errors, TestResult};
+use crate::{
+    diff::lossy, github_actions, parser::Pattern, rustc_stderr::Message, Error, Errors,
+    ExitExpectation, NearMiss, SimilarPattern, TestResult, Timings,
+};
 use std::{
+    collections::{BTreeMap, HashMap},
     fmt::{Debug, Write as _},
     io::Write as _,
-    path::Path,
+    path::{Path, PathBuf},
     process::Command,
+    sync::Mutex,
+    time::{Duration, Instant},
 };
 
+#[cfg(test)]
+mod tests;
+
 /// A generic way to handle the output of this crate.
+///
+/// `run_tests_generic` invokes the methods in this order: `shuffle_seed`, `report_slowest` and
+/// `no_failures_recorded`, then
+/// `test_started`, `test_result`, `test_duration` and `test_warnings` for the tests as they run,
+/// then `shard` and `tests_not_run`,
+/// and `finalize` once all tests finished. Then, for every failed test, `failed_test` is invoked
+/// and the errors are passed to `Summary::test_failure` of the summary returned by `finalize`,
+/// before the guard returned by `failed_test` is dropped. Dropping the summary ends the run.
+///
+/// Emitters can be combined with a tuple or a `Composite`, which invoke each method on all of
+/// their emitters before returning, so every emitter sees all tests before any `finalize`.
 pub trait StatusEmitter: Sync {
     /// Invoked before each failed test prints its errors along with a drop guard that can
-    /// gets invoked afterwards.
+    /// gets invoked afterwards. `steps` are the commands that ran successfully before `cmd`,
+    /// labeled with what they did, and `artifacts` the directory the logs of the test were
+    /// written to, see `TestResult::Errored`.
     fn failed_test<'a>(
         &'a self,
         revision: &'a str,
         path: &'a Path,
         cmd: &'a Command,
+        steps: &'a [(String, String)],
+        artifacts: Option<&'a Path>,
         stderr: &'a [u8],
     ) -> Box<dyn Debug + 'a>;
 
+    /// A test is about to be run, after the tests it `//@depends-on` finished.
+    /// Invoked for every revision of it that was not filtered out.
+    fn test_started(&mut self, _path: &Path, _revision: &str) {}
+
     /// A test has finished, handle the result immediately.
     fn test_result(&mut self, _path: &Path, _revision: &str, _result: &TestResult) {}
 
+    /// How long a test that was run took, after its `test_result`.
+    fn test_duration(
+        &mut self,
+        _path: &Path,
+        _revision: &str,
+        _duration: Duration,
+        _timings: Timings,
+    ) {
+    }
+
+    /// A test produced warnings (e.g. uses of deprecated comments), which do not fail it.
+    fn test_warnings(&mut self, _path: &Path, _revision: &str, _warnings: &Errors) {}
+
+    /// With `--shuffle-seed`, the seed the order of the tests was shuffled with.
+    /// Invoked before any test is run.
+    fn shuffle_seed(&mut self, _seed: u64) {}
+
+    /// With `--report-slowest`, how many of the slowest tests to report at the end.
+    /// Invoked before any test is run.
+    fn report_slowest(&mut self, _count: usize) {}
+
+    /// With `--rerun-failed`, no failures were recorded in the last run, so all tests are run.
+    /// Invoked before any test is run.
+    fn no_failures_recorded(&mut self) {}
+
+    /// With `--shard`, which shard was run and how many tests belong to the other shards.
+    /// Invoked before `finalize`.
+    fn shard(&mut self, _shard: (usize, usize), _other_shards: usize) {}
+
+    /// With `--fail-fast`, the number of tests that were not started after the first failure.
+    /// Invoked before `finalize`.
+    fn tests_not_run(&mut self, _count: usize) {}
+
+    /// Output files that no test produces, see `TestSuiteResult::orphaned_outputs`, and whether
+    /// they were `removed` by blessing or `--prune`. Invoked before `finalize`.
+    fn orphaned_outputs(&mut self, _paths: &[PathBuf], _removed: bool) {}
+
     /// Create a report about the entire test run at the end.
     #[allow(clippy::type_complexity)]
     fn finalize(
@@ -50,16 +114,203 @@ pub struct Text {
     /// In case of `Some`, the `usize` is the number of tests
     /// that were already executed.
     quiet: Option<usize>,
+    /// The reasons of the ignored tests and the tests, for the summary at the end.
+    ignored: Vec<(String, String)>,
+    /// The number of tests that were not run due to `--fail-fast`.
+    not_run: usize,
+    /// The shard that was run and the number of tests in the other shards.
+    shard: Option<((usize, usize), usize)>,
+    /// The seed the tests were shuffled with, to be able to reproduce failures.
+    shuffle_seed: Option<u64>,
+    /// In case of `Some`, a progress bar is shown instead of one line per test.
+    progress: Option<ProgressBar>,
+    /// How many of the slowest tests to print in the summary.
+    report_slowest: usize,
+    /// The tests that were run, with how long they took.
+    durations: Vec<(String, Duration, Timings)>,
+    /// The output files that no test produces, and whether they were removed.
+    orphans: Vec<(PathBuf, bool)>,
+}
+
+/// The state of the progress bar of `Text::progress`.
+#[derive(Default)]
+struct ProgressBar {
+    passed: usize,
+    failed: usize,
+    ignored: usize,
+    /// The names of the running tests and when they started, oldest first.
+    running: Vec<(String, Instant)>,
+}
+
+impl ProgressBar {
+    /// Removes the progress bar, so something else can be printed in its place.
+    fn clear(&self) {
+        eprint!("\r\x1b[2K");
+    }
+
+    /// Replaces the progress bar with its current state.
+    fn draw(&self) {
+        // Lines that wrap can't be redrawn in place.
+        let line = self.line(terminal_width().saturating_sub(1));
+        eprint!("\r\x1b[2K{line}");
+    }
+
+    /// The current state, shortened to at most `width` characters.
+    fn line(&self, width: usize) -> String {
+        let mut line = format!(
+            "{} passed, {} failed, {} ignored",
+            self.passed, self.failed, self.ignored
+        );
+        if !self.running.is_empty() {
+            let running: Vec<_> = self
+                .running
+                .iter()
+                .take(3)
+                .map(|(name, start)| format!("{name} ({}s)", start.elapsed().as_secs()))
+                .collect();
+            write!(line, " | running: {}", running.join(", ")).unwrap();
+            if self.running.len() > 3 {
+                write!(line, " and {} more", self.running.len() - 3).unwrap();
+            }
+        }
+        if line.chars().count() > width {
+            line = line.chars().take(width.saturating_sub(3)).collect();
+            line.push_str("...");
+        }
+        line
+    }
+}
+
+/// Whether stderr is an interactive terminal, which can show a progress bar.
+fn stderr_is_terminal() -> bool {
+    #[cfg(unix)]
+    // SAFETY: `isatty` only inspects the file descriptor.
+    return unsafe { libc::isatty(libc::STDERR_FILENO) == 1 };
+    #[cfg(not(unix))]
+    return false;
+}
+
+/// The number of columns of the terminal on stderr.
+fn terminal_width() -> usize {
+    #[cfg(unix)]
+    {
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        // SAFETY: `TIOCGWINSZ` only writes to the `winsize` it is given.
+        if unsafe { libc::ioctl(libc::STDERR_FILENO, libc::TIOCGWINSZ, &mut size) } == 0
+            && size.ws_col > 0
+        {
+            return size.ws_col.into();
+        }
+    }
+    80
+}
+
+/// The name of a test revision for humans.
+fn display_name(path: &Path, revision: &str) -> String {
+    if revision.is_empty() {
+        path.display().to_string()
+    } else {
+        format!("{} ({revision})", path.display())
+    }
+}
+
+/// Prints the heading of the details of a failed test, with the commands to reproduce it.
+fn print_failed_test(
+    revision: &str,
+    path: &Path,
+    cmd: &Command,
+    steps: &[(String, String)],
+    artifacts: Option<&Path>,
+) {
+    eprintln!();
+    let path = path.display().to_string();
+    eprint!("{}", path.underline().bold());
+    let revision = if revision.is_empty() {
+        String::new()
+    } else {
+        format!(" (revision `{revision}`)")
+    };
+    eprint!("{revision}");
+    eprint!(" {}", "FAILED:".red().bold());
+    eprintln!();
+    for (label, step) in steps {
+        eprintln!("{label}: {step}");
+    }
+    eprintln!("command: {}", crate::shell_command(cmd));
+    if let Some(artifacts) = artifacts {
+        eprintln!("artifacts: {}", artifacts.display());
+    }
+    eprintln!();
+}
+
+/// Prints the full stderr of a failed test, after its errors.
+fn print_full_stderr(stderr: &[u8]) {
+    eprintln!("full stderr:");
+    eprint!("{}", lossy(stderr));
+    eprintln!();
+    eprintln!();
 }
 
 impl Text {
     /// Print one line per test that gets run.
     pub fn verbose() -> Self {
-        Self { quiet: None }
+        Self {
+            quiet: None,
+            ignored: vec![],
+            not_run: 0,
+            shard: None,
+            shuffle_seed: None,
+            progress: None,
+            report_slowest: 10,
+            durations: vec![],
+            orphans: vec![],
+        }
+    }
+
+    /// Show a single line with the number of passed, failed and ignored tests and the tests that
+    /// have been running for the longest time, which is updated in place. The details of failed
+    /// tests are printed above it as soon as they fail. Falls back to `quiet` if stderr is not
+    /// a terminal.
+    pub fn progress() -> Self {
+        if !stderr_is_terminal() {
+            return Self::quiet();
+        }
+        Self {
+            progress: Some(ProgressBar::default()),
+            ..Self::verbose()
+        }
+    }
+
+    /// Print one line per test that gets run and, when running in github actions
+    /// (`GITHUB_ACTIONS=true`), also emit the failures as github actions annotations.
+    pub fn auto() -> Box<dyn StatusEmitter + Send> {
+        if github_actions::enabled() {
+            Box::new((
+                Self::verbose(),
+                Gha {
+                    name: "ui_test".into(),
+                    group_by_file: true,
+                },
+            ))
+        } else {
+            Box::new(Self::verbose())
+        }
     }
-    /// Print one `.` per test that gets run.
+
+    /// Print one `.` per passed test, `F` per failed test and `i` per ignored test,
+    /// and the details of the failures only at the end. `run_tests` uses this for `--quiet`.
     pub fn quiet() -> Self {
-        Self { quiet: Some(0) }
+        Self {
+            quiet: Some(0),
+            ignored: vec![],
+            not_run: 0,
+            shard: None,
+            shuffle_seed: None,
+            progress: None,
+            report_slowest: 10,
+            durations: vec![],
+            orphans: vec![],
+        }
     }
 }
 
@@ -69,43 +320,79 @@ impl StatusEmitter for Text {
         revision: &str,
         path: &Path,
         cmd: &Command,
+        steps: &[(String, String)],
+        artifacts: Option<&Path>,
         stderr: &'a [u8],
     ) -> Box<dyn Debug + 'a> {
-        eprintln!();
-        let path = path.display().to_string();
-        eprint!("{}", path.underline().bold());
-        let revision = if revision.is_empty() {
-            String::new()
-        } else {
-            format!(" (revision `{revision}`)")
-        };
-        eprint!("{revision}");
-        eprint!(" {}", "FAILED:".red().bold());
-        eprintln!();
-        eprintln!("command: {cmd:?}");
-        eprintln!();
+        if self.progress.is_some() {
+            // Already printed when the test failed.
+            return Box::new(());
+        }
+        print_failed_test(revision, path, cmd, steps, artifacts);
 
         #[derive(Debug)]
         struct Guard<'a>(&'a [u8]);
         impl<'a> Drop for Guard<'a> {
             fn drop(&mut self) {
-                eprintln!("full stderr:");
-                std::io::stderr().write_all(self.0).unwrap();
-                eprintln!();
-                eprintln!();
+                print_full_stderr(self.0);
             }
         }
         Box::new(Guard(stderr))
     }
 
+    fn test_started(&mut self, path: &Path, revision: &str) {
+        if let Some(progress) = &mut self.progress {
+            progress
+                .running
+                .push((display_name(path, revision), Instant::now()));
+            progress.draw();
+        }
+    }
+
     fn test_result(&mut self, path: &Path, revision: &str, result: &TestResult) {
-        if let Some(n) = &mut self.quiet {
+        if let TestResult::Ignored { reason } = result {
+            let test = if revision.is_empty() {
+                path.display().to_string()
+            } else {
+                format!("{} (revision {revision})", path.display())
+            };
+            self.ignored.push((reason.clone(), test));
+        }
+        if let Some(progress) = &mut self.progress {
+            let name = display_name(path, revision);
+            if let Some(i) = progress.running.iter().position(|(n, _)| *n == name) {
+                progress.running.remove(i);
+            }
+            // Keep the failure details and the redrawn bar from interleaving with other output.
+            let _lock = std::io::stderr().lock();
+            match result {
+                TestResult::Ok | TestResult::Cached => progress.passed += 1,
+                TestResult::Errored {
+                    command,
+                    steps,
+                    errors,
+                    stderr,
+                    artifacts,
+                } => {
+                    progress.failed += 1;
+                    progress.clear();
+                    print_failed_test(revision, path, command, steps, artifacts.as_deref());
+                    for error in errors {
+                        print_error(error, &path.display().to_string());
+                    }
+                    print_full_stderr(stderr);
+                }
+                TestResult::Ignored { .. } => progress.ignored += 1,
+                TestResult::Filtered => {}
+            }
+            progress.draw();
+        } else if let Some(n) = &mut self.quiet {
             // Humans start counting at 1
             *n += 1;
             match result {
-                TestResult::Ok => eprint!("{}", ".".green()),
+                TestResult::Ok | TestResult::Cached => eprint!("{}", ".".green()),
                 TestResult::Errored { .. } => eprint!("{}", "F".red().bold()),
-                TestResult::Ignored => eprint!("{}", "i".yellow()),
+                TestResult::Ignored { .. } => eprint!("{}", "i".yellow()),
                 TestResult::Filtered => {}
             }
             if *n % 100 == 0 {
@@ -114,8 +401,9 @@ impl StatusEmitter for Text {
         } else {
             let result = match result {
                 TestResult::Ok => "ok".green(),
+                TestResult::Cached => "cached: ok".green(),
                 TestResult::Errored { .. } => "FAILED".red().bold(),
-                TestResult::Ignored => "ignored (in-test comment)".yellow(),
+                TestResult::Ignored { reason } => format!("ignored ({reason})").yellow(),
                 TestResult::Filtered => return,
             };
             eprint!(
@@ -131,6 +419,52 @@ impl StatusEmitter for Text {
         }
     }
 
+    fn test_warnings(&mut self, path: &Path, _revision: &str, warnings: &Errors) {
+        let _lock = std::io::stderr().lock();
+        if let Some(progress) = &self.progress {
+            progress.clear();
+        } else if self.quiet.is_some() {
+            eprintln!();
+        }
+        for warning in warnings {
+            eprint!("{}: ", "warning".yellow().bold());
+            print_error(warning, &path.display().to_string());
+        }
+        if let Some(progress) = &self.progress {
+            progress.draw();
+        }
+    }
+
+    fn tests_not_run(&mut self, count: usize) {
+        self.not_run = count;
+    }
+
+    fn test_duration(&mut self, path: &Path, revision: &str, duration: Duration, timings: Timings) {
+        self.durations
+            .push((display_name(path, revision), duration, timings));
+    }
+
+    fn shuffle_seed(&mut self, seed: u64) {
+        self.shuffle_seed = Some(seed);
+    }
+
+    fn report_slowest(&mut self, count: usize) {
+        self.report_slowest = count;
+    }
+
+    fn no_failures_recorded(&mut self) {
+        eprintln!("no failed tests were recorded in the last run, running all tests");
+    }
+
+    fn shard(&mut self, shard: (usize, usize), other_shards: usize) {
+        self.shard = Some((shard, other_shards));
+    }
+
+    fn orphaned_outputs(&mut self, paths: &[PathBuf], removed: bool) {
+        self.orphans
+            .extend(paths.iter().map(|path| (path.clone(), removed)));
+    }
+
     fn finalize(
         &self,
         failures: usize,
@@ -138,9 +472,16 @@ impl StatusEmitter for Text {
         ignored: usize,
         filtered: usize,
     ) -> Box<dyn Summary> {
+        if let Some(progress) = &self.progress {
+            progress.clear();
+        }
         // Print all errors in a single thread to show reliable output
         if failures == 0 {
             eprintln!();
+            print_ignored(&self.ignored);
+            print_orphans(&self.orphans);
+            print_slowest(&self.durations, self.report_slowest);
+            print_shard(self.shard);
             eprintln!(
                 "test result: {}. {} tests passed, {} ignored, {} filtered out",
                 "ok".green(),
@@ -156,12 +497,29 @@ impl StatusEmitter for Text {
                 succeeded: usize,
                 ignored: usize,
                 filtered: usize,
+                /// The output files that could not be blessed, as they are read-only.
+                read_only: Vec<PathBuf>,
+                /// The reasons of the ignored tests and the tests.
+                ignored_tests: Vec<(String, String)>,
+                not_run: usize,
+                shard: Option<((usize, usize), usize)>,
+                shuffle_seed: Option<u64>,
+                /// Whether the errors were already printed when the tests failed.
+                errors_printed: bool,
+                durations: Vec<(String, Duration, Timings)>,
+                report_slowest: usize,
+                orphans: Vec<(PathBuf, bool)>,
             }
 
             impl Summary for Summarizer {
                 fn test_failure(&mut self, path: &Path, revision: &str, errors: &Errors) {
                     for error in errors {
-                        print_error(error, &path.display().to_string());
+                        // Listed together once all tests ran.
+                        if let Error::ReadOnlyOutput { path } = error {
+                            self.read_only.push(path.clone());
+                        } else if !self.errors_printed {
+                            print_error(error, &path.display().to_string());
+                        }
                     }
 
                     self.failures.push(if revision.is_empty() {
@@ -174,11 +532,39 @@ impl StatusEmitter for Text {
 
             impl Drop for Summarizer {
                 fn drop(&mut self) {
+                    if !self.read_only.is_empty() {
+                        eprintln!(
+                            "{}",
+                            "Some output files could not be blessed, because the test files are in a read-only location:"
+                                .red()
+                                .bold()
+                        );
+                        for path in &self.read_only {
+                            eprintln!("    {}", path.display());
+                        }
+                        eprintln!(
+                            "Set `Config::output_base` to a writable directory to write the blessed files there instead."
+                        );
+                        eprintln!();
+                    }
                     eprintln!("{}", "FAILURES:".red().underline().bold());
                     for line in &self.failures {
                         eprintln!("{line}");
                     }
                     eprintln!();
+                    print_ignored(&self.ignored_tests);
+                    print_orphans(&self.orphans);
+                    print_slowest(&self.durations, self.report_slowest);
+                    print_shard(self.shard);
+                    if let Some(seed) = self.shuffle_seed {
+                        eprintln!("the tests were shuffled, pass `--shuffle-seed {seed}` to run them in the same order");
+                    }
+                    if self.not_run > 0 {
+                        eprintln!(
+                            "{} tests were not run, because `--fail-fast` stopped at the first failure",
+                            self.not_run.to_string().yellow(),
+                        );
+                    }
                     eprintln!(
                         "test result: {}. {} tests failed, {} tests passed, {} ignored, {} filtered out",
                         "FAIL".red(),
@@ -194,11 +580,123 @@ impl StatusEmitter for Text {
                 succeeded,
                 ignored,
                 filtered,
+                read_only: vec![],
+                ignored_tests: self.ignored.clone(),
+                not_run: self.not_run,
+                shard: self.shard,
+                shuffle_seed: self.shuffle_seed,
+                errors_printed: self.progress.is_some(),
+                durations: self.durations.clone(),
+                report_slowest: self.report_slowest,
+                orphans: self.orphans.clone(),
             })
         }
     }
 }
 
+/// Print the `count` slowest tests, and where their time went.
+fn print_slowest(durations: &[(String, Duration, Timings)], count: usize) {
+    if count == 0 || durations.is_empty() {
+        return;
+    }
+    let mut slowest: Vec<_> = durations.iter().collect();
+    slowest.sort_by(|(_, a, _), (_, b, _)| b.cmp(a));
+    eprintln!("{}", "SLOWEST TESTS:".underline().bold());
+    for (test, duration, timings) in slowest.into_iter().take(count) {
+        eprintln!(
+            "    {test}: {:.2}s (compile {:.2}s, run {:.2}s, check {:.2}s)",
+            duration.as_secs_f64(),
+            timings.compile.as_secs_f64(),
+            timings.run.as_secs_f64(),
+            timings.check.as_secs_f64(),
+        );
+    }
+    eprintln!();
+}
+
+/// Print which shard was run, if the tests were sharded.
+fn print_shard(shard: Option<((usize, usize), usize)>) {
+    if let Some(((index, count), other_shards)) = shard {
+        eprintln!(
+            "ran shard {index}/{count}, {} tests belong to other shards",
+            other_shards.to_string().yellow()
+        );
+    }
+}
+
+/// Print the output files that no test produces, and whether they were removed.
+fn print_orphans(orphans: &[(PathBuf, bool)]) {
+    if orphans.is_empty() {
+        return;
+    }
+    eprintln!("{}", "ORPHANED OUTPUT FILES:".yellow().underline().bold());
+    for (path, removed) in orphans {
+        if *removed {
+            eprintln!("    {} (removed)", path.display());
+        } else {
+            eprintln!("    {}", path.display());
+        }
+    }
+    if orphans.iter().any(|(_, removed)| !removed) {
+        eprintln!("no test produces these files, bless the tests or pass `--prune` to remove them");
+    }
+    eprintln!();
+}
+
+/// Print the ignored tests, grouped by the reason they were ignored for.
+fn print_ignored(ignored: &[(String, String)]) {
+    if ignored.is_empty() {
+        return;
+    }
+    let mut by_reason = BTreeMap::<&str, Vec<&str>>::new();
+    for (reason, test) in ignored {
+        by_reason.entry(reason).or_default().push(test);
+    }
+    eprintln!("{}", "IGNORED:".yellow().underline().bold());
+    for (reason, mut tests) in by_reason {
+        tests.sort_unstable();
+        eprintln!("    {reason}:");
+        for test in tests {
+            eprintln!("        {test}");
+        }
+    }
+    eprintln!();
+}
+
+/// The headline of `Error::OutputDiffers`, which calls out that there was no output at all, as
+/// the diff against nothing is easy to misread.
+fn output_differs_msg(actual: &[u8]) -> &'static str {
+    if actual.is_empty() {
+        "expected output but compiler produced none"
+    } else {
+        "actual output differed from expected"
+    }
+}
+
+/// A diagnostic outside the test file, prefixed with the file it is in, if it has one.
+fn other_file_msg(msg: &Message) -> String {
+    match &msg.file {
+        Some(file) => format!("{file}: {}", test_file_msg(msg)),
+        None => test_file_msg(msg),
+    }
+}
+
+/// A diagnostic in the test file, followed by the lines of its other primary spans, if any,
+/// and the diagnostic it is attached to, if it is a note or help of one.
+fn test_file_msg(msg: &Message) -> String {
+    let mut text = format!("{:?}: {}", msg.level, msg.message);
+    let lines: Vec<_> = msg.other_lines().map(|line| line.to_string()).collect();
+    match &lines[..] {
+        [] => {}
+        [line] => text.push_str(&format!(" (also at line {line})")),
+        lines => text.push_str(&format!(" (also at lines {})", lines.join(", "))),
+    }
+    if let Some(parent) = &msg.parent {
+        text.push_str(&format!(" (attached to: {parent})"));
+    }
+    text
+}
+
 fn print_error(error: &Error, path: &str) {
     match error {
         Error::ExitStatus {
@@ -211,9 +709,72 @@ fn print_error(error: &Error, path: &str) {
         Error::Command { kind, status } => {
             eprintln!("{kind} failed with {status}");
         }
+        Error::Ice {
+            message,
+            query_stack,
+        } => {
+            eprintln!(
+                "{}",
+                "the compiler crashed with an internal compiler error".red()
+            );
+            eprintln!("{message}");
+            if !query_stack.is_empty() {
+                eprintln!("query stack:");
+                for query in query_stack {
+                    eprintln!("{query}");
+                }
+            }
+        }
+        Error::IceNotFound => {
+            eprintln!(
+                "{}",
+                "expected an internal compiler error, but the compiler did not crash".red()
+            );
+        }
+        Error::DidNotPanic => {
+            eprintln!(
+                "{}",
+                "expected the test binary to panic, but it exited successfully".red()
+            );
+        }
+        Error::PanicMessageNotFound { pattern, line } => {
+            eprintln!(
+                "the test binary panicked, but {pattern} {} in its stderr",
+                "was not found".red()
+            );
+            eprintln!(
+                "expected because of `run-panic` here: {}",
+                format!("{path}:{line}").bold()
+            );
+        }
+        Error::ConflictingDiagnostics => {
+            eprintln!(
+                "the compiler emitted {} on stdout and stderr; set `Config::diagnostics_stream` to the one to use",
+                "different JSON diagnostics".red()
+            );
+        }
+        Error::TimedOut { timeout } => {
+            eprintln!(
+                "{}",
+                format!("timed out after {}s", timeout.as_secs()).red()
+            );
+        }
+        Error::TimeLimitExceeded { duration, limit } => {
+            eprintln!("{}", time_limit_message(*duration, *limit));
+        }
+        Error::DeprecatedComment { msg, line, since } => {
+            eprintln!(
+                "Deprecated comment in {path}:{line}: {msg} (deprecated since ui_test {since})"
+            )
+        }
+        Error::UnusedNormalization { rule, line } => match line {
+            Some(line) => eprintln!("normalization {rule} in {path}:{line} replaced nothing"),
+            None => eprintln!("filter {rule} replaced nothing in any test"),
+        },
         Error::PatternNotFound {
             pattern,
             definition_line,
+            near_misses,
         } => {
             match pattern {
                 Pattern::SubString(s) => {
@@ -222,6 +783,170 @@ fn print_error(error: &Error, path: &str) {
                 Pattern::Regex(r) => {
                     eprintln!("`/{r}/` does {} stderr output", "not match".red())
                 }
+                Pattern::Code(code) => {
+                    eprintln!("code `{code}` {} in stderr output", "not found".red())
+                }
+            }
+            eprintln!(
+                "expected because of pattern here: {}",
+                format!("{path}:{definition_line}").bold()
+            );
+            if !near_misses.is_empty() {
+                eprintln!("similar diagnostics:");
+            }
+            for NearMiss {
+                msg,
+                line,
+                similarity,
+            } in near_misses
+            {
+                let Message { level, message, .. } = msg;
+                match line {
+                    Some(line) => {
+                        eprintln!("    {path}:{line}: {level:?}: {message} ({similarity}% similar)")
+                    }
+                    None => eprintln!("    {} ({similarity}% similar)", other_file_msg(msg)),
+                }
+            }
+        }
+        Error::CodeMismatch {
+            expected,
+            actual,
+            definition_line,
+        } => {
+            match actual {
+                Some(actual) => eprintln!(
+                    "expected error code `{expected}`, but {} `{actual}`",
+                    "found".red()
+                ),
+                None => eprintln!(
+                    "error code `{expected}` {} in stderr output",
+                    "not found".red()
+                ),
+            }
+            eprintln!(
+                "expected because of pattern here: {}",
+                format!("{path}:{definition_line}").bold()
+            );
+        }
+        Error::WrongMatchCount {
+            expected,
+            found,
+            definition_line,
+        } => {
+            eprintln!(
+                "expected {expected} matching diagnostics, but {} {found}",
+                "found".red()
+            );
+            eprintln!(
+                "expected because of pattern here: {}",
+                format!("{path}:{definition_line}").bold()
+            );
+        }
+        Error::NegatedPatternFound {
+            definition_line,
+            msg: Message { level, message, .. },
+        } => {
+            eprintln!(
+                "{level:?}: {message} {}",
+                "found despite negated pattern".red()
+            );
+            eprintln!(
+                "forbidden because of pattern here: {}",
+                format!("{path}:{definition_line}").bold()
+            );
+        }
+        Error::ColumnNotFound {
+            column,
+            columns,
+            definition_line,
+        } => {
+            eprintln!(
+                "no diagnostic {} at column {column}, only at columns {columns:?}",
+                "found".red()
+            );
+            eprintln!(
+                "expected because of pattern here: {}",
+                format!("{path}:{definition_line}").bold()
+            );
+        }
+        Error::SuggestionNotFound {
+            pattern,
+            suggestions,
+            definition_line,
+        } => {
+            match pattern {
+                Pattern::SubString(s) => {
+                    eprintln!("suggestion `{s}` {}", "not found".red())
+                }
+                Pattern::Regex(r) => {
+                    eprintln!("`/{r}/` does {} any suggestion", "not match".red())
+                }
+                Pattern::Code(code) => {
+                    eprintln!("suggestion `{code}` {}", "not found".red())
+                }
+            }
+            eprintln!(
+                "expected because of pattern here: {}",
+                format!("{path}:{definition_line}").bold()
+            );
+            if suggestions.is_empty() {
+                eprintln!("there are no suggestions on that line");
+            } else {
+                eprintln!("the suggestions on that line are:");
+                for suggestion in suggestions {
+                    eprintln!("    `{suggestion}`");
+                }
+            }
+        }
+        Error::AnnotationRewritten {
+            definition_line,
+            old,
+            new,
+        } => {
+            eprintln!(
+                "rewrote annotation at {}: `{old}` -> `{new}`",
+                format!("{path}:{definition_line}").bold()
+            );
+        }
+        Error::AnnotationsInserted {
+            line,
+            removed,
+            added,
+        } => {
+            eprintln!(
+                "inserted annotations for the diagnostics at {} (run the test again to update the line numbers in the output files):",
+                format!("{path}:{line}").bold()
+            );
+            if let Some(removed) = removed {
+                eprintln!("{}", format!("-{removed}").red());
+            }
+            for added in added {
+                eprintln!("{}", format!("+{added}").green());
+            }
+        }
+        Error::AnnotationRemoved {
+            definition_line,
+            annotation,
+        } => {
+            eprintln!(
+                "removed annotation that matched no diagnostic at {} (run the test again to update the line numbers in the output files):",
+                format!("{path}:{definition_line}").bold()
+            );
+            eprintln!("{}", format!("-{annotation}").red());
+        }
+        Error::AmbiguousAnnotation {
+            definition_line,
+            line,
+            msgs,
+        } => {
+            eprintln!(
+                "could not rewrite the annotation, there are {} diagnostics on line {line} it {}",
+                msgs.len(),
+                "could stand for".red()
+            );
+            for Message { level, message, .. } in msgs {
+                eprintln!("    {level:?}: {message}")
             }
             eprintln!(
                 "expected because of pattern here: {}",
@@ -239,8 +964,9 @@ fn print_error(error: &Error, path: &str) {
             actual,
             expected,
             bless_command,
+            diff_context,
         } => {
-            eprintln!("{}", "actual output differed from expected".underline());
+            eprintln!("{}", output_differs_msg(actual).underline());
             eprintln!(
                 "Execute `{}` to update `{}` to the actual output",
                 bless_command,
@@ -248,36 +974,124 @@ fn print_error(error: &Error, path: &str) {
             );
             eprintln!("{}", format!("--- {}", output_path.display()).red());
             eprintln!("{}", "+++ <stderr output>".green());
-            crate::diff::print_diff(expected, actual);
+            crate::diff::print_diff(expected, actual, *diff_context);
         }
-        Error::ErrorsWithoutPattern { path: None, msgs } => {
+        Error::StaleOutput {
+            path: output_path,
+            bless_command,
+        } => {
             eprintln!(
-                "There were {} unmatched diagnostics that occurred outside the testfile and had no pattern",
-                msgs.len(),
+                "{} exists, but the test does not produce it",
+                output_path.display().to_string().underline()
             );
-            for Message { level, message } in msgs {
-                eprintln!("    {level:?}: {message}")
+            eprintln!(
+                "Execute `{}` to remove `{}`",
+                bless_command,
+                output_path.display()
+            );
+        }
+        Error::ReadOnlyOutput { path: output_path } => {
+            eprintln!(
+                "could not bless `{}`, it is in a read-only location",
+                output_path.display()
+            );
+        }
+        Error::ErrorsWithoutPattern {
+            path: None,
+            msgs,
+            similar_patterns,
+        } => {
+            eprintln!(
+                "There were {} unmatched diagnostics that occurred outside the testfile and had no pattern",
+                msgs.len(),
+            );
+            for msg in msgs {
+                eprintln!("    {}", other_file_msg(msg))
             }
+            print_similar_patterns(path, similar_patterns);
         }
         Error::ErrorsWithoutPattern {
             path: Some((path, line)),
             msgs,
+            similar_patterns,
         } => {
             let path = path.display();
             eprintln!(
                 "There were {} unmatched diagnostics at {path}:{line}",
                 msgs.len(),
             );
-            for Message { level, message } in msgs {
-                eprintln!("    {level:?}: {message}")
+            for msg in msgs {
+                eprintln!("    {}", test_file_msg(msg))
             }
+            print_similar_patterns(path, similar_patterns);
         }
         Error::InvalidComment { msg, line } => {
             eprintln!("Could not parse comment in {path}:{line} because\n{msg}",)
         }
+        Error::UnknownTriple { condition, line } => {
+            eprintln!(
+                "`{condition}` {} any known target triple, it is probably misspelled",
+                "does not match".red()
+            );
+            eprintln!("condition defined here: {path}:{line}");
+        }
+        Error::UnknownChannel { condition } => {
+            eprintln!(
+                "the release channel of the program could not be detected, so `{condition}` {}; set `Config::channel`",
+                "cannot be checked".red()
+            );
+        }
+        Error::UnknownSysroot => {
+            eprintln!(
+                "the sysroot of the program could not be detected, so `{{{{sysroot}}}}` {}; set `Config::sysroot`",
+                "cannot be replaced".red()
+            );
+        }
+        Error::RunnerRequired { target } => {
+            eprintln!(
+                "binaries for `{target}` {} on the host; set `Config::runner` to run them, e.g. in an emulator",
+                "cannot be executed".red()
+            );
+        }
         Error::Bug(msg) => {
             eprintln!("A bug in `ui_test` occurred: {msg}");
         }
+        Error::FixpointNotReached {
+            iterations,
+            diagnostics,
+        } => {
+            eprintln!(
+                "rustfix did not reach a fixpoint after {iterations} iterations, remaining diagnostics:"
+            );
+            eprintln!("{}", lossy(diagnostics));
+        }
+        Error::FixedHasDiagnostics { level, stderr } => {
+            eprintln!("the `.fixed` file emitted diagnostics at level {level:?} or above:");
+            eprintln!("{}", lossy(stderr));
+        }
+        Error::ForbiddenEnv { var, layer } => {
+            eprintln!("environment variable `{var}` is forbidden, but was set by {layer}");
+        }
+        Error::StdinFile {
+            path: stdin_path,
+            line,
+            error,
+        } => {
+            eprintln!(
+                "Could not read stdin file {} from {path}:{line}: {error}",
+                stdin_path.display()
+            );
+        }
+        Error::DependencyFailed {
+            path: dep_path,
+            reason,
+            line,
+        } => {
+            eprintln!(
+                "Dependency {} from {path}:{line} {reason}",
+                dep_path.display()
+            );
+        }
         Error::Aux {
             path: aux_path,
             errors,
@@ -292,6 +1106,32 @@ fn print_error(error: &Error, path: &str) {
     eprintln!();
 }
 
+fn print_similar_patterns(path: impl std::fmt::Display, similar_patterns: &[SimilarPattern]) {
+    if !similar_patterns.is_empty() {
+        eprintln!("similar patterns that were not found:");
+    }
+    for SimilarPattern {
+        pattern,
+        definition_line,
+        similarity,
+    } in similar_patterns
+    {
+        eprintln!(
+            "    {pattern} at {} ({similarity}% similar)",
+            format!("{path}:{definition_line}").bold()
+        );
+    }
+}
+
+/// Describes an `Error::TimeLimitExceeded`.
+fn time_limit_message(duration: Duration, limit: Duration) -> String {
+    format!(
+        "took {:.2}s, longer than the time limit of {:.2}s",
+        duration.as_secs_f64(),
+        limit.as_secs_f64()
+    )
+}
+
 fn gha_error(error: &Error, path: &str, revision: &str) {
     match error {
         Error::ExitStatus {
@@ -307,12 +1147,173 @@ fn gha_error(error: &Error, path: &str, revision: &str) {
         Error::Command { kind, status } => {
             github_actions::error(path, format!("{kind}{revision} failed with {status}"));
         }
+        Error::Ice {
+            message,
+            query_stack,
+        } => {
+            let mut err = github_actions::error(
+                path,
+                format!("test{revision} crashed with an internal compiler error"),
+            );
+            writeln!(err, "{message}").unwrap();
+            for query in query_stack {
+                writeln!(err, "{query}").unwrap();
+            }
+        }
+        Error::IceNotFound => {
+            github_actions::error(
+                path,
+                format!("test{revision} was expected to crash with an internal compiler error"),
+            );
+        }
+        Error::DidNotPanic => {
+            github_actions::error(
+                path,
+                format!("test{revision} binary was expected to panic, but exited successfully"),
+            );
+        }
+        Error::PanicMessageNotFound { pattern, line } => {
+            github_actions::error(
+                path,
+                format!(
+                    "test{revision} binary panicked, but {pattern} was not found in its stderr"
+                ),
+            )
+            .line(*line);
+        }
+        Error::ConflictingDiagnostics => {
+            github_actions::error(
+                path,
+                format!("different JSON diagnostics on stdout and stderr{revision}"),
+            );
+        }
+        Error::TimedOut { timeout } => {
+            github_actions::error(
+                path,
+                format!("test{revision} timed out after {}s", timeout.as_secs()),
+            );
+        }
+        Error::TimeLimitExceeded { duration, limit } => {
+            github_actions::warning(
+                path,
+                format!("test{revision}: {}", time_limit_message(*duration, *limit)),
+            );
+        }
+        Error::DeprecatedComment { msg, line, since } => {
+            let mut err = github_actions::error(path, format!("Deprecated comment")).line(*line);
+            writeln!(err, "{msg} (deprecated since ui_test {since})").unwrap();
+        }
+        Error::UnusedNormalization { rule, line } => {
+            let warn = github_actions::warning(path, format!("Unused normalization{revision}"));
+            let mut warn = match line {
+                Some(line) => warn.line(*line),
+                None => warn,
+            };
+            writeln!(warn, "{rule} replaced nothing").unwrap();
+        }
         Error::PatternNotFound {
             pattern: _,
             definition_line,
+            near_misses,
+        } => {
+            let mut err = github_actions::error(path, format!("Pattern not found{revision}"))
+                .line(*definition_line);
+            for NearMiss {
+                msg,
+                line,
+                similarity,
+            } in near_misses
+            {
+                let Message { level, message, .. } = msg;
+                match line {
+                    Some(line) => writeln!(
+                        err,
+                        "similar diagnostic at line {line}: {level:?}: {message} ({similarity}% similar)"
+                    ),
+                    None => writeln!(
+                        err,
+                        "similar diagnostic: {} ({similarity}% similar)",
+                        other_file_msg(msg)
+                    ),
+                }
+                .unwrap();
+            }
+        }
+        Error::CodeMismatch {
+            expected,
+            actual,
+            definition_line,
+        } => {
+            let msg = match actual {
+                Some(actual) => {
+                    format!("expected error code `{expected}`, found `{actual}`{revision}")
+                }
+                None => format!("Error code `{expected}` not found{revision}"),
+            };
+            github_actions::error(path, msg).line(*definition_line);
+        }
+        Error::WrongMatchCount {
+            expected,
+            found,
+            definition_line,
         } => {
-            github_actions::error(path, format!("Pattern not found{revision}"))
+            github_actions::error(
+                path,
+                format!("expected {expected} matching diagnostics, found {found}{revision}"),
+            )
+            .line(*definition_line);
+        }
+        Error::NegatedPatternFound {
+            definition_line,
+            msg: Message { level, message, .. },
+        } => {
+            let mut err = github_actions::error(path, format!("Negated pattern found{revision}"))
                 .line(*definition_line);
+            writeln!(err, "{level:?}: {message}").unwrap();
+        }
+        Error::ColumnNotFound {
+            column,
+            columns,
+            definition_line,
+        } => {
+            github_actions::error(
+                path,
+                format!("No diagnostic at column {column}, only at columns {columns:?}{revision}"),
+            )
+            .line(*definition_line);
+        }
+        Error::SuggestionNotFound {
+            pattern: _,
+            suggestions,
+            definition_line,
+        } => {
+            let mut err = github_actions::error(path, format!("Suggestion not found{revision}"))
+                .line(*definition_line);
+            for suggestion in suggestions {
+                writeln!(err, "found suggestion `{suggestion}`").unwrap();
+            }
+        }
+        Error::AnnotationRewritten { .. }
+        | Error::AnnotationsInserted { .. }
+        | Error::AnnotationRemoved { .. } => {}
+        Error::AmbiguousAnnotation {
+            definition_line,
+            line,
+            msgs,
+        } => {
+            let mut err = github_actions::error(
+                path,
+                format!("Could not rewrite ambiguous annotation{revision}"),
+            )
+            .line(*definition_line);
+            writeln!(
+                err,
+                "the annotation could stand for any of these diagnostics on line {line}:"
+            )
+            .unwrap();
+            for Message { level, message, .. } in msgs {
+                writeln!(err, "{level:?}: {message}").unwrap();
+            }
         }
         Error::NoPatternsFound => {
             github_actions::error(
@@ -328,6 +1329,7 @@ fn gha_error(error: &Error, path: &str, revision: &str) {
             actual,
             expected,
             bless_command: _,
+            diff_context: _,
         } => {
             let mut err = github_actions::error(
                 if expected.is_empty() {
@@ -335,11 +1337,12 @@ fn gha_error(error: &Error, path: &str, revision: &str) {
                 } else {
                     output_path.display().to_string()
                 },
-                "actual output differs from expected",
-            );
+                output_differs_msg(actual),
+            )
+            .line(1);
             writeln!(err, "```diff").unwrap();
             let mut seen_diff_line = Some(0);
-            for r in ::diff::lines(expected.to_str().unwrap(), actual.to_str().unwrap()) {
+            for r in ::diff::lines(&lossy(expected), &lossy(actual)) {
                 if let Some(line) = &mut seen_diff_line {
                     *line += 1;
                 }
@@ -370,24 +1373,70 @@ fn gha_error(error: &Error, path: &str, revision: &str) {
             }
             writeln!(err, "```").unwrap();
         }
-        Error::ErrorsWithoutPattern { path: None, msgs } => {
+        Error::StaleOutput {
+            path: output_path,
+            bless_command: _,
+        } => {
+            github_actions::error(
+                output_path.display(),
+                format!("stale output file, the test does not produce it{revision}"),
+            )
+            .line(1);
+        }
+        Error::ReadOnlyOutput { path: output_path } => {
+            github_actions::error(
+                output_path.display(),
+                format!("could not bless output file, it is in a read-only location{revision}"),
+            )
+            .line(1);
+        }
+        Error::ErrorsWithoutPattern {
+            path: None,
+            msgs,
+            similar_patterns,
+        } => {
             let mut err = github_actions::error(
                 path,
                 format!("Unmatched diagnostics outside the testfile{revision}"),
             );
-            for Message { level, message } in msgs {
-                writeln!(err, "{level:?}: {message}").unwrap();
+            for msg in msgs {
+                writeln!(err, "{}", other_file_msg(msg)).unwrap();
+            }
+            for SimilarPattern {
+                pattern,
+                definition_line,
+                similarity,
+            } in similar_patterns
+            {
+                writeln!(
+                    err,
+                    "similar pattern {pattern} at line {definition_line} ({similarity}% similar)"
+                )
+                .unwrap();
             }
         }
         Error::ErrorsWithoutPattern {
             path: Some((path, line)),
             msgs,
+            similar_patterns,
         } => {
             let path = path.display();
             let mut err = github_actions::error(&path, format!("Unmatched diagnostics{revision}"))
                 .line(*line);
-            for Message { level, message } in msgs {
-                writeln!(err, "{level:?}: {message}").unwrap();
+            for msg in msgs {
+                writeln!(err, "{}", test_file_msg(msg)).unwrap();
+            }
+            for SimilarPattern {
+                pattern,
+                definition_line,
+                similarity,
+            } in similar_patterns
+            {
+                writeln!(
+                    err,
+                    "similar pattern {pattern} at line {definition_line} ({similarity}% similar)"
+                )
+                .unwrap();
             }
         }
         Error::InvalidComment { msg, line } => {
@@ -395,7 +1444,83 @@ fn gha_error(error: &Error, path: &str, revision: &str) {
                 github_actions::error(path, format!("Could not parse comment")).line(*line);
             writeln!(err, "{msg}").unwrap();
         }
+        Error::UnknownTriple { condition, line } => {
+            github_actions::error(
+                path,
+                format!("`{condition}` does not match any known target triple{revision}"),
+            )
+            .line(*line);
+        }
+        Error::UnknownChannel { condition } => {
+            github_actions::error(
+                path,
+                format!(
+                    "the release channel is unknown, so `{condition}` cannot be checked{revision}"
+                ),
+            );
+        }
+        Error::UnknownSysroot => {
+            github_actions::error(
+                path,
+                format!(
+                    "the sysroot is unknown, so `{{{{sysroot}}}}` cannot be replaced{revision}"
+                ),
+            );
+        }
+        Error::RunnerRequired { target } => {
+            github_actions::error(
+                path,
+                format!(
+                    "binaries for `{target}` cannot be executed without `Config::runner`{revision}"
+                ),
+            );
+        }
         Error::Bug(_) => {}
+        Error::FixpointNotReached {
+            iterations,
+            diagnostics,
+        } => {
+            let mut err = github_actions::error(
+                path,
+                format!("rustfix{revision} did not reach a fixpoint after {iterations} iterations"),
+            );
+            writeln!(err, "{}", lossy(diagnostics)).unwrap();
+        }
+        Error::FixedHasDiagnostics { level, stderr } => {
+            let mut err = github_actions::error(
+                path,
+                format!(
+                    "the `.fixed` file{revision} emitted diagnostics at level {level:?} or above"
+                ),
+            );
+            writeln!(err, "{}", lossy(stderr)).unwrap();
+        }
+        Error::ForbiddenEnv { var, layer } => {
+            github_actions::error(
+                path,
+                format!("test{revision} saw forbidden env var `{var}`, set by {layer}"),
+            );
+        }
+        Error::StdinFile {
+            path: stdin_path,
+            line,
+            error,
+        } => {
+            let mut err = github_actions::error(
+                path,
+                format!("Could not read stdin file {}", stdin_path.display()),
+            )
+            .line(*line);
+            writeln!(err, "{error}").unwrap();
+        }
+        Error::DependencyFailed {
+            path: dep_path,
+            reason,
+            line,
+        } => {
+            github_actions::error(path, format!("Dependency {} {reason}", dep_path.display()))
+                .line(*line);
+        }
         Error::Aux {
             path: aux_path,
             errors,
@@ -410,22 +1535,27 @@ fn gha_error(error: &Error, path: &str, revision: &str) {
     eprintln!();
 }
 
-/// Emits Github Actions Workspace commands to show the failures directly in the github diff view.
-/// If the const generic `GROUP` boolean is `true`, also emit `::group` commands.
-pub struct Gha<const GROUP: bool> {
+/// Emits Github Actions Workspace commands to show the failures directly in the github diff view,
+/// e.g. unmatched annotations at the line of the annotation and differing output at the top of the
+/// output file. The commands are only emitted when running in github actions.
+pub struct Gha {
     /// Show a specific name for the final summary.
     pub name: String,
+    /// Group the output of each failed test with `::group` commands.
+    pub group_by_file: bool,
 }
 
-impl<const GROUP: bool> StatusEmitter for Gha<GROUP> {
+impl StatusEmitter for Gha {
     fn failed_test(
         &self,
         revision: &str,
         path: &Path,
         _cmd: &Command,
+        _steps: &[(String, String)],
+        _artifacts: Option<&Path>,
         _stderr: &[u8],
     ) -> Box<dyn Debug> {
-        if GROUP {
+        if self.group_by_file {
             Box::new(github_actions::group(format_args!(
                 "{}:{revision}",
                 path.display()
@@ -437,6 +1567,38 @@ impl<const GROUP: bool> StatusEmitter for Gha<GROUP> {
 
     fn test_result(&mut self, _path: &Path, _revision: &str, _result: &TestResult) {}
 
+    fn orphaned_outputs(&mut self, paths: &[PathBuf], removed: bool) {
+        if removed {
+            return;
+        }
+        for path in paths {
+            let mut warn = github_actions::warning(path.display(), "Orphaned output file");
+            writeln!(warn, "no test produces this file").unwrap();
+        }
+    }
+
+    fn test_warnings(&mut self, path: &Path, revision: &str, warnings: &Errors) {
+        for warning in warnings {
+            match warning {
+                Error::DeprecatedComment { msg, line, since } => {
+                    let mut warn =
+                        github_actions::warning(path.display(), format!("Deprecated comment"))
+                            .line(*line);
+                    writeln!(warn, "{msg} (deprecated since ui_test {since})").unwrap();
+                }
+                Error::TimeLimitExceeded { .. } | Error::UnusedNormalization { .. } => {
+                    let revision = if revision.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" (revision: {revision})")
+                    };
+                    gha_error(warning, &path.display().to_string(), &revision)
+                }
+                _ => {}
+            }
+        }
+    }
+
     fn finalize(
         &self,
         _failures: usize,
@@ -444,7 +1606,7 @@ impl<const GROUP: bool> StatusEmitter for Gha<GROUP> {
         ignored: usize,
         filtered: usize,
     ) -> Box<dyn Summary> {
-        struct Summarizer<const GROUP: bool> {
+        struct Summarizer {
             failures: Vec<String>,
             succeeded: usize,
             ignored: usize,
@@ -452,7 +1614,7 @@ impl<const GROUP: bool> StatusEmitter for Gha<GROUP> {
             name: String,
         }
 
-        impl<const GROUP: bool> Summary for Summarizer<GROUP> {
+        impl Summary for Summarizer {
             fn test_failure(&mut self, path: &Path, revision: &str, errors: &Errors) {
                 let revision = if revision.is_empty() {
                     "".to_string()
@@ -465,7 +1627,7 @@ impl<const GROUP: bool> StatusEmitter for Gha<GROUP> {
                 self.failures.push(format!("{}{revision}", path.display()));
             }
         }
-        impl<const GROUP: bool> Drop for Summarizer<GROUP> {
+        impl Drop for Summarizer {
             fn drop(&mut self) {
                 if let Some(mut file) = github_actions::summary() {
                     writeln!(file, "### {}", self.name).unwrap();
@@ -488,7 +1650,7 @@ impl<const GROUP: bool> StatusEmitter for Gha<GROUP> {
             }
         }
 
-        Box::new(Summarizer::<GROUP> {
+        Box::new(Summarizer {
             failures: vec![],
             succeeded,
             ignored,
@@ -498,25 +1660,904 @@ impl<const GROUP: bool> StatusEmitter for Gha<GROUP> {
     }
 }
 
+/// Writes newline-delimited JSON events for machine consumption, e.g. to feed a dashboard.
+/// Every test revision gets a `started` event and one of `ok`, `failed` or `ignored`,
+/// and the run ends with a `suite` event containing the counts:
+///
+/// ```text
+/// {"type":"test","event":"started","name":"tests/ui/foo.rs","revision":""}
+/// {"type":"test","event":"failed","name":"tests/ui/foo.rs","revision":"","duration_ms":42,"errors":[...]}
+/// {"type":"suite","event":"failed","passed":0,"failed":1,"ignored":0,"filtered_out":0,"not_run":0}
+/// ```
+///
+/// The errors of failed tests are objects with a `kind`, a `message`, and, where applicable, a
+/// `line`, `path`, and the `expected` and `actual` values. Output files that no test produces
+/// get an `orphaned_output` event with their `path` and whether they were `removed`, before the
+/// `suite` event. Combine it with `Text` in a tuple (e.g. `(Text::quiet(), Json::new(file))`)
+/// to still show the progress to humans.
+pub struct Json {
+    out: Mutex<Box<dyn std::io::Write + Send>>,
+    durations: Durations,
+    not_run: usize,
+}
+
+/// Measures how long the revisions of tests take, as `test_result` isn't told.
+#[derive(Default)]
+struct Durations {
+    /// When the currently running revision of each test started.
+    started: HashMap<PathBuf, Instant>,
+}
+
+impl Durations {
+    fn start(&mut self, path: &Path) {
+        // All revisions are announced before the first one is run, and then run one after the other.
+        self.started
+            .entry(path.to_owned())
+            .or_insert_with(Instant::now);
+    }
+
+    /// How long the revision of the test that just finished took, if it was started.
+    fn finish(&mut self, path: &Path) -> Option<Duration> {
+        let started = self.started.get_mut(path)?;
+        let now = Instant::now();
+        let duration = now.duration_since(*started);
+        // The next revision starts right after this one.
+        *started = now;
+        Some(duration)
+    }
+}
+
+impl Json {
+    /// Write the events to `out`, one line per event.
+    pub fn new(out: impl std::io::Write + Send + 'static) -> Self {
+        Self {
+            out: Mutex::new(Box::new(out)),
+            durations: Durations::default(),
+            not_run: 0,
+        }
+    }
+
+    fn emit(&self, event: serde_json::Value) {
+        let mut out = self.out.lock().unwrap();
+        writeln!(out, "{event}").unwrap();
+        out.flush().unwrap();
+    }
+}
+
+impl StatusEmitter for Json {
+    fn failed_test<'a>(
+        &'a self,
+        _revision: &'a str,
+        _path: &'a Path,
+        _cmd: &'a Command,
+        _steps: &'a [(String, String)],
+        _artifacts: Option<&'a Path>,
+        _stderr: &'a [u8],
+    ) -> Box<dyn Debug + 'a> {
+        Box::new(())
+    }
+
+    fn test_started(&mut self, path: &Path, revision: &str) {
+        self.durations.start(path);
+        self.emit(serde_json::json!({
+            "type": "test",
+            "event": "started",
+            "name": path.display().to_string(),
+            "revision": revision,
+        }));
+    }
+
+    fn test_result(&mut self, path: &Path, revision: &str, result: &TestResult) {
+        let mut event = serde_json::json!({
+            "type": "test",
+            "name": path.display().to_string(),
+            "revision": revision,
+        });
+        let fields = event.as_object_mut().unwrap();
+        let kind = match result {
+            TestResult::Ok => "ok",
+            TestResult::Cached => {
+                fields.insert("cached".into(), true.into());
+                "ok"
+            }
+            TestResult::Ignored { reason } => {
+                fields.insert("reason".into(), reason.as_str().into());
+                "ignored"
+            }
+            TestResult::Errored {
+                command,
+                steps,
+                errors,
+                artifacts,
+                ..
+            } => {
+                fields.insert("command".into(), crate::shell_command(command).into());
+                let steps = steps
+                    .iter()
+                    .map(|(label, step)| serde_json::json!({ "label": label, "command": step }))
+                    .collect();
+                fields.insert("steps".into(), steps);
+                if let Some(artifacts) = artifacts {
+                    fields.insert("artifacts".into(), artifacts.display().to_string().into());
+                }
+                fields.insert("errors".into(), errors.iter().map(json_error).collect());
+                "failed"
+            }
+            TestResult::Filtered => return,
+        };
+        fields.insert("event".into(), kind.into());
+        if let Some(duration) = self.durations.finish(path) {
+            fields.insert("duration_ms".into(), (duration.as_millis() as u64).into());
+        }
+        self.emit(event);
+    }
+
+    fn tests_not_run(&mut self, count: usize) {
+        self.not_run = count;
+    }
+
+    fn orphaned_outputs(&mut self, paths: &[PathBuf], removed: bool) {
+        for path in paths {
+            self.emit(serde_json::json!({
+                "type": "orphaned_output",
+                "path": path.display().to_string(),
+                "removed": removed,
+            }));
+        }
+    }
+
+    fn finalize(
+        &self,
+        failed: usize,
+        succeeded: usize,
+        ignored: usize,
+        filtered: usize,
+    ) -> Box<dyn Summary> {
+        self.emit(serde_json::json!({
+            "type": "suite",
+            "event": if failed == 0 { "ok" } else { "failed" },
+            "passed": succeeded,
+            "failed": failed,
+            "ignored": ignored,
+            "filtered_out": filtered,
+            "not_run": self.not_run,
+        }));
+        Box::new(())
+    }
+}
+
+/// An `Error` as a JSON object with its `kind`, a human readable `message` and,
+/// where applicable, the `line`, `path`, and `expected` and `actual` values.
+fn json_error(error: &Error) -> serde_json::Value {
+    use serde_json::{json, Value};
+    let msgs = |msgs: &[Message]| -> Value { msgs.iter().map(test_file_msg).collect() };
+    let (kind, message, mut fields) = match error {
+        Error::ExitStatus {
+            mode,
+            status,
+            expected,
+        } => (
+            "exit_status",
+            format!("{mode} test got {status}, but expected {expected}"),
+            match expected {
+                ExitExpectation::Code(code) => json!({ "expected": code, "actual": status.code() }),
+                ExitExpectation::Signal(_) | ExitExpectation::AnyCode | ExitExpectation::Panic => {
+                    json!({ "expected": expected.to_string(), "actual": status.code() })
+                }
+            },
+        ),
+        Error::Command { kind, status } => (
+            "command",
+            format!("{kind} failed with {status}"),
+            json!({ "actual": status.code() }),
+        ),
+        Error::Ice {
+            message,
+            query_stack,
+        } => (
+            "ice",
+            format!("the compiler crashed with an internal compiler error"),
+            json!({ "actual": message, "query_stack": query_stack }),
+        ),
+        Error::IceNotFound => (
+            "ice_not_found",
+            format!("expected an internal compiler error, but the compiler did not crash"),
+            json!({}),
+        ),
+        Error::DidNotPanic => (
+            "did_not_panic",
+            format!("expected the test binary to panic, but it exited successfully"),
+            json!({}),
+        ),
+        Error::PanicMessageNotFound { pattern, line } => (
+            "panic_message_not_found",
+            format!("the test binary panicked, but {pattern} was not found in its stderr"),
+            json!({ "line": line, "expected": pattern.to_string() }),
+        ),
+        Error::ConflictingDiagnostics => (
+            "conflicting_diagnostics",
+            format!("the compiler emitted different JSON diagnostics on stdout and stderr"),
+            json!({}),
+        ),
+        Error::TimedOut { timeout } => (
+            "timed_out",
+            format!("timed out after {}s", timeout.as_secs()),
+            json!({}),
+        ),
+        Error::TimeLimitExceeded { duration, limit } => (
+            "time_limit_exceeded",
+            time_limit_message(*duration, *limit),
+            json!({
+                "actual": duration.as_secs_f64(),
+                "expected": limit.as_secs_f64(),
+            }),
+        ),
+        Error::DeprecatedComment { msg, line, since } => (
+            "deprecated_comment",
+            format!("{msg} (deprecated since ui_test {since})"),
+            json!({ "line": line }),
+        ),
+        Error::UnusedNormalization { rule, line } => (
+            "unused_normalization",
+            format!("{rule} replaced nothing"),
+            json!({ "line": line }),
+        ),
+        Error::PatternNotFound {
+            pattern,
+            definition_line,
+            near_misses,
+        } => (
+            "pattern_not_found",
+            format!("{pattern} not found in stderr output"),
+            json!({
+                "line": definition_line,
+                "expected": pattern.to_string(),
+                "near_misses": near_misses
+                    .iter()
+                    .map(|near_miss| json!({
+                        "line": near_miss.line,
+                        "message": format!("{:?}: {}", near_miss.msg.level, near_miss.msg.message),
+                        "similarity": near_miss.similarity,
+                    }))
+                    .collect::<Value>(),
+            }),
+        ),
+        Error::CodeMismatch {
+            expected,
+            actual,
+            definition_line,
+        } => (
+            "code_mismatch",
+            match actual {
+                Some(actual) => format!("expected error code `{expected}`, but found `{actual}`"),
+                None => format!("error code `{expected}` not found in stderr output"),
+            },
+            json!({ "line": definition_line, "expected": expected, "actual": actual }),
+        ),
+        Error::WrongMatchCount {
+            expected,
+            found,
+            definition_line,
+        } => (
+            "wrong_match_count",
+            format!("expected {expected} matching diagnostics, but found {found}"),
+            json!({ "line": definition_line, "expected": expected, "actual": found }),
+        ),
+        Error::NegatedPatternFound {
+            definition_line,
+            msg,
+        } => (
+            "negated_pattern_found",
+            format!(
+                "{:?}: {} found despite negated pattern",
+                msg.level, msg.message
+            ),
+            json!({ "line": definition_line, "actual": msg.message }),
+        ),
+        Error::ColumnNotFound {
+            column,
+            columns,
+            definition_line,
+        } => (
+            "column_not_found",
+            format!("no diagnostic found at column {column}, only at columns {columns:?}"),
+            json!({ "line": definition_line, "expected": column, "actual": columns }),
+        ),
+        Error::SuggestionNotFound {
+            pattern,
+            suggestions,
+            definition_line,
+        } => (
+            "suggestion_not_found",
+            format!("suggestion {pattern} not found"),
+            json!({
+                "line": definition_line,
+                "expected": pattern.to_string(),
+                "actual": suggestions,
+            }),
+        ),
+        Error::AnnotationRewritten {
+            definition_line,
+            old,
+            new,
+        } => (
+            "annotation_rewritten",
+            format!("rewrote annotation `{old}` -> `{new}`"),
+            json!({ "line": definition_line, "expected": new, "actual": old }),
+        ),
+        Error::AnnotationsInserted {
+            line,
+            removed,
+            added,
+        } => (
+            "annotations_inserted",
+            format!("inserted annotations for the diagnostics"),
+            json!({ "line": line, "removed": removed, "added": added }),
+        ),
+        Error::AnnotationRemoved {
+            definition_line,
+            annotation,
+        } => (
+            "annotation_removed",
+            format!("removed annotation that matched no diagnostic"),
+            json!({ "line": definition_line, "actual": annotation }),
+        ),
+        Error::AmbiguousAnnotation {
+            definition_line,
+            line,
+            msgs: diagnostics,
+        } => (
+            "ambiguous_annotation",
+            format!(
+                "could not rewrite the annotation, there are {} diagnostics on line {line} it could stand for",
+                diagnostics.len()
+            ),
+            json!({ "line": definition_line, "actual": msgs(diagnostics) }),
+        ),
+        Error::NoPatternsFound => (
+            "no_patterns_found",
+            format!("no error patterns found in fail test"),
+            json!({}),
+        ),
+        Error::PatternFoundInPassTest => (
+            "pattern_found_in_pass_test",
+            format!("error pattern found in pass test"),
+            json!({}),
+        ),
+        Error::OutputDiffers {
+            path,
+            actual,
+            expected,
+            bless_command,
+            diff_context: _,
+        } => (
+            "output_differs",
+            format!(
+                "{}, execute `{bless_command}` to update it",
+                output_differs_msg(actual)
+            ),
+            json!({
+                "path": path.display().to_string(),
+                "expected": lossy(expected),
+                "actual": lossy(actual),
+            }),
+        ),
+        Error::StaleOutput {
+            path,
+            bless_command,
+        } => (
+            "stale_output",
+            format!("the test does not produce this file, execute `{bless_command}` to remove it"),
+            json!({ "path": path.display().to_string() }),
+        ),
+        Error::ReadOnlyOutput { path } => (
+            "read_only_output",
+            format!("could not bless the file, it is in a read-only location"),
+            json!({ "path": path.display().to_string() }),
+        ),
+        Error::ErrorsWithoutPattern {
+            msgs: diagnostics,
+            path,
+            similar_patterns,
+        } => (
+            "errors_without_pattern",
+            format!("there were {} unmatched diagnostics", diagnostics.len()),
+            json!({
+                "path": path.as_ref().map(|(path, _)| path.display().to_string()),
+                "line": path.as_ref().map(|(_, line)| line),
+                "actual": match path {
+                    Some(_) => msgs(diagnostics),
+                    None => diagnostics.iter().map(other_file_msg).collect(),
+                },
+                "similar_patterns": similar_patterns
+                    .iter()
+                    .map(|similar| json!({
+                        "line": similar.definition_line,
+                        "pattern": similar.pattern.to_string(),
+                        "similarity": similar.similarity,
+                    }))
+                    .collect::<Value>(),
+            }),
+        ),
+        Error::InvalidComment { msg, line } => {
+            ("invalid_comment", msg.clone(), json!({ "line": line }))
+        }
+        Error::UnknownTriple { condition, line } => (
+            "unknown_triple",
+            format!("`{condition}` does not match any known target triple, it is probably misspelled"),
+            json!({ "line": line }),
+        ),
+        Error::UnknownChannel { condition } => (
+            "unknown_channel",
+            format!("the release channel of the program could not be detected, so `{condition}` cannot be checked"),
+            json!({}),
+        ),
+        Error::UnknownSysroot => (
+            "unknown_sysroot",
+            "the sysroot of the program could not be detected, so `{{sysroot}}` cannot be replaced"
+                .into(),
+            json!({}),
+        ),
+        Error::RunnerRequired { target } => (
+            "runner_required",
+            format!("binaries for `{target}` cannot be executed on the host"),
+            json!({}),
+        ),
+        Error::Bug(msg) => (
+            "bug",
+            format!("A bug in `ui_test` occurred: {msg}"),
+            json!({}),
+        ),
+        Error::FixpointNotReached {
+            iterations,
+            diagnostics,
+        } => (
+            "fixpoint_not_reached",
+            format!("rustfix did not reach a fixpoint after {iterations} iterations"),
+            json!({ "actual": lossy(diagnostics) }),
+        ),
+        Error::FixedHasDiagnostics { level, stderr } => (
+            "fixed_has_diagnostics",
+            format!("the `.fixed` file emitted diagnostics at level {level:?} or above"),
+            json!({ "actual": lossy(stderr) }),
+        ),
+        Error::ForbiddenEnv { var, layer } => (
+            "forbidden_env",
+            format!("environment variable `{var}` is forbidden, but was set by {layer}"),
+            json!({}),
+        ),
+        Error::StdinFile { path, line, error } => (
+            "stdin_file",
+            format!("could not read stdin file: {error}"),
+            json!({ "path": path.display().to_string(), "line": line }),
+        ),
+        Error::DependencyFailed { path, reason, line } => (
+            "dependency_failed",
+            format!("dependency {reason}"),
+            json!({ "path": path.display().to_string(), "line": line }),
+        ),
+        Error::Aux { path, errors, line } => (
+            "aux",
+            format!("aux build failed"),
+            json!({
+                "path": path.display().to_string(),
+                "line": line,
+                "errors": errors.iter().map(json_error).collect::<Value>(),
+            }),
+        ),
+    };
+    let fields = fields.as_object_mut().unwrap();
+    fields.insert("kind".into(), kind.into());
+    fields.insert("message".into(), message.into());
+    fields.retain(|_, value| !value.is_null());
+    Value::Object(std::mem::take(fields))
+}
+
+/// Writes a JUnit XML report for CI systems like Jenkins or GitLab. The report is a single
+/// `<testsuite>` with a `<testcase>` per test revision, written to the file at the end of the
+/// run (also if tests failed). The test's directory is the `classname`, its file name and
+/// revision (`foo.rs#revision`) the `name`. Failures contain the errors, including the diffs
+/// of output files, and ignored tests are `<skipped>` with the reason.
+pub struct JUnit {
+    path: PathBuf,
+    /// The test cases in the order they finished.
+    cases: Vec<TestCase>,
+    durations: Durations,
+}
+
+/// A `<testcase>` of a JUnit report.
+struct TestCase {
+    classname: String,
+    name: String,
+    time: Duration,
+    outcome: Outcome,
+}
+
+/// How a JUnit test case ended.
+enum Outcome {
+    Passed,
+    /// Ignored, with the reason.
+    Skipped(String),
+    /// Failed, with the first error's message and kind, and the details of all errors.
+    Failed {
+        message: String,
+        kind: String,
+        details: String,
+    },
+}
+
+impl JUnit {
+    /// Write the report to the file at `path` once all tests finished.
+    pub fn to_file(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            cases: vec![],
+            durations: Durations::default(),
+        }
+    }
+
+    /// The report as an XML document.
+    fn report(&self) -> String {
+        let count =
+            |f: fn(&Outcome) -> bool| self.cases.iter().filter(|case| f(&case.outcome)).count();
+        let failures = count(|outcome| matches!(outcome, Outcome::Failed { .. }));
+        let skipped = count(|outcome| matches!(outcome, Outcome::Skipped(_)));
+        let time: Duration = self.cases.iter().map(|case| case.time).sum();
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        writeln!(
+            xml,
+            "<testsuite name=\"ui_test\" tests=\"{}\" failures=\"{failures}\" errors=\"0\" skipped=\"{skipped}\" time=\"{:.3}\">",
+            self.cases.len(),
+            time.as_secs_f64(),
+        )
+        .unwrap();
+        for case in &self.cases {
+            write!(
+                xml,
+                "  <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"",
+                xml_escape(&case.classname),
+                xml_escape(&case.name),
+                case.time.as_secs_f64(),
+            )
+            .unwrap();
+            match &case.outcome {
+                Outcome::Passed => xml.push_str("/>\n"),
+                Outcome::Skipped(reason) => writeln!(
+                    xml,
+                    ">\n    <skipped message=\"{}\"/>\n  </testcase>",
+                    xml_escape(reason)
+                )
+                .unwrap(),
+                Outcome::Failed {
+                    message,
+                    kind,
+                    details,
+                } => writeln!(
+                    xml,
+                    ">\n    <failure message=\"{}\" type=\"{}\">{}</failure>\n  </testcase>",
+                    xml_escape(message),
+                    xml_escape(kind),
+                    xml_escape(details),
+                )
+                .unwrap(),
+            }
+        }
+        xml.push_str("</testsuite>\n");
+        xml
+    }
+}
+
+impl StatusEmitter for JUnit {
+    fn failed_test<'a>(
+        &'a self,
+        _revision: &'a str,
+        _path: &'a Path,
+        _cmd: &'a Command,
+        _steps: &'a [(String, String)],
+        _artifacts: Option<&'a Path>,
+        _stderr: &'a [u8],
+    ) -> Box<dyn Debug + 'a> {
+        Box::new(())
+    }
+
+    fn test_started(&mut self, path: &Path, _revision: &str) {
+        self.durations.start(path);
+    }
+
+    fn test_result(&mut self, path: &Path, revision: &str, result: &TestResult) {
+        let outcome = match result {
+            TestResult::Ok | TestResult::Cached => Outcome::Passed,
+            TestResult::Ignored { reason } => Outcome::Skipped(reason.clone()),
+            TestResult::Errored {
+                command,
+                steps,
+                errors,
+                artifacts,
+                ..
+            } => {
+                let first = errors.first().map(json_error).unwrap_or_default();
+                let path = path.display().to_string();
+                let mut details: String = errors
+                    .iter()
+                    .map(|error| error_text(error, &path))
+                    .collect();
+                for (label, step) in steps {
+                    writeln!(details, "{label}: {step}").unwrap();
+                }
+                writeln!(details, "command: {}", crate::shell_command(command)).unwrap();
+                if let Some(artifacts) = artifacts {
+                    writeln!(details, "artifacts: {}", artifacts.display()).unwrap();
+                }
+                Outcome::Failed {
+                    message: first["message"].as_str().unwrap_or_default().into(),
+                    kind: first["kind"].as_str().unwrap_or_default().into(),
+                    details,
+                }
+            }
+            TestResult::Filtered => return,
+        };
+        let file_name = path.file_name().unwrap().to_string_lossy();
+        self.cases.push(TestCase {
+            classname: path
+                .parent()
+                .map(|dir| dir.display().to_string())
+                .unwrap_or_default(),
+            name: if revision.is_empty() {
+                file_name.into_owned()
+            } else {
+                format!("{file_name}#{revision}")
+            },
+            time: self.durations.finish(path).unwrap_or_default(),
+            outcome,
+        });
+    }
+
+    fn finalize(
+        &self,
+        _failed: usize,
+        _succeeded: usize,
+        _ignored: usize,
+        _filtered: usize,
+    ) -> Box<dyn Summary> {
+        if let Some(dir) = self.path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        if let Err(err) = std::fs::write(&self.path, self.report()) {
+            eprintln!(
+                "{}: could not write the JUnit report to {}: {err}",
+                "warning".yellow().bold(),
+                self.path.display()
+            );
+        }
+        Box::new(())
+    }
+}
+
+/// The details of an `Error` as plain text, for reports that are not printed to a terminal.
+fn error_text(error: &Error, path: &str) -> String {
+    let json = json_error(error);
+    let mut text = match &json["line"] {
+        serde_json::Value::Null => String::new(),
+        line => format!("{path}:{line}: "),
+    };
+    writeln!(text, "{}", json["message"].as_str().unwrap()).unwrap();
+    match error {
+        Error::OutputDiffers {
+            path,
+            actual,
+            expected,
+            diff_context,
+            ..
+        } => {
+            writeln!(text, "--- {}\n+++ <output>", path.display()).unwrap();
+            text.push_str(&crate::diff::plain_diff(expected, actual, *diff_context));
+        }
+        Error::Aux { path, errors, .. } => {
+            for error in errors {
+                text.push_str(&error_text(error, &path.display().to_string()));
+            }
+        }
+        _ => {
+            for field in ["expected", "actual"] {
+                match &json[field] {
+                    serde_json::Value::Null => {}
+                    serde_json::Value::String(value) => writeln!(text, "{field}: {value}").unwrap(),
+                    value => writeln!(text, "{field}: {value}").unwrap(),
+                }
+            }
+        }
+    }
+    text.push('\n');
+    text
+}
+
+/// Escapes `text` for XML attributes and text. Characters that can't appear in XML 1.0 at all,
+/// like the escape sequences of colored output, are replaced.
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => escaped.push(char::REPLACEMENT_CHARACTER),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Passes all events to each of the emitters in turn, for any number of emitters.
+/// Like with a tuple of emitters, `finalize` and the summaries go to the emitters in
+/// reverse order, so the first emitter's summary is printed last.
+pub struct Composite(pub Vec<Box<dyn StatusEmitter + Send>>);
+
+impl StatusEmitter for Composite {
+    fn failed_test<'a>(
+        &'a self,
+        revision: &'a str,
+        path: &'a Path,
+        cmd: &'a Command,
+        steps: &'a [(String, String)],
+        artifacts: Option<&'a Path>,
+        stderr: &'a [u8],
+    ) -> Box<dyn Debug + 'a> {
+        Box::new(
+            self.0
+                .iter()
+                .map(|emitter| emitter.failed_test(revision, path, cmd, steps, artifacts, stderr))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    fn test_started(&mut self, path: &Path, revision: &str) {
+        for emitter in &mut self.0 {
+            emitter.test_started(path, revision);
+        }
+    }
+
+    fn test_result(&mut self, path: &Path, revision: &str, result: &TestResult) {
+        for emitter in &mut self.0 {
+            emitter.test_result(path, revision, result);
+        }
+    }
+
+    fn test_warnings(&mut self, path: &Path, revision: &str, warnings: &Errors) {
+        for emitter in &mut self.0 {
+            emitter.test_warnings(path, revision, warnings);
+        }
+    }
+
+    fn test_duration(&mut self, path: &Path, revision: &str, duration: Duration, timings: Timings) {
+        for emitter in &mut self.0 {
+            emitter.test_duration(path, revision, duration, timings);
+        }
+    }
+
+    fn shuffle_seed(&mut self, seed: u64) {
+        for emitter in &mut self.0 {
+            emitter.shuffle_seed(seed);
+        }
+    }
+
+    fn report_slowest(&mut self, count: usize) {
+        for emitter in &mut self.0 {
+            emitter.report_slowest(count);
+        }
+    }
+
+    fn no_failures_recorded(&mut self) {
+        for emitter in &mut self.0 {
+            emitter.no_failures_recorded();
+        }
+    }
+
+    fn shard(&mut self, shard: (usize, usize), other_shards: usize) {
+        for emitter in &mut self.0 {
+            emitter.shard(shard, other_shards);
+        }
+    }
+
+    fn tests_not_run(&mut self, count: usize) {
+        for emitter in &mut self.0 {
+            emitter.tests_not_run(count);
+        }
+    }
+
+    fn orphaned_outputs(&mut self, paths: &[PathBuf], removed: bool) {
+        for emitter in &mut self.0 {
+            emitter.orphaned_outputs(paths, removed);
+        }
+    }
+
+    fn finalize(
+        &self,
+        failed: usize,
+        succeeded: usize,
+        ignored: usize,
+        filtered: usize,
+    ) -> Box<dyn Summary> {
+        let summaries: Vec<_> = self
+            .0
+            .iter()
+            .rev()
+            .map(|emitter| emitter.finalize(failed, succeeded, ignored, filtered))
+            .collect();
+        Box::new(summaries)
+    }
+}
+
 impl<T: StatusEmitter, U: StatusEmitter> StatusEmitter for (T, U) {
     fn failed_test<'a>(
         &'a self,
         revision: &'a str,
         path: &'a Path,
         cmd: &'a Command,
+        steps: &'a [(String, String)],
+        artifacts: Option<&'a Path>,
         stderr: &'a [u8],
     ) -> Box<dyn Debug + 'a> {
         Box::new((
-            self.0.failed_test(revision, path, cmd, stderr),
-            self.1.failed_test(revision, path, cmd, stderr),
+            self.0
+                .failed_test(revision, path, cmd, steps, artifacts, stderr),
+            self.1
+                .failed_test(revision, path, cmd, steps, artifacts, stderr),
         ))
     }
 
+    fn test_started(&mut self, path: &Path, revision: &str) {
+        self.0.test_started(path, revision);
+        self.1.test_started(path, revision);
+    }
+
     fn test_result(&mut self, path: &Path, revision: &str, result: &TestResult) {
         self.0.test_result(path, revision, result);
         self.1.test_result(path, revision, result);
     }
 
+    fn test_warnings(&mut self, path: &Path, revision: &str, warnings: &Errors) {
+        self.0.test_warnings(path, revision, warnings);
+        self.1.test_warnings(path, revision, warnings);
+    }
+
+    fn tests_not_run(&mut self, count: usize) {
+        self.0.tests_not_run(count);
+        self.1.tests_not_run(count);
+    }
+
+    fn orphaned_outputs(&mut self, paths: &[PathBuf], removed: bool) {
+        self.0.orphaned_outputs(paths, removed);
+        self.1.orphaned_outputs(paths, removed);
+    }
+
+    fn test_duration(&mut self, path: &Path, revision: &str, duration: Duration, timings: Timings) {
+        self.0.test_duration(path, revision, duration, timings);
+        self.1.test_duration(path, revision, duration, timings);
+    }
+
+    fn shuffle_seed(&mut self, seed: u64) {
+        self.0.shuffle_seed(seed);
+        self.1.shuffle_seed(seed);
+    }
+
+    fn report_slowest(&mut self, count: usize) {
+        self.0.report_slowest(count);
+        self.1.report_slowest(count);
+    }
+
+    fn no_failures_recorded(&mut self) {
+        self.0.no_failures_recorded();
+        self.1.no_failures_recorded();
+    }
+
+    fn shard(&mut self, shard: (usize, usize), other_shards: usize) {
+        self.0.shard(shard, other_shards);
+        self.1.shard(shard, other_shards);
+    }
+
     fn finalize(
         &self,
         failures: usize,
@@ -537,15 +2578,53 @@ impl<T: StatusEmitter + ?Sized> StatusEmitter for Box<T> {
         revision: &'a str,
         path: &'a Path,
         cmd: &'a Command,
+        steps: &'a [(String, String)],
+        artifacts: Option<&'a Path>,
         stderr: &'a [u8],
     ) -> Box<dyn Debug + 'a> {
-        (**self).failed_test(revision, path, cmd, stderr)
+        (**self).failed_test(revision, path, cmd, steps, artifacts, stderr)
+    }
+
+    fn test_started(&mut self, path: &Path, revision: &str) {
+        (**self).test_started(path, revision);
     }
 
     fn test_result(&mut self, path: &Path, revision: &str, result: &TestResult) {
         (**self).test_result(path, revision, result);
     }
 
+    fn test_warnings(&mut self, path: &Path, revision: &str, warnings: &Errors) {
+        (**self).test_warnings(path, revision, warnings);
+    }
+
+    fn tests_not_run(&mut self, count: usize) {
+        (**self).tests_not_run(count);
+    }
+
+    fn orphaned_outputs(&mut self, paths: &[PathBuf], removed: bool) {
+        (**self).orphaned_outputs(paths, removed);
+    }
+
+    fn test_duration(&mut self, path: &Path, revision: &str, duration: Duration, timings: Timings) {
+        (**self).test_duration(path, revision, duration, timings);
+    }
+
+    fn shuffle_seed(&mut self, seed: u64) {
+        (**self).shuffle_seed(seed);
+    }
+
+    fn report_slowest(&mut self, count: usize) {
+        (**self).report_slowest(count);
+    }
+
+    fn no_failures_recorded(&mut self) {
+        (**self).no_failures_recorded();
+    }
+
+    fn shard(&mut self, shard: (usize, usize), other_shards: usize) {
+        (**self).shard(shard, other_shards);
+    }
+
     fn finalize(
         &self,
         failures: usize,
@@ -557,6 +2636,14 @@ impl<T: StatusEmitter + ?Sized> StatusEmitter for Box<T> {
     }
 }
 
+impl Summary for Vec<Box<dyn Summary>> {
+    fn test_failure(&mut self, path: &Path, revision: &str, errors: &Errors) {
+        for summary in self {
+            summary.test_failure(path, revision, errors);
+        }
+    }
+}
+
 impl Summary for (Box<dyn Summary>, Box<dyn Summary>) {
     fn test_failure(&mut self, path: &Path, revision: &str, errors: &Errors) {
         self.0.test_failure(path, revision, errors);
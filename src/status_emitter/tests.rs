@@ -0,0 +1,47 @@
+use super::{ProgressBar, StatusEmitter, Text};
+use crate::TestResult;
+use std::{path::Path, process::Command};
+
+#[test]
+fn progress_bar() {
+    let mut text = Text {
+        progress: Some(ProgressBar::default()),
+        ..Text::verbose()
+    };
+    let line = |text: &Text, width| text.progress.as_ref().unwrap().line(width);
+    for name in ["a.rs", "b.rs", "c.rs", "d.rs", "e.rs"] {
+        text.test_started(Path::new(name), "");
+    }
+    text.test_started(Path::new("f.rs"), "x");
+    assert_eq!(
+        line(&text, 200),
+        "0 passed, 0 failed, 0 ignored | running: a.rs (0s), b.rs (0s), c.rs (0s) and 3 more"
+    );
+
+    text.test_result(Path::new("a.rs"), "", &TestResult::Ok);
+    text.test_result(Path::new("b.rs"), "", &TestResult::Cached);
+    text.test_result(
+        Path::new("c.rs"),
+        "",
+        &TestResult::Ignored {
+            reason: "nope".into(),
+        },
+    );
+    text.test_result(
+        Path::new("d.rs"),
+        "",
+        &TestResult::Errored {
+            command: Command::new("cake"),
+            steps: vec![],
+            errors: vec![],
+            stderr: vec![],
+            artifacts: None,
+        },
+    );
+    assert_eq!(
+        line(&text, 200),
+        "2 passed, 1 failed, 1 ignored | running: e.rs (0s), f.rs (x) (0s)"
+    );
+    // Lines that would wrap are cut off.
+    assert_eq!(line(&text, 20), "2 passed, 1 faile...");
+}
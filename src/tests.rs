@@ -1,11 +1,19 @@
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+use crate::parser::Pattern;
 use crate::rustc_stderr::Level;
 use crate::rustc_stderr::Message;
 
 use super::*;
 
-fn config() -> Config {
+/// Why the test should not be run according to its in-file conditions.
+fn skip_reason(comments: &Comments, config: &Config, revision: &str) -> Option<String> {
+    test_file_conditions(comments, config, revision).map(|skip| skip.reason(config))
+}
+
+pub(crate) fn config() -> Config {
     Config {
         root_dir: PathBuf::from("$RUSTROOT"),
         program: CommandBuilder::cmd("cake"),
@@ -13,6 +21,134 @@ fn config() -> Config {
     }
 }
 
+/// A suite of the tests in `dir/tests`, "compiled" with `true`, so they pass unless they
+/// expect some output.
+pub(crate) fn noop_suite(dir: &Path) -> Config {
+    Config {
+        root_dir: dir.join("tests"),
+        out_dir: dir.join("out"),
+        cache_dir: Some(dir.join("cache")),
+        logs_dir: dir.join("logs"),
+        last_failures: dir.join("last-failures.json"),
+        program: CommandBuilder::cmd("true"),
+        mode: Mode::Pass,
+        host: Some("x86_64-unknown-linux-gnu".into()),
+        channel: Some(Channel::Stable),
+        ..config()
+    }
+}
+
+pub(crate) type Log = std::sync::Arc<std::sync::Mutex<Vec<String>>>;
+
+/// A `StatusEmitter` recording the events it gets in a `Log`, e.g. `started a.rs` or
+/// `warning a.rs: TimeLimitExceeded { .. }`, prefixed with its name if it has one.
+pub(crate) struct Recorder(pub(crate) &'static str, pub(crate) Log);
+
+impl Recorder {
+    fn log(&self, event: String) {
+        let event = if self.0.is_empty() {
+            event
+        } else {
+            format!("{}: {event}", self.0)
+        };
+        self.1.lock().unwrap().push(event);
+    }
+}
+
+/// The file name of a test, which is all the recorded events mention of it.
+fn file_name(path: &Path) -> &str {
+    path.file_name().unwrap().to_str().unwrap()
+}
+
+impl status_emitter::StatusEmitter for Recorder {
+    fn failed_test<'a>(
+        &'a self,
+        _revision: &'a str,
+        path: &'a Path,
+        _cmd: &'a Command,
+        _steps: &'a [(String, String)],
+        _artifacts: Option<&'a Path>,
+        _stderr: &'a [u8],
+    ) -> Box<dyn std::fmt::Debug + 'a> {
+        self.log(format!("failed {}", file_name(path)));
+        Box::new(())
+    }
+    fn test_started(&mut self, path: &Path, _revision: &str) {
+        self.log(format!("started {}", file_name(path)));
+    }
+    fn test_result(&mut self, path: &Path, _revision: &str, result: &TestResult) {
+        let result = match result {
+            TestResult::Ok => "ok",
+            TestResult::Errored { .. } => "errored",
+            _ => "other",
+        };
+        self.log(format!("{result} {}", file_name(path)));
+    }
+    fn test_duration(&mut self, path: &Path, revision: &str, _: Duration, timings: Timings) {
+        self.log(format!(
+            "duration {} {revision}, run {:?}",
+            file_name(path),
+            timings.run
+        ));
+    }
+    fn test_warnings(&mut self, path: &Path, _revision: &str, warnings: &Errors) {
+        for warning in warnings {
+            self.log(format!("warning {}: {warning:?}", file_name(path)));
+        }
+    }
+    fn report_slowest(&mut self, count: usize) {
+        self.log(format!("slowest {count}"));
+    }
+    fn no_failures_recorded(&mut self) {
+        self.log("no failures recorded".into());
+    }
+    fn finalize(
+        &self,
+        failed: usize,
+        succeeded: usize,
+        _: usize,
+        _: usize,
+    ) -> Box<dyn status_emitter::Summary> {
+        self.log(format!("finalize {failed} {succeeded}"));
+        Box::new(Recorder(self.0, self.1.clone()))
+    }
+}
+
+impl status_emitter::Summary for Recorder {
+    fn test_failure(&mut self, path: &Path, _revision: &str, _errors: &Errors) {
+        self.log(format!("summary {}", file_name(path)));
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.log("dropped".into());
+    }
+}
+
+/// The events in `log` of the given kinds, e.g. `["started", "ok"]`.
+pub(crate) fn recorded(log: &Log, kinds: &[&str]) -> Vec<String> {
+    log.lock()
+        .unwrap()
+        .iter()
+        .filter(|event| {
+            // Unnamed recorders don't prefix their events.
+            let unprefixed = event.split_once(": ").map(|(_, event)| event);
+            [Some(event.as_str()), unprefixed]
+                .into_iter()
+                .flatten()
+                .any(|event| {
+                    kinds.iter().any(|kind| {
+                        event
+                            .strip_prefix(kind)
+                            .is_some_and(|rest| rest.is_empty() || rest.starts_with(' '))
+                    })
+                })
+        })
+        .cloned()
+        .collect()
+}
+
 #[test]
 fn issue_2156() {
     let s = r"
@@ -28,10 +164,7 @@ fn main() {
     let messages = vec![
         vec![], vec![], vec![], vec![], vec![],
         vec![
-            Message {
-                message:"Undefined Behavior: type validation failed: encountered a dangling reference (address 0x10 is unallocated)".to_string(),
-                level: Level::Error,
-            }
+            Message::new(Level::Error, "Undefined Behavior: type validation failed: encountered a dangling reference (address 0x10 is unallocated)")
         ]
     ];
     check_annotations(
@@ -66,10 +199,7 @@ fn main() {
     let config = config();
     {
         let messages = vec![vec![], vec![], vec![], vec![], vec![], vec![
-                Message {
-                    message: "Undefined Behavior: type validation failed: encountered a dangling reference (address 0x10 is unallocated)".to_string(),
-                    level: Level::Error,
-                }
+                Message::new(Level::Error, "Undefined Behavior: type validation failed: encountered a dangling reference (address 0x10 is unallocated)")
             ]
         ];
         let mut errors = vec![];
@@ -91,10 +221,7 @@ fn main() {
     // only difference to above is a wrong line number
     {
         let messages = vec![vec![], vec![], vec![], vec![], vec![
-                Message {
-                    message: "Undefined Behavior: type validation failed: encountered a dangling reference (address 0x10 is unallocated)".to_string(),
-                    level: Level::Error,
-                }
+                Message::new(Level::Error, "Undefined Behavior: type validation failed: encountered a dangling reference (address 0x10 is unallocated)")
             ]
         ];
         let mut errors = vec![];
@@ -122,10 +249,7 @@ fn main() {
         let messages = vec![
             vec![], vec![], vec![], vec![], vec![],
             vec![
-                Message {
-                    message: "Undefined Behavior: type validation failed: encountered a dangling reference (address 0x10 is unallocated)".to_string(),
-                    level: Level::Note,
-                }
+                Message::new(Level::Note, "Undefined Behavior: type validation failed: encountered a dangling reference (address 0x10 is unallocated)")
             ]
         ];
         let mut errors = vec![];
@@ -163,10 +287,7 @@ fn main() {
     let messages = vec![
         vec![], vec![], vec![], vec![], vec![],
         vec![
-            Message {
-                message: "Undefined Behavior: type validation failed: encountered a dangling reference (address 0x10 is unallocated)".to_string(),
-                level: Level::Error,
-            }
+            Message::new(Level::Error, "Undefined Behavior: type validation failed: encountered a dangling reference (address 0x10 is unallocated)")
         ]
     ];
     let mut errors = vec![];
@@ -187,6 +308,327 @@ fn main() {
     }
 }
 
+#[test]
+fn pattern_without_line() {
+    let s = r"
+//~? ERROR: the crate requires a feature
+//~? WARN: unused configuration
+fn main() {}
+    ";
+    let comments = Comments::parse(s).unwrap();
+    let config = config();
+    // Messages on a line must not satisfy `//~?` patterns.
+    let messages = vec![
+        vec![],
+        vec![],
+        vec![],
+        vec![Message::new(Level::Warn, "unused configuration")],
+    ];
+    let messages_from_unknown_file_or_line =
+        vec![Message::new(Level::Error, "the crate requires a feature")];
+    let mut errors = vec![];
+    check_annotations(
+        messages,
+        messages_from_unknown_file_or_line,
+        Path::new("moobar"),
+        &mut errors,
+        &config,
+        "",
+        &comments,
+    );
+    match &errors[..] {
+        [Error::PatternNotFound {
+            definition_line: 3, ..
+        }, Error::ErrorsWithoutPattern {
+            path: Some((_, 3)), ..
+        }] => {}
+        _ => panic!("{:#?}", errors),
+    }
+}
+
+#[test]
+fn error_in_other_file() {
+    let s = r"
+//@error-in-other-file: auxiliary/helper.rs: cannot find value
+//@error-in-other-file: foo/src/lib.rs: unused variable
+fn main() {}
+    ";
+    let comments = Comments::parse(s).unwrap();
+    let mut config = config();
+    config
+        .try_stderr_filter(r"/home/[a-z]+/\.cargo/registry/[^/]+/", "$$CARGO_REGISTRY/")
+        .unwrap();
+    let msg = |message: &str, file: &str| Message {
+        file: Some(file.to_string()),
+        ..Message::new(Level::Error, message)
+    };
+    let messages_from_unknown_file_or_line = vec![
+        msg("cannot find value `x` in this scope", "tests/ui/helper.rs"),
+        msg(
+            "cannot find value `y` in this scope",
+            "tests/ui/auxiliary/helper.rs",
+        ),
+        msg(
+            "unused variable: `z`",
+            "/home/user/.cargo/registry/src/foo/src/lib.rs",
+        ),
+    ];
+    let mut errors = vec![];
+    check_annotations(
+        vec![],
+        messages_from_unknown_file_or_line,
+        Path::new("tests/ui/main.rs"),
+        &mut errors,
+        &config,
+        "",
+        &comments,
+    );
+    match &errors[..] {
+        [Error::ErrorsWithoutPattern {
+            path: None, msgs, ..
+        }] => {
+            assert_eq!(msgs.len(), 1);
+            assert_eq!(msgs[0].file.as_deref(), Some("tests/ui/helper.rs"));
+        }
+        _ => panic!("{:#?}", errors),
+    }
+}
+
+#[test]
+fn error_code_mismatch() {
+    let s = r"
+fn main() {
+    let _x: String = 42; //~ ERROR[E0277]
+    let _y: String = 42; //~ ERROR[E0308]: mismatched types
+}
+    ";
+    let comments = Comments::parse(s).unwrap();
+    let config = config();
+    let mismatched_types = || Message {
+        code: Some("E0308".to_string()),
+        ..Message::new(Level::Error, "mismatched types")
+    };
+    let messages = vec![
+        vec![],
+        vec![],
+        vec![],
+        vec![mismatched_types()],
+        vec![mismatched_types()],
+    ];
+    let mut errors = vec![];
+    check_annotations(
+        messages,
+        vec![],
+        Path::new("moobar"),
+        &mut errors,
+        &config,
+        "",
+        &comments,
+    );
+    match &errors[..] {
+        [Error::CodeMismatch {
+            expected,
+            actual: Some(actual),
+            definition_line: 3,
+        }, Error::ErrorsWithoutPattern {
+            path: Some((_, 3)), ..
+        }] if expected == "E0277" && actual == "E0308" => {}
+        _ => panic!("{:#?}", errors),
+    }
+}
+
+#[test]
+fn lint_name_pattern() {
+    let s = r"
+fn main() {
+    return; //~ WARN clippy::needless_return
+    return; //~ WARN unused_mut
+}
+    ";
+    let comments = Comments::parse(s).unwrap();
+    let config = config();
+    let needless_return = || Message {
+        code: Some("clippy::needless_return".to_string()),
+        ..Message::new(Level::Warn, "unneeded `return` statement")
+    };
+    let messages = vec![
+        vec![],
+        vec![],
+        vec![],
+        vec![needless_return()],
+        vec![needless_return()],
+    ];
+    let mut errors = vec![];
+    check_annotations(
+        messages,
+        vec![],
+        Path::new("moobar"),
+        &mut errors,
+        &config,
+        "",
+        &comments,
+    );
+    match &errors[..] {
+        [Error::PatternNotFound {
+            pattern: Pattern::Code(code),
+            definition_line: 4,
+            ..
+        }, Error::ErrorsWithoutPattern {
+            path: Some((_, 4)), ..
+        }] if code == "unused_mut" => {}
+        _ => panic!("{:#?}", errors),
+    }
+}
+
+#[test]
+fn pattern_count() {
+    let s = r"
+fn main() {
+    foo!(); //~ ERROR*2: mismatched types
+    foo!(); //~ ERROR*2: mismatched types
+}
+    ";
+    let comments = Comments::parse(s).unwrap();
+    let config = config();
+    let mismatched_types = || Message {
+        code: Some("E0308".to_string()),
+        ..Message::new(Level::Error, "mismatched types")
+    };
+    let messages = vec![
+        vec![],
+        vec![],
+        vec![],
+        vec![mismatched_types(), mismatched_types()],
+        vec![mismatched_types(), mismatched_types(), mismatched_types()],
+    ];
+    let mut errors = vec![];
+    check_annotations(
+        messages,
+        vec![],
+        Path::new("moobar"),
+        &mut errors,
+        &config,
+        "",
+        &comments,
+    );
+    match &errors[..] {
+        [Error::WrongMatchCount {
+            expected: 2,
+            found: 3,
+            definition_line: 4,
+        }] => {}
+        _ => panic!("{:#?}", errors),
+    }
+}
+
+#[test]
+fn negated_pattern() {
+    let s = r"
+fn main() {
+    let x = 1; //~ WARN: unused variable
+    //~^ !WARN: unused variable
+    let _y = 2; //~ !WARN: unused variable
+}
+    ";
+    let comments = Comments::parse(s).unwrap();
+    let config = config();
+    let unused_variable = || Message {
+        code: Some("unused_variables".to_string()),
+        ..Message::new(Level::Warn, "unused variable: `x`")
+    };
+    let messages = vec![vec![], vec![], vec![], vec![unused_variable()]];
+    let mut errors = vec![];
+    check_annotations(
+        messages,
+        vec![],
+        Path::new("moobar"),
+        &mut errors,
+        &config,
+        "",
+        &comments,
+    );
+    // The negated pattern doesn't consume the diagnostic, so the annotation on line 3 still matches.
+    match &errors[..] {
+        [Error::NegatedPatternFound {
+            definition_line: 4, ..
+        }] => {}
+        _ => panic!("{:#?}", errors),
+    }
+}
+
+#[test]
+fn negated_pattern_is_no_fail_pattern() {
+    let s = r"
+fn main() {
+    let _y = 2; //~ !WARN: unused variable
+}
+    ";
+    let comments = Comments::parse(s).unwrap();
+    let config = config();
+    let mut errors = vec![];
+    check_annotations(
+        vec![],
+        vec![],
+        Path::new("moobar"),
+        &mut errors,
+        &config,
+        "",
+        &comments,
+    );
+    match &errors[..] {
+        [Error::NoPatternsFound] => {}
+        _ => panic!("{:#?}", errors),
+    }
+}
+
+#[test]
+fn column_anchors() {
+    let s = r"
+fn main() {
+    let _x: String = 1 + 2; //~ ERROR: mismatched types
+    //~^ ERROR@22: mismatched types
+    let _y: String = 1 + 2; //~ ERROR@5: mismatched types
+}
+    ";
+    let comments = Comments::parse(s).unwrap();
+    let config = config();
+    let mismatched_types = |column| Message {
+        code: Some("E0308".to_string()),
+        column: Some(column),
+        ..Message::new(Level::Error, "mismatched types")
+    };
+    let messages = vec![
+        vec![],
+        vec![],
+        vec![],
+        // The annotation with a column must get the second diagnostic,
+        // even though the one without a column comes first.
+        vec![mismatched_types(22), mismatched_types(13)],
+        vec![],
+        vec![mismatched_types(22)],
+    ];
+    let mut errors = vec![];
+    check_annotations(
+        messages,
+        vec![],
+        Path::new("moobar"),
+        &mut errors,
+        &config,
+        "",
+        &comments,
+    );
+    match &errors[..] {
+        [Error::ColumnNotFound {
+            column: 5,
+            columns,
+            definition_line: 5,
+        }, Error::ErrorsWithoutPattern {
+            path: Some((_, 5)), ..
+        }] if columns == &[22] => {}
+        _ => panic!("{:#?}", errors),
+    }
+}
+
 #[test]
 fn missing_pattern() {
     let s = r"
@@ -201,14 +643,8 @@ fn main() {
     let messages = vec![
         vec![], vec![], vec![], vec![], vec![],
         vec![
-            Message {
-                message: "Undefined Behavior: type validation failed: encountered a dangling reference (address 0x10 is unallocated)".to_string(),
-                level: Level::Error,
-            },
-            Message {
-                message: "Undefined Behavior: type validation failed: encountered a dangling reference (address 0x10 is unallocated)".to_string(),
-                level: Level::Error,
-            }
+            Message::new(Level::Error, "Undefined Behavior: type validation failed: encountered a dangling reference (address 0x10 is unallocated)"),
+            Message::new(Level::Error, "Undefined Behavior: type validation failed: encountered a dangling reference (address 0x10 is unallocated)")
         ]
     ];
     let mut errors = vec![];
@@ -248,18 +684,9 @@ fn main() {
         vec![],
         vec![],
         vec![
-            Message {
-                message: "Undefined Behavior: type validation failed: encountered a dangling reference (address 0x10 is unallocated)".to_string(),
-                level: Level::Error,
-            },
-            Message {
-                message: "kaboom".to_string(),
-                level: Level::Warn,
-            },
-            Message {
-                message: "cake".to_string(),
-                level: Level::Warn,
-            },
+            Message::new(Level::Error, "Undefined Behavior: type validation failed: encountered a dangling reference (address 0x10 is unallocated)"),
+            Message::new(Level::Warn, "kaboom"),
+            Message::new(Level::Warn, "cake"),
         ],
     ];
     let mut errors = vec![];
@@ -281,6 +708,7 @@ fn main() {
             [Message {
                 message,
                 level: Level::Warn,
+                ..
             }] if message == "kaboom" => {}
             _ => panic!("{:#?}", msgs),
         },
@@ -307,18 +735,9 @@ fn main() {
         vec![],
         vec![],
         vec![
-            Message {
-                message: "Undefined Behavior: type validation failed: encountered a dangling reference (address 0x10 is unallocated)".to_string(),
-                level: Level::Error,
-            },
-            Message {
-                message: "kaboom".to_string(),
-                level: Level::Warn,
-            },
-            Message {
-                message: "cake".to_string(),
-                level: Level::Warn,
-            },
+            Message::new(Level::Error, "Undefined Behavior: type validation failed: encountered a dangling reference (address 0x10 is unallocated)"),
+            Message::new(Level::Warn, "kaboom"),
+            Message::new(Level::Warn, "cake"),
         ],
     ];
     let mut errors = vec![];
@@ -336,3 +755,3041 @@ fn main() {
         _ => panic!("{:#?}", errors),
     }
 }
+
+#[test]
+fn config_require_annotations_for_level() {
+    let warning = || Message::new(Level::Warn, "unused variable: `x`");
+    let error = || Message::new(Level::Error, "mismatched types");
+    let mut config = config();
+    config.require_annotations_for_level = Some(Level::Warn);
+    let check = |s: &str| {
+        let comments = Comments::parse(s).unwrap();
+        let messages = vec![vec![], vec![], vec![], vec![warning(), error()]];
+        let mut errors = vec![];
+        check_annotations(
+            messages,
+            vec![],
+            Path::new("moobar"),
+            &mut errors,
+            &config,
+            "",
+            &comments,
+        );
+        errors
+    };
+    // Only errors are annotated, but warnings need annotations, too.
+    let s = r"
+fn main() {
+    let x: u32 = 1i32; //~ ERROR: mismatched types
+}
+    ";
+    match &check(s)[..] {
+        [Error::ErrorsWithoutPattern { msgs, .. }] => assert_eq!(msgs[0].level, Level::Warn),
+        errors => panic!("{:#?}", errors),
+    }
+    // The test's own command wins.
+    let s = r"//@require-annotations-for-level: ERROR
+fn main() {
+    let x: u32 = 1i32; //~ ERROR: mismatched types
+}
+    ";
+    assert!(check(s).is_empty(), "{:#?}", check(s));
+}
+
+#[test]
+fn disable_named_filter() {
+    let mut config = config();
+    config
+        .try_named_stderr_filter("timings", "[0-9]+ms", "$$TIME")
+        .unwrap();
+    let stderr = b"finished in 42ms";
+
+    let comments = Comments::parse("").unwrap();
+    let mut errors = vec![];
+    let filters = stderr_filters(&config, &comments, "", &mut errors);
+    assert!(errors.is_empty(), "{errors:#?}");
+    let output = normalize(
+        Path::new("a/b.rs"),
+        stderr,
+        &filters,
+        |r| &r.normalize_stderr,
+        &comments,
+        "",
+    )
+    .text;
+    assert_eq!(output, b"finished in $TIME");
+
+    let comments = Comments::parse("//@disable-filter: timings").unwrap();
+    let filters = stderr_filters(&config, &comments, "", &mut errors);
+    assert!(errors.is_empty(), "{errors:#?}");
+    let output = normalize(
+        Path::new("a/b.rs"),
+        stderr,
+        &filters,
+        |r| &r.normalize_stderr,
+        &comments,
+        "",
+    )
+    .text;
+    assert_eq!(output, b"finished in 42ms");
+}
+
+#[test]
+fn disable_unknown_filter() {
+    let mut config = config();
+    config
+        .try_named_stderr_filter("timings", "[0-9]+ms", "$$TIME")
+        .unwrap();
+    let comments = Comments::parse("\n//@disable-filter: timing").unwrap();
+    let mut errors = vec![];
+    stderr_filters(&config, &comments, "", &mut errors);
+    match &errors[..] {
+        [Error::InvalidComment { msg, line: 2 }] => assert_eq!(
+            msg,
+            "`timing` is not a named filter, the registered filters are: `timings`"
+        ),
+        _ => panic!("{errors:#?}"),
+    }
+
+    // Every registered name is listed once, even if several filters share it.
+    config
+        .try_named_stderr_filter("addresses", "0x[0-9a-f]+", "$$HEX")
+        .unwrap();
+    config
+        .try_named_stderr_filter("timings", "[0-9]+s", "$$TIME")
+        .unwrap();
+    let mut errors = vec![];
+    stderr_filters(&config, &comments, "", &mut errors);
+    match &errors[..] {
+        [Error::InvalidComment { msg, line: 2 }] => assert_eq!(
+            msg,
+            "`timing` is not a named filter, the registered filters are: `addresses`, `timings`"
+        ),
+        _ => panic!("{errors:#?}"),
+    }
+}
+
+#[test]
+fn active_named_filters() {
+    let mut config = config();
+    config
+        .try_named_stderr_filter("timings", "[0-9]+ms", "$$TIME")
+        .unwrap();
+    config
+        .try_named_stderr_filter("addresses", "0x[0-9a-f]+", "$$HEX")
+        .unwrap();
+    config
+        .try_named_stderr_filter("timings", "[0-9]+s", "$$TIME")
+        .unwrap();
+    let comments = Comments::parse("//@revisions: a b\n//@[a] disable-filter: timings\n").unwrap();
+    // The replacements of the named filters that are applied after the global ones.
+    let active = |revision| {
+        let mut errors = vec![];
+        let filters = stderr_filters(&config, &comments, revision, &mut errors);
+        assert!(errors.is_empty(), "{errors:#?}");
+        filters[1 + config.stderr_filters.len()..]
+            .iter()
+            .map(|(_, replacement)| String::from_utf8(replacement.to_vec()).unwrap())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(active("a"), ["$$HEX"]);
+    assert_eq!(active("b"), ["$$TIME", "$$HEX", "$$TIME"]);
+}
+
+#[test]
+fn normalize_stdout_separately() {
+    let s = r#"
+//@normalize-stderr-test: "foo" -> "bar"
+//@normalize-stdout-test: "foo" -> "baz"
+"#;
+    let comments = Comments::parse(s).unwrap();
+    let path = Path::new("a/b.rs");
+    let stderr = normalize(
+        path,
+        b"foo",
+        &vec![],
+        |r| &r.normalize_stderr,
+        &comments,
+        "",
+    )
+    .text;
+    assert_eq!(stderr, b"bar");
+    let stdout = normalize(
+        path,
+        b"foo",
+        &vec![],
+        |r| &r.normalize_stdout,
+        &comments,
+        "",
+    )
+    .text;
+    assert_eq!(stdout, b"baz");
+}
+
+#[cfg(unix)]
+#[test]
+fn normalize_stdout_needs_check_stdout() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("foo.rs");
+    std::fs::write(path.with_extension("stdout"), "report: baz\n").unwrap();
+    let mut config = Config {
+        out_dir: dir.path().join("out"),
+        mode: Mode::Pass,
+        output_conflict_handling: OutputConflictHandling::Error("bless".into()),
+        ..config()
+    };
+    config.program = CommandBuilder::cmd("sh");
+    config.program.args = vec!["-c".into(), "echo report: foo".into()];
+    let run = |source: &str| {
+        std::fs::write(&path, source).unwrap();
+        let comments = parse_comments_in_file(&path, &config).unwrap();
+        let runs = test_file(&path, &config, comments, |_| true, None, None);
+        match runs.into_iter().next().unwrap().result {
+            TestResult::Ok => vec![],
+            TestResult::Errored { errors, .. } => errors,
+            result => panic!("{result:#?}"),
+        }
+    };
+    let errors = run("//@check-stdout\n//@normalize-stdout-test: \"foo\" -> \"baz\"\n");
+    assert!(errors.is_empty(), "{errors:#?}");
+    match &run("//@normalize-stdout-test: \"foo\" -> \"baz\"\n")[..] {
+        [Error::InvalidComment { msg, line: 1 }] => assert_eq!(
+            msg,
+            "`normalize-stdout-test` needs `check-stdout`, unless the test is run"
+        ),
+        errors => panic!("{errors:#?}"),
+    }
+    // The stderr normalizations don't apply to stdout.
+    match &run("//@normalize-stderr-test: \"foo\" -> \"baz\"\n")[..] {
+        [Error::OutputDiffers { path: p, .. }] => assert_eq!(p, &path.with_extension("stdout")),
+        errors => panic!("{errors:#?}"),
+    }
+}
+
+/// Probes the program's `--print cfg` for the host, which is used without a `--target` flag,
+/// and for a fixed target. Only a few cfgs are checked, as the rest change between toolchains.
+#[test]
+fn probe_print_cfg() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config {
+        out_dir: dir.path().join("out"),
+        output_conflict_handling: OutputConflictHandling::Bless,
+        ..Config::rustc(dir.path().into())
+    };
+    config.fill_host_and_target().unwrap();
+    let probe = |name: &str, source: &str| {
+        let path = dir.path().join(name);
+        std::fs::write(&path, source).unwrap();
+        let comments = parse_comments_in_file(&path, &config).unwrap();
+        let runs = test_file(&path, &config, comments, |_| true, None, None);
+        match &runs[0].result {
+            TestResult::Ok => {}
+            result => panic!("{result:#?}"),
+        }
+        std::fs::read_to_string(path.with_extension("stdout")).unwrap()
+    };
+
+    let host = probe("host.rs", "//@probe: --print cfg\n");
+    for cfg in [
+        format!("target_arch=\"{}\"", std::env::consts::ARCH),
+        format!("target_os=\"{}\"", std::env::consts::OS),
+    ] {
+        assert!(host.lines().any(|line| line == cfg), "{cfg} in {host}");
+    }
+
+    let linux = probe(
+        "linux.rs",
+        "//@probe: --print cfg\n//@compile-flags: --target x86_64-unknown-linux-gnu\n",
+    );
+    for cfg in [
+        "target_arch=\"x86_64\"",
+        "target_os=\"linux\"",
+        "target_env=\"gnu\"",
+        "target_pointer_width=\"64\"",
+        "unix",
+    ] {
+        assert!(linux.lines().any(|line| line == cfg), "{cfg} in {linux}");
+    }
+}
+
+#[test]
+fn normalize_backslashes_under_root() {
+    let config = Config {
+        root_dir: PathBuf::from("tests/ui"),
+        stderr_filters: vec![],
+        ..config()
+    };
+    let comments = Comments::parse("").unwrap();
+    let mut errors = vec![];
+    let filters = stderr_filters(&config, &comments, "", &mut errors);
+    assert!(errors.is_empty(), "{errors:#?}");
+    let stderr =
+        b"  --> tests\\ui\\foo.rs:1:1\nnote: see tests\\ui\\auxiliary\\bar\nnot\\a\\test\\path\n";
+    let output = normalize(
+        Path::new("src/main.rs"),
+        stderr,
+        &filters,
+        |r| &r.normalize_stderr,
+        &comments,
+        "",
+    )
+    .text;
+    assert_eq!(
+        output,
+        b"  --> tests/ui/foo.rs:1:1\nnote: see tests/ui/auxiliary/bar\nnot\\a\\test\\path\n"
+    );
+}
+
+#[test]
+fn path_filter_variants() {
+    let filters: Vec<_> = Match::path_variants(Path::new(r"\\?\C:\crate\tests"))
+        .into_iter()
+        .map(|variant| (variant, b"$DIR" as &[u8]))
+        .collect();
+    let comments = Comments::parse("").unwrap();
+    let stderr = b"\\\\?\\C:\\crate\\tests\\a.rs\nC:\\crate\\tests\\b.rs\nC:/crate/tests/c.rs\n";
+    let output = normalize(
+        Path::new("src/main.rs"),
+        stderr,
+        &filters,
+        |r| &r.normalize_stderr,
+        &comments,
+        "",
+    )
+    .text;
+    assert_eq!(output, b"$DIR\\a.rs\n$DIR\\b.rs\n$DIR/c.rs\n");
+}
+
+#[test]
+fn deprecated_comment_warning() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(
+        file.path(),
+        "//@error-pattern: foo\n//@ignore-test: not compiled\n",
+    )
+    .unwrap();
+
+    let runs = parse_and_test_file(file.path(), &config());
+    match &runs[..] {
+        [TestRun {
+            result: TestResult::Ignored { .. },
+            warnings,
+            ..
+        }] => match &warnings[..] {
+            [Error::DeprecatedComment { line: 1, .. }] => {}
+            _ => panic!("{warnings:#?}"),
+        },
+        _ => panic!("expected a single ignored test with a warning"),
+    }
+}
+
+#[test]
+fn deny_deprecated_comment() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(
+        file.path(),
+        "//@error-pattern: foo\n//@ignore-test: not compiled\n",
+    )
+    .unwrap();
+
+    let config = Config {
+        deny_deprecated: true,
+        ..config()
+    };
+    let runs = parse_and_test_file(file.path(), &config);
+    match &runs[..] {
+        [TestRun {
+            result: TestResult::Errored { errors, .. },
+            ..
+        }] => match &errors[..] {
+            [Error::DeprecatedComment { line: 1, .. }] => {}
+            _ => panic!("{errors:#?}"),
+        },
+        _ => panic!("expected a single failed test"),
+    }
+}
+
+#[test]
+fn legacy_annotation_order() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(
+        file.path(),
+        "//@revisions: a\n//@ignore-test: not compiled\nfn main() {} //[a]~ ERROR: foo\n",
+    )
+    .unwrap();
+
+    let runs = parse_and_test_file(file.path(), &config());
+    match &runs[..] {
+        [TestRun { warnings, .. }] => match &warnings[..] {
+            [Error::DeprecatedComment { line: 3, .. }] => {}
+            _ => panic!("{warnings:#?}"),
+        },
+        _ => panic!("expected a single ignored test"),
+    }
+
+    let config = Config {
+        allow_legacy_directives: true,
+        ..config()
+    };
+    let runs = parse_and_test_file(file.path(), &config);
+    match &runs[..] {
+        [TestRun { warnings, .. }] => assert!(warnings.is_empty(), "{warnings:#?}"),
+        _ => panic!("expected a single ignored test"),
+    }
+
+    // Blessing annotations migrates them to the canonical order.
+    std::fs::write(
+        file.path(),
+        "//@revisions: a\n//@ignore-test: not compiled\nfn main() { \"//[a]~\"; } //[a]~ ERROR: foo\n",
+    )
+    .unwrap();
+    let config = Config {
+        allow_legacy_directives: false,
+        bless_annotations: true,
+        output_conflict_handling: OutputConflictHandling::Bless,
+        ..config
+    };
+    let runs = parse_and_test_file(file.path(), &config);
+    match &runs[..] {
+        [TestRun { warnings, .. }] => match &warnings[..] {
+            [Error::AnnotationRewritten {
+                definition_line: 3,
+                old,
+                new,
+            }] => assert_eq!((&**old, &**new), ("//[a]~", "//~[a]")),
+            _ => panic!("{warnings:#?}"),
+        },
+        _ => panic!("expected a single ignored test"),
+    }
+    assert_eq!(
+        std::fs::read_to_string(file.path()).unwrap(),
+        "//@revisions: a\n//@ignore-test: not compiled\nfn main() { \"//[a]~\"; } //~[a] ERROR: foo\n"
+    );
+    let runs = parse_and_test_file(file.path(), &config);
+    match &runs[..] {
+        [TestRun { warnings, .. }] => assert!(warnings.is_empty(), "{warnings:#?}"),
+        _ => panic!("expected a single ignored test"),
+    }
+}
+
+#[test]
+fn line_endings() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("foo.rs");
+    let stderr = dir.path().join("foo.stderr");
+    let comments = Comments::parse("").unwrap();
+    let mut config = config();
+    config.target = Some("x86_64-unknown-linux-gnu".into());
+    let check = |config: &Config, expected: &str, actual: &str| {
+        std::fs::write(&stderr, expected).unwrap();
+        let mut errors = vec![];
+        let mut artifacts = Artifacts::new(config);
+        check_test_output(
+            &path,
+            &mut errors,
+            "",
+            config,
+            &comments,
+            b"",
+            Some(actual.as_bytes()),
+            &mut artifacts,
+            &mut Usage::default(),
+        );
+        errors.is_empty()
+    };
+    for expected in ["error\nfoo\n", "error\r\nfoo\r\n", "error\nfoo"] {
+        for actual in ["error\nfoo\n", "error\r\nfoo\r\n", "error\nfoo"] {
+            assert!(check(&config, expected, actual), "{expected:?} {actual:?}");
+        }
+    }
+    assert!(!check(&config, "error\nfoo\n", "error\nbar\n"));
+    assert!(!check(&config, "error\nfoo\n", "error\nfoo\n\n"));
+
+    config.preserve_line_endings = true;
+    assert!(check(&config, "error\r\nfoo\r\n", "error\r\nfoo\r\n"));
+    assert!(!check(&config, "error\r\nfoo\r\n", "error\nfoo\n"));
+    assert!(!check(&config, "error\nfoo\r\n", "error\nfoo\n"));
+    assert!(!check(&config, "error\nfoo", "error\nfoo\n"));
+
+    // Blessing writes `\n` line endings, unless asked to keep them.
+    for (preserve, expected) in [(false, "error\nfoo\n"), (true, "error\r\nfoo\r\n")] {
+        config.preserve_line_endings = preserve;
+        config.output_conflict_handling = OutputConflictHandling::Bless;
+        assert!(check(&config, "", "error\r\nfoo\r\n"));
+        assert_eq!(std::fs::read_to_string(&stderr).unwrap(), expected);
+    }
+}
+
+#[test]
+fn invalid_utf8_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("foo.rs");
+    let stderr = dir.path().join("foo.stderr");
+    let output = b"error: \xff\xfe\n{ \xc3 }\n";
+    let diagnostics = rustc_stderr::process(&path, output, false, true);
+    assert_eq!(diagnostics.rendered, output);
+
+    let comments = Comments::parse("").unwrap();
+    let mut config = config();
+    config.target = Some("x86_64-unknown-linux-gnu".into());
+    let check = |config: &Config| {
+        let mut errors = vec![];
+        let mut artifacts = Artifacts::new(config);
+        check_test_output(
+            &path,
+            &mut errors,
+            "",
+            config,
+            &comments,
+            b"",
+            Some(output),
+            &mut artifacts,
+            &mut Usage::default(),
+        );
+        errors
+    };
+    config.output_conflict_handling = OutputConflictHandling::Bless;
+    assert!(check(&config).is_empty());
+    assert_eq!(std::fs::read(&stderr).unwrap(), output);
+    config.output_conflict_handling = OutputConflictHandling::Error("bless".into());
+    let errors = check(&config);
+    assert!(errors.is_empty(), "{errors:#?}");
+}
+
+#[test]
+fn stale_stderr_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("foo.rs");
+    let stderr = dir.path().join("foo.stderr");
+    std::fs::write(&stderr, "error: old output").unwrap();
+    let comments = Comments::parse("//@dont-check-compiler-stderr").unwrap();
+
+    let mut config = config();
+    config.target = Some("x86_64-unknown-linux-gnu".into());
+    let mut errors = vec![];
+    let mut artifacts = Artifacts::new(&config);
+    check_test_output(
+        &path,
+        &mut errors,
+        "",
+        &config,
+        &comments,
+        b"",
+        None,
+        &mut artifacts,
+        &mut Usage::default(),
+    );
+    match &errors[..] {
+        [Error::StaleOutput { path, .. }] => assert_eq!(path, &stderr),
+        _ => panic!("{errors:#?}"),
+    }
+
+    config.output_conflict_handling = OutputConflictHandling::Bless;
+    let mut errors = vec![];
+    let mut artifacts = Artifacts::new(&config);
+    check_test_output(
+        &path,
+        &mut errors,
+        "",
+        &config,
+        &comments,
+        b"",
+        None,
+        &mut artifacts,
+        &mut Usage::default(),
+    );
+    assert!(errors.is_empty(), "{errors:#?}");
+    assert!(!stderr.exists());
+}
+
+#[test]
+fn empty_stderr_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("foo.rs");
+    let stderr = dir.path().join("foo.stderr");
+    let comments = Comments::parse("").unwrap();
+    let mut config = config();
+    config.target = Some("x86_64-unknown-linux-gnu".into());
+    let check = |config: &Config, stderr: &[u8]| {
+        let mut errors = vec![];
+        let mut artifacts = Artifacts::new(config);
+        check_test_output(
+            &path,
+            &mut errors,
+            "",
+            config,
+            &comments,
+            b"",
+            Some(stderr),
+            &mut artifacts,
+            &mut Usage::default(),
+        );
+        errors
+    };
+
+    // An empty file is the same as a missing one.
+    std::fs::write(&stderr, "").unwrap();
+    let errors = check(&config, b"");
+    assert!(errors.is_empty(), "{errors:#?}");
+    match &check(&config, b"error: new")[..] {
+        [Error::OutputDiffers {
+            expected, actual, ..
+        }] => assert_eq!((&expected[..], &actual[..]), (&b""[..], &b"error: new"[..])),
+        errors => panic!("{errors:#?}"),
+    }
+    let stale = Comments::parse("//@dont-check-compiler-stderr").unwrap();
+    let mut errors = vec![];
+    check_stale_output(&path, &mut errors, "stderr".into(), &config, &stale, "");
+    assert!(errors.is_empty(), "{errors:#?}");
+
+    // A non-empty file is a conflict if there is no output.
+    std::fs::write(&stderr, "error: old").unwrap();
+    match &check(&config, b"")[..] {
+        [Error::OutputDiffers { actual, .. }] => assert!(actual.is_empty()),
+        errors => panic!("{errors:#?}"),
+    }
+
+    // Blessing removes the file, even if it is already empty.
+    config.output_conflict_handling = OutputConflictHandling::Bless;
+    std::fs::write(&stderr, "").unwrap();
+    let errors = check(&config, b"");
+    assert!(errors.is_empty(), "{errors:#?}");
+    assert!(!stderr.exists());
+}
+
+#[test]
+fn bless_into_output_base() {
+    let root = tempfile::tempdir().unwrap();
+    let output_base = tempfile::tempdir().unwrap();
+    let path = root.path().join("sub/foo.rs");
+
+    let mut config = config();
+    config.root_dir = root.path().into();
+    config.output_base = Some(output_base.path().into());
+    let mut errors = vec![];
+    let written = bless_output(
+        &path.with_extension("stderr"),
+        b"error",
+        &config,
+        &mut errors,
+    );
+    assert!(errors.is_empty(), "{errors:#?}");
+    assert_eq!(written, output_base.path().join("sub/foo.stderr"));
+    assert_eq!(std::fs::read(written).unwrap(), b"error");
+    assert!(!path.with_extension("stderr").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn bless_read_only() {
+    use std::os::unix::fs::PermissionsExt;
+    let root = tempfile::tempdir().unwrap();
+    std::fs::set_permissions(root.path(), std::fs::Permissions::from_mode(0o555)).unwrap();
+    if std::fs::write(root.path().join("probe"), "").is_ok() {
+        // Running as root, which can write anyway.
+        return;
+    }
+
+    let mut errors = vec![];
+    let path = root.path().join("foo.stderr");
+    bless_output(&path, b"error", &config(), &mut errors);
+    match &errors[..] {
+        [Error::ReadOnlyOutput { path: p }] => assert_eq!(p, &path),
+        _ => panic!("{errors:#?}"),
+    }
+}
+
+/// Running a whole suite whose test files are in a read-only directory reports each output
+/// file that could not be blessed.
+#[cfg(unix)]
+#[test]
+fn bless_read_only_suite() {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = tempfile::tempdir().unwrap();
+    let tests = dir.path().join("tests");
+    std::fs::create_dir(&tests).unwrap();
+    std::fs::write(tests.join("a.rs"), "").unwrap();
+    std::fs::write(tests.join("b.rs"), "").unwrap();
+    let permissions =
+        |mode| std::fs::set_permissions(&tests, std::fs::Permissions::from_mode(mode));
+    permissions(0o555).unwrap();
+    if std::fs::write(tests.join("probe"), "").is_ok() {
+        // Running as root, which can write anyway.
+        return;
+    }
+
+    let mut config = Config {
+        output_conflict_handling: OutputConflictHandling::Bless,
+        ..noop_suite(dir.path())
+    };
+    config.program = CommandBuilder::cmd("sh");
+    config.program.args = vec!["-c".into(), "echo error >&2".into()];
+    let result = run_tests_generic(
+        config,
+        Args::default(),
+        default_file_filter,
+        default_per_file_config,
+        Recorder("", Log::default()),
+    )
+    .unwrap();
+    permissions(0o755).unwrap();
+    let mut failures: Vec<_> = result
+        .failures()
+        .map(|run| match &run.result {
+            TestResult::Errored { errors, .. } => match &errors[..] {
+                [Error::ReadOnlyOutput { path }] => path.clone(),
+                _ => panic!("{errors:#?}"),
+            },
+            result => panic!("{result:#?}"),
+        })
+        .collect();
+    failures.sort();
+    assert_eq!(failures, [tests.join("a.stderr"), tests.join("b.stderr")]);
+}
+
+#[cfg(unix)]
+#[test]
+fn fingerprints() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("foo.rs");
+    std::fs::write(&path, "fn main() {}\n").unwrap();
+    let config = Config {
+        fingerprint_dir: Some(dir.path().join("fingerprints")),
+        ..noop_suite(dir.path())
+    };
+    let run = |config: &Config, force_rerun| {
+        let comments = parse_comments_in_file(&path, config).unwrap();
+        let fingerprints = Fingerprints::new(config, force_rerun);
+        let runs = test_file(
+            &path,
+            config,
+            comments,
+            |_| true,
+            fingerprints.as_ref(),
+            None,
+        );
+        match &runs[..] {
+            [TestRun { result, .. }] => match result {
+                TestResult::Ok => "ok",
+                TestResult::Cached => "cached",
+                TestResult::Errored { .. } => "failed",
+                _ => panic!("{result:#?}"),
+            },
+            _ => panic!("{runs:#?}"),
+        }
+    };
+    assert_eq!(run(&config, false), "ok");
+    assert_eq!(run(&config, false), "cached");
+    assert_eq!(run(&config, true), "ok");
+    assert_eq!(run(&config, false), "cached");
+
+    // Changing the output files, the test or the flags invalidates the fingerprint.
+    std::fs::write(path.with_extension("stderr"), "error").unwrap();
+    assert_eq!(run(&config, false), "failed");
+    std::fs::remove_file(path.with_extension("stderr")).unwrap();
+    assert_eq!(run(&config, false), "ok");
+    std::fs::write(&path, "fn main() { }\n").unwrap();
+    assert_eq!(run(&config, false), "ok");
+    let mut flagged = config.clone();
+    flagged.program.args.push("-O".into());
+    assert_eq!(run(&flagged, false), "ok");
+    assert_eq!(run(&flagged, false), "cached");
+    assert_eq!(run(&config, false), "ok");
+
+    // Blessing runs all tests.
+    let bless = Config {
+        output_conflict_handling: OutputConflictHandling::Bless,
+        ..config.clone()
+    };
+    assert_eq!(run(&bless, false), "ok");
+    assert_eq!(run(&config, false), "cached");
+}
+
+/// Aux builds are reused from the cache until a file they load changes.
+#[test]
+fn aux_cache_submodule() {
+    let dir = tempfile::tempdir().unwrap();
+    let aux_dir = dir.path().join("auxiliary");
+    std::fs::create_dir(&aux_dir).unwrap();
+    std::fs::write(aux_dir.join("dep.rs"), "pub mod sub;\n").unwrap();
+    std::fs::write(aux_dir.join("sub.rs"), "pub fn f() {}\n").unwrap();
+    let path = dir.path().join("foo.rs");
+    std::fs::write(&path, "//@aux-build: dep.rs\nfn main() { dep::sub::f() }\n").unwrap();
+    let mut config = Config {
+        out_dir: dir.path().join("out"),
+        cache_dir: Some(dir.path().join("cache")),
+        mode: Mode::Check,
+        ..Config::rustc(dir.path().into())
+    };
+    config.fill_host_and_target().unwrap();
+    let run = || {
+        let comments = parse_comments_in_file(&path, &config).unwrap();
+        let runs = test_file(&path, &config, comments, |_| true, None, None);
+        match &runs[0].result {
+            TestResult::Ok => "ok",
+            TestResult::Errored { .. } => "failed",
+            result => panic!("{result:#?}"),
+        }
+    };
+    assert_eq!(run(), "ok");
+    assert_eq!(run(), "ok");
+    std::fs::write(aux_dir.join("sub.rs"), "pub fn renamed() {}\n").unwrap();
+    assert_eq!(run(), "failed");
+}
+
+#[test]
+fn sort_diagnostics() {
+    let diagnostic = |line: usize, code: &str| {
+        format!(
+            r#"{{"rendered":"error[{code}]: line {line}\n","spans":[{{"line_start":{line},"column_start":5,"file_name":"a/b.rs","is_primary":true,"expansion":null}}],"level":"error","message":"line {line}","children":[],"code":{{"code":"{code}"}}}}"#
+        )
+    };
+    let summary = r#"{"rendered":"error: aborting due to 2 previous errors\n","spans":[],"level":"error","message":"aborting due to 2 previous errors","children":[],"code":null}"#;
+    let first = diagnostic(3, "E0308");
+    let second = diagnostic(7, "E0425");
+    let in_order = format!("{first}\n{second}\n{summary}\n");
+    let reversed = format!("{second}\n{first}\n{summary}\n");
+
+    let path = Path::new("a/b.rs");
+    let sorted = rustc_stderr::process(path, in_order.as_bytes(), true, true).rendered;
+    assert_eq!(
+        sorted,
+        rustc_stderr::process(path, reversed.as_bytes(), true, true).rendered
+    );
+    assert_eq!(
+        sorted.to_str().unwrap(),
+        "error[E0308]: line 3\nerror[E0425]: line 7\nerror: aborting due to 2 previous errors\n"
+    );
+    // Without sorting, the compiler's order is kept.
+    assert_eq!(
+        rustc_stderr::process(path, reversed.as_bytes(), false, true)
+            .rendered
+            .to_str()
+            .unwrap(),
+        "error[E0425]: line 7\nerror[E0308]: line 3\nerror: aborting due to 2 previous errors\n"
+    );
+}
+
+#[test]
+fn suggestion_annotations() {
+    let s = r"
+fn main() {
+    let _x = (1); //~ ERROR: unnecessary parentheses
+    //~^ SUGGESTION: 1
+    //~| SUGGESTION: /^$/
+}
+    ";
+    let comments = Comments::parse(s).unwrap();
+    let diagnostic = r#"{"rendered":"error: unnecessary parentheses\n","spans":[{"line_start":3,"column_start":14,"file_name":"a/b.rs","is_primary":true,"expansion":null}],"level":"error","message":"unnecessary parentheses around assigned value","code":{"code":"unused_parens"},"children":[{"rendered":null,"spans":[{"line_start":3,"column_start":14,"file_name":"a/b.rs","is_primary":true,"expansion":null,"suggested_replacement":""},{"line_start":3,"column_start":16,"file_name":"a/b.rs","is_primary":true,"expansion":null,"suggested_replacement":""}],"level":"help","message":"remove these parentheses","code":null,"children":[]}]}"#;
+    let diagnostics =
+        rustc_stderr::process(Path::new("a/b.rs"), diagnostic.as_bytes(), false, true);
+    let mut errors = vec![];
+    check_annotations(
+        diagnostics.messages,
+        diagnostics.messages_from_unknown_file_or_line,
+        Path::new("a/b.rs"),
+        &mut errors,
+        &config(),
+        "",
+        &comments,
+    );
+    match &errors[..] {
+        [Error::SuggestionNotFound {
+            pattern: Pattern::SubString(pattern),
+            suggestions,
+            definition_line: 4,
+        }] if pattern == "1" && suggestions == &["", ""] => {}
+        _ => panic!("{:#?}", errors),
+    }
+}
+
+#[test]
+fn macro_expansion_site() {
+    // `x!()` on line 7 expands to code whose error points into the macro definition, in the
+    // test file on line 2, or in another crate.
+    let diagnostic = |file: &str, line: usize| {
+        format!(
+            r#"{{"rendered":"error: mismatched types\n","spans":[{{"line_start":{line},"column_start":24,"file_name":"{file}","is_primary":true,"expansion":{{"span":{{"line_start":7,"column_start":5,"file_name":"a/b.rs","is_primary":false,"expansion":null}}}}}}],"level":"error","message":"mismatched types","code":null,"children":[]}}"#
+        )
+    };
+    let lines = |diagnostics: &Diagnostics| -> Vec<(usize, Option<usize>)> {
+        diagnostics
+            .messages
+            .iter()
+            .enumerate()
+            .flat_map(|(line, msgs)| msgs.iter().map(move |msg| (line, msg.column)))
+            .collect()
+    };
+    let path = Path::new("a/b.rs");
+    let local = diagnostic("a/b.rs", 2);
+    let diagnostics = rustc_stderr::process(path, local.as_bytes(), false, true);
+    assert_eq!(lines(&diagnostics), [(7, Some(5))]);
+    let diagnostics = rustc_stderr::process(path, local.as_bytes(), false, false);
+    assert_eq!(lines(&diagnostics), [(2, Some(24))]);
+
+    let foreign = diagnostic("$CARGO_REGISTRY/foo/src/lib.rs", 10);
+    let diagnostics = rustc_stderr::process(path, foreign.as_bytes(), false, true);
+    assert_eq!(lines(&diagnostics), [(7, Some(5))]);
+    // The rendered output is the compiler's, pointing at the definition.
+    assert_eq!(diagnostics.rendered, b"error: mismatched types\n");
+    let diagnostics = rustc_stderr::process(path, foreign.as_bytes(), false, false);
+    assert_eq!(lines(&diagnostics), []);
+    assert_eq!(diagnostics.messages_from_unknown_file_or_line.len(), 1);
+}
+
+#[test]
+fn multiple_primary_spans() {
+    // The arms of the `match` on lines 4 and 5 have incompatible types.
+    let stderr = r#"{"rendered":"error: `match` arms have incompatible types\n","spans":[{"line_start":4,"column_start":14,"file_name":"a/b.rs","is_primary":true,"expansion":null},{"line_start":5,"column_start":14,"file_name":"a/b.rs","is_primary":true,"expansion":null},{"line_start":3,"column_start":5,"file_name":"a/b.rs","is_primary":false,"expansion":null}],"level":"error","message":"`match` arms have incompatible types","code":{"code":"E0308"},"children":[]}"#;
+    let path = Path::new("a/b.rs");
+    let diagnostics = rustc_stderr::process(path, stderr.as_bytes(), false, true);
+    assert_eq!(diagnostics.messages.len(), 5);
+    assert_eq!(diagnostics.messages[4][0].other_sites, [(5, 14)]);
+
+    let check = |s: &str| {
+        let comments = Comments::parse(s).unwrap();
+        let diagnostics = rustc_stderr::process(path, stderr.as_bytes(), false, true);
+        let mut errors = vec![];
+        check_annotations(
+            diagnostics.messages,
+            vec![],
+            path,
+            &mut errors,
+            &config(),
+            "",
+            &comments,
+        );
+        errors
+    };
+    // Either line can be annotated.
+    let on_first = r"
+fn main() {
+    match 0 {
+        0 => 1, //~ ERROR: incompatible types
+        _ => (),
+    };
+}
+    ";
+    assert!(check(on_first).is_empty(), "{:#?}", check(on_first));
+    let on_second = r"
+fn main() {
+    match 0 {
+        0 => 1,
+        _ => (), //~ ERROR: incompatible types
+    };
+}
+    ";
+    assert!(check(on_second).is_empty(), "{:#?}", check(on_second));
+    // The diagnostic is only consumed by one of the annotations.
+    let on_both = r"
+fn main() {
+    match 0 {
+        0 => 1, //~ ERROR: incompatible types
+        _ => (), //~ ERROR: incompatible types
+    };
+}
+    ";
+    match &check(on_both)[..] {
+        [Error::PatternNotFound {
+            definition_line: 5, ..
+        }] => {}
+        errors => panic!("{:#?}", errors),
+    }
+    // Unmatched, it is reported on the first line, mentioning the other one.
+    let none = r"
+fn main() {
+    match 0 {
+        0 => 1,
+        _ => (),
+    };
+}
+    ";
+    match &check(none)[..] {
+        [Error::ErrorsWithoutPattern {
+            path: Some((_, 4)),
+            msgs,
+            ..
+        }, Error::NoPatternsFound] => assert_eq!(msgs[0].other_lines().collect::<Vec<_>>(), [5]),
+        errors => panic!("{:#?}", errors),
+    }
+}
+
+#[test]
+fn child_diagnostics() {
+    // The error on line 5 has a note pointing at the bound on line 2 and a help without spans.
+    let stderr = r#"{"rendered":"error[E0277]: `T` cannot be sent between threads safely\n","spans":[{"line_start":5,"column_start":10,"file_name":"a/b.rs","is_primary":true,"expansion":null}],"level":"error","message":"`T` cannot be sent between threads safely","code":{"code":"E0277"},"children":[{"rendered":null,"spans":[{"line_start":2,"column_start":12,"file_name":"a/b.rs","is_primary":true,"expansion":null}],"level":"note","message":"required by a bound in `send`","code":null,"children":[]},{"rendered":null,"spans":[],"level":"help","message":"consider restricting type parameter `T`","code":null,"children":[]}]}"#;
+    let path = Path::new("a/b.rs");
+    let diagnostics = rustc_stderr::process(path, stderr.as_bytes(), false, true);
+    let note = &diagnostics.messages[2][0];
+    assert_eq!(note.message, "required by a bound in `send`");
+    assert_eq!(
+        note.parent.as_deref(),
+        Some("`T` cannot be sent between threads safely")
+    );
+    let levels: Vec<_> = diagnostics.messages[5]
+        .iter()
+        .map(|msg| msg.level)
+        .collect();
+    assert_eq!(levels, [Level::Error, Level::Help]);
+
+    let check = |s: &str| {
+        let comments = Comments::parse(s).unwrap();
+        let diagnostics = rustc_stderr::process(path, stderr.as_bytes(), false, true);
+        let mut errors = vec![];
+        check_annotations(
+            diagnostics.messages,
+            vec![],
+            path,
+            &mut errors,
+            &config(),
+            "",
+            &comments,
+        );
+        errors
+    };
+    // The note is matched on its own line, the help on the line of the error.
+    let annotated = r"
+fn send<T: Send>(_: T) {} //~ NOTE: required by a bound
+fn main() {
+    let x = std::rc::Rc::new(());
+    send(x);
+    //~^ ERROR: cannot be sent
+    //~| HELP: consider restricting
+}
+    ";
+    assert!(check(annotated).is_empty(), "{:#?}", check(annotated));
+    // Annotating the note doesn't match the error.
+    let note_only = r"
+fn send<T: Send>(_: T) {} //~ NOTE: required by a bound
+fn main() {
+    let x = std::rc::Rc::new(());
+    send(x);
+    //~^ HELP: consider restricting
+}
+    ";
+    match &check(note_only)[..] {
+        [Error::ErrorsWithoutPattern {
+            path: Some((_, 5)),
+            msgs,
+            ..
+        }] => assert_eq!(msgs[0].level, Level::Error),
+        errors => panic!("{:#?}", errors),
+    }
+    // Annotating the error doesn't match the note, which is reported with its parent.
+    let error_only = r"//@require-annotations-for-level: NOTE
+fn send<T: Send>(_: T) {}
+fn main() {
+    let x = std::rc::Rc::new(());
+    send(x);
+    //~^ ERROR: cannot be sent
+    //~| HELP: consider restricting
+}
+    ";
+    match &check(error_only)[..] {
+        [Error::ErrorsWithoutPattern {
+            path: Some((_, 2)),
+            msgs,
+            ..
+        }] => assert_eq!(
+            msgs[0].parent(),
+            Some("`T` cannot be sent between threads safely")
+        ),
+        errors => panic!("{:#?}", errors),
+    }
+}
+
+#[test]
+fn diagnostics_on_stdout() {
+    let json = |message: &str| {
+        format!(
+            r#"{{"rendered":"error: {message}\n","spans":[{{"line_start":3,"column_start":5,"file_name":"a/b.rs","is_primary":true,"expansion":null}}],"level":"error","message":"{message}","code":null,"children":[]}}"#
+        )
+    };
+    let output = |stdout: String, stderr: String| std::process::Output {
+        status: std::process::ExitStatus::default(),
+        stdout: stdout.into_bytes(),
+        stderr: stderr.into_bytes(),
+    };
+    let path = Path::new("a/b.rs");
+    let messages = |streams: &rustc_stderr::Streams| -> Vec<String> {
+        let diagnostics = streams.process(path, false, true);
+        diagnostics
+            .messages
+            .iter()
+            .flatten()
+            .map(|msg| msg.message.clone())
+            .collect()
+    };
+
+    // The driver renders the diagnostics on stderr itself.
+    let out = output(
+        format!("some output\n{}\n", json("mismatched types")),
+        "error: mismatched types\n".into(),
+    );
+    let mut errors = vec![];
+    let streams = rustc_stderr::split_output(&out, DiagnosticsStream::Auto, &mut errors);
+    assert!(errors.is_empty());
+    assert_eq!(messages(&streams), ["mismatched types"]);
+    assert_eq!(streams.stdout, b"some output\n");
+    assert_eq!(
+        streams.process(path, false, true).rendered,
+        b"error: mismatched types\n"
+    );
+    // Pinned to stderr, stdout is left alone.
+    let streams = rustc_stderr::split_output(&out, DiagnosticsStream::Stderr, &mut errors);
+    assert!(errors.is_empty());
+    assert!(messages(&streams).is_empty());
+    assert_eq!(streams.stdout, out.stdout);
+
+    // The same diagnostics on both streams are fine, different ones are not.
+    let out = output(
+        format!("{}\n", json("mismatched types")),
+        format!("{}\n", json("mismatched types")),
+    );
+    let streams = rustc_stderr::split_output(&out, DiagnosticsStream::Auto, &mut errors);
+    assert!(errors.is_empty());
+    assert_eq!(messages(&streams), ["mismatched types"]);
+    assert!(streams.stdout.is_empty());
+    let out = output(
+        format!("{}\n", json("mismatched types")),
+        format!("{}\n", json("unused variable")),
+    );
+    let streams = rustc_stderr::split_output(&out, DiagnosticsStream::Auto, &mut errors);
+    assert!(matches!(errors[..], [Error::ConflictingDiagnostics]));
+    assert_eq!(messages(&streams), ["unused variable"]);
+
+    // Other JSON on stderr is not a conflict.
+    let out = output(
+        format!("{}\n", json("mismatched types")),
+        "{\"artifact\":\"a/b.rmeta\",\"emit\":\"metadata\"}\n".into(),
+    );
+    let mut errors = vec![];
+    let streams = rustc_stderr::split_output(&out, DiagnosticsStream::Auto, &mut errors);
+    assert!(errors.is_empty(), "{errors:#?}");
+    assert_eq!(messages(&streams), ["mismatched types"]);
+}
+
+/// The dumped JSON diagnostics are the ones that were used, even if they were on stdout.
+#[cfg(unix)]
+#[test]
+fn diagnostics_artifact_from_stdout() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("a.rs");
+    std::fs::write(&path, "").unwrap();
+    let json = r#"{"rendered":"error: oops\n","spans":[],"level":"error","message":"oops","code":null,"children":[]}"#;
+    let mut config = Config {
+        logs_dir: dir.path().join("logs"),
+        ..noop_suite(dir.path())
+    };
+    config.program = CommandBuilder::cmd("sh");
+    config.program.args = vec![
+        "-c".into(),
+        r#"printf '%s\n' "$0"; echo 'error: oops' >&2"#.into(),
+        json.into(),
+    ];
+    let comments = parse_comments_in_file(&path, &config).unwrap();
+    let runs = test_file(&path, &config, comments, |_| true, None, None);
+    let TestResult::Errored {
+        artifacts: Some(artifacts),
+        ..
+    } = &runs[0].result
+    else {
+        panic!("{runs:#?}")
+    };
+    assert_eq!(
+        std::fs::read_to_string(artifacts.join("diagnostics.json")).unwrap(),
+        format!("{json}\n")
+    );
+}
+
+#[test]
+fn invalid_filter_regex() {
+    let mut config = config();
+    let stderr_filters = config.stderr_filters.len();
+    let stdout_filters = config.stdout_filters.len();
+    let err = config.try_stderr_filter("(unclosed", "").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid regex for stderr filter `(unclosed`"
+    );
+    let err = config.try_stdout_filter("[a-", "").unwrap_err();
+    assert_eq!(err.to_string(), "invalid regex for stdout filter `[a-`");
+    let err = config
+        .try_named_stderr_filter("timings", "*ms", "")
+        .unwrap_err();
+    assert_eq!(err.to_string(), "invalid regex for stderr filter `*ms`");
+    assert_eq!(config.stderr_filters.len(), stderr_filters);
+    assert_eq!(config.stdout_filters.len(), stdout_filters);
+    assert!(config.named_stderr_filters.is_empty());
+}
+
+#[test]
+fn invalid_path_filter() {
+    let mut config = config();
+    let err = config
+        .try_path_stderr_filter(Path::new("does/not/exist"), "$DIR")
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid path for stderr filter: does/not/exist"
+    );
+}
+
+#[test]
+fn extern_crate_name() {
+    let mut config = config();
+    config.extern_crate("my_crate", "libmy_crate.rlib").unwrap();
+    assert_eq!(
+        config.program.args[config.program.args.len() - 2..],
+        ["--extern", "my_crate=libmy_crate.rlib"]
+    );
+    for name in ["", "my-crate", "1crate", "my crate"] {
+        let err = config.extern_crate(name, "lib.rlib").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "invalid crate name `{name}`: must be a non-empty identifier, and can't contain `-`"
+            )
+        );
+    }
+}
+
+#[test]
+fn out_dir_created_eagerly() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = config();
+    let out_dir = dir.path().join("a/b");
+    config.out_dir(&out_dir).unwrap();
+    assert!(out_dir.is_dir());
+    assert_eq!(config.out_dir, out_dir);
+
+    let file = dir.path().join("file");
+    std::fs::write(&file, "").unwrap();
+    let err = config.out_dir(file.join("out")).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!("could not create out dir {}", file.join("out").display())
+    );
+    assert_eq!(config.out_dir, out_dir);
+}
+
+#[test]
+fn rustfix_only_machine_applicable() {
+    // A lint suggestion that rustc isn't sure about.
+    let stderr = r#"{"rendered":"warning: unused variable\n","spans":[],"level":"warning","message":"unused variable: `x`","code":null,"children":[{"rendered":null,"spans":[{"file_name":"a/b.rs","byte_start":8,"byte_end":9,"line_start":1,"line_end":1,"column_start":9,"column_end":10,"is_primary":true,"text":[{"text":"fn f() { x }","highlight_start":9,"highlight_end":10}],"label":null,"suggested_replacement":"_x","suggestion_applicability":"MaybeIncorrect","expansion":null}],"level":"help","message":"prefix it with an underscore","code":null,"children":[]}]}"#;
+    let suggestions = |config: &Config, comments: &Comments| {
+        rustfix::get_suggestions_from_json(
+            stderr,
+            &HashSet::new(),
+            rustfix_filter(config, comments, ""),
+        )
+        .unwrap()
+    };
+
+    let mut config = config();
+    let comments = Comments::parse("").unwrap();
+    assert!(suggestions(&config, &comments).is_empty());
+
+    config.rustfix_all_suggestions = true;
+    assert_eq!(suggestions(&config, &comments).len(), 1);
+
+    let comments = Comments::parse("//@rustfix-only-machine-applicable").unwrap();
+    assert!(suggestions(&config, &comments).is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn rustfix_maybe_incorrect_only() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("foo.rs");
+    let source = "//@rustfix-only-machine-applicable\nfn f() { x }\n";
+    std::fs::write(&path, source).unwrap();
+    let stderr = r#"{"rendered":"warning: unused variable\n","spans":[],"level":"warning","message":"unused variable: `x`","code":null,"children":[{"rendered":null,"spans":[{"file_name":"foo.rs","byte_start":44,"byte_end":45,"line_start":2,"line_end":2,"column_start":10,"column_end":11,"is_primary":true,"text":[{"text":"fn f() { x }","highlight_start":10,"highlight_end":11}],"label":null,"suggested_replacement":"_x","suggestion_applicability":"MaybeIncorrect","expansion":null}],"level":"help","message":"prefix it with an underscore","code":null,"children":[]}]}"#;
+    let mut config = Config {
+        out_dir: dir.path().join("out"),
+        mode: Mode::Fix,
+        output_conflict_handling: OutputConflictHandling::Bless,
+        rustfix_all_suggestions: true,
+        ..config()
+    };
+    config.program = CommandBuilder::cmd("sh");
+    config.program.args = vec![
+        "-c".into(),
+        r#"case "$*" in *foo.rs*) printf '%s\n' "$0" >&2;; esac"#.into(),
+        stderr.into(),
+    ];
+    let comments = parse_comments_in_file(&path, &config).unwrap();
+    test_file(&path, &config, comments, |_| true, None, None);
+    assert_eq!(
+        std::fs::read_to_string(path.with_extension("fixed")).unwrap(),
+        source
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn rustfix_fixpoint_timeout() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("foo.rs");
+    std::fs::write(&path, "fn f() { x }\n").unwrap();
+    let stderr = r#"{"rendered":"warning: unused variable\n","spans":[],"level":"warning","message":"unused variable: `x`","code":null,"children":[{"rendered":null,"spans":[{"file_name":"foo.rs","byte_start":9,"byte_end":10,"line_start":1,"line_end":1,"column_start":10,"column_end":11,"is_primary":true,"text":[{"text":"fn f() { x }","highlight_start":10,"highlight_end":11}],"label":null,"suggested_replacement":"_x","suggestion_applicability":"MachineApplicable","expansion":null}],"level":"help","message":"prefix it with an underscore","code":null,"children":[]}]}"#;
+    let mut config = Config {
+        out_dir: dir.path().join("out"),
+        mode: Mode::Fix,
+        output_conflict_handling: OutputConflictHandling::Ignore,
+        default_timeout: Some(Duration::from_millis(100)),
+        ..config()
+    };
+    // Suggests a fix for the test, but hangs when recompiling the fixed code.
+    config.program = CommandBuilder::cmd("sh");
+    config.program.args = vec![
+        "-c".into(),
+        r#"case "$*" in *foo.rs*) printf '%s\n' "$0" >&2;; *rustfix*) sleep 5;; esac"#.into(),
+        stderr.into(),
+    ];
+    let comments = parse_comments_in_file(&path, &config).unwrap();
+    let runs = test_file(&path, &config, comments, |_| true, None, None);
+    match &runs[0].result {
+        TestResult::Errored { errors, .. } => assert!(
+            errors
+                .iter()
+                .any(|error| matches!(error, Error::TimedOut { .. })),
+            "{errors:#?}"
+        ),
+        result => panic!("{result:#?}"),
+    }
+}
+
+/// The aux builds, the compilation of the `.fixed` file and probes are killed after the
+/// timeout, just like the compilation of the test.
+#[cfg(unix)]
+#[test]
+fn timeouts_of_other_commands() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("auxiliary")).unwrap();
+    std::fs::write(dir.path().join("auxiliary/dep.rs"), "").unwrap();
+    let stderr = r#"{"rendered":"warning: unused variable\n","spans":[],"level":"warning","message":"unused variable: `x`","code":null,"children":[{"rendered":null,"spans":[{"file_name":"foo.rs","byte_start":9,"byte_end":10,"line_start":1,"line_end":1,"column_start":10,"column_end":11,"is_primary":true,"text":[{"text":"fn f() { x }","highlight_start":10,"highlight_end":11}],"label":null,"suggested_replacement":"_x","suggestion_applicability":"MachineApplicable","expansion":null}],"level":"help","message":"prefix it with an underscore","code":null,"children":[]}]}"#;
+    let run = |mode, source: &str, script: &str| {
+        let path = dir.path().join("foo.rs");
+        std::fs::write(&path, source).unwrap();
+        let mut config = Config {
+            out_dir: dir.path().join("out"),
+            mode,
+            output_conflict_handling: OutputConflictHandling::Ignore,
+            default_timeout: Some(Duration::from_millis(100)),
+            ..config()
+        };
+        config.program = CommandBuilder::cmd("sh");
+        config.program.args = vec!["-c".into(), script.into(), stderr.into()];
+        let comments = parse_comments_in_file(&path, &config).unwrap();
+        let runs = test_file(&path, &config, comments, |_| true, None, None);
+        match &runs[0].result {
+            TestResult::Errored { errors, .. } => match &errors[..] {
+                [Error::TimedOut { .. }] => {}
+                [Error::Aux { errors, .. }] if matches!(errors[..], [Error::TimedOut { .. }]) => {}
+                _ => panic!("{errors:#?}"),
+            },
+            result => panic!("{result:#?}"),
+        }
+    };
+    run(
+        Mode::Pass,
+        "//@aux-build: dep.rs\nfn main() {}\n",
+        r#"case "$*" in *dep.rs*) sleep 5;; esac"#,
+    );
+    run(
+        Mode::Fix,
+        "fn f() { x }\n",
+        r#"case "$*" in *foo.fixed*) sleep 5;; *foo.rs*) printf '%s\n' "$0" >&2;; esac"#,
+    );
+    run(Mode::Pass, "//@probe: --print cfg\n", "sleep 5");
+}
+
+#[test]
+fn forbidden_env_layers() {
+    let forbidden = |config: &Config, comments: &str, compile| {
+        let comments = Comments::parse(comments).unwrap();
+        let mut errors = vec![];
+        check_forbidden_env(config, &comments, "", compile, &mut errors);
+        errors
+            .into_iter()
+            .map(|err| match err {
+                Error::ForbiddenEnv { var, layer } => format!("{var}: {layer}"),
+                _ => panic!("{err:?}"),
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut config = config();
+    // Tests run by cargo always see this one.
+    config.forbidden_env.push("CARGO".into());
+    assert_eq!(
+        forbidden(&config, "", true),
+        ["CARGO: the environment of the test runner"]
+    );
+    assert_eq!(
+        forbidden(&config, "", false),
+        ["CARGO: the environment of the test runner"]
+    );
+
+    config.program.envs.push(("CARGO".into(), None));
+    assert!(forbidden(&config, "", true).is_empty());
+    assert_eq!(
+        forbidden(&config, "", false),
+        ["CARGO: the environment of the test runner"]
+    );
+
+    config.forbidden_env.clear();
+    config
+        .program
+        .envs
+        .push(("UI_TEST_FORBIDDEN".into(), Some("1".into())));
+    let comments = "//@forbid-env: UI_TEST_FORBIDDEN\n//@forbid-env: UI_TEST_OTHER";
+    assert_eq!(
+        forbidden(&config, comments, true),
+        ["UI_TEST_FORBIDDEN: `Config::program.envs`"]
+    );
+    assert!(forbidden(&config, comments, false).is_empty());
+
+    let comments = "//@forbid-env: UI_TEST_OTHER\n//@rustc-env: UI_TEST_OTHER=1";
+    assert_eq!(
+        forbidden(&config, comments, true),
+        ["UI_TEST_OTHER: a `//@rustc-env` comment"]
+    );
+}
+
+#[test]
+fn near_misses() {
+    let s = r"
+fn main() {
+    let _x = 1; //~ ERROR: mismatched typse
+    let _y = 2; //~ WARN: something else entirely
+}
+    ";
+    let comments = Comments::parse(s).unwrap();
+    let mut errors = vec![];
+    let config = config();
+    let msg = |level, message: &str| Message::new(level, message);
+    let messages = vec![
+        vec![],
+        vec![],
+        vec![],
+        vec![msg(Level::Error, "unused variable: `_x`")],
+        vec![msg(Level::Error, "error: mismatched types")],
+    ];
+    check_annotations(
+        messages,
+        vec![],
+        Path::new("moobar"),
+        &mut errors,
+        &config,
+        "",
+        &comments,
+    );
+    match &errors[..] {
+        [Error::PatternNotFound {
+            definition_line: 3,
+            near_misses: first,
+            ..
+        }, Error::PatternNotFound {
+            definition_line: 4,
+            near_misses: second,
+            ..
+        }, Error::ErrorsWithoutPattern {
+            path: Some((_, 3)),
+            similar_patterns: unrelated,
+            ..
+        }, Error::ErrorsWithoutPattern {
+            path: Some((_, 4)),
+            similar_patterns,
+            ..
+        }] => {
+            match &first[..] {
+                [NearMiss {
+                    line: Some(4),
+                    msg,
+                    similarity,
+                }] => {
+                    assert_eq!(msg.message, "error: mismatched types");
+                    assert_eq!(*similarity, 94);
+                }
+                _ => panic!("{:#?}", first),
+            }
+            assert!(second.is_empty());
+            assert!(unrelated.is_empty());
+            match &similar_patterns[..] {
+                [SimilarPattern {
+                    definition_line: 3,
+                    similarity: 94,
+                    ..
+                }] => {}
+                _ => panic!("{:#?}", similar_patterns),
+            }
+        }
+        _ => panic!("{:#?}", errors),
+    }
+}
+
+#[test]
+fn bless_annotations() {
+    let s = "fn main() {
+    let _x = 1; //~ ERROR: old message
+    let _y = 2; //~ ERROR: /regex/
+    let _z = 3; //~ WARN: ambiguous
+}
+";
+    let file = tempfile::Builder::new().suffix(".rs").tempfile().unwrap();
+    let path = file.path();
+    std::fs::write(path, s).unwrap();
+    let comments = Comments::parse(s).unwrap();
+    let mut errors = vec![];
+    let config = Config {
+        output_conflict_handling: OutputConflictHandling::Bless,
+        bless_annotations: true,
+        ..config()
+    };
+    let msg = |level, message: &str| Message::new(level, message);
+    let messages = vec![
+        vec![],
+        vec![],
+        vec![msg(Level::Error, "new message\nwith details")],
+        vec![msg(Level::Error, "no match")],
+        vec![msg(Level::Warn, "one"), msg(Level::Warn, "two")],
+    ];
+    check_annotations(messages, vec![], path, &mut errors, &config, "", &comments);
+    let blessed = std::fs::read_to_string(path).unwrap();
+    assert_eq!(
+        blessed,
+        s.replace("ERROR: old message", "ERROR: new message")
+    );
+    match &errors[..] {
+        [Error::AnnotationRewritten {
+            definition_line: 2,
+            old,
+            new,
+        }, Error::PatternNotFound {
+            definition_line: 3, ..
+        }, Error::AmbiguousAnnotation {
+            definition_line: 4,
+            line: 4,
+            msgs,
+        }, ..]
+            if old == "old message" && new == "new message" && msgs.len() == 2 => {}
+        _ => panic!("{:#?}", errors),
+    }
+}
+
+#[test]
+fn insert_annotations() {
+    let s = "fn main() {
+    let _x = 1;
+    let _y = 2; // a comment
+    //~^ ERROR: annotated
+    let _z = 3;
+}
+";
+    let file = tempfile::Builder::new().suffix(".rs").tempfile().unwrap();
+    let path = file.path();
+    std::fs::write(path, s).unwrap();
+    let comments = Comments::parse(s).unwrap();
+    let mut errors = vec![];
+    let config = Config {
+        output_conflict_handling: OutputConflictHandling::Bless,
+        insert_annotations: true,
+        ..config()
+    };
+    let msg = |level, message: &str| Message::new(level, message);
+    let messages = vec![
+        vec![],
+        vec![],
+        vec![msg(Level::Error, "first")],
+        vec![msg(Level::Error, "annotated"), msg(Level::Error, "second")],
+        vec![],
+        vec![msg(Level::Error, "third"), msg(Level::Warn, "fourth")],
+    ];
+    check_annotations(messages, vec![], path, &mut errors, &config, "", &comments);
+    let blessed = std::fs::read_to_string(path).unwrap();
+    assert_eq!(
+        blessed,
+        "fn main() {
+    let _x = 1; //~ ERROR: first
+    let _y = 2; // a comment
+    //~^ ERROR: annotated
+    //~^^ ERROR: second
+    let _z = 3; //~ ERROR: third
+}
+"
+    );
+    // The warning does not need an annotation, as there are only `ERROR` annotations.
+    match &errors[..] {
+        [Error::AnnotationsInserted {
+            line: 5,
+            removed: Some(_),
+            ..
+        }, Error::AnnotationsInserted {
+            line: 3,
+            removed: None,
+            ..
+        }, Error::AnnotationsInserted { line: 2, .. }] => {}
+        _ => panic!("{:#?}", errors),
+    }
+    assert_eq!(Comments::parse(&blessed).unwrap().revisioned.len(), 1);
+}
+
+#[test]
+fn insert_annotations_with_revisions() {
+    let s = "//@revisions: a b
+fn main() {
+    let _x = 1;
+}
+";
+    let file = tempfile::Builder::new().suffix(".rs").tempfile().unwrap();
+    let path = file.path();
+    std::fs::write(path, s).unwrap();
+    let comments = Comments::parse(s).unwrap();
+    let config = Config {
+        output_conflict_handling: OutputConflictHandling::Bless,
+        insert_annotations: true,
+        ..config()
+    };
+    let msg = Message::new(Level::Error, "unannotated");
+    for revision in ["a", "b"] {
+        let mut errors = vec![];
+        let messages = vec![vec![], vec![], vec![], vec![msg.clone()]];
+        check_annotations(
+            messages,
+            vec![],
+            path,
+            &mut errors,
+            &config,
+            revision,
+            &comments,
+        );
+        assert!(
+            matches!(
+                &errors[..],
+                [Error::ErrorsWithoutPattern { .. }, Error::NoPatternsFound]
+            ),
+            "{errors:#?}"
+        );
+    }
+    assert_eq!(std::fs::read_to_string(path).unwrap(), s);
+}
+
+#[test]
+fn prune_annotations() {
+    let s = "fn main() {
+    let _x = 1; //~ ERROR: stale
+    //~| ERROR: kept x
+    let _y = 2;
+    //~^ ERROR: stale
+    //~| ERROR: kept y
+    //~vvv ERROR: kept z
+    //~vv ERROR: stale
+    //~LINE:10 ERROR: kept z too
+    let _z = 3;
+}
+";
+    let file = tempfile::Builder::new().suffix(".rs").tempfile().unwrap();
+    let path = file.path();
+    std::fs::write(path, s).unwrap();
+    let comments = Comments::parse(s).unwrap();
+    let mut errors = vec![];
+    let config = Config {
+        output_conflict_handling: OutputConflictHandling::Bless,
+        prune_annotations: true,
+        ..config()
+    };
+    let msg = |message: &str| Message::new(Level::Error, message);
+    let mut messages = vec![vec![]; 11];
+    messages[2] = vec![msg("kept x")];
+    messages[4] = vec![msg("kept y")];
+    messages[10] = vec![msg("kept z"), msg("kept z too")];
+    check_annotations(messages, vec![], path, &mut errors, &config, "", &comments);
+    let blessed = std::fs::read_to_string(path).unwrap();
+    assert_eq!(
+        blessed,
+        "fn main() {
+    let _x = 1;
+    //~^ ERROR: kept x
+    let _y = 2;
+    //~^ ERROR: kept y
+    //~vv ERROR: kept z
+    //~LINE:8 ERROR: kept z too
+    let _z = 3;
+}
+"
+    );
+    match &errors[..] {
+        [Error::AnnotationRemoved {
+            definition_line: 2, ..
+        }, Error::AnnotationRemoved {
+            definition_line: 5, ..
+        }, Error::AnnotationRemoved {
+            definition_line: 8,
+            annotation,
+        }] if annotation == "//~vv ERROR: stale" => {}
+        _ => panic!("{:#?}", errors),
+    }
+    let comments = Comments::parse(&blessed).unwrap();
+    let lines: Vec<_> = comments.revisioned[&RevisionFilter::default()]
+        .error_matches
+        .iter()
+        .map(|error_match| error_match.line)
+        .collect();
+    assert_eq!(lines, [Some(2), Some(4), Some(8), Some(8)]);
+}
+
+#[test]
+fn os_family_conditions() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(file.path(), "//@only-unix\nfn main() {}\n").unwrap();
+    let on = |target: &str| Config {
+        target: Some(target.into()),
+        host: Some(target.into()),
+        ..config()
+    };
+
+    let runs = parse_and_test_file(file.path(), &on("x86_64-pc-windows-msvc"));
+    match &runs[..] {
+        [TestRun {
+            result: TestResult::Ignored { .. },
+            ..
+        }] => {}
+        _ => panic!("expected a single ignored test"),
+    }
+
+    let comments = Comments::parse("//@ignore-windows\n//@ignore-wasm\n").unwrap();
+    for (target, run) in [
+        ("x86_64-pc-windows-gnu", false),
+        ("aarch64-uwp-windows-msvc", false),
+        ("wasm32-unknown-unknown", false),
+        ("x86_64-unknown-linux-gnu", true),
+        ("aarch64-apple-darwin", true),
+    ] {
+        assert_eq!(
+            skip_reason(&comments, &on(target), "").is_none(),
+            run,
+            "{target}"
+        );
+    }
+    for (target, os) in [
+        ("x86_64-unknown-linux-gnu", Some("linux")),
+        ("aarch64-linux-android", Some("android")),
+        ("x86_64-apple-darwin", Some("macos")),
+        ("aarch64-apple-ios-sim", Some("ios")),
+        ("wasm32-wasip1", Some("wasi")),
+        ("x86_64-unknown-illumos", Some("illumos")),
+        ("thumbv7em-none-eabihf", None),
+    ] {
+        assert_eq!(get_target_os(target), os, "{target}");
+    }
+}
+
+#[test]
+fn endian_conditions() {
+    let comments = Comments::parse("//@ignore-endian-big\n").unwrap();
+    let on = |target: &str| Config {
+        target: Some(target.into()),
+        ..config()
+    };
+    for (target, reason) in [
+        (
+            "s390x-unknown-linux-gnu",
+            Some("ignore-endian-big at line 1"),
+        ),
+        (
+            "powerpc64-unknown-linux-gnu",
+            Some("ignore-endian-big at line 1"),
+        ),
+        ("powerpc64le-unknown-linux-gnu", None),
+        ("mips64el-unknown-linux-gnuabi64", None),
+        ("x86_64-unknown-linux-gnu", None),
+    ] {
+        assert_eq!(
+            skip_reason(&comments, &on(target), "").as_deref(),
+            reason,
+            "{target}"
+        );
+    }
+    let comments = Comments::parse("//@only-endian-little\n").unwrap();
+    assert_eq!(
+        skip_reason(&comments, &on("sparc64-unknown-linux-gnu"), "").as_deref(),
+        Some("only-endian-little at line 1")
+    );
+}
+
+#[test]
+fn detected_pointer_width() {
+    let comments = Comments::parse("//@ignore-16bit\n").unwrap();
+    let on = |target: &str, width| Config {
+        target: Some(target.into()),
+        target_pointer_width: width,
+        ..config()
+    };
+    assert_eq!(
+        skip_reason(&comments, &on("msp430-none-elf", Some(16)), "").as_deref(),
+        Some("ignore-16bit at line 1")
+    );
+    // Without a detected width, the width is guessed from the triple.
+    assert_eq!(
+        skip_reason(&comments, &on("msp430-none-elf", None), "").as_deref(),
+        Some("ignore-16bit at line 1")
+    );
+    assert_eq!(
+        skip_reason(&comments, &on("x86_64-unknown-linux-gnu", Some(64)), ""),
+        None
+    );
+}
+
+#[test]
+fn env_conditions() {
+    let env: HashMap<OsString, OsString> = [("CI".into(), "true".into())].into_iter().collect();
+    assert!(test_env_condition("CI", None, &env));
+    assert!(test_env_condition("CI", Some("true"), &env));
+    assert!(!test_env_condition("CI", Some("1"), &env));
+    assert!(!test_env_condition("UI_TEST_SLOW", None, &env));
+
+    // The reason names the env var, so the summary shows why the test was skipped.
+    let comments = Comments::parse("//@only-env-UI_TEST_NOT_SET_ANYWHERE=1\n").unwrap();
+    let config = Config {
+        target: Some("x86_64-unknown-linux-gnu".into()),
+        ..config()
+    };
+    assert_eq!(
+        skip_reason(&comments, &config, "").as_deref(),
+        Some("only-env-UI_TEST_NOT_SET_ANYWHERE=1 at line 1")
+    );
+}
+
+#[test]
+fn target_regex_conditions() {
+    let on = |target: &str| Config {
+        target: Some(target.into()),
+        ..config()
+    };
+    let regex = Comments::parse("//@ignore-target: /^arm-/\n").unwrap();
+    let substring = Comments::parse("//@ignore-target-arm\n").unwrap();
+    for (target, regex_ignores, substring_ignores) in [
+        ("arm-unknown-linux-gnueabi", true, true),
+        ("armv7-unknown-linux-gnueabihf", false, true),
+        ("aarch64-unknown-linux-gnu", false, false),
+    ] {
+        assert_eq!(
+            skip_reason(&regex, &on(target), "").is_some(),
+            regex_ignores,
+            "{target}"
+        );
+        assert_eq!(
+            skip_reason(&substring, &on(target), "").is_some(),
+            substring_ignores,
+            "{target}"
+        );
+    }
+    assert_eq!(
+        skip_reason(&regex, &on("arm-unknown-linux-gnueabi"), "").as_deref(),
+        Some("ignore-target: /^arm-/ at line 1")
+    );
+}
+
+#[test]
+fn custom_conditions() {
+    let config = Config {
+        target: Some("x86_64-unknown-linux-gnu".into()),
+        custom_conditions: [("backend-x".into(), true), ("backend-y".into(), false)].into(),
+        ..config()
+    };
+    let parse = |s: &str| Comments::parse_with_config(s, &config);
+    let comments = parse("//@only-backend-x\n//@ignore-backend-y\n").unwrap();
+    assert_eq!(skip_reason(&comments, &config, ""), None);
+    let comments = parse("//@ignore-backend-x\n").unwrap();
+    assert_eq!(
+        skip_reason(&comments, &config, "").as_deref(),
+        Some("ignore-backend-x at line 1")
+    );
+    let comments = parse("//@only-backend-y\n").unwrap();
+    assert_eq!(
+        skip_reason(&comments, &config, "").as_deref(),
+        Some("only-backend-y at line 1")
+    );
+    // Typos are still caught.
+    let errors = parse("//@only-backend-z\n").unwrap_err();
+    match &errors[..] {
+        [Error::InvalidComment { msg, line: 1 }] => {
+            assert!(
+                msg.starts_with("`backend-z` is not a valid condition"),
+                "{msg}"
+            )
+        }
+        _ => panic!("{errors:#?}"),
+    }
+}
+
+#[test]
+fn cross_compile_conditions() {
+    let cross = Config {
+        host: Some("x86_64-unknown-linux-gnu".into()),
+        target: Some("aarch64-unknown-linux-gnu".into()),
+        ..config()
+    };
+    let native = Config {
+        target: cross.host.clone(),
+        ..cross.clone()
+    };
+    let comments = Comments::parse("//@ignore-cross-compile\n").unwrap();
+    assert_eq!(
+        skip_reason(&comments, &cross, "").as_deref(),
+        Some("ignore-cross-compile at line 1")
+    );
+    assert_eq!(skip_reason(&comments, &native, ""), None);
+
+    // Run tests need a `Config::runner` instead.
+    let comments = Comments::parse("//@run\n").unwrap();
+    assert_eq!(skip_reason(&comments, &cross, ""), None);
+    assert_eq!(skip_reason(&comments, &native, ""), None);
+    let cross = Config {
+        skip_run_tests_when_cross: true,
+        ..cross
+    };
+    assert_eq!(
+        skip_reason(&comments, &cross, "").as_deref(),
+        Some("run test while cross compiling")
+    );
+    // A runner can execute them.
+    let with_runner = Config {
+        runner: Some(vec!["qemu-aarch64".into()]),
+        ..cross.clone()
+    };
+    assert_eq!(skip_reason(&comments, &with_runner, ""), None);
+    let native = Config {
+        skip_run_tests_when_cross: true,
+        ..native
+    };
+    assert_eq!(skip_reason(&comments, &native, ""), None);
+    let comments = Comments::parse("//@check-pass\n").unwrap();
+    assert_eq!(skip_reason(&comments, &cross, ""), None);
+}
+
+#[test]
+fn cross_compile_command() {
+    let cross = Config {
+        host: Some("x86_64-unknown-linux-gnu".into()),
+        target: Some("aarch64-unknown-linux-gnu".into()),
+        ..config()
+    };
+    let comments = Comments::parse("").unwrap();
+    let cmd = build_command(Path::new("a.rs"), &cross, "", &comments, &mut vec![]);
+    let args: Vec<_> = cmd.get_args().collect();
+    assert!(args
+        .windows(2)
+        .any(|w| w == ["--target", "aarch64-unknown-linux-gnu"]));
+
+    let native = Config {
+        target: cross.host.clone(),
+        ..cross.clone()
+    };
+    let cmd = build_command(Path::new("a.rs"), &native, "", &comments, &mut vec![]);
+    assert!(!cmd.get_args().any(|arg| arg == "--target"));
+
+    let mut cross = cross;
+    cross
+        .program
+        .args
+        .push("--target=i686-unknown-linux-gnu".into());
+    let err = cross.fill_host_and_target().unwrap_err();
+    assert!(err.to_string().contains("contain `--target`"), "{err}");
+
+    let args = Args::from_args(["ui_tests", "--target", "wasm32-unknown-unknown"]).unwrap();
+    assert_eq!(args.target.as_deref(), Some("wasm32-unknown-unknown"));
+}
+
+#[test]
+fn runner() {
+    let exe = Path::new("out/test");
+    let runner = |args: &[&str]| {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let cmd = runner_command(&args, exe);
+        std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|arg| arg.to_str().unwrap().to_owned())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        runner(&["qemu-aarch64", "-L", "/usr/aarch64-linux-gnu"]),
+        ["qemu-aarch64", "-L", "/usr/aarch64-linux-gnu", "out/test"]
+    );
+    assert_eq!(
+        runner(&["wrapper.sh", "--exe={}", "--verbose"]),
+        ["wrapper.sh", "--exe=out/test", "--verbose"]
+    );
+}
+
+#[test]
+fn channel_conditions() {
+    let comments = Comments::parse("//@only-nightly\n").unwrap();
+    let on = |channel| Config {
+        target: Some("x86_64-unknown-linux-gnu".into()),
+        channel: Some(channel),
+        ..config()
+    };
+    assert_eq!(skip_reason(&comments, &on(Channel::Nightly), ""), None);
+    assert_eq!(
+        skip_reason(&comments, &on(Channel::Stable), "").as_deref(),
+        Some("only-nightly at line 1: the program is stable")
+    );
+    let comments = Comments::parse("//@ignore-beta\n").unwrap();
+    assert_eq!(
+        skip_reason(&comments, &on(Channel::Beta), "").as_deref(),
+        Some("ignore-beta at line 1: the program is beta")
+    );
+
+    // Without a known channel, only tests that use channel conditions fail.
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(file.path(), "//@ignore-stable\n").unwrap();
+    let config = Config {
+        target: Some("x86_64-unknown-linux-gnu".into()),
+        ..config()
+    };
+    let runs = parse_and_test_file(file.path(), &config);
+    match &runs[..] {
+        [TestRun {
+            result: TestResult::Errored { errors, .. },
+            ..
+        }] => match &errors[..] {
+            [Error::UnknownChannel { condition }] => assert_eq!(condition, "ignore-stable"),
+            _ => panic!("{errors:#?}"),
+        },
+        _ => panic!("expected a single failed test"),
+    }
+}
+
+#[test]
+fn unknown_triple_conditions() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(
+        file.path(),
+        "//@ignore-test: not compiled\n//@only-target-x86_46\n//@ignore-host-linux\n//@ignore-target-armv7\n",
+    )
+    .unwrap();
+    let config = Config {
+        host: Some("x86_64-unknown-linux-gnu".into()),
+        target: Some("x86_64-unknown-linux-gnu".into()),
+        known_triples: Some(vec!["armv7-unknown-linux-gnueabihf".into()]),
+        ..config()
+    };
+
+    let runs = parse_and_test_file(file.path(), &config);
+    match &runs[..] {
+        [TestRun {
+            result: TestResult::Ignored { .. },
+            warnings,
+            ..
+        }] => match &warnings[..] {
+            [Error::UnknownTriple { condition, line: 2 }] => {
+                assert_eq!(condition, "only-target-x86_46")
+            }
+            _ => panic!("{warnings:#?}"),
+        },
+        _ => panic!("expected a single ignored test with a warning"),
+    }
+
+    let config = Config {
+        check_conditions: true,
+        ..config
+    };
+    let runs = parse_and_test_file(file.path(), &config);
+    match &runs[..] {
+        [TestRun {
+            result: TestResult::Errored { errors, .. },
+            ..
+        }] => assert!(matches!(
+            &errors[..],
+            [Error::UnknownTriple { line: 2, .. }]
+        )),
+        _ => panic!("expected a single failed test"),
+    }
+
+    // Without a list of known triples, nothing is checked.
+    let config = Config {
+        known_triples: None,
+        ..config
+    };
+    match &parse_and_test_file(file.path(), &config)[..] {
+        [TestRun { warnings, .. }] => assert!(warnings.is_empty(), "{warnings:#?}"),
+        _ => panic!("expected a single ignored test"),
+    }
+}
+
+#[test]
+fn opt_level_conditions() {
+    let s = "//@revisions: opt dbg\n//@[opt] compile-flags: -O\n//@[opt] only-release\n//@[dbg] only-debug\n";
+    let comments = Comments::parse(s).unwrap();
+    let config = Config {
+        target: Some("x86_64-unknown-linux-gnu".into()),
+        ..config()
+    };
+    assert_eq!(skip_reason(&comments, &config, "opt"), None);
+    assert_eq!(skip_reason(&comments, &config, "dbg"), None);
+
+    let mut config = config;
+    config.program.args.push("-Copt-level=3".into());
+    assert_eq!(
+        skip_reason(&comments, &config, "dbg").as_deref(),
+        Some("only-debug at line 4")
+    );
+    config.program.args.push("-C".into());
+    config.program.args.push("debug-assertions".into());
+    assert_eq!(
+        skip_reason(&comments, &config, "opt").as_deref(),
+        Some("only-release at line 3")
+    );
+    assert_eq!(skip_reason(&comments, &config, "dbg"), None);
+}
+
+#[test]
+fn config_from_toml() {
+    let config = Config::from_toml(
+        r#"
+root_dir = "tests/ui"
+mode = "run"
+exit_code = 101
+edition = "2018"
+compile_flags = ["-O"]
+runner = ["qemu-aarch64", "{}"]
+
+[[filters]]
+pattern = 'in [0-9\.]+s'
+replacement = "in $$TIME"
+stream = "both"
+"#,
+    )
+    .unwrap();
+    assert_eq!(config.root_dir, Path::new("tests/ui"));
+    assert!(matches!(
+        config.mode,
+        Mode::Run {
+            exit: ExitExpectation::Code(101)
+        }
+    ));
+    assert_eq!(config.edition.as_deref(), Some("2018"));
+    assert_eq!(config.program.args.last().unwrap(), "-O");
+    assert_eq!(config.runner.unwrap(), ["qemu-aarch64", "{}"]);
+    assert_eq!(config.stderr_filters.last().unwrap().1, b"in $$TIME");
+    assert_eq!(config.stdout_filters.last().unwrap().1, b"in $$TIME");
+}
+
+#[test]
+fn config_from_toml_errors() {
+    let err = |toml: &str| format!("{:?}", Config::from_toml(toml).unwrap_err());
+
+    let msg = err("root_dir = \"ui\"\n[[filters]]\npattern = \"x\"\nreplacment = \"y\"\n");
+    assert!(msg.contains("unknown field `replacment`"), "{msg}");
+    assert!(msg.contains("filters"), "{msg}");
+
+    let msg = err("root_dir = \"ui\"\n\n[[filters]]\npattern = \"(\"\nreplacement = \"\"\n");
+    assert!(msg.contains("in the filter at line 4"), "{msg}");
+    assert!(msg.contains("invalid regex for stderr filter `(`"), "{msg}");
+
+    let msg = err("root_dir = \"ui\"\nmode = \"pass\"\nexit_code = 1\n");
+    assert!(
+        msg.contains("`exit_code` at line 3 is only allowed with `mode = \"run\"`"),
+        "{msg}"
+    );
+}
+
+#[test]
+fn args_from_command_line() {
+    let args = Args::from_args([
+        "ui_tests", "foo", "--bless", "--skip", "bar", "bar.rs", "--skip", "baz", "--exact", "-q",
+    ])
+    .unwrap();
+    assert_eq!(args.filters, ["foo", "bar.rs"]);
+    assert_eq!(args.skip, ["bar", "baz"]);
+    assert!(args.bless && args.exact && args.quiet && !args.check);
+
+    let err = Args::from_args(["ui_tests", "--blessed"])
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("--blessed"), "{err}");
+    assert!(
+        err.contains("accepted options: `--quiet`, `--check`, `--bless`"),
+        "{err}"
+    );
+}
+
+#[test]
+fn filter_by_args() {
+    let args = |args: &[&str]| Args::from_args(Some("ui_tests").iter().chain(args)).unwrap();
+    let path = Path::new("tests/ui/foo/bar.rs");
+    assert!(default_filter_by_arg(path, &args(&[])));
+    assert!(default_filter_by_arg(path, &args(&["foo"])));
+    assert!(!default_filter_by_arg(path, &args(&["baz"])));
+    assert!(!default_filter_by_arg(
+        path,
+        &args(&["foo", "--skip", "bar"])
+    ));
+    assert!(!default_filter_by_arg(path, &args(&["foo", "--exact"])));
+    assert!(default_filter_by_arg(
+        path,
+        &args(&["tests/ui/foo/bar.rs", "--exact"])
+    ));
+}
+
+#[test]
+fn select_tests_by_name() {
+    let args = |args: &[&str]| Args::from_args(Some("ui_tests").iter().chain(args)).unwrap();
+    assert!(args(&["foo/bar.rs"]).selects("foo/bar.rs", "a"));
+    assert!(args(&["bar.rs#a"]).selects("foo/bar.rs", "a"));
+    assert!(!args(&["bar.rs#a"]).selects("foo/bar.rs", "b"));
+    assert!(!args(&["--skip", "#b"]).selects("foo/bar.rs", "b"));
+    assert!(args(&["--skip", "#b"]).selects("foo/bar.rs", ""));
+    assert!(args(&["--exact", "foo/bar.rs"]).selects("foo/bar.rs", "a"));
+    assert!(args(&["--exact", "foo/bar.rs#a"]).selects("foo/bar.rs", "a"));
+    assert!(!args(&["--exact", "foo/bar.rs#a"]).selects("foo/bar.rs", "b"));
+    assert!(!args(&["--exact", "bar.rs"]).selects("foo/bar.rs", ""));
+
+    let path = Path::new("tests/ui/foo/bar.rs");
+    assert_eq!(test_name(Path::new("tests/ui"), path), "foo/bar.rs");
+    assert_eq!(test_name(path, path), "tests/ui/foo/bar.rs");
+}
+
+#[test]
+fn filtered_revisions() {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(
+        file.path(),
+        "//@revisions: a b\n//@error-pattern: foo\n//@ignore-test: not compiled\n",
+    )
+    .unwrap();
+
+    let comments = parse_comments_in_file(file.path(), &config()).unwrap();
+    let runs = test_file(
+        file.path(),
+        &config(),
+        comments,
+        |revision| revision == "b",
+        None,
+        None,
+    );
+    match &runs[..] {
+        [TestRun {
+            result: TestResult::Filtered,
+            warnings: filtered_warnings,
+            ..
+        }, TestRun {
+            result: TestResult::Ignored { .. },
+            warnings,
+            ..
+        }] => {
+            assert!(filtered_warnings.is_empty());
+            match &warnings[..] {
+                [Error::DeprecatedComment { line: 2, .. }] => {}
+                _ => panic!("{warnings:#?}"),
+            }
+        }
+        _ => panic!("expected a filtered and an ignored revision"),
+    }
+}
+
+#[test]
+fn fail_fast_rejects_blessing() {
+    let args = Args::from_args(["ui_tests", "--fail-fast", "--bless"]).unwrap();
+    assert!(args.fail_fast);
+    let err = run_tests_generic(
+        config(),
+        args,
+        default_file_filter,
+        default_per_file_config,
+        status_emitter::Text::quiet(),
+    )
+    .unwrap_err()
+    .to_string();
+    assert!(
+        err.contains("`--fail-fast` can't be combined with blessing"),
+        "{err}"
+    );
+}
+
+#[test]
+fn threads_from_command_line() {
+    let args = |args: &[&str]| Args::from_args(Some("ui_tests").iter().chain(args));
+    assert_eq!(args(&["-j", "2"]).unwrap().threads, NonZeroUsize::new(2));
+    assert_eq!(args(&["--jobs=1"]).unwrap().threads, NonZeroUsize::new(1));
+    assert_eq!(
+        args(&["--test-threads", "3"]).unwrap().threads,
+        NonZeroUsize::new(3)
+    );
+    let err = args(&["--jobs", "0"]).unwrap_err().to_string();
+    assert!(err.contains("invalid value '0' for '--jobs <N>'"), "{err}");
+}
+
+#[test]
+fn suite_result() {
+    let run = |result| TestRun {
+        result,
+        path: "foo.rs".into(),
+        revision: String::new(),
+        duration: Duration::ZERO,
+        timings: Timings::default(),
+        command: None,
+        warnings: vec![],
+    };
+    let passed = TestSuiteResult {
+        tests: vec![run(TestResult::Ok), run(TestResult::Filtered)],
+        orphaned_outputs: vec![],
+    };
+    assert_eq!(passed.failures().count(), 0);
+    passed.into_result().unwrap();
+    let failed = TestSuiteResult {
+        tests: vec![
+            run(TestResult::Ok),
+            run(TestResult::Errored {
+                command: Command::new("cake"),
+                steps: vec![],
+                errors: vec![Error::Bug("oops".into())],
+                stderr: vec![],
+                artifacts: None,
+            }),
+        ],
+        orphaned_outputs: vec![],
+    };
+    assert_eq!(failed.failures().count(), 1);
+    assert_eq!(
+        failed.into_result().unwrap_err().to_string(),
+        "tests failed"
+    );
+}
+
+#[test]
+fn run_single_file() {
+    let config = Config {
+        host: Some("x86_64-unknown-linux-gnu".into()),
+        target: Some("x86_64-unknown-linux-gnu".into()),
+        channel: Some(Channel::Nightly),
+        target_pointer_width: Some(64),
+        known_triples: Some(vec![]),
+        ..config()
+    };
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(
+        file.path(),
+        "//@revisions: a b\n//@ignore-test: not compiled\n",
+    )
+    .unwrap();
+    std::thread::scope(|s| {
+        for _ in 0..2 {
+            s.spawn(|| {
+                let result = run_file(&config, file.path()).unwrap();
+                match &result.revisions[..] {
+                    [TestRun {
+                        result: TestResult::Ignored { .. },
+                        revision: a,
+                        ..
+                    }, TestRun {
+                        result: TestResult::Ignored { .. },
+                        revision: b,
+                        ..
+                    }] => assert_eq!((a.as_str(), b.as_str()), ("a", "b")),
+                    _ => panic!("{result:#?}"),
+                }
+            });
+        }
+    });
+
+    std::fs::write(file.path(), "//@frobnicate\n").unwrap();
+    let result = run_file(&config, file.path()).unwrap();
+    assert_eq!(result.failures().count(), 1);
+
+    let comments = Comments::parse_with_config("//@ignore-test: in memory\n", &config).unwrap();
+    let result = run_file_with_comments(&config, file.path(), comments).unwrap();
+    match &result.revisions[..] {
+        [TestRun {
+            result: TestResult::Ignored { reason },
+            ..
+        }] => assert_eq!(reason, "in memory"),
+        _ => panic!("{result:#?}"),
+    }
+}
+
+#[test]
+fn list() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("a.rs"),
+        "//@revisions: x y\n//@[x] disable-filter: timings\n",
+    )
+    .unwrap();
+    std::fs::write(dir.path().join("b.rs"), "//@ignore-test: nope\n").unwrap();
+    let mut config = Config {
+        root_dir: dir.path().into(),
+        host: Some("x86_64-unknown-linux-gnu".into()),
+        target: Some("x86_64-unknown-linux-gnu".into()),
+        channel: Some(Channel::Nightly),
+        ..config()
+    };
+    config
+        .try_named_stderr_filter("timings", "[0-9]+ms", "$$TIME")
+        .unwrap();
+    config
+        .try_named_stderr_filter("addresses", "0x[0-9a-f]+", "$$HEX")
+        .unwrap();
+    config
+        .try_named_stderr_filter("timings", "[0-9]+s", "$$TIME")
+        .unwrap();
+    let files = vec![dir.path().join("a.rs"), dir.path().join("b.rs")];
+    let list = |args: &[&str]| {
+        let args = Args::from_args(Some("ui_tests").iter().chain(args)).unwrap();
+        let schedule = Schedule::new(files.clone(), &config).unwrap();
+        let mut out = vec![];
+        let selected = |path: &Path, revision: &str| {
+            args.selects(&test_name(&config.root_dir, path), revision)
+        };
+        list_tests(
+            &config,
+            &args,
+            &schedule,
+            &|_, _, _| None,
+            &selected,
+            &mut out,
+        )
+        .unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    assert_eq!(list(&["--list"]), "a.rs#x\na.rs#y\n");
+    assert_eq!(list(&["--list", "#y"]), "a.rs#y\n");
+    assert_eq!(
+        list(&["--list", "--include-ignored"]),
+        "a.rs#x\na.rs#y\nb.rs (ignored: nope)\n"
+    );
+    assert_eq!(
+        list(&["--list", "--format", "json", "--include-ignored", "b.rs"]),
+        format!(
+            "{{\"filters\":[\"timings\",\"addresses\"],\"ignored\":\"nope\",\"name\":\"b.rs\",\"path\":{:?},\"revision\":\"\"}}\n",
+            dir.path().join("b.rs")
+        )
+    );
+    // The filters that a revision disabled are not listed for it.
+    let filters = |list: String| -> Vec<serde_json::Value> {
+        list.lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["filters"].clone())
+            .collect()
+    };
+    assert_eq!(
+        filters(list(&["--list", "--format", "json", "a.rs"])),
+        [
+            serde_json::json!(["addresses"]),
+            serde_json::json!(["timings", "addresses"])
+        ]
+    );
+}
+
+#[test]
+fn shards() {
+    let args = |args: &[&str]| Args::from_args(Some("ui_tests").iter().chain(args));
+    assert_eq!(args(&["--shard", "2/8"]).unwrap().shard, Some((2, 8)));
+    assert_eq!(args(&[]).unwrap().shard, None);
+    for (shard, msg) in [
+        ("2", "expected `I/N`, got `2`"),
+        ("1/0", "the number of shards must be at least 1"),
+        ("0/2", "shards are numbered from 1 to 2"),
+        ("3/2", "shards are numbered from 1 to 2"),
+        ("a/2", "invalid number `a`"),
+    ] {
+        let err = args(&["--shard", shard]).unwrap_err().to_string();
+        assert!(err.contains(msg), "{err}");
+    }
+
+    // The partition must never change, so other machines agree on it.
+    assert_eq!(shard_of("foo/bar.rs", "", 8), 7);
+    assert_eq!(shard_of("foo/bar.rs", "a", 8), 3);
+    let shards: Vec<_> = (0..100)
+        .map(|i| shard_of(&format!("test{i}.rs"), "", 4))
+        .collect();
+    assert!((1..=4).all(|shard| shards.contains(&shard)));
+    assert!(shards.iter().all(|shard| (1..=4).contains(shard)));
+}
+
+#[test]
+fn shuffled_order() {
+    let sorted: Vec<usize> = (0..20).collect();
+    let shuffled = |seed| {
+        let mut items = sorted.clone();
+        shuffle(&mut items, seed);
+        items
+    };
+    // The same seed always results in the same order.
+    assert_eq!(shuffled(42), shuffled(42));
+    assert_ne!(shuffled(42), shuffled(43));
+    assert_ne!(shuffled(42), sorted);
+    let mut items = shuffled(42);
+    items.sort();
+    assert_eq!(items, sorted);
+
+    let args = Args::from_args(["ui_tests", "--shuffle-seed", "42"]).unwrap();
+    assert_eq!(args.shuffle_seed, Some(42));
+}
+
+#[test]
+fn cargo_metadata_paths() {
+    let config = Config::rustc("tests".into()).with_cargo_metadata().unwrap();
+    let workspace_root = config.workspace_root.unwrap();
+    assert_eq!(workspace_root, Path::new(env!("CARGO_MANIFEST_DIR")));
+    let target_dir = config.target_dir.unwrap();
+    match std::env::var_os("CARGO_TARGET_DIR") {
+        Some(dir) => assert_eq!(target_dir, workspace_root.join(dir)),
+        None => assert_eq!(target_dir, workspace_root.join("target")),
+    }
+    assert_eq!(config.out_dir, target_dir.join("ui"));
+    assert_eq!(config.cache_dir, Some(target_dir.join("ui_test/cache")));
+    let replacements: Vec<_> = config.stderr_filters.iter().map(|(_, r)| *r).collect();
+    assert!(replacements.ends_with(&[b"$DIR", b"$WORKSPACE"]));
+}
+
+#[test]
+fn dependency_features() {
+    let config = Config {
+        out_dir: PathBuf::from("target/ui"),
+        ..config()
+    };
+    let dir = |features: &[&str], no_default_features| {
+        let config = Config {
+            dependency_builder_features: features.iter().map(|f| f.to_string()).collect(),
+            dependency_builder_no_default_features: no_default_features,
+            ..config.clone()
+        };
+        crate::dependencies::dependency_target_dir(&config)
+    };
+    assert_eq!(dir(&[], false), Path::new("target/ui"));
+    assert_eq!(
+        dir(&["serde/std", "a"], false),
+        Path::new("target/ui/dependencies-a,serde_std")
+    );
+    assert_eq!(
+        dir(&["a", "serde/std"], false),
+        dir(&["serde/std", "a"], false)
+    );
+    assert_eq!(
+        dir(&[], true),
+        Path::new("target/ui/dependencies-no-default-features")
+    );
+
+    let args = Args::from_args([
+        "ui_tests",
+        "--dependency-features",
+        "a,b",
+        "--dependency-features=c",
+        "--dependency-no-default-features",
+    ])
+    .unwrap();
+    assert_eq!(args.dependency_features, ["a", "b", "c"]);
+    assert!(args.dependency_no_default_features);
+}
+
+#[test]
+fn json_status_emitter() {
+    #[derive(Clone, Default)]
+    struct Buffer(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+    impl std::io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    use status_emitter::StatusEmitter;
+    let buffer = Buffer::default();
+    let mut emitter = status_emitter::Json::new(buffer.clone());
+    let path = Path::new("tests/ui/foo.rs");
+    emitter.test_started(path, "a");
+    emitter.test_started(path, "b");
+    emitter.test_result(path, "a", &TestResult::Ok);
+    emitter.test_result(
+        path,
+        "b",
+        &TestResult::Errored {
+            command: Command::new("tests/ui/foo"),
+            steps: vec![
+                (
+                    "aux-build: tests/ui/auxiliary/dep.rs".into(),
+                    "rustc tests/ui/auxiliary/dep.rs".into(),
+                ),
+                ("build".into(), "rustc tests/ui/foo.rs".into()),
+            ],
+            errors: vec![
+                Error::OutputDiffers {
+                    path: path.with_extension("stderr"),
+                    actual: b"new".to_vec(),
+                    expected: b"old".to_vec(),
+                    bless_command: "bless".into(),
+                    diff_context: 5,
+                },
+                Error::CodeMismatch {
+                    expected: "E0308".into(),
+                    actual: None,
+                    definition_line: 3,
+                },
+            ],
+            stderr: vec![],
+            artifacts: None,
+        },
+    );
+    emitter.test_result(path, "c", &TestResult::Filtered);
+    emitter.finalize(1, 1, 0, 1);
+
+    let output = buffer.0.lock().unwrap().clone();
+    let mut events: Vec<serde_json::Value> = output
+        .lines()
+        .map(|line| serde_json::from_slice(line).unwrap())
+        .collect();
+    // Durations are only reported for finished tests.
+    for event in &mut events {
+        let finished = event["type"] == "test" && event["event"] != "started";
+        let duration = event.as_object_mut().unwrap().remove("duration_ms");
+        assert_eq!(duration.is_some(), finished, "{event}");
+    }
+    assert_eq!(
+        events,
+        [
+            serde_json::json!({"type": "test", "event": "started", "name": "tests/ui/foo.rs", "revision": "a"}),
+            serde_json::json!({"type": "test", "event": "started", "name": "tests/ui/foo.rs", "revision": "b"}),
+            serde_json::json!({"type": "test", "event": "ok", "name": "tests/ui/foo.rs", "revision": "a"}),
+            serde_json::json!({"type": "test", "event": "failed", "name": "tests/ui/foo.rs", "revision": "b", "command": "tests/ui/foo", "steps": [
+                {"label": "aux-build: tests/ui/auxiliary/dep.rs", "command": "rustc tests/ui/auxiliary/dep.rs"},
+                {"label": "build", "command": "rustc tests/ui/foo.rs"},
+            ], "errors": [
+                {
+                    "kind": "output_differs",
+                    "message": "actual output differed from expected, execute `bless` to update it",
+                    "path": "tests/ui/foo.stderr",
+                    "expected": "old",
+                    "actual": "new",
+                },
+                {
+                    "kind": "code_mismatch",
+                    "message": "error code `E0308` not found in stderr output",
+                    "line": 3,
+                    "expected": "E0308",
+                },
+            ]}),
+            serde_json::json!({"type": "suite", "event": "failed", "passed": 1, "failed": 1, "ignored": 0, "filtered_out": 1, "not_run": 0}),
+        ]
+    );
+}
+
+#[test]
+fn junit_status_emitter() {
+    use status_emitter::StatusEmitter;
+    let dir = tempfile::tempdir().unwrap();
+    let report = dir.path().join("reports/junit.xml");
+    let mut emitter = status_emitter::JUnit::to_file(&report);
+    let path = Path::new("tests/ui/foo.rs");
+    emitter.test_started(path, "a");
+    emitter.test_started(path, "b");
+    emitter.test_result(path, "a", &TestResult::Ok);
+    emitter.test_result(
+        path,
+        "b",
+        &TestResult::Errored {
+            command: Command::new("rustc"),
+            steps: vec![],
+            errors: vec![Error::OutputDiffers {
+                path: path.with_extension("b.stderr"),
+                actual: b"error: expected `<T>` & \x1b[1mbold\x1b[0m\n".to_vec(),
+                expected: b"error: expected `T`\n".to_vec(),
+                bless_command: "bless".into(),
+                diff_context: 5,
+            }],
+            stderr: vec![],
+            artifacts: None,
+        },
+    );
+    emitter.test_started(Path::new("bar.rs"), "");
+    emitter.test_result(
+        Path::new("bar.rs"),
+        "",
+        &TestResult::Ignored {
+            reason: "only on \"nightly\"".into(),
+        },
+    );
+    emitter.test_result(path, "c", &TestResult::Filtered);
+    emitter.finalize(1, 1, 1, 1);
+
+    let xml = std::fs::read_to_string(report).unwrap();
+    let xml = Regex::new(r#"time="[0-9.]+""#)
+        .unwrap()
+        .replace_all(xml.as_bytes(), &b"time=\"T\""[..]);
+    assert_eq!(
+        xml.to_str().unwrap(),
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuite name="ui_test" tests="3" failures="1" errors="0" skipped="1" time="T">
+  <testcase classname="tests/ui" name="foo.rs#a" time="T"/>
+  <testcase classname="tests/ui" name="foo.rs#b" time="T">
+    <failure message="actual output differed from expected, execute `bless` to update it" type="output_differs">actual output differed from expected, execute `bless` to update it
+--- tests/ui/foo.b.stderr
++++ &lt;output&gt;
+-error: expected `T`
++error: expected `&lt;T&gt;` &amp; �[1mbold�[0m
+
+command: rustc
+</failure>
+  </testcase>
+  <testcase classname="" name="bar.rs" time="T">
+    <skipped message="only on &quot;nightly&quot;"/>
+  </testcase>
+</testsuite>
+"#
+    );
+}
+
+#[test]
+fn shell_command() {
+    let mut cmd = Command::new("rustc");
+    cmd.arg("--cfg=feature=\"a b\"")
+        .arg("it's")
+        .arg("")
+        .arg("tests/ui/foo.rs")
+        .env("RUSTC_LOG", "debug")
+        .env_remove("RUSTFLAGS")
+        .current_dir("my dir");
+    assert_eq!(
+        crate::shell_command(&cmd),
+        r#"cd 'my dir' && env -u RUSTFLAGS RUSTC_LOG=debug rustc '--cfg=feature="a b"' 'it'\''s' '' tests/ui/foo.rs"#
+    );
+}
+
+/// The aux builds of a failing test are reported as the steps that ran before it.
+#[cfg(unix)]
+#[test]
+fn aux_build_steps() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("auxiliary")).unwrap();
+    std::fs::write(dir.path().join("auxiliary/dep.rs"), "").unwrap();
+    let path = dir.path().join("foo.rs");
+    std::fs::write(&path, "//@aux-build: dep.rs\n").unwrap();
+    let mut config = Config {
+        out_dir: dir.path().join("out"),
+        cache_dir: None,
+        mode: Mode::Pass,
+        ..config()
+    };
+    // Builds the aux file, but fails to build the test.
+    config.program = CommandBuilder::cmd("sh");
+    config.program.args = vec![
+        "-c".into(),
+        r#"case "$*" in *file-names*) echo libdep.rlib;; *foo.rs*) exit 1;; esac"#.into(),
+        "sh".into(),
+    ];
+    let comments = parse_comments_in_file(&path, &config).unwrap();
+    let runs = test_file(&path, &config, comments, |_| true, None, None);
+    match &runs[0].result {
+        TestResult::Errored { steps, .. } => match &steps[..] {
+            [(label, command)] => {
+                let aux = dir.path().join("auxiliary/dep.rs");
+                assert_eq!(label, &format!("aux-build: {}", aux.display()));
+                assert!(command.contains("--crate-type lib"), "{command}");
+            }
+            steps => panic!("{steps:#?}"),
+        },
+        result => panic!("{result:#?}"),
+    }
+}
+
+#[test]
+fn github_actions_escaping() {
+    let mut err =
+        github_actions::error("tests/ui/a,b:c.rs", "Pattern not found (revision: a)").line(3);
+    std::fmt::Write::write_str(&mut err, "100% sure\r\nsecond line:,\n").unwrap();
+    assert_eq!(
+        err.command(),
+        "error file=tests/ui/a%2Cb%3Ac.rs,line=3,title=Pattern not found (revision%3A a)::100%25 sure%0D%0Asecond line:,"
+    );
+    std::mem::forget(err);
+}
+
+#[cfg(unix)]
+#[test]
+fn composite_status_emitter() {
+    use status_emitter::Composite;
+
+    let dir = tempfile::tempdir().unwrap();
+    let tests = dir.path().join("tests");
+    std::fs::create_dir(&tests).unwrap();
+    std::fs::write(tests.join("a.rs"), "").unwrap();
+    std::fs::write(tests.join("b.rs"), "").unwrap();
+    std::fs::write(tests.join("b.stderr"), "error").unwrap();
+    let config = Config {
+        num_test_threads: NonZeroUsize::new(1).unwrap(),
+        ..noop_suite(dir.path())
+    };
+    let log = Log::default();
+    let composite = Composite(vec![
+        Box::new(Recorder("x", log.clone())),
+        Box::new(Recorder("y", log.clone())),
+    ]);
+    let result = run_tests_generic(
+        config,
+        Args::default(),
+        default_file_filter,
+        default_per_file_config,
+        composite,
+    )
+    .unwrap();
+    assert_eq!(result.failures().count(), 1);
+    assert_eq!(
+        recorded(
+            &log,
+            &["started", "ok", "errored", "finalize", "failed", "summary", "dropped"]
+        ),
+        [
+            "x: started a.rs",
+            "y: started a.rs",
+            "x: ok a.rs",
+            "y: ok a.rs",
+            "x: started b.rs",
+            "y: started b.rs",
+            "x: errored b.rs",
+            "y: errored b.rs",
+            "y: finalize 1 1",
+            "x: finalize 1 1",
+            "x: failed b.rs",
+            "y: failed b.rs",
+            "y: summary b.rs",
+            "x: summary b.rs",
+            "y: dropped",
+            "x: dropped",
+            "x: dropped",
+            "y: dropped",
+        ]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn time_limit_warning() {
+    let dir = tempfile::tempdir().unwrap();
+    let tests = dir.path().join("tests");
+    std::fs::create_dir(&tests).unwrap();
+    std::fs::write(tests.join("a.rs"), "//@revisions: x y\n").unwrap();
+    let config = Config {
+        num_test_threads: NonZeroUsize::new(1).unwrap(),
+        time_limit_warning: Some(Duration::ZERO),
+        ..noop_suite(dir.path())
+    };
+    let log = Log::default();
+    let result = run_tests_generic(
+        config,
+        Args::default(),
+        default_file_filter,
+        default_per_file_config,
+        Recorder("", log.clone()),
+    )
+    .unwrap();
+    assert_eq!(result.failures().count(), 0);
+    let log = recorded(&log, &["duration", "warning", "slowest"]);
+    assert_eq!(log[0], "slowest 10");
+    assert_eq!(log[1], "duration a.rs x, run 0ns");
+    assert!(
+        log[2].starts_with("warning a.rs: TimeLimitExceeded"),
+        "expected a time limit warning, got {log:?}"
+    );
+    assert_eq!(log[3], "duration a.rs y, run 0ns");
+    assert!(log[4].starts_with("warning a.rs: TimeLimitExceeded"));
+    assert_eq!(log.len(), 5);
+}
+
+#[cfg(unix)]
+#[test]
+fn dump_artifacts() {
+    let dir = tempfile::tempdir().unwrap();
+    let tests = dir.path().join("tests");
+    std::fs::create_dir(&tests).unwrap();
+    std::fs::write(tests.join("a.rs"), "").unwrap();
+    std::fs::write(tests.join("b.rs"), "").unwrap();
+    std::fs::write(tests.join("b.stderr"), "error").unwrap();
+    let logs_dir = dir.path().join("logs");
+    std::fs::create_dir(&logs_dir).unwrap();
+    std::fs::write(logs_dir.join("stale"), "").unwrap();
+    let run = |dump_artifacts| {
+        let config = Config {
+            logs_dir: logs_dir.clone(),
+            dump_artifacts,
+            ..noop_suite(dir.path())
+        };
+        run_tests_generic(
+            config,
+            Args::default(),
+            default_file_filter,
+            default_per_file_config,
+            status_emitter::Text::quiet(),
+        )
+        .unwrap()
+    };
+
+    let result = run(DumpArtifacts::OnFailure);
+    assert!(!logs_dir.join("stale").exists());
+    let failed: Vec<_> = result.failures().collect();
+    let TestResult::Errored {
+        artifacts: Some(artifacts),
+        ..
+    } = &failed[0].result
+    else {
+        panic!("{failed:#?}")
+    };
+    assert!(artifacts.starts_with(&logs_dir));
+    assert!(artifacts.ends_with("tests/b.rs"));
+    assert_eq!(std::fs::read(artifacts.join("stderr")).unwrap(), b"");
+    assert_eq!(std::fs::read(artifacts.join("raw.stderr")).unwrap(), b"");
+    let commands = std::fs::read_to_string(artifacts.join("commands.sh")).unwrap();
+    assert!(commands.starts_with("# command\n"), "{commands}");
+    // Only the failed test gets a directory.
+    assert!(!artifacts.with_file_name("a.rs").exists());
+
+    run(DumpArtifacts::Always);
+    assert!(artifacts
+        .with_file_name("a.rs")
+        .join("commands.sh")
+        .exists());
+
+    std::fs::write(logs_dir.join("stale"), "").unwrap();
+    run(DumpArtifacts::Never);
+    assert!(logs_dir.join("stale").exists());
+}
+
+#[test]
+#[cfg(unix)]
+fn run_signal_exit_status() {
+    use std::{os::unix::process::ExitStatusExt, process::ExitStatus};
+    let abort = ExitStatus::from_raw(libc::SIGABRT);
+    let segv = ExitStatus::from_raw(libc::SIGSEGV);
+    let exit_134 = ExitStatus::from_raw(134 << 8);
+    let mode = Mode::Run {
+        exit: ExitExpectation::Signal(Signal::Abrt),
+    };
+    assert!(mode.ok(abort).is_empty());
+    for status in [segv, exit_134] {
+        match &mode.ok(status)[..] {
+            [Error::ExitStatus { expected, .. }] => {
+                assert_eq!(expected.to_string(), "signal SIGABRT")
+            }
+            errors => panic!("{errors:#?}"),
+        }
+    }
+    // The numeric form doesn't match signals.
+    let mode = Mode::Run {
+        exit: ExitExpectation::Code(134),
+    };
+    assert!(mode.ok(exit_134).is_empty());
+    assert_eq!(mode.ok(abort).len(), 1);
+}
+
+#[test]
+#[cfg(unix)]
+fn run_panic_exit_status() {
+    use std::{os::unix::process::ExitStatusExt, process::ExitStatus};
+    let mode = Mode::Run {
+        exit: ExitExpectation::Panic,
+    };
+    assert_eq!(mode.to_string(), "run-panic");
+    // Panicking with `-Cpanic=unwind` and `-Cpanic=abort`.
+    assert!(mode.ok(ExitStatus::from_raw(101 << 8)).is_empty());
+    assert!(mode.ok(ExitStatus::from_raw(libc::SIGABRT)).is_empty());
+    assert!(matches!(
+        &mode.ok(ExitStatus::from_raw(0))[..],
+        [Error::DidNotPanic]
+    ));
+    match &mode.ok(ExitStatus::from_raw(1 << 8))[..] {
+        [Error::ExitStatus { expected, .. }] => assert_eq!(expected.to_string(), "a panic"),
+        errors => panic!("{errors:#?}"),
+    }
+}
+
+#[test]
+#[cfg(unix)]
+fn yolo_exit_status() {
+    use std::{os::unix::process::ExitStatusExt, process::ExitStatus};
+    let expected = ExitExpectation::AnyCode;
+    assert!(expected.matches(ExitStatus::from_raw(0)));
+    assert!(expected.matches(ExitStatus::from_raw(101 << 8)));
+    assert!(!expected.matches(ExitStatus::from_raw(libc::SIGSEGV)));
+    // Compiler exit statuses are never checked, only ICEs are.
+    assert!(Mode::Yolo.ok(ExitStatus::from_raw(1 << 8)).is_empty());
+}
+
+#[test]
+fn rustdoc_command() {
+    let config = Config {
+        out_dir: PathBuf::from("out"),
+        host: Some("x86_64-unknown-linux-gnu".into()),
+        target: Some("x86_64-unknown-linux-gnu".into()),
+        ..Config::rustdoc(PathBuf::from("tests/ui"))
+    };
+    assert!(matches!(config.mode, Mode::Pass));
+    let comments = Comments::parse("//@revisions: a b\n").unwrap();
+    let path = Path::new("tests/ui/links/broken.rs");
+    let cmd = build_command(path, &config, "a", &comments, &mut vec![]);
+    assert!(cmd.get_program().to_string_lossy().contains("rustdoc"));
+    let args: Vec<_> = cmd.get_args().collect();
+    assert_eq!(args[0], "--error-format=json");
+    // Every test and revision gets a directory for its documentation.
+    assert!(args
+        .windows(2)
+        .any(|w| w[0] == "--out-dir"
+            && Path::new(w[1]) == Path::new("out/rustdoc/links/broken.rs/a")));
+
+    // Warnings with the counts of emitted warnings don't need annotations.
+    let stderr = br#"{"$message_type":"diagnostic","message":"2 warnings emitted","code":null,"level":"warning","spans":[],"children":[],"rendered":"warning: 2 warnings emitted\n\n"}"#;
+    let diagnostics = rustc_stderr::process(path, stderr, false, true);
+    assert!(diagnostics.messages_from_unknown_file_or_line.is_empty());
+}
+
+#[test]
+fn per_file_config_heuristics() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("a.rs");
+    let added_flags = |source: &str| {
+        std::fs::write(&path, source).unwrap();
+        let config = config();
+        let comments = Comments::parse(source).unwrap();
+        let per_file = default_per_file_config(&config, &path, &comments).unwrap();
+        per_file.program.args[config.program.args.len()..].to_vec()
+    };
+    assert_eq!(added_flags("#[test]\nfn t() {}\n"), ["--test"]);
+    assert_eq!(added_flags("fn f() {}\n"), ["--crate-type=lib"]);
+    // Flags picking the crate type can't be passed twice.
+    assert!(added_flags("//@compile-flags: --test\n#[test]\nfn t() {}\n").is_empty());
+    assert!(added_flags("//@compile-flags: --crate-type=rlib\nfn f() {}\n").is_empty());
+    // Unless only some revisions pick it.
+    assert_eq!(
+        added_flags("//@revisions: a b\n//@[a] compile-flags: --crate-type=rlib\nfn f() {}\n"),
+        ["--crate-type=lib"]
+    );
+}
+
+#[test]
+fn crate_type_command() {
+    let mut config = Config::rustc(PathBuf::from("tests/ui"));
+    config.program.args.push("--crate-type=lib".into());
+    config.program.args.push("-Dwarnings".into());
+    let path = Path::new("tests/ui/no_main.rs");
+    let comments =
+        Comments::parse("//@revisions: a b\n//@[a] crate-type: cdylib\n//@crate-name: mycrate")
+            .unwrap();
+    let args = |revision| -> Vec<String> {
+        build_command(path, &config, revision, &comments, &mut vec![])
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    };
+    let a = args("a");
+    assert!(!a.iter().any(|arg| arg == "--crate-type=lib"));
+    assert!(a.iter().any(|arg| arg == "-Dwarnings"));
+    // The test's flags come after the ones of the `Config`.
+    assert_eq!(
+        a[a.len() - 4..],
+        ["--crate-type", "cdylib", "--crate-name", "mycrate"]
+    );
+    let b = args("b");
+    assert!(b.iter().any(|arg| arg == "--crate-type=lib"));
+    assert!(!b.iter().any(|arg| arg == "--crate-type"));
+}
@@ -0,0 +1,55 @@
+//! Which filters and `normalize-*-test` rules replaced anything, to warn about those that
+//! didn't, see `Config::report_unused_normalizations`.
+
+use crate::{diff::lossy, Error, Match};
+use std::collections::{BTreeMap, HashSet};
+
+#[cfg(test)]
+mod tests;
+
+/// The filters and `normalize-*-test` rules that replaced anything in the output of a test.
+#[derive(Default)]
+pub(crate) struct Usage {
+    /// The filters, as described by `describe`, because each test gets its own copy of them.
+    pub filters: HashSet<String>,
+    /// The lines of the `normalize-*-test` comments.
+    pub normalizations: HashSet<usize>,
+}
+
+/// Describes a filter for humans, which also identifies it across tests.
+pub(crate) fn describe(rule: &Match, replacement: &[u8]) -> String {
+    let rule = match rule {
+        Match::Regex(regex) => format!("`{}`", regex.as_str()),
+        Match::Exact(exact) => format!("`{}`", lossy(exact)),
+        Match::PathBackslash => "backslashes in paths".into(),
+        Match::PathBackslashUnder(root) => {
+            format!("backslashes in paths under `{}`", root.display())
+        }
+    };
+    format!("{rule} -> `{}`", lossy(replacement))
+}
+
+/// The `normalize-*-test` rules of the `revisions` that replaced nothing.
+pub(crate) fn unused_normalizations<'a>(
+    comments: &crate::Comments,
+    revisions: impl Iterator<Item = &'a str>,
+    usage: &Usage,
+) -> Vec<Error> {
+    let mut unused = BTreeMap::new();
+    for revision in revisions {
+        for r in comments.for_revision(revision) {
+            for (regex, _, line) in r.normalize_stderr.iter().chain(&r.normalize_stdout) {
+                if !usage.normalizations.contains(line) {
+                    unused.insert(*line, regex.as_str().to_owned());
+                }
+            }
+        }
+    }
+    unused
+        .into_iter()
+        .map(|(line, rule)| Error::UnusedNormalization {
+            rule: format!("`{rule}`"),
+            line: Some(line),
+        })
+        .collect()
+}
@@ -0,0 +1,57 @@
+use crate::{
+    default_file_filter, default_per_file_config, run_tests_generic,
+    tests::{noop_suite, recorded, Log, Recorder},
+    Args, CommandBuilder, Config, Match, OutputConflictHandling,
+};
+
+#[cfg(unix)]
+#[test]
+fn unused_normalizations() {
+    let dir = tempfile::tempdir().unwrap();
+    let tests = dir.path().join("tests");
+    std::fs::create_dir(&tests).unwrap();
+    std::fs::write(
+        tests.join("a.rs"),
+        "//@normalize-stderr-test: \"found\" -> \"FOUND\"\n//@normalize-stderr-test: \"missing\" -> \"x\"\n",
+    )
+    .unwrap();
+    // Filtered out, so it doesn't keep the filters from being reported.
+    std::fs::write(tests.join("b.rs"), "").unwrap();
+    let mut config = Config {
+        output_conflict_handling: OutputConflictHandling::Ignore,
+        report_unused_normalizations: true,
+        ..noop_suite(dir.path())
+    };
+    config.program = CommandBuilder::cmd("sh");
+    config.program.args = vec!["-c".into(), "echo found >&2; echo out".into()];
+    config.try_stderr_filter("fou", "fou").unwrap();
+    config.try_stderr_filter("nothing", "x").unwrap();
+    config.try_stderr_filter("nothing", "x").unwrap();
+    config.try_stdout_filter("out", "out").unwrap();
+    config.try_stdout_filter("nostdout", "z").unwrap();
+    config
+        .try_named_stderr_filter("named", "nowhere", "y")
+        .unwrap();
+    // The same as the filter `Config::rustc` adds, but not added by it.
+    config.stderr_filters.push((Match::PathBackslash, b"/"));
+    let log = Log::default();
+    let result = run_tests_generic(
+        config,
+        Args::from_args(["ui_tests", "a.rs"]).unwrap(),
+        default_file_filter,
+        default_per_file_config,
+        Recorder("", log.clone()),
+    )
+    .unwrap();
+    assert_eq!(result.failures().count(), 0);
+    assert_eq!(
+        recorded(&log, &["warning"]),
+        [
+            r#"warning a.rs: UnusedNormalization { rule: "`missing`", line: Some(2) }"#,
+            r#"warning tests: UnusedNormalization { rule: "`nostdout` -> `z`", line: None }"#,
+            r#"warning tests: UnusedNormalization { rule: "`nothing` -> `x`", line: None }"#,
+            r#"warning tests: UnusedNormalization { rule: "`nowhere` -> `y`", line: None }"#,
+            r#"warning tests: UnusedNormalization { rule: "backslashes in paths -> `/`", line: None }"#,
+        ]
+    );
+}
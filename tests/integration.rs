@@ -47,42 +47,47 @@ fn run(name: &str, mode: Mode) -> Result<()> {
         .envs
         .push(("BLESS".into(), (!args.check).then(|| String::new().into())));
 
-    config.stdout_filter("in ([0-9]m )?[0-9\\.]+s", "");
-    config.stderr_filter(r#""--out-dir"(,)? "[^"]+""#, r#""--out-dir"$1 "$$TMP"#);
-    config.stderr_filter(
+    config.try_stdout_filter("in ([0-9]m )?[0-9\\.]+s", "")?;
+    config.try_stderr_filter(r#""--out-dir"(,)? "[^"]+""#, r#""--out-dir"$1 "$$TMP"#)?;
+    config.try_stderr_filter("--out-dir [^ ]+", "--out-dir $$TMP")?;
+    config.try_stderr_filter(
         "( *process didn't exit successfully: `[^-]+)-[0-9a-f]+",
         "$1-HASH",
-    );
+    )?;
     // Windows io::Error uses "exit code".
-    config.stderr_filter("exit code", "exit status");
+    config.try_stderr_filter("exit code", "exit status")?;
     // The order of the `/deps` directory flag is flaky
-    config.stderr_filter("/deps", "");
-    config.path_stderr_filter(&std::path::Path::new(path), "$DIR");
-    config.stderr_filter("[0-9a-f]+\\.rmeta", "$$HASH.rmeta");
+    config.try_stderr_filter("/deps", "")?;
+    config.try_path_stderr_filter(&std::path::Path::new(path), "$DIR")?;
+    config.try_stderr_filter("[0-9a-f]+\\.rmeta", "$$HASH.rmeta")?;
     // Windows backslashes are sometimes escaped.
     // Insert the replacement filter at the start to make sure the filter for single backslashes
     // runs afterwards.
     config
         .stderr_filters
         .insert(0, (Match::Exact(b"\\\\".iter().copied().collect()), b"\\"));
-    config.stderr_filter("\\.exe", b"");
-    config.stderr_filter(r#"(panic.*)\.rs:[0-9]+:[0-9]+"#, "$1.rs");
-    config.stderr_filter("   [0-9]: .*", "");
-    config.stderr_filter("/target/[^/]+/[^/]+/debug", "/target/$$TMP/$$TRIPLE/debug");
-    config.stderr_filter("/target/[^/]+/tests", "/target/$$TMP/tests");
+    config.try_stderr_filter("\\.exe", b"")?;
+    config.try_stderr_filter(r#"(panic.*)\.rs:[0-9]+:[0-9]+"#, "$1.rs")?;
+    config.try_stderr_filter("   [0-9]: .*", "")?;
+    config.try_stderr_filter("/target/[^/]+/[^/]+/debug", "/target/$$TMP/$$TRIPLE/debug")?;
+    config.try_stderr_filter("/target/[^/]+/tests", "/target/$$TMP/tests")?;
+    config.try_stderr_filter("/ui_test/cache/aux-[0-9a-f]+", "/ui_test/cache/aux-$$HASH")?;
     // Normalize proc macro filenames on windows to their linux repr
-    config.stderr_filter("/([^/\\.]+)\\.dll", "/lib$1.so");
+    config.try_stderr_filter("/([^/\\.]+)\\.dll", "/lib$1.so")?;
     // Normalize proc macro filenames on mac to their linux repr
-    config.stderr_filter("/([^/\\.]+)\\.dylib", "/$1.so");
-    config.stderr_filter("(command: )\"[^<rp][^\"]+", "$1\"$$CMD");
-    config.stderr_filter("(src/.*?\\.rs):[0-9]+:[0-9]+", "$1:LL:CC");
-    config.stderr_filter("program not found", "No such file or directory");
-    config.stderr_filter(" \\(os error [0-9]+\\)", "");
+    config.try_stderr_filter("/([^/\\.]+)\\.dylib", "/$1.so")?;
+    config.try_stderr_filter("(command: )[^<rp'`][^ \n]*", "$1$$CMD")?;
+    config.try_stderr_filter("(src/.*?\\.rs):[0-9]+:[0-9]+", "$1:LL:CC")?;
+    config.try_stderr_filter("program not found", "No such file or directory")?;
+    config.try_stderr_filter(" \\(os error [0-9]+\\)", "")?;
+    config.try_stderr_filter("(artifacts: ).*/ui_test/logs/", "$1$$LOGS/")?;
+    // Which tests are the slowest and how long they took differs between runs.
+    config.try_stderr_filter("SLOWEST TESTS:\n(    [^\n]*\n)*\n", "")?;
 
     run_tests_generic(
         config,
         args,
-        |path, args| {
+        |path, _args| {
             let fail = path
                 .parent()
                 .unwrap()
@@ -103,16 +108,19 @@ fn run(name: &str, mode: Mode) -> Result<()> {
                     // multiple [[test]]s exist. If there's only one test, it returns
                     // 1 on failure.
                     Mode::Panic => fail,
-                    Mode::Fix | Mode::Run { .. } | Mode::Yolo | Mode::Fail { .. } => unreachable!(),
+                    Mode::Check | Mode::Fix | Mode::Run { .. } | Mode::Yolo | Mode::Fail { .. } => {
+                        unreachable!()
+                    }
                 }
-                && default_filter_by_arg(path, args)
         },
-        |_, _| None,
+        |_, _, _| None,
         (
             ui_test::status_emitter::Text::verbose(),
-            ui_test::status_emitter::Gha::<true> {
+            ui_test::status_emitter::Gha {
                 name: format!("{mode:?}"),
+                group_by_file: true,
             },
         ),
-    )
+    )?
+    .into_result()
 }
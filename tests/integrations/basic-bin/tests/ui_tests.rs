@@ -9,18 +9,18 @@ fn main() -> ui_test::color_eyre::Result<()> {
     if std::env::var_os("BLESS").is_some() {
         config.output_conflict_handling = OutputConflictHandling::Bless
     }
-    config.stderr_filter("in ([0-9]m )?[0-9\\.]+s", "");
-    config.stdout_filter("in ([0-9]m )?[0-9\\.]+s", "");
-    config.stderr_filter(r"[^ ]*/\.?cargo/registry/.*/", "$$CARGO_REGISTRY");
-    config.stderr_filter(r"\.exe", "");
-    config.stderr_filter("/target/[^/]+/[^/]+/debug", "/target/$$TMP/$$TRIPLE/debug");
-    config.path_stderr_filter(&std::path::Path::new(path), "$DIR");
+    config.try_stderr_filter("in ([0-9]m )?[0-9\\.]+s", "")?;
+    config.try_stdout_filter("in ([0-9]m )?[0-9\\.]+s", "")?;
+    config.try_stderr_filter(r"[^ ]*/\.?cargo/registry/.*/", "$$CARGO_REGISTRY")?;
+    config.try_stderr_filter(r"\.exe", "")?;
+    config.try_stderr_filter("/target/[^/]+/[^/]+/debug", "/target/$$TMP/$$TRIPLE/debug")?;
+    config.try_path_stderr_filter(&std::path::Path::new(path), "$DIR")?;
 
     // hide binaries generated for successfully passing tests
     let tmp_dir = tempfile::tempdir_in(path)?;
     let tmp_dir = tmp_dir.path();
     config.out_dir = tmp_dir.into();
-    config.path_stderr_filter(tmp_dir, "$$TMP");
+    config.try_path_stderr_filter(tmp_dir, "$$TMP")?;
 
     run_tests_generic(
         config,
@@ -29,5 +29,6 @@ fn main() -> ui_test::color_eyre::Result<()> {
         default_per_file_config,
         // Avoid github actions, as these would end up showing up in `Cargo.stderr`
         status_emitter::Text::verbose(),
-    )
+    )?
+    .into_result()
 }
@@ -12,10 +12,10 @@ fn main() -> ui_test::color_eyre::Result<()> {
     if std::env::var_os("BLESS").is_some() {
         config.output_conflict_handling = OutputConflictHandling::Bless
     }
-    config.stderr_filter("in ([0-9]m )?[0-9\\.]+s", "");
-    config.stdout_filter("in ([0-9]m )?[0-9\\.]+s", "");
-    config.stderr_filter(r"[^ ]*/\.?cargo/registry/.*/", "$$CARGO_REGISTRY");
-    config.path_stderr_filter(&std::path::Path::new(path), "$DIR");
+    config.try_stderr_filter("in ([0-9]m )?[0-9\\.]+s", "")?;
+    config.try_stdout_filter("in ([0-9]m )?[0-9\\.]+s", "")?;
+    config.try_stderr_filter(r"[^ ]*/\.?cargo/registry/.*/", "$$CARGO_REGISTRY")?;
+    config.try_path_stderr_filter(&std::path::Path::new(path), "$DIR")?;
 
     run_tests_generic(
         config,
@@ -24,5 +24,6 @@ fn main() -> ui_test::color_eyre::Result<()> {
         default_per_file_config,
         // Avoid github actions, as these would end up showing up in `Cargo.stderr`
         status_emitter::Text::verbose(),
-    )
+    )?
+    .into_result()
 }
@@ -0,0 +1,4 @@
+fn main() {
+    let _: usize = "42";
+    //~^ ERROR: mismatched types
+}
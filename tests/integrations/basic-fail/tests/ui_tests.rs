@@ -18,12 +18,12 @@ fn main() -> ui_test::color_eyre::Result<()> {
     let tmp_dir = tempfile::tempdir_in(path)?;
     let tmp_dir = tmp_dir.path();
     config.out_dir = tmp_dir.into();
-    config.path_stderr_filter(tmp_dir, "$TMP");
+    config.try_path_stderr_filter(tmp_dir, "$TMP")?;
 
-    config.stderr_filter("in ([0-9]m )?[0-9\\.]+s", "");
-    config.stdout_filter("in ([0-9]m )?[0-9\\.]+s", "");
-    config.stderr_filter(r"[^ ]*/\.?cargo/registry/.*/", "$$CARGO_REGISTRY");
-    config.path_stderr_filter(&std::path::Path::new(path), "$DIR");
+    config.try_stderr_filter("in ([0-9]m )?[0-9\\.]+s", "")?;
+    config.try_stdout_filter("in ([0-9]m )?[0-9\\.]+s", "")?;
+    config.try_stderr_filter(r"[^ ]*/\.?cargo/registry/.*/", "$$CARGO_REGISTRY")?;
+    config.try_path_stderr_filter(&std::path::Path::new(path), "$DIR")?;
 
     run_tests_generic(
         config,
@@ -32,5 +32,6 @@ fn main() -> ui_test::color_eyre::Result<()> {
         default_per_file_config,
         // Avoid github actions, as these would end up showing up in `Cargo.stderr`
         status_emitter::Text::verbose(),
-    )
+    )?
+    .into_result()
 }
@@ -20,5 +20,6 @@ fn main() -> ui_test::color_eyre::Result<()> {
         default_per_file_config,
         // Avoid github actions, as these would end up showing up in `Cargo.stderr`
         status_emitter::Text::verbose(),
-    )
+    )?
+    .into_result()
 }
@@ -1,6 +1,6 @@
 //@aux-build:derive_proc_macro.rs:proc-macro
 //@run-rustfix
-
+//@no-verify-fixed
 #[macro_use]
 extern crate derive_proc_macro;
 
@@ -0,0 +1,8 @@
+//@depends-on: chain_producer.rs
+//@check-pass
+
+extern crate chain_producer;
+
+fn main() {
+    assert_eq!(chain_producer::answer(), 42);
+}
@@ -0,0 +1,5 @@
+//@check-pass
+
+pub fn answer() -> u32 {
+    42
+}
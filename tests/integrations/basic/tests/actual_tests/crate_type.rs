@@ -0,0 +1,6 @@
+//@crate-type: lib
+//@crate-name: renamed
+#![deny(dead_code)]
+
+// Without `crate-type`, this file would be compiled as a binary because of its `main`.
+fn main() {} //~ ERROR: function `main` is never used
@@ -0,0 +1,5 @@
+//@check-pass
+//@only-backend-x
+//@ignore-backend-y
+
+fn main() {}
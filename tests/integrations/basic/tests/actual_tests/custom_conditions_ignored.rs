@@ -0,0 +1,5 @@
+//@ignore-backend-x: the test would fail with backend-x
+
+fn main() {
+    let x: u32 = "not a number";
+}
@@ -0,0 +1,7 @@
+//@revisions: filtered unfiltered
+//@[unfiltered] disable-filter: answer
+
+fn main() {
+    let forty_two: () = 42;
+    //~^ ERROR: mismatched types
+}
@@ -0,0 +1,8 @@
+//@run-panic: the answer is 42
+//@check-run-results
+// Newer Rust versions print the id of the panicking thread.
+//@normalize-stderr-test: "thread 'main' \(\d+\)" -> "thread 'main'"
+
+fn main() {
+    panic!("the answer is {}", 6 * 7);
+}
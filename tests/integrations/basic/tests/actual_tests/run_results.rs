@@ -0,0 +1,7 @@
+//@run
+//@check-run-results
+
+fn main() {
+    println!("to stdout");
+    eprintln!("to stderr");
+}
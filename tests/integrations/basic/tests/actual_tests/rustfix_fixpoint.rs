@@ -0,0 +1,7 @@
+//@run-rustfix
+#![deny(warnings)]
+
+fn main() {
+    let _x = ((1));
+    //~^ ERROR: unnecessary parentheses
+}
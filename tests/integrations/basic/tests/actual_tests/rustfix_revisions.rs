@@ -0,0 +1,13 @@
+//@run-rustfix
+//@revisions: a b
+#![deny(warnings)]
+
+fn main() {
+    #[cfg(a)]
+    let mut x = 42;
+    //~[a]^ ERROR: does not need to be mutable
+    #[cfg(b)]
+    let x = (42);
+    //~[b]^ ERROR: unnecessary parentheses
+    println!("{x}");
+}
@@ -0,0 +1,12 @@
+//@run
+//@stdin: stdin_echo.input
+
+use std::io::Read;
+
+fn main() {
+    let mut input = String::new();
+    std::io::stdin().read_to_string(&mut input).unwrap();
+    for line in input.lines() {
+        println!("{}", line.to_uppercase());
+    }
+}
@@ -0,0 +1,9 @@
+//@yolo
+//@run
+
+// Neither the warning nor the binary's exit code and output are checked.
+fn main() {
+    let unused = 1;
+    println!("whatever");
+    std::process::exit(3);
+}
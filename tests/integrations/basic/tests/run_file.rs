@@ -9,7 +9,7 @@ fn run_file() -> Result<()> {
     let tmp_dir = tempfile::tempdir_in(env!("CARGO_TARGET_TMPDIR"))?;
     let tmp_dir = tmp_dir.path();
     config.out_dir = tmp_dir.into();
-    config.path_stderr_filter(tmp_dir, "$TMP");
+    config.try_path_stderr_filter(tmp_dir, "$TMP")?;
 
     let mut result = ui_test::test_command(
         config,
@@ -31,7 +31,7 @@ fn run_file_with_deps() -> Result<()> {
     let tmp_dir = tempfile::tempdir_in(path)?;
     let tmp_dir = tmp_dir.path();
     config.out_dir = tmp_dir.into();
-    config.path_stderr_filter(tmp_dir, "$TMP");
+    config.try_path_stderr_filter(tmp_dir, "$TMP")?;
 
     // Don't build a binary, we only provide .rmeta dependencies for now
     config.program.args.push("--emit=metadata".into());
@@ -4,23 +4,22 @@ use ui_test::*;
 fn main() -> ui_test::color_eyre::Result<()> {
     let path = "../../../target";
     let mut config = Config {
-        dependencies_crate_manifest_path: Some("Cargo.toml".into()),
         num_test_threads: NonZeroUsize::new(1).unwrap(),
-        ..Config::rustc("tests/actual_tests".into())
+        ..Config::from_file("tests/ui_test.toml".as_ref())?
     };
     if std::env::var_os("BLESS").is_none() {
         config.output_conflict_handling = OutputConflictHandling::Bless;
     }
-    config.stderr_filter("in ([0-9]m )?[0-9\\.]+s", "");
-    config.stdout_filter("in ([0-9]m )?[0-9\\.]+s", "");
-    config.stderr_filter(r"[^ ]*/\.?cargo/registry/.*/", "$$CARGO_REGISTRY");
-    config.path_stderr_filter(&std::path::Path::new(path), "$DIR");
+    config.custom_conditions = [("backend-x".into(), true), ("backend-y".into(), false)].into();
+    config.try_path_stderr_filter(&std::path::Path::new(path), "$DIR")?;
+    // Disabled by `disable_filter.rs`.
+    config.try_named_stderr_filter("answer", "forty_two", "$$ANSWER")?;
 
     // hide binaries generated for successfully passing tests
     let tmp_dir = tempfile::tempdir_in(path)?;
     let tmp_dir = tmp_dir.path();
     config.out_dir = tmp_dir.into();
-    config.path_stderr_filter(tmp_dir, "$TMP");
+    config.try_path_stderr_filter(tmp_dir, "$TMP")?;
 
     run_tests_generic(
         config,
@@ -29,5 +28,6 @@ fn main() -> ui_test::color_eyre::Result<()> {
         default_per_file_config,
         // Avoid github actions, as these would end up showing up in `Cargo.stderr`
         status_emitter::Text::verbose(),
-    )
+    )?
+    .into_result()
 }
@@ -0,0 +1,20 @@
+//! A stand-in for `rustc` that reports how it was invoked before forwarding to `rustc`.
+
+use std::process::Command;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Ok(greeting) = std::env::var("SHIM_GREETING") {
+        eprintln!("shim: {greeting}");
+    }
+    eprintln!("shim: {args:?}");
+    let status = Command::new(std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into()))
+        .args(&args)
+        .status()
+        .unwrap();
+    // Let the tests check that the exit code of the program is what counts.
+    if let Ok(code) = std::env::var("SHIM_EXIT_CODE") {
+        std::process::exit(code.parse().unwrap());
+    }
+    std::process::exit(status.code().unwrap_or(1));
+}
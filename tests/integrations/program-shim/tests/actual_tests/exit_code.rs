@@ -0,0 +1,4 @@
+//@rustc-env: SHIM_EXIT_CODE=1
+//@compile-flags: -O
+
+fn main() {}
@@ -0,0 +1,4 @@
+//@check-pass
+//@edition: 2018
+
+fn main() {}
@@ -0,0 +1,41 @@
+use ui_test::*;
+
+fn main() -> ui_test::color_eyre::Result<()> {
+    let path = "../../../target";
+    let mut config = Config {
+        mode: Mode::Fail {
+            require_patterns: false,
+        },
+        ..Config::rustc("tests/actual_tests".into())
+    };
+    config.program.program = env!("CARGO_BIN_EXE_program-shim").into();
+    config
+        .program
+        .envs
+        .push(("SHIM_GREETING".into(), Some("hello".into())));
+    if std::env::var_os("BLESS").is_some() {
+        config.output_conflict_handling = OutputConflictHandling::Bless
+    }
+    config.try_stderr_filter("in ([0-9]m )?[0-9\\.]+s", "")?;
+    config.try_stdout_filter("in ([0-9]m )?[0-9\\.]+s", "")?;
+    config.try_stderr_filter(r"\.exe", "")?;
+    config.try_path_stderr_filter(&std::path::Path::new(path), "$DIR")?;
+
+    // hide binaries generated for successfully passing tests
+    let tmp_dir = tempfile::tempdir_in(path)?;
+    let tmp_dir = tmp_dir.path();
+    config.out_dir = tmp_dir.into();
+    config.try_path_stderr_filter(tmp_dir, "$$TMP")?;
+    // The shim prints the `--out-dir`, which is not canonicalized.
+    config.try_stderr_filter(r#"/\.tmp[^/"]+"#, "/$$TMP")?;
+
+    run_tests_generic(
+        config,
+        Args::default(),
+        default_file_filter,
+        default_per_file_config,
+        // Avoid github actions, as these would end up showing up in `Cargo.stderr`
+        status_emitter::Text::verbose(),
+    )?
+    .into_result()
+}
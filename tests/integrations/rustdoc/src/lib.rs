@@ -0,0 +1 @@
+//! Nothing to see here, the tests are documented by `rustdoc`.
@@ -0,0 +1,7 @@
+//@aux-build: dep.rs
+//@compile-flags: --crate-type=lib
+
+/// Links to [`dep::Thing`], which rustdoc can only resolve if the aux crate was built.
+pub fn thing() -> dep::Thing {
+    dep::Thing
+}
@@ -0,0 +1,2 @@
+/// A type that the documentation of the test links to.
+pub struct Thing;
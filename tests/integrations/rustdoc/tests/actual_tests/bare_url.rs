@@ -0,0 +1,6 @@
+//@compile-flags: --crate-type=lib
+#![warn(rustdoc::bare_urls)]
+
+/// See https://example.com for details.
+//~^ WARN: this URL is not a hyperlink
+pub fn documented() {}
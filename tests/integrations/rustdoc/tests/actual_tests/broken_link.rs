@@ -0,0 +1,5 @@
+//@compile-flags: --crate-type=lib
+
+/// Links to [`Missing`], which doesn't exist.
+//~^ WARN: unresolved link to `Missing`
+pub struct Documented;
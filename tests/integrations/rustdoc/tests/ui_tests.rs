@@ -0,0 +1,27 @@
+use ui_test::*;
+
+fn main() -> ui_test::color_eyre::Result<()> {
+    let path = "../../../target";
+    let mut config = Config::rustdoc("tests/actual_tests".into());
+    if std::env::var_os("BLESS").is_some() {
+        config.output_conflict_handling = OutputConflictHandling::Bless
+    }
+    config.try_stderr_filter("in ([0-9]m )?[0-9\\.]+s", "")?;
+    config.try_stdout_filter("in ([0-9]m )?[0-9\\.]+s", "")?;
+
+    // hide the documentation generated for the tests
+    let tmp_dir = tempfile::tempdir_in(path)?;
+    let tmp_dir = tmp_dir.path();
+    config.out_dir = tmp_dir.into();
+    config.try_path_stderr_filter(tmp_dir, "$$TMP")?;
+
+    run_tests_generic(
+        config,
+        Args::default(),
+        default_file_filter,
+        default_per_file_config,
+        // Avoid github actions, as these would end up showing up in `Cargo.stderr`
+        status_emitter::Text::verbose(),
+    )?
+    .into_result()
+}